@@ -1,27 +1,46 @@
-use crate::rtsp_machine::RtspMachine;
+use crate::rtsp_machine::{RtspEvent, RtspMachine};
 use std::io::{BufRead, BufReader, BufWriter};
 use std::{collections::HashMap, env};
 
 pub(crate) mod io_macros;
+pub(crate) mod record;
+pub(crate) mod record_dump;
+pub(crate) mod record_mp4;
+pub(crate) mod record_segment;
+pub(crate) mod record_ts;
+pub(crate) mod rtp_aac;
+pub(crate) mod rtp_audio;
+pub(crate) mod rtp_depacketizer;
+pub(crate) mod rtp_h264;
+pub(crate) mod rtp_h265;
+pub(crate) mod rtp_jitter;
+pub(crate) mod rtp_jpeg;
+pub(crate) mod rtp_metadata;
+pub(crate) mod rtp_packet;
+pub(crate) mod rtp_ports;
+pub(crate) mod rtp_receiver;
+pub(crate) mod rtp_rtcp;
+pub(crate) mod rtp_sender;
+pub(crate) mod rtp_srtp;
 pub(crate) mod rtsp_camera;
 pub(crate) mod rtsp_frame;
 pub(crate) mod rtsp_machine;
+pub(crate) mod rtsp_media_properties;
 pub(crate) mod rtsp_request;
+pub(crate) mod rtsp_sdp;
 pub(crate) mod rtsp_session;
+pub(crate) mod rtsp_tls;
+pub(crate) mod rtsp_transport;
+pub(crate) mod rtsp_tunnel;
+pub(crate) mod rtsp_websocket;
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    // Init io streams
-    #[allow(unused_assignments)]
-    let (cin, cout, mut buf) = new_bufio!();
-    let (mut cin_lock, _) = init_lockedio!(cin, cout);
-
     // Get input
     let args = env::args().skip(1).collect::<Vec<_>>();
-    assert_eq!(
-        args.len(),
-        6,
-        "Invalid input args! Usage: rtsp_invitor -url/-u <rtsp_url> -port/-p <rtsp_port:rtp_port> -camera/-c <camera>."
+    assert!(
+        args.len() >= 6 && args.len() % 2 == 0,
+        "Invalid input args! Usage: rtsp_invitor -url/-u <rtsp_url> -port/-p <rtsp_port[:rtp_port]> -camera/-c <camera> [-start/-s <time> [-end/-e <time>]] [-backchannel/-b <audio_file>] [-publish/-P <audio_file>] [-redirects/-r <count>] [-version/-v <1.0|2.0>] [-ca/-a <ca_file>] [-cert/-t <cert_file> -key/-k <key_file>] [-insecure/-i <true|false>] [-tunnel/-T <http_port> [-proxy/-x <host:port>]] [-websocket/-w <ws_url> [-subprotocol/-W <protocol>]] [-multicast/-m <true|false> [-interface/-I <interface>]] [-nat/-n <true|false>] [-portrange/-o <first-last>] [-metadata/-M <true|false>] [-latency/-l <ms>] [-record/-R <file.mp4|file.ts|udp://host:port> [-segment/-S <seconds[:bytes]> [-retention/-K <seconds[:bytes]>]] | -dump/-D <directory>]."
     );
 
     // Parse input
@@ -40,15 +59,206 @@ async fn main() -> tokio::io::Result<()> {
                 "-camera" | "-c" => {
                     inputs.insert(String::from("Camera"), input[1].clone());
                 }
-                _ => panic!("Invalid input args {}: {}! Usage: rtsp_invitor -url/-u <rtsp_url> -port/-p <rtsp_port:rtp_port> -camera/-c <camera>.", &input[0], &input[1]),
+                "-start" | "-s" => {
+                    inputs.insert(String::from("Start"), input[1].clone());
+                }
+                "-end" | "-e" => {
+                    inputs.insert(String::from("End"), input[1].clone());
+                }
+                "-backchannel" | "-b" => {
+                    inputs.insert(String::from("Backchannel"), input[1].clone());
+                }
+                "-publish" | "-P" => {
+                    inputs.insert(String::from("Publish"), input[1].clone());
+                }
+                "-redirects" | "-r" => {
+                    inputs.insert(String::from("Redirects"), input[1].clone());
+                }
+                "-version" | "-v" => {
+                    inputs.insert(String::from("Version"), input[1].clone());
+                }
+                "-ca" | "-a" => {
+                    inputs.insert(String::from("Ca"), input[1].clone());
+                }
+                "-cert" | "-t" => {
+                    inputs.insert(String::from("Cert"), input[1].clone());
+                }
+                "-key" | "-k" => {
+                    inputs.insert(String::from("Key"), input[1].clone());
+                }
+                "-insecure" | "-i" => {
+                    inputs.insert(String::from("Insecure"), input[1].clone());
+                }
+                "-tunnel" | "-T" => {
+                    inputs.insert(String::from("Tunnel"), input[1].clone());
+                }
+                "-proxy" | "-x" => {
+                    inputs.insert(String::from("Proxy"), input[1].clone());
+                }
+                "-websocket" | "-w" => {
+                    inputs.insert(String::from("WebSocket"), input[1].clone());
+                }
+                "-subprotocol" | "-W" => {
+                    inputs.insert(String::from("WebSocketProtocol"), input[1].clone());
+                }
+                "-multicast" | "-m" => {
+                    inputs.insert(String::from("Multicast"), input[1].clone());
+                }
+                "-interface" | "-I" => {
+                    inputs.insert(String::from("Interface"), input[1].clone());
+                }
+                "-nat" | "-n" => {
+                    inputs.insert(String::from("Nat"), input[1].clone());
+                }
+                "-portrange" | "-o" => {
+                    inputs.insert(String::from("PortRange"), input[1].clone());
+                }
+                "-metadata" | "-M" => {
+                    inputs.insert(String::from("Metadata"), input[1].clone());
+                }
+                "-latency" | "-l" => {
+                    inputs.insert(String::from("Latency"), input[1].clone());
+                }
+                "-record" | "-R" => {
+                    inputs.insert(String::from("Record"), input[1].clone());
+                }
+                "-segment" | "-S" => {
+                    inputs.insert(String::from("Segment"), input[1].clone());
+                }
+                "-retention" | "-K" => {
+                    inputs.insert(String::from("Retention"), input[1].clone());
+                }
+                "-dump" | "-D" => {
+                    inputs.insert(String::from("Dump"), input[1].clone());
+                }
+                _ => panic!("Invalid input args {}: {}! Usage: rtsp_invitor -url/-u <rtsp_url> -port/-p <rtsp_port[:rtp_port]> -camera/-c <camera> [-start/-s <time> [-end/-e <time>]] [-backchannel/-b <audio_file>] [-publish/-P <audio_file>] [-redirects/-r <count>] [-version/-v <1.0|2.0>] [-ca/-a <ca_file>] [-cert/-t <cert_file> -key/-k <key_file>] [-insecure/-i <true|false>] [-tunnel/-T <http_port> [-proxy/-x <host:port>]] [-websocket/-w <ws_url> [-subprotocol/-W <protocol>]] [-multicast/-m <true|false> [-interface/-I <interface>]] [-nat/-n <true|false>] [-portrange/-o <first-last>] [-metadata/-M <true|false>] [-latency/-l <ms>] [-record/-R <file.mp4|file.ts|udp://host:port> [-segment/-S <seconds[:bytes]> [-retention/-K <seconds[:bytes]>]] | -dump/-D <directory>].", &input[0], &input[1]),
             }
         });
 
     // Create rtsp machine
     let mut rtsp_machine = RtspMachine::new(&inputs).await?;
 
+    // Read stdin on its own thread, the requests of the server are served meanwhile
+    let (line_sender, mut line_receiver) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        // Init io streams
+        let (cin, cout, _) = new_bufio!();
+        let (mut cin_lock, _) = init_lockedio!(cin, cout);
+        while let Some(Ok(buf)) = cin_lock.next() {
+            if line_sender.send(buf).is_err() {
+                break;
+            }
+        }
+    });
+
     // Start rtsp machine
     let mut c_seq = 1;
+    loop {
+        c_seq = negotiate(&mut rtsp_machine, c_seq).await?;
+
+        println!("rtsp-invitor is done!");
+
+        // Serve the requests of the server until `exit` entered or the session is redirected
+        println!("Enter `stats` to print the RTP statistics, `exit` to exit rtsp-invitor...");
+        let mut serving = true;
+        let location = loop {
+            tokio::select! {
+                buf = line_receiver.recv() => match buf {
+                    Some(buf) if buf.trim() == "stats" => print_rtp_stats(&rtsp_machine),
+                    Some(buf) if buf.trim() != "exit" => {
+                        println!("Enter `stats` to print the RTP statistics, `exit` to exit rtsp-invitor...");
+                    }
+                    _ => break None,
+                },
+                event = rtsp_machine.process_server_request(), if serving => match event {
+                    Ok(Some(RtspEvent::Redirect(location))) => break Some(location),
+                    Ok(Some(RtspEvent::PlayNotify(reason))) => {
+                        println!("Play notified by server: {}", reason);
+                    }
+                    Ok(Some(RtspEvent::Announce(sdp))) => {
+                        let medias = sdp.medias.iter().map(|media| media.media.as_str());
+                        println!(
+                            "Session description updated by server: {}",
+                            medias.collect::<Vec<_>>().join(", ")
+                        );
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        println!("Serve server request failed with error: {:?}", err);
+                        // Stop serving once the connection is lost
+                        serving = err.kind() == tokio::io::ErrorKind::Other;
+                    }
+                },
+            }
+        };
+
+        match location {
+            Some(location) => {
+                // Tear down the session and set it up again at the new location
+                if let Err(err) = rtsp_machine.shut_down(c_seq).await {
+                    println!("Send TEARDOWN failed with error: {:?}", err);
+                }
+                c_seq += 1;
+                rtsp_machine.relocate(&location).await?;
+            }
+            None => {
+                // Close rtsp machine when `exit` entered
+                rtsp_machine.shut_down(c_seq).await?;
+                break;
+            }
+        }
+    }
+
+    // Exit
+    Ok(())
+}
+
+/// It prints the reception statistics of the sources of each track
+fn print_rtp_stats(rtsp_machine: &RtspMachine) {
+    for (track, sources) in rtsp_machine.rtp_stats().iter().enumerate() {
+        for (ssrc, source) in sources {
+            // The wall clock is known once the server sent a sender report
+            let wall_clock = source
+                .wall_clock(source.timestamp())
+                .map_or(String::from("unknown"), |time| time.to_rfc3339());
+            println!(
+                "Track {} SSRC {:08x} ({}): received {} packets ({} octets), lost {}, jitter {:.3} ms, wall clock {}",
+                track,
+                ssrc,
+                source.cname().unwrap_or("unknown"),
+                source.received(),
+                source.octets(),
+                source.lost(),
+                source.jitter_ms(),
+                wall_clock
+            );
+        }
+    }
+    for (track, frames) in rtsp_machine.frame_stats().iter().enumerate() {
+        if let Some(frames) = frames {
+            println!(
+                "Track {}: {} frames, {} key frames, {} packets lost, {:.3} s of media",
+                track,
+                frames.frames,
+                frames.key_frames,
+                frames.lost,
+                frames.duration.as_secs_f64()
+            );
+        }
+    }
+}
+
+/// It sends the requests of the machine and processes the responses until the session is set up
+///
+/// Arguments:
+///
+/// * `rtsp_machine`: &mut RtspMachine
+/// * `c_seq`: u16, the CSeq of the first request
+///
+/// Returns:
+///
+/// The CSeq of the next request.
+async fn negotiate(rtsp_machine: &mut RtspMachine, mut c_seq: u16) -> tokio::io::Result<u16> {
     let mut repeat_request = 0;
     let mut repeat_response = 0;
     loop {
@@ -68,8 +278,7 @@ async fn main() -> tokio::io::Result<()> {
                 println!("Send request failed with error: {:?}, try again!", err);
                 repeat_request += 1;
                 if repeat_request >= 3 {
-                    return Err(tokio::io::Error::new(
-                        tokio::io::ErrorKind::Other,
+                    return Err(tokio::io::Error::other(
                         "Send request failed after 3-times retry!",
                     ));
                 }
@@ -78,9 +287,6 @@ async fn main() -> tokio::io::Result<()> {
             }
         }
 
-        // Clear buffer
-        rtsp_machine.clear_buf();
-
         // Recv response
         let response = rtsp_machine.process_response(c_seq).await;
 
@@ -96,10 +302,7 @@ async fn main() -> tokio::io::Result<()> {
                             c_seq = c_seq_resp;
                         } else {
                             // CSeq mismatch, throw error
-                            return Err(tokio::io::Error::new(
-                                tokio::io::ErrorKind::Other,
-                                "CSeq mismatch!",
-                            ));
+                            return Err(tokio::io::Error::other("CSeq mismatch!"));
                         }
                     }
                 }
@@ -108,31 +311,13 @@ async fn main() -> tokio::io::Result<()> {
                 repeat_response += 1;
                 println!("Recv response failed with error: {:?}, try again!", err);
                 if repeat_response >= 3 {
-                    return Err(tokio::io::Error::new(
-                        tokio::io::ErrorKind::Other,
+                    return Err(tokio::io::Error::other(
                         "Recv response failed after 3-times retry!",
                     ));
                 }
                 continue;
             }
         }
-
-        // Clear buffer
-        rtsp_machine.clear_buf();
     }
-
-    println!("rtsp-invitor is done!");
-
-    // Close rtsp machine when `exit` entered
-    loop {
-        println!("Enter `exit` to exit rtsp-invitor...");
-        buf = getline!(cin_lock).unwrap_or_default();
-        if buf.trim() == "exit" {
-            rtsp_machine.shut_down(c_seq).await?;
-            break;
-        }
-    }
-
-    // Exit
-    Ok(())
+    Ok(c_seq)
 }
@@ -0,0 +1,214 @@
+use crate::record_dump::DumpWriter;
+use crate::record_mp4::Mp4Writer;
+use crate::record_segment::{SegmentLimits, SegmentWriter};
+use crate::record_ts::{TsSink, TsWriter};
+use crate::rtp_depacketizer::Frame;
+use crate::rtsp_sdp::MediaDescription;
+use std::fs::OpenOptions;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The recorder shared by the receivers of the tracks
+pub(crate) type SharedRecorder = Arc<Mutex<Recorder>>;
+
+/// `Recorder` writes the frames of the tracks of the session to a file or a stream, by container
+/// format
+#[derive(Debug)]
+pub(crate) enum Recorder {
+    Mp4(Mp4Writer),
+    Ts(TsWriter),
+    Dump(DumpWriter),
+    Segmented(SegmentWriter),
+}
+
+impl Recorder {
+    /// It creates the file recorded to, an existing file is not overwritten unless it is a named
+    /// pipe. MPEG-TS can also be streamed to UDP. The standard output is left to the messages of
+    /// the client, MPEG-TS is piped to another process through a named pipe
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: &str, the file, its extension gives the format: `.mp4` or `.ts`, or
+    ///   `udp://<host>:<port>` for MPEG-TS over UDP
+    ///
+    /// Returns:
+    ///
+    /// A Result<Recorder>.
+    pub(crate) fn create(path: &str) -> Result<Self> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        let open = || {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(|err| {
+                    Error::new(err.kind(), format!("Record to {} failed: {}", path, err))
+                })
+        };
+        if let Some(address) = path.strip_prefix("udp://") {
+            let socket = UdpSocket::bind("0.0.0.0:0")
+                .and_then(|socket| socket.connect(address).map(|_| socket))
+                .map_err(|err| {
+                    Error::new(err.kind(), format!("Record to {} failed: {}", path, err))
+                })?;
+            return Ok(Self::Ts(TsWriter::new(TsSink::Udp(socket))));
+        }
+        match extension.as_deref() {
+            Some("mp4") => Ok(Self::Mp4(Mp4Writer::new(open()?))),
+            Some("ts") if is_fifo(path) => {
+                let file = OpenOptions::new().write(true).open(path)?;
+                Ok(Self::Ts(TsWriter::new(TsSink::File(file))))
+            }
+            Some("ts") => Ok(Self::Ts(TsWriter::new(TsSink::File(open()?)))),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported recording format {}", path),
+            )),
+        }
+    }
+
+    /// It records to a file per segment, the first one is created once the tracks are set up
+    ///
+    /// Arguments:
+    ///
+    /// * `pattern`: &str, the strftime pattern of the files, `.mp4` or `.ts`
+    /// * `camera`: &str, the address of the camera, for `{camera}` in the pattern
+    /// * `segment`: SegmentLimits, the limits of each segment
+    /// * `retention`: Option<SegmentLimits>, the limits of the segments kept
+    ///
+    /// Returns:
+    ///
+    /// A Result<Recorder>.
+    pub(crate) fn segmented(
+        pattern: &str,
+        camera: &str,
+        segment: SegmentLimits,
+        retention: Option<SegmentLimits>,
+    ) -> Result<Self> {
+        Ok(Self::Segmented(SegmentWriter::new(
+            pattern, camera, segment, retention,
+        )?))
+    }
+
+    /// It creates the directory the tracks are dumped to as elementary streams
+    ///
+    /// Arguments:
+    ///
+    /// * `directory`: &str, the directory, created if it does not exist
+    ///
+    /// Returns:
+    ///
+    /// A Result<Recorder>.
+    pub(crate) fn dump(directory: &str) -> Result<Self> {
+        Ok(Self::Dump(DumpWriter::new(directory)?))
+    }
+
+    /// It takes the tracks of the session once they are set up
+    ///
+    /// Arguments:
+    ///
+    /// * `medias`: &[MediaDescription], the media of the tracks received, in the order of the
+    ///   track indexes given to `write`
+    pub(crate) fn start(&mut self, medias: &[MediaDescription]) -> Result<()> {
+        match self {
+            Self::Mp4(writer) => writer.start(medias),
+            Self::Ts(writer) => writer.start(medias),
+            Self::Dump(writer) => writer.start(medias),
+            Self::Segmented(writer) => writer.start(medias),
+        }
+    }
+
+    /// It writes a frame of a track
+    ///
+    /// Arguments:
+    ///
+    /// * `track`: usize, the index of the track
+    /// * `timestamp`: u64, the unwrapped RTP timestamp of the frame
+    /// * `frame`: &Frame, the frame
+    pub(crate) fn write(&mut self, track: usize, timestamp: u64, frame: &Frame) -> Result<()> {
+        match self {
+            Self::Mp4(writer) => writer.write(track, timestamp, frame),
+            Self::Ts(writer) => writer.write(track, timestamp, frame),
+            Self::Dump(writer) => writer.write(track, frame),
+            Self::Segmented(writer) => writer.write(track, timestamp, frame),
+        }
+    }
+
+    /// It writes the frames left, e.g. when the session is torn down
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        match self {
+            Self::Mp4(writer) => writer.finish(),
+            Self::Ts(writer) => writer.finish(),
+            Self::Dump(_) => Ok(()),
+            Self::Segmented(writer) => writer.finish(),
+        }
+    }
+}
+
+/// A named pipe is written to as it is, e.g. read by another process
+#[cfg(unix)]
+fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &str) -> bool {
+    false
+}
+
+/// The SPS of 1920x1080 H.264 of the baseline profile
+#[cfg(test)]
+pub(crate) const TEST_SPS: &[u8] = &[0x67, 0x42, 0x00, 0x28, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x95];
+
+/// An H.264 access unit with its delimiter and a slice of `len` bytes, a key frame with its
+/// parameter sets
+#[cfg(test)]
+pub(crate) fn test_video(timestamp: u32, key_frame: bool, len: usize) -> Frame {
+    use bytes::Bytes;
+
+    let mut nal_units = vec![Bytes::from_static(&[0x09, 0xf0])];
+    if key_frame {
+        nal_units.push(Bytes::from_static(TEST_SPS));
+        nal_units.push(Bytes::from_static(&[0x68, 0xce, 0x38, 0x80]));
+    }
+    nal_units.push(Bytes::from(vec![0x65; len]));
+    Frame::Video(crate::rtp_depacketizer::AccessUnit {
+        timestamp,
+        key_frame,
+        nal_units,
+    })
+}
+
+/// An AAC frame of 1024 samples
+#[cfg(test)]
+pub(crate) fn test_audio(timestamp: u32) -> Frame {
+    Frame::Audio(crate::rtp_depacketizer::AudioFrame {
+        timestamp,
+        duration: 1024,
+        data: bytes::Bytes::from_static(&[0x21, 0x10]),
+    })
+}
+
+/// The file recorded from the tracks of the session description, its name gives the format
+#[cfg(test)]
+pub(crate) fn test_record(name: &str, sdp: &str, record: impl FnOnce(&mut Recorder)) -> Vec<u8> {
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let path = std::env::temp_dir().join(format!("rtsp_invitor_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    let mut recorder = Recorder::create(path.to_str().unwrap()).unwrap();
+    recorder.start(&medias).unwrap();
+    record(&mut recorder);
+    recorder.finish().unwrap();
+    drop(recorder);
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    data
+}
@@ -0,0 +1,305 @@
+use crate::rtp_aac::AudioSpecificConfig;
+use crate::rtp_depacketizer::Frame;
+use crate::rtsp_sdp::MediaDescription;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The start code of the NAL units of an Annex B byte stream
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// `DumpTrack` is the elementary stream of a track being dumped.
+#[derive(Debug)]
+enum DumpTrack {
+    /// An H.264 or H.265 Annex B byte stream, written from the first key frame on
+    Video { file: File, started: bool },
+    /// AAC frames with their ADTS header
+    Aac {
+        file: File,
+        config: AudioSpecificConfig,
+    },
+    /// A JPEG file per picture, numbered in order
+    Jpeg { prefix: PathBuf, count: u32 },
+}
+
+/// `DumpWriter` writes each track to its own elementary stream in a directory, e.g.
+/// `track0.h264`, `track1.aac` or `track2_000000.jpg`, so it can be inspected with the usual
+/// tools without a container.
+///
+/// Properties:
+///
+/// * `directory`: The directory of the streams.
+/// * `tracks`: The streams by index of the track, `None` for a codec not dumped.
+/// * `started`: The streams are created, they go on when the session is set up again.
+#[derive(Debug)]
+pub(crate) struct DumpWriter {
+    directory: PathBuf,
+    tracks: Vec<Option<DumpTrack>>,
+    started: bool,
+}
+
+impl DumpWriter {
+    /// It creates the directory of the streams if it does not exist
+    pub(crate) fn new(directory: &str) -> Result<Self> {
+        create_dir_all(directory).map_err(|err| {
+            Error::new(err.kind(), format!("Dump to {} failed: {}", directory, err))
+        })?;
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            tracks: vec![],
+            started: false,
+        })
+    }
+
+    /// It creates the streams of the tracks, once the dump started the same streams are written
+    /// to, e.g. after a redirect
+    ///
+    /// Arguments:
+    ///
+    /// * `medias`: &[MediaDescription], the media of the tracks received
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an error if a stream cannot be created or no track can be dumped.
+    pub(crate) fn start(&mut self, medias: &[MediaDescription]) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.tracks = medias
+            .iter()
+            .enumerate()
+            .map(|(index, media)| DumpTrack::from_media(&self.directory, index, media))
+            .collect::<Result<Vec<_>>>()?;
+        if self.tracks.iter().all(Option::is_none) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "No H.264, H.265, AAC or JPEG track to dump",
+            ));
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    /// It writes a frame of a track to its stream
+    ///
+    /// Arguments:
+    ///
+    /// * `track`: usize, the index of the track
+    /// * `frame`: &Frame, the frame
+    pub(crate) fn write(&mut self, track: usize, frame: &Frame) -> Result<()> {
+        match (self.tracks.get_mut(track), frame) {
+            (Some(Some(DumpTrack::Video { file, started })), Frame::Video(access_unit)) => {
+                // The parameter sets come with the key frames from the depacketizer
+                *started |= access_unit.key_frame;
+                if !*started {
+                    return Ok(());
+                }
+                let mut data = Vec::new();
+                for nal_unit in access_unit.nal_units.iter() {
+                    data.extend_from_slice(&START_CODE);
+                    data.extend_from_slice(nal_unit);
+                }
+                file.write_all(&data)
+            }
+            (Some(Some(DumpTrack::Aac { file, config })), Frame::Audio(audio)) => {
+                let mut data = config.adts_header(audio.data.len())?.to_vec();
+                data.extend_from_slice(&audio.data);
+                file.write_all(&data)
+            }
+            (Some(Some(DumpTrack::Jpeg { prefix, count })), Frame::Jpeg(image)) => {
+                let path = format!("{}_{:06}.jpg", prefix.display(), count);
+                *count += 1;
+                create(Path::new(&path))?.write_all(&image.data)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DumpTrack {
+    /// The stream of a track, `None` if its codec is not dumped
+    fn from_media(
+        directory: &Path,
+        index: usize,
+        media: &MediaDescription,
+    ) -> Result<Option<Self>> {
+        let encoding = match media.rtpmap {
+            Some(ref rtpmap) => rtpmap.encoding.to_uppercase(),
+            None => return Ok(None),
+        };
+        let path = |extension: &str| directory.join(format!("track{}.{}", index, extension));
+        let track = match encoding.as_str() {
+            "H264" => Self::Video {
+                file: create(&path("h264"))?,
+                started: false,
+            },
+            "H265" => Self::Video {
+                file: create(&path("h265"))?,
+                started: false,
+            },
+            "MPEG4-GENERIC" => match media.fmtp.get("config") {
+                Some(config) => Self::Aac {
+                    config: AudioSpecificConfig::parse(config)?,
+                    file: create(&path("aac"))?,
+                },
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "mpeg4-generic config missing",
+                    ))
+                }
+            },
+            "JPEG" => Self::Jpeg {
+                prefix: directory.join(format!("track{}", index)),
+                count: 0,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(track))
+    }
+}
+
+/// A file of the dump, an existing file is not overwritten
+fn create(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("Dump to {} failed: {}", path.display(), err),
+            )
+        })
+}
+
+#[cfg(test)]
+const TEST_SDP: &str = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+    m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+    m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/44100\r\na=fmtp:97 config=1210\r\n\
+    m=application 0 RTP/AVP 98\r\na=rtpmap:98 vnd.onvif.metadata/90000\r\n\
+    m=video 0 RTP/AVP 26\r\na=rtpmap:26 JPEG/90000\r\n";
+
+/// A dump of the tracks of the session description to a directory of its own
+#[cfg(test)]
+fn test_dump(name: &str, sdp: &str) -> (DumpWriter, PathBuf) {
+    let directory =
+        std::env::temp_dir().join(format!("rtsp_invitor_dump_{}_{}", std::process::id(), name));
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let mut writer = DumpWriter::new(directory.to_str().unwrap()).unwrap();
+    writer.start(&medias).unwrap();
+    (writer, directory)
+}
+
+#[cfg(test)]
+fn test_video(key_frame: bool, nal_unit: &'static [u8]) -> Frame {
+    Frame::Video(crate::rtp_depacketizer::AccessUnit {
+        timestamp: 0,
+        key_frame,
+        nal_units: vec![bytes::Bytes::from_static(nal_unit)],
+    })
+}
+
+#[test]
+fn test_dump_video_from_key_frame() {
+    let (mut writer, directory) = test_dump("video", TEST_SDP);
+    assert!(writer.tracks[2].is_none());
+
+    // The video starts at the key frame, the frames of other tracks are ignored
+    writer.write(0, &test_video(false, &[0x41, 1])).unwrap();
+    writer.write(0, &test_video(true, &[0x65, 2])).unwrap();
+    writer.write(0, &test_video(false, &[0x41, 3])).unwrap();
+    writer.write(2, &test_video(true, &[0x65, 4])).unwrap();
+    writer.write(9, &test_video(true, &[0x65, 5])).unwrap();
+    let video = std::fs::read(directory.join("track0.h264")).unwrap();
+    assert_eq!(video, [0, 0, 0, 1, 0x65, 2, 0, 0, 0, 1, 0x41, 3]);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_dump_aac_with_adts() {
+    let (mut writer, directory) = test_dump("aac", TEST_SDP);
+    let audio = Frame::Audio(crate::rtp_depacketizer::AudioFrame {
+        timestamp: 0,
+        duration: 1024,
+        data: bytes::Bytes::from_static(&[0x21; 9]),
+    });
+    writer.write(1, &audio).unwrap();
+    let aac = std::fs::read(directory.join("track1.aac")).unwrap();
+    assert_eq!(&aac[..7], &[0xff, 0xf1, 0x50, 0x80, 0x02, 0x1f, 0xfc]);
+    assert_eq!(&aac[7..], &[0x21; 9]);
+    std::fs::remove_dir_all(directory).unwrap();
+
+    // ADTS cannot carry every AudioSpecificConfig
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/48000\r\na=fmtp:97 config=f8e620\r\n";
+    let (mut writer, directory) = test_dump("eld", sdp);
+    let err = writer.write(0, &audio).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_dump_jpeg_files() {
+    let (mut writer, directory) = test_dump("jpeg", TEST_SDP);
+    for data in [&[0xff, 0xd8, 1][..], &[0xff, 0xd8, 2][..]] {
+        let image = crate::rtp_jpeg::JpegImage {
+            timestamp: 0,
+            width: 8,
+            height: 8,
+            data: bytes::Bytes::copy_from_slice(data),
+        };
+        writer.write(3, &Frame::Jpeg(image)).unwrap();
+    }
+    let first = std::fs::read(directory.join("track3_000000.jpg")).unwrap();
+    assert_eq!(first, [0xff, 0xd8, 1]);
+    let second = std::fs::read(directory.join("track3_000001.jpg")).unwrap();
+    assert_eq!(second, [0xff, 0xd8, 2]);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_dump_streams_kept() {
+    let (mut writer, directory) = test_dump("kept", TEST_SDP);
+    writer.write(0, &test_video(true, &[0x65, 1])).unwrap();
+
+    // The session set up again goes on with the same streams
+    let medias = crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+        .unwrap()
+        .medias;
+    writer.start(&medias).unwrap();
+    writer.write(0, &test_video(false, &[0x41, 2])).unwrap();
+    let video = std::fs::read(directory.join("track0.h264")).unwrap();
+    assert_eq!(video, [0, 0, 0, 1, 0x65, 1, 0, 0, 0, 1, 0x41, 2]);
+
+    // Another dump does not overwrite them
+    let mut other = DumpWriter::new(directory.to_str().unwrap()).unwrap();
+    let err = other.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_dump_tracks() {
+    // Only H.264, H.265, AAC and JPEG are dumped, AAC needs its config
+    let directory =
+        std::env::temp_dir().join(format!("rtsp_invitor_dump_{}_none", std::process::id()));
+    let mut writer = DumpWriter::new(directory.to_str().unwrap()).unwrap();
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\nm=audio 0 RTP/AVP 0\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    std::fs::remove_dir_all(directory).unwrap();
+}
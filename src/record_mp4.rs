@@ -0,0 +1,1240 @@
+#[cfg(test)]
+use crate::record::{test_audio, test_record, test_video, Recorder, TEST_SPS};
+use crate::rtp_aac::AudioSpecificConfig;
+use crate::rtp_depacketizer::{AccessUnit, Frame};
+use crate::rtsp_sdp::MediaDescription;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::fs::File;
+use std::io::Write;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The unity matrix of the movie and track headers
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// The longest fragment in seconds. Fragments start at the key frames of the first video track
+/// and are cut sooner for long GOPs and audio, so little is lost if the recording is cut short
+const FRAGMENT_SECONDS: u64 = 1;
+
+/// The sample flags of a sync sample: sample_depends_on = 2, it depends on no other sample
+const SYNC_SAMPLE: u32 = 0x0200_0000;
+
+/// The sample flags of another sample: sample_depends_on = 1 and sample_is_non_sync_sample
+const NON_SYNC_SAMPLE: u32 = 0x0101_0000;
+
+/// The codecs recorded to MP4, with what their sample entries need
+#[derive(Debug)]
+enum Codec {
+    H264,
+    H265,
+    Aac(AudioSpecificConfig),
+}
+
+/// `Sample` is a frame waiting for its fragment.
+///
+/// Properties:
+///
+/// * `time`: The presentation time in the timescale of the track.
+/// * `duration`: The duration of an audio frame, 0 for video as it is given by the next frame.
+/// * `key_frame`: The sample is a sync sample.
+/// * `data`: The sample, NAL units are prefixed with their 4-byte length.
+#[derive(Debug)]
+struct Sample {
+    time: u64,
+    duration: u32,
+    key_frame: bool,
+    data: Bytes,
+}
+
+/// `Mp4Track` is a track of the recording.
+///
+/// Properties:
+///
+/// * `input`: The index of the track of the session.
+/// * `codec`: The codec of the track.
+/// * `timescale`: The timescale of the track, the RTP clock rate.
+/// * `parameter_sets`: The parameter sets of the last key frame, for the sample entry of video.
+/// * `origin`: The unwrapped RTP timestamp of the time 0 of the track, `None` until the first
+///   sample, or the first one after the track is resumed.
+/// * `end`: The decode time the next fragment of the track starts at.
+/// * `last_duration`: The duration of the last sample written, for a last sample without next.
+/// * `samples`: The samples of the fragment being put together.
+#[derive(Debug)]
+struct Mp4Track {
+    input: usize,
+    codec: Codec,
+    timescale: u32,
+    parameter_sets: Vec<Bytes>,
+    origin: Option<i64>,
+    end: u64,
+    last_duration: u32,
+    samples: Vec<Sample>,
+}
+
+/// `TrackRun` is the samples of a track in a fragment, in decode order.
+///
+/// Properties:
+///
+/// * `track_id`: The track ID.
+/// * `decode_time`: The decode time of the first sample.
+/// * `samples`: The duration, size, flags and composition offset of each sample.
+/// * `data`: The samples.
+struct TrackRun {
+    track_id: u32,
+    decode_time: u64,
+    samples: Vec<(u32, u32, u32, i32)>,
+    data: Vec<Bytes>,
+}
+
+/// `Mp4Writer` records H.264, H.265 and AAC tracks to a fragmented MP4 file (ISO/IEC 14496-12):
+/// the `moov` box once the parameter sets are known, then a `moof` and `mdat` per GOP or per
+/// second. Each fragment is written as a whole, so the file stays playable up to the last
+/// fragment if the recording is cut short. The file is synced to disk once it is finished, e.g.
+/// when a segment is closed, not per fragment while the recorder is locked by the receivers.
+///
+/// Properties:
+///
+/// * `file`: The file recorded to.
+/// * `tracks`: The tracks recorded, the other tracks of the session are left out.
+/// * `initialized`: The `moov` box was written.
+/// * `unconfigured`: A key frame came without the parameter sets the `moov` box needs, which
+///   is logged once.
+/// * `sequence_number`: The sequence number of the last fragment.
+#[derive(Debug)]
+pub(crate) struct Mp4Writer {
+    file: File,
+    tracks: Vec<Mp4Track>,
+    initialized: bool,
+    unconfigured: bool,
+    sequence_number: u32,
+}
+
+/// `NalReader` reads the fields of a parameter set, the emulation prevention bytes removed
+struct NalReader {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Mp4Writer {
+    pub(crate) fn new(file: File) -> Self {
+        Self {
+            file,
+            tracks: vec![],
+            initialized: false,
+            unconfigured: false,
+            sequence_number: 0,
+        }
+    }
+
+    /// It takes the tracks of the session. Once the recording started, the tracks go on from
+    /// where they stopped: the session is the same one set up again, e.g. after a redirect
+    ///
+    /// Arguments:
+    ///
+    /// * `medias`: &[MediaDescription], the media of the tracks received
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an error if no track can be recorded.
+    pub(crate) fn start(&mut self, medias: &[MediaDescription]) -> Result<()> {
+        if self.initialized {
+            self.write_fragment(None)?;
+            self.tracks.iter_mut().for_each(|track| track.origin = None);
+            return Ok(());
+        }
+        self.tracks = medias
+            .iter()
+            .enumerate()
+            .filter_map(|(input, media)| Mp4Track::from_media(input, media).transpose())
+            .collect::<Result<_>>()?;
+        if self.tracks.is_empty() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "No H.264, H.265 or AAC track to record to MP4",
+            ));
+        }
+        Ok(())
+    }
+
+    /// It writes a frame of a track, video is written from its first key frame on
+    ///
+    /// Arguments:
+    ///
+    /// * `input`: usize, the index of the track of the session
+    /// * `timestamp`: u64, the unwrapped RTP timestamp of the frame
+    /// * `frame`: &Frame, the frame
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, frames of the tracks not recorded are ignored.
+    pub(crate) fn write(&mut self, input: usize, timestamp: u64, frame: &Frame) -> Result<()> {
+        let index = match self.tracks.iter().position(|track| track.input == input) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let sample = match (&self.tracks[index].codec, frame) {
+            (Codec::H264 | Codec::H265, Frame::Video(access_unit)) => {
+                self.tracks[index].save_parameter_sets(access_unit);
+                self.tracks[index].sample(access_unit)
+            }
+            (Codec::Aac(_), Frame::Audio(audio)) => Sample {
+                time: 0,
+                duration: audio.duration,
+                key_frame: true,
+                data: audio.data.clone(),
+            },
+            _ => return Ok(()),
+        };
+
+        // The sample entries need the parameter sets of every video track
+        if !self.initialized {
+            let track = &self.tracks[index];
+            if sample.key_frame && !track.is_configured() && !self.unconfigured {
+                println!(
+                    "MP4 recording cannot start: no parameter sets in the key frame of track {}",
+                    track.input
+                );
+                self.unconfigured = true;
+            }
+            if !self.tracks.iter().all(Mp4Track::is_configured) {
+                return Ok(());
+            }
+            self.write_init()?;
+            self.initialized = true;
+        }
+        let track = &mut self.tracks[index];
+        if track.origin.is_none() && !sample.key_frame {
+            return Ok(());
+        }
+        let origin = *track
+            .origin
+            .get_or_insert(timestamp as i64 - track.end as i64);
+        let sample = Sample {
+            time: (timestamp as i64 - origin).max(0) as u64,
+            ..sample
+        };
+
+        // A key frame of the first video track starts a fragment, as does a sample of a track
+        // whose samples already last the longest fragment, the other samples are not sync
+        let video = self.tracks.iter().position(Mp4Track::is_video);
+        let track = &self.tracks[index];
+        let fragment = (video == Some(index) && sample.key_frame)
+            || track.samples.first().is_some_and(|first| {
+                sample.time >= first.time + FRAGMENT_SECONDS * track.timescale as u64
+            });
+        if fragment && !track.samples.is_empty() {
+            self.write_fragment(Some((index, sample.time)))?;
+        }
+        self.tracks[index].samples.push(sample);
+        Ok(())
+    }
+
+    /// It writes the samples left and syncs the file to disk, the file is complete
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.write_fragment(None)?;
+        self.file.sync_data()
+    }
+
+    /// `ftyp` and `moov`, with the sample entries and an empty sample table for each track
+    fn write_init(&mut self) -> Result<()> {
+        let entries = self
+            .tracks
+            .iter()
+            .map(Mp4Track::sample_entry)
+            .collect::<Result<Vec<_>>>()?;
+        let mut buf = BytesMut::new();
+        write_box(&mut buf, b"ftyp", |buf| {
+            buf.put_slice(b"isom");
+            buf.put_u32(0x200);
+            for brand in [b"isom", b"iso6", b"mp41"] {
+                buf.put_slice(brand);
+            }
+        });
+        write_box(&mut buf, b"moov", |buf| {
+            write_full_box(buf, b"mvhd", 0, 0, |buf| {
+                buf.put_u64(0); // creation_time, modification_time
+                buf.put_u32(1000); // timescale
+                buf.put_u32(0); // duration, given by the fragments
+                buf.put_u32(0x0001_0000); // rate
+                buf.put_u16(0x0100); // volume
+                buf.put_slice(&[0; 10]);
+                MATRIX.iter().for_each(|value| buf.put_u32(*value));
+                buf.put_slice(&[0; 24]);
+                buf.put_u32(self.tracks.len() as u32 + 1); // next_track_ID
+            });
+            for (index, (track, (entry, width, height))) in
+                self.tracks.iter().zip(entries).enumerate()
+            {
+                track.write_trak(buf, index as u32 + 1, &entry, width, height);
+            }
+            write_box(buf, b"mvex", |buf| {
+                for index in 0..self.tracks.len() {
+                    write_full_box(buf, b"trex", 0, 0, |buf| {
+                        buf.put_u32(index as u32 + 1); // track_ID
+                        buf.put_u32(1); // default_sample_description_index
+                        buf.put_u32(0); // default_sample_duration
+                        buf.put_u32(0); // default_sample_size
+                        buf.put_u32(0); // default_sample_flags
+                    });
+                }
+            });
+        });
+        self.file.write_all(&buf)
+    }
+
+    /// A `moof` and `mdat` of the samples of all tracks
+    ///
+    /// Arguments:
+    ///
+    /// * `next`: Option<(usize, u64)>, the index of the track and the time of the sample
+    ///   starting the next fragment, which ends the last sample of that track
+    fn write_fragment(&mut self, next: Option<(usize, u64)>) -> Result<()> {
+        let runs = self
+            .tracks
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, track)| !track.samples.is_empty())
+            .map(|(index, track)| {
+                let next = next
+                    .filter(|(next, _)| *next == index)
+                    .map(|(_, time)| time);
+                track.take_run(index as u32 + 1, next)
+            })
+            .collect::<Vec<_>>();
+        if runs.is_empty() {
+            return Ok(());
+        }
+        self.sequence_number += 1;
+
+        // The data offsets are relative to the `moof` box, whose size does not depend on them
+        let moof_len = Self::moof(self.sequence_number, &runs, &vec![0; runs.len()]).len();
+        let mut offsets = vec![];
+        let mut offset = moof_len as u32 + 8;
+        for run in runs.iter() {
+            offsets.push(offset);
+            offset += run.data.iter().map(|data| data.len() as u32).sum::<u32>();
+        }
+        let mut buf = Self::moof(self.sequence_number, &runs, &offsets);
+        write_box(&mut buf, b"mdat", |buf| {
+            runs.iter()
+                .flat_map(|run| run.data.iter())
+                .for_each(|data| buf.put_slice(data));
+        });
+        self.file.write_all(&buf)
+    }
+
+    fn moof(sequence_number: u32, runs: &[TrackRun], offsets: &[u32]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_box(&mut buf, b"moof", |buf| {
+            write_full_box(buf, b"mfhd", 0, 0, |buf| buf.put_u32(sequence_number));
+            for (run, offset) in runs.iter().zip(offsets) {
+                write_box(buf, b"traf", |buf| {
+                    // default-base-is-moof
+                    write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| buf.put_u32(run.track_id));
+                    write_full_box(buf, b"tfdt", 1, 0, |buf| buf.put_u64(run.decode_time));
+                    // data-offset, sample-duration, sample-size, sample-flags and signed
+                    // sample-composition-time-offset
+                    write_full_box(buf, b"trun", 1, 0x0f01, |buf| {
+                        buf.put_u32(run.samples.len() as u32);
+                        buf.put_u32(*offset);
+                        for (duration, size, flags, offset) in run.samples.iter() {
+                            buf.put_u32(*duration);
+                            buf.put_u32(*size);
+                            buf.put_u32(*flags);
+                            buf.put_i32(*offset);
+                        }
+                    });
+                });
+            }
+        });
+        buf
+    }
+}
+
+impl Mp4Track {
+    /// The track of a media, `None` if its codec is not recorded
+    fn from_media(input: usize, media: &MediaDescription) -> Result<Option<Self>> {
+        let rtpmap = match media.rtpmap {
+            Some(ref rtpmap) => rtpmap,
+            None => return Ok(None),
+        };
+        let codec = match rtpmap.encoding.to_uppercase().as_str() {
+            "H264" => Codec::H264,
+            "H265" => Codec::H265,
+            "MPEG4-GENERIC" => match media.fmtp.get("config") {
+                Some(config) => Codec::Aac(AudioSpecificConfig::parse(config)?),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "mpeg4-generic config missing",
+                    ))
+                }
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            input,
+            codec,
+            timescale: rtpmap.clock_rate,
+            parameter_sets: vec![],
+            origin: None,
+            end: 0,
+            last_duration: 0,
+            samples: vec![],
+        }))
+    }
+
+    fn is_video(&self) -> bool {
+        !matches!(self.codec, Codec::Aac(_))
+    }
+
+    /// The type of a NAL unit, by codec
+    fn nal_type(&self, nal_unit: &[u8]) -> u8 {
+        match self.codec {
+            Codec::H264 => nal_unit[0] & 0x1f,
+            _ => nal_unit[0] >> 1 & 0x3f,
+        }
+    }
+
+    /// The NAL unit types of the parameter sets, in the order of the sample entry
+    fn parameter_set_types(&self) -> &'static [u8] {
+        match self.codec {
+            Codec::H264 => &[7, 8],
+            Codec::H265 => &[32, 33, 34],
+            Codec::Aac(_) => &[],
+        }
+    }
+
+    /// The access unit delimiter, left out of the samples
+    fn is_delimiter(&self, nal_type: u8) -> bool {
+        match self.codec {
+            Codec::H264 => nal_type == 9,
+            _ => nal_type == 35,
+        }
+    }
+
+    /// The first parameter set of a type
+    fn parameter_set(&self, nal_type: u8) -> Option<&Bytes> {
+        self.parameter_sets
+            .iter()
+            .find(|nal_unit| self.nal_type(nal_unit) == nal_type)
+    }
+
+    /// The sample entry can be written: video needs every type of parameter set
+    fn is_configured(&self) -> bool {
+        self.parameter_set_types()
+            .iter()
+            .all(|nal_type| self.parameter_set(*nal_type).is_some())
+    }
+
+    fn save_parameter_sets(&mut self, access_unit: &AccessUnit) {
+        let types = self.parameter_set_types();
+        let parameter_sets = access_unit
+            .nal_units
+            .iter()
+            .filter(|nal_unit| !nal_unit.is_empty() && types.contains(&self.nal_type(nal_unit)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !parameter_sets.is_empty() {
+            self.parameter_sets = parameter_sets;
+        }
+    }
+
+    /// The NAL units of the picture prefixed with their length, the parameter sets are in the
+    /// sample entry
+    fn sample(&self, access_unit: &AccessUnit) -> Sample {
+        let types = self.parameter_set_types();
+        let mut data = BytesMut::new();
+        for nal_unit in access_unit.nal_units.iter().filter(|nal_unit| {
+            !nal_unit.is_empty() && {
+                let nal_type = self.nal_type(nal_unit);
+                !types.contains(&nal_type) && !self.is_delimiter(nal_type)
+            }
+        }) {
+            data.put_u32(nal_unit.len() as u32);
+            data.put_slice(nal_unit);
+        }
+        Sample {
+            time: 0,
+            duration: 0,
+            key_frame: access_unit.key_frame,
+            data: data.freeze(),
+        }
+    }
+
+    /// The samples of the fragment in decode order. The RTP timestamps are presentation times,
+    /// the decode times are the presentation times in increasing order and the difference is
+    /// the composition offset
+    fn take_run(&mut self, track_id: u32, next: Option<u64>) -> TrackRun {
+        let samples = std::mem::take(&mut self.samples);
+        let mut times = samples.iter().map(|sample| sample.time).collect::<Vec<_>>();
+        times.sort_unstable();
+        let mut decode_times = vec![];
+        let mut decode_time = self.end;
+        for time in times {
+            decode_time = decode_time.max(time);
+            decode_times.push(decode_time);
+        }
+        let mut run = TrackRun {
+            track_id,
+            decode_time: decode_times[0],
+            samples: vec![],
+            data: vec![],
+        };
+        for (index, sample) in samples.into_iter().enumerate() {
+            let decode_time = decode_times[index];
+            let duration = match decode_times.get(index + 1) {
+                Some(next) => next - decode_time,
+                None => next
+                    .filter(|next| *next > decode_time)
+                    .map(|next| next - decode_time)
+                    .or((sample.duration > 0).then_some(sample.duration as u64))
+                    .unwrap_or(self.last_duration as u64),
+            }
+            .min(u32::MAX as u64) as u32;
+            if duration > 0 {
+                self.last_duration = duration;
+            }
+            self.end = decode_time + duration as u64;
+            let flags = if sample.key_frame {
+                SYNC_SAMPLE
+            } else {
+                NON_SYNC_SAMPLE
+            };
+            let offset = (sample.time as i64 - decode_time as i64) as i32;
+            run.samples
+                .push((duration, sample.data.len() as u32, flags, offset));
+            run.data.push(sample.data);
+        }
+        run
+    }
+
+    /// The sample entry of the track, with the width and the height of video
+    fn sample_entry(&self) -> Result<(BytesMut, u16, u16)> {
+        let mut buf = BytesMut::new();
+        let parameter_set = |nal_type: u8| self.parameter_set(nal_type).unwrap().clone();
+        match self.codec {
+            Codec::H264 => {
+                let sps = parameter_set(7);
+                let info = SpsInfo::parse_h264(&sps)?;
+                write_visual_sample_entry(&mut buf, b"avc1", &info, |buf| {
+                    write_box(buf, b"avcC", |buf| {
+                        buf.put_u8(1); // configurationVersion
+                        buf.put_slice(&sps[1..4]); // profile, compatibility and level
+                        buf.put_u8(0xfc | 3); // lengthSizeMinusOne
+                        buf.put_u8(0xe0 | 1);
+                        buf.put_u16(sps.len() as u16);
+                        buf.put_slice(&sps);
+                        let pps = parameter_set(8);
+                        buf.put_u8(1);
+                        buf.put_u16(pps.len() as u16);
+                        buf.put_slice(&pps);
+                        if !matches!(sps[1], 66 | 77 | 88) {
+                            buf.put_u8(0xfc | info.chroma_format);
+                            buf.put_u8(0xf8 | info.bit_depth_luma);
+                            buf.put_u8(0xf8 | info.bit_depth_chroma);
+                            buf.put_u8(0); // numOfSequenceParameterSetExt
+                        }
+                    });
+                });
+                Ok((buf, info.width, info.height))
+            }
+            Codec::H265 => {
+                let sps = parameter_set(33);
+                let info = SpsInfo::parse_h265(&sps)?;
+                write_visual_sample_entry(&mut buf, b"hvc1", &info, |buf| {
+                    write_box(buf, b"hvcC", |buf| {
+                        buf.put_u8(1); // configurationVersion
+                        buf.put_slice(&info.profile_tier_level);
+                        buf.put_u16(0xf000); // min_spatial_segmentation_idc
+                        buf.put_u8(0xfc); // parallelismType
+                        buf.put_u8(0xfc | info.chroma_format);
+                        buf.put_u8(0xf8 | info.bit_depth_luma);
+                        buf.put_u8(0xf8 | info.bit_depth_chroma);
+                        buf.put_u16(0); // avgFrameRate
+                        buf.put_u8(info.temporal_layers << 3 | info.temporal_id_nested << 2 | 3);
+                        buf.put_u8(3); // numOfArrays
+                        for nal_type in [32, 33, 34] {
+                            let nal_unit = parameter_set(nal_type);
+                            buf.put_u8(0x80 | nal_type); // array_completeness
+                            buf.put_u16(1);
+                            buf.put_u16(nal_unit.len() as u16);
+                            buf.put_slice(&nal_unit);
+                        }
+                    });
+                });
+                Ok((buf, info.width, info.height))
+            }
+            Codec::Aac(ref config) => {
+                write_box(&mut buf, b"mp4a", |buf| {
+                    buf.put_slice(&[0; 6]);
+                    buf.put_u16(1); // data_reference_index
+                    buf.put_u64(0);
+                    buf.put_u16(config.channels);
+                    buf.put_u16(16); // samplesize
+                    buf.put_u32(0);
+                    buf.put_u32(self.timescale.min(0xffff) << 16); // samplerate
+                    write_full_box(buf, b"esds", 0, 0, |buf| {
+                        // ES_Descriptor with the DecoderConfigDescriptor of audio (ISO/IEC
+                        // 14496-1 section 7.2.6.5) and the SLConfigDescriptor of MP4
+                        let mut decoder_config = BytesMut::new();
+                        decoder_config.put_u8(0x40); // objectTypeIndication, MPEG-4 audio
+                        decoder_config.put_u8(0x15); // streamType audio, upStream 0, reserved 1
+                        decoder_config.put_slice(&[0; 11]); // bufferSizeDB, bitrates
+                        write_descriptor(&mut decoder_config, 0x05, &config.data);
+                        let mut es = BytesMut::new();
+                        es.put_u16(0); // ES_ID
+                        es.put_u8(0);
+                        write_descriptor(&mut es, 0x04, &decoder_config);
+                        write_descriptor(&mut es, 0x06, &[0x02]);
+                        write_descriptor(buf, 0x03, &es);
+                    });
+                });
+                Ok((buf, 0, 0))
+            }
+        }
+    }
+
+    fn write_trak(&self, buf: &mut BytesMut, track_id: u32, entry: &[u8], width: u16, height: u16) {
+        let video = self.is_video();
+        write_box(buf, b"trak", |buf| {
+            // track_enabled, track_in_movie
+            write_full_box(buf, b"tkhd", 0, 3, |buf| {
+                buf.put_u64(0); // creation_time, modification_time
+                buf.put_u32(track_id);
+                buf.put_u32(0);
+                buf.put_u32(0); // duration, given by the fragments
+                buf.put_u64(0);
+                buf.put_u32(0); // layer, alternate_group
+                buf.put_u16(if video { 0 } else { 0x0100 }); // volume
+                buf.put_u16(0);
+                MATRIX.iter().for_each(|value| buf.put_u32(*value));
+                buf.put_u32((width as u32) << 16);
+                buf.put_u32((height as u32) << 16);
+            });
+            write_box(buf, b"mdia", |buf| {
+                write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                    buf.put_u64(0); // creation_time, modification_time
+                    buf.put_u32(self.timescale);
+                    buf.put_u32(0); // duration
+                    buf.put_u16(0x55c4); // language `und`
+                    buf.put_u16(0);
+                });
+                write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                    buf.put_u32(0);
+                    buf.put_slice(if video { b"vide" } else { b"soun" });
+                    buf.put_slice(&[0; 12]);
+                    buf.put_slice(if video {
+                        b"VideoHandler\0"
+                    } else {
+                        b"SoundHandler\0"
+                    });
+                });
+                write_box(buf, b"minf", |buf| {
+                    if video {
+                        write_full_box(buf, b"vmhd", 0, 1, |buf| buf.put_u64(0));
+                    } else {
+                        write_full_box(buf, b"smhd", 0, 0, |buf| buf.put_u32(0));
+                    }
+                    write_box(buf, b"dinf", |buf| {
+                        write_full_box(buf, b"dref", 0, 0, |buf| {
+                            buf.put_u32(1);
+                            // The media is in the same file
+                            write_full_box(buf, b"url ", 0, 1, |_| {});
+                        });
+                    });
+                    write_box(buf, b"stbl", |buf| {
+                        write_full_box(buf, b"stsd", 0, 0, |buf| {
+                            buf.put_u32(1);
+                            buf.put_slice(entry);
+                        });
+                        // The samples are in the fragments
+                        for kind in [b"stts", b"stsc", b"stco"] {
+                            write_full_box(buf, kind, 0, 0, |buf| buf.put_u32(0));
+                        }
+                        write_full_box(buf, b"stsz", 0, 0, |buf| buf.put_u64(0));
+                    });
+                });
+            });
+        });
+    }
+}
+
+/// `SpsInfo` is what the sample entry of video needs from the sequence parameter set.
+///
+/// Properties:
+///
+/// * `width`: The width of the pictures, cropped.
+/// * `height`: The height of the pictures, cropped.
+/// * `chroma_format`: The chroma_format_idc.
+/// * `bit_depth_luma`: The bit_depth_luma_minus8.
+/// * `bit_depth_chroma`: The bit_depth_chroma_minus8.
+/// * `profile_tier_level`: The general profile, tier and level of H.265, as in `hvcC`.
+/// * `temporal_layers`: The sub-layers of H.265.
+/// * `temporal_id_nested`: The sps_temporal_id_nesting_flag of H.265.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SpsInfo {
+    width: u16,
+    height: u16,
+    chroma_format: u8,
+    bit_depth_luma: u8,
+    bit_depth_chroma: u8,
+    profile_tier_level: Vec<u8>,
+    temporal_layers: u8,
+    temporal_id_nested: u8,
+}
+
+impl SpsInfo {
+    /// The H.264 sequence parameter set (ITU-T H.264 section 7.3.2.1.1)
+    fn parse_h264(sps: &[u8]) -> Result<Self> {
+        let mut reader = NalReader::new(sps);
+        reader.skip(8)?; // NAL unit header
+        let profile = reader.read(8)?;
+        reader.skip(16)?; // constraint flags, level
+        reader.read_ue()?; // seq_parameter_set_id
+        let mut info = SpsInfo {
+            chroma_format: 1,
+            ..Self::default()
+        };
+        let mut separate_colour_plane = false;
+        if matches!(
+            profile,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            info.chroma_format = reader.read_ue()? as u8;
+            if info.chroma_format == 3 {
+                separate_colour_plane = reader.read(1)? == 1;
+            }
+            info.bit_depth_luma = reader.read_ue()? as u8;
+            info.bit_depth_chroma = reader.read_ue()? as u8;
+            reader.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+            if reader.read(1)? == 1 {
+                let lists = if info.chroma_format == 3 { 12 } else { 8 };
+                for list in 0..lists {
+                    if reader.read(1)? == 1 {
+                        reader.skip_scaling_list(if list < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+        reader.read_ue()?; // log2_max_frame_num_minus4
+        match reader.read_ue()? {
+            0 => {
+                reader.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                reader.skip(1)?; // delta_pic_order_always_zero_flag
+                reader.read_se()?;
+                reader.read_se()?;
+                for _ in 0..reader.read_ue()? {
+                    reader.read_se()?;
+                }
+            }
+            _ => {}
+        }
+        reader.read_ue()?; // max_num_ref_frames
+        reader.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+        let width_in_mbs = reader.read_ue()? as u64 + 1;
+        let height_in_map_units = reader.read_ue()? as u64 + 1;
+        let frame_mbs_only = reader.read(1)?;
+        if frame_mbs_only == 0 {
+            reader.skip(1)?; // mb_adaptive_frame_field_flag
+        }
+        reader.skip(1)?; // direct_8x8_inference_flag
+        let mut crop = [0; 4];
+        if reader.read(1)? == 1 {
+            for offset in crop.iter_mut() {
+                *offset = reader.read_ue()?;
+            }
+        }
+        let (crop_x, crop_y) = match (info.chroma_format, separate_colour_plane) {
+            (0, _) | (3, true) => (1, 2 - frame_mbs_only),
+            (1, _) => (2, 2 * (2 - frame_mbs_only)),
+            (2, _) => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        info.width = cropped(width_in_mbs * 16, crop_x, crop[0], crop[1])?;
+        info.height = cropped(
+            (2 - frame_mbs_only as u64) * height_in_map_units * 16,
+            crop_y,
+            crop[2],
+            crop[3],
+        )?;
+        Ok(info)
+    }
+
+    /// The H.265 sequence parameter set (ITU-T H.265 section 7.3.2.2.1)
+    fn parse_h265(sps: &[u8]) -> Result<Self> {
+        let mut reader = NalReader::new(sps);
+        reader.skip(16)?; // NAL unit header
+        reader.skip(4)?; // sps_video_parameter_set_id
+        let max_sub_layers = reader.read(3)? + 1;
+        let temporal_id_nested = reader.read(1)? as u8;
+        // general_profile_space to general_level_idc
+        let profile_tier_level = (0..12)
+            .map(|_| reader.read(8).map(|byte| byte as u8))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sub_layers = vec![];
+        for _ in 1..max_sub_layers {
+            sub_layers.push((reader.read(1)?, reader.read(1)?));
+        }
+        if max_sub_layers > 1 {
+            reader.skip(2 * (9 - max_sub_layers as usize))?;
+        }
+        for (profile_present, level_present) in sub_layers {
+            reader.skip(88 * profile_present as usize + 8 * level_present as usize)?;
+        }
+        reader.read_ue()?; // sps_seq_parameter_set_id
+        let chroma_format = reader.read_ue()?;
+        let separate_colour_plane = chroma_format == 3 && reader.read(1)? == 1;
+        let width = reader.read_ue()?;
+        let height = reader.read_ue()?;
+        let mut crop = [0; 4];
+        if reader.read(1)? == 1 {
+            for offset in crop.iter_mut() {
+                *offset = reader.read_ue()?;
+            }
+        }
+        let (crop_x, crop_y) = match (chroma_format, separate_colour_plane) {
+            (1, _) => (2, 2),
+            (2, false) => (2, 1),
+            _ => (1, 1),
+        };
+        Ok(SpsInfo {
+            width: cropped(width as u64, crop_x, crop[0], crop[1])?,
+            height: cropped(height as u64, crop_y, crop[2], crop[3])?,
+            chroma_format: chroma_format as u8,
+            bit_depth_luma: reader.read_ue()? as u8,
+            bit_depth_chroma: reader.read_ue()? as u8,
+            profile_tier_level,
+            temporal_layers: max_sub_layers as u8,
+            temporal_id_nested,
+        })
+    }
+}
+
+/// It crops a dimension of the pictures by the offsets of the SPS, in units of `unit` samples
+///
+/// Returns:
+///
+/// A Result<u16>, an error if the SPS crops more than the pictures have or they are too large.
+fn cropped(size: u64, unit: u32, start: u32, end: u32) -> Result<u16> {
+    size.checked_sub(unit as u64 * (start as u64 + end as u64))
+        .and_then(|size| u16::try_from(size).ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid SPS cropping"))
+}
+
+impl NalReader {
+    /// A reader of the RBSP of the NAL unit, `0x000003` is read as `0x0000`
+    fn new(nal_unit: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal_unit.len());
+        let mut zeros = 0;
+        for byte in nal_unit {
+            if zeros >= 2 && *byte == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if *byte == 0 { zeros + 1 } else { 0 };
+            data.push(*byte);
+        }
+        Self { data, position: 0 }
+    }
+
+    /// It reads a field of up to 32 bits
+    fn read(&mut self, bits: u32) -> Result<u32> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Parameter set truncated",
+            ));
+        }
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let bit = self.data[self.position / 8] >> (7 - self.position % 8) & 1;
+            value = value << 1 | bit as u64;
+            self.position += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn skip(&mut self, bits: usize) -> Result<()> {
+        for _ in 0..bits / 32 {
+            self.read(32)?;
+        }
+        self.read((bits % 32) as u32).map(|_| ())
+    }
+
+    /// ue(v), the unsigned Exp-Golomb code
+    fn read_ue(&mut self) -> Result<u32> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid Exp-Golomb code",
+                ));
+            }
+        }
+        Ok(((1u64 << zeros) - 1 + self.read(zeros)? as u64) as u32)
+    }
+
+    /// se(v), the signed Exp-Golomb code
+    fn read_se(&mut self) -> Result<i32> {
+        let code = self.read_ue()? as i64;
+        Ok(if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -code / 2
+        } as i32)
+    }
+
+    /// scaling_list() of H.264, only its length is known from the deltas
+    fn skip_scaling_list(&mut self, size: usize) -> Result<()> {
+        let (mut last, mut next) = (8, 8);
+        for _ in 0..size {
+            if next != 0 {
+                next = (last + self.read_se()? + 256) % 256;
+            }
+            if next != 0 {
+                last = next;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// It writes a box: size(32) | type(32) | body
+fn write_box(buf: &mut BytesMut, kind: &[u8; 4], body: impl FnOnce(&mut BytesMut)) {
+    let start = buf.len();
+    buf.put_u32(0);
+    buf.put_slice(kind);
+    body(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// It writes a full box: size(32) | type(32) | version(8) | flags(24) | body
+fn write_full_box(
+    buf: &mut BytesMut,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut BytesMut),
+) {
+    write_box(buf, kind, |buf| {
+        buf.put_u32((version as u32) << 24 | flags);
+        body(buf);
+    });
+}
+
+/// The VisualSampleEntry of the pictures described by the sequence parameter set
+fn write_visual_sample_entry(
+    buf: &mut BytesMut,
+    kind: &[u8; 4],
+    info: &SpsInfo,
+    configuration: impl FnOnce(&mut BytesMut),
+) {
+    write_box(buf, kind, |buf| {
+        buf.put_slice(&[0; 6]);
+        buf.put_u16(1); // data_reference_index
+        buf.put_slice(&[0; 16]);
+        buf.put_u16(info.width);
+        buf.put_u16(info.height);
+        buf.put_u32(0x0048_0000); // horizresolution, 72 dpi
+        buf.put_u32(0x0048_0000); // vertresolution
+        buf.put_u32(0);
+        buf.put_u16(1); // frame_count
+        buf.put_slice(&[0; 32]); // compressorname
+        buf.put_u16(0x0018); // depth
+        buf.put_i16(-1);
+        configuration(buf);
+    });
+}
+
+/// It writes a descriptor of ISO/IEC 14496-1: tag(8) | size, 7 bits a byte | body
+fn write_descriptor(buf: &mut BytesMut, tag: u8, body: &[u8]) {
+    buf.put_u8(tag);
+    let size = body.len();
+    for shift in [21, 14, 7] {
+        if size >> shift > 0 {
+            buf.put_u8(0x80 | (size >> shift & 0x7f) as u8);
+        }
+    }
+    buf.put_u8((size & 0x7f) as u8);
+    buf.put_slice(body);
+}
+
+/// The top level boxes and their offset and size, each within the data
+#[cfg(test)]
+fn test_boxes(data: &[u8]) -> Vec<(String, usize, usize)> {
+    let mut boxes = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        assert!(size >= 8 && offset + size <= data.len());
+        let kind = String::from_utf8_lossy(&data[offset + 4..offset + 8]).to_string();
+        boxes.push((kind, offset, size));
+        offset += size;
+    }
+    boxes
+}
+
+/// The decode time and the samples of the first track run of the fragment at an offset
+#[cfg(test)]
+fn test_run(data: &[u8], moof: usize) -> (u64, Vec<(u32, u32, u32, i32)>) {
+    let find = |kind: &[u8]| moof + data[moof..].windows(4).position(|k| k == kind).unwrap();
+    let tfdt = find(b"tfdt");
+    let decode_time = u64::from_be_bytes(data[tfdt + 8..tfdt + 16].try_into().unwrap());
+    let trun = find(b"trun");
+    let read = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+    let samples = (0..read(trun + 8) as usize)
+        .map(|index| trun + 16 + index * 16)
+        .map(|entry| {
+            let offset = read(entry + 12) as i32;
+            (read(entry), read(entry + 4), read(entry + 8), offset)
+        })
+        .collect();
+    (decode_time, samples)
+}
+
+#[cfg(test)]
+const TEST_VIDEO_SDP: &str = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+    m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n";
+
+#[test]
+fn test_sps_dimensions() {
+    // Baseline profile 1920x1080, 68 macroblock rows cropped by 8 lines
+    let info = SpsInfo::parse_h264(TEST_SPS).unwrap();
+    assert_eq!((info.width, info.height), (1920, 1080));
+    assert!(SpsInfo::parse_h264(&TEST_SPS[..4]).is_err());
+}
+
+#[test]
+fn test_sps_invalid_cropping() {
+    // A macroblock 16 samples wide cropped by 2 * 9 samples on the left
+    let sps = [0x67, 0x42, 0x00, 0x28, 0xf4, 0xf8, 0xaf];
+    let err = SpsInfo::parse_h264(&sps).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_mp4_fragments_per_gop() {
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+        m=audio 0 RTP/AVP 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\na=fmtp:97 config=1410\r\n";
+    let data = test_record("gop.mp4", sdp, |recorder| {
+        let Recorder::Mp4(writer) = recorder else {
+            unreachable!()
+        };
+        assert_eq!(writer.tracks.len(), 2);
+
+        // Audio waits for the first key frame, then two GOPs are written
+        writer.write(2, 1 << 32, &test_audio(0)).unwrap();
+        writer
+            .write(0, 1 << 32 | 3000, &test_video(3000, false, 4))
+            .unwrap();
+        assert!(!writer.initialized);
+        writer
+            .write(0, 1 << 32 | 6000, &test_video(6000, true, 4))
+            .unwrap();
+        assert!(writer.initialized);
+        writer.write(2, 1 << 32 | 1024, &test_audio(1024)).unwrap();
+        writer
+            .write(0, 1 << 32 | 9000, &test_video(9000, false, 4))
+            .unwrap();
+        writer
+            .write(0, 1 << 32 | 12000, &test_video(12000, true, 4))
+            .unwrap();
+    });
+    let boxes = test_boxes(&data);
+    let kinds = boxes.iter().map(|(kind, _, _)| kind.as_str());
+    assert_eq!(
+        kinds.collect::<Vec<_>>(),
+        ["ftyp", "moov", "moof", "mdat", "moof", "mdat"]
+    );
+
+    // The first GOP: 2 video samples of 3000 ticks, without delimiter and parameter sets
+    let (decode_time, samples) = test_run(&data, boxes[2].1);
+    assert_eq!(decode_time, 0);
+    assert_eq!(
+        samples,
+        [(3000, 8, SYNC_SAMPLE, 0), (3000, 8, NON_SYNC_SAMPLE, 0)]
+    );
+    let (decode_time, samples) = test_run(&data, boxes[4].1);
+    assert_eq!(decode_time, 6000);
+    assert_eq!(samples, [(3000, 8, SYNC_SAMPLE, 0)]);
+}
+
+#[test]
+fn test_mp4_negative_composition_offsets() {
+    // I P B B in decode order, the B-frames are presented before the P-frame
+    let data = test_record("bframes.mp4", TEST_VIDEO_SDP, |recorder| {
+        for (timestamp, key_frame) in [(6000, true), (15000, false), (9000, false), (12000, false)]
+        {
+            recorder
+                .write(0, timestamp as u64, &test_video(timestamp, key_frame, 4))
+                .unwrap();
+        }
+        recorder
+            .write(0, 18000, &test_video(18000, true, 4))
+            .unwrap();
+    });
+    let boxes = test_boxes(&data);
+    let (_, samples) = test_run(&data, boxes[2].1);
+    let offsets = samples
+        .iter()
+        .map(|(duration, _, _, offset)| (*duration, *offset));
+    assert_eq!(
+        offsets.collect::<Vec<_>>(),
+        [(3000, 0), (3000, 6000), (3000, -3000), (3000, -3000)]
+    );
+    // The version 1 trun has signed offsets
+    let trun = boxes[2].1
+        + data[boxes[2].1..]
+            .windows(4)
+            .position(|k| k == b"trun")
+            .unwrap();
+    assert_eq!(data[trun + 4], 1);
+}
+
+#[test]
+fn test_mp4_audio_fragments() {
+    // Without video, audio is cut every second
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\na=fmtp:97 config=1410\r\n";
+    let data = test_record("audio.mp4", sdp, |recorder| {
+        for frame in 0..20 {
+            recorder
+                .write(0, frame * 1024, &test_audio(frame as u32 * 1024))
+                .unwrap();
+        }
+    });
+    let boxes = test_boxes(&data);
+    assert_eq!(boxes.len(), 6);
+    let (decode_time, samples) = test_run(&data, boxes[2].1);
+    assert_eq!((decode_time, samples.len()), (0, 16));
+    assert!(samples
+        .iter()
+        .all(|sample| *sample == (1024, 2, SYNC_SAMPLE, 0)));
+    let (decode_time, samples) = test_run(&data, boxes[4].1);
+    assert_eq!((decode_time, samples.len()), (16384, 4));
+}
+
+#[test]
+fn test_mp4_resumed_session() {
+    // The session set up again goes on where the tracks stopped, whatever its timestamps
+    let medias = crate::rtsp_sdp::SessionDescription::parse(TEST_VIDEO_SDP)
+        .unwrap()
+        .medias;
+    let data = test_record("resume.mp4", TEST_VIDEO_SDP, |recorder| {
+        recorder.write(0, 1000, &test_video(1000, true, 4)).unwrap();
+        recorder
+            .write(0, 4000, &test_video(4000, false, 4))
+            .unwrap();
+        recorder.start(&medias).unwrap();
+        recorder.write(0, 500, &test_video(500, false, 4)).unwrap();
+        recorder.write(0, 700, &test_video(700, true, 4)).unwrap();
+    });
+    let boxes = test_boxes(&data);
+    assert_eq!(boxes.len(), 6);
+    let (_, samples) = test_run(&data, boxes[2].1);
+    assert_eq!(samples.len(), 2);
+    // The frame before the first key frame of the session is left out
+    assert_eq!(
+        test_run(&data, boxes[4].1),
+        (6000, vec![(3000, 8, SYNC_SAMPLE, 0)])
+    );
+}
+
+#[test]
+fn test_mp4_tracks() {
+    // Only H.264, H.265 and AAC are recorded, AAC needs its config
+    let path = std::env::temp_dir().join(format!("rtsp_invitor_{}_none.mp4", std::process::id()));
+    let mut writer = Mp4Writer::new(File::create(&path).unwrap());
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\nm=audio 0 RTP/AVP 0\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mp4_truncated_after_first_fragment() {
+    // A GOP of 2 seconds at 30 frames per second is cut after a second
+    let mut data = test_record("cut.mp4", TEST_VIDEO_SDP, |recorder| {
+        for frame in 0..60 {
+            let timestamp = frame * 3000;
+            recorder
+                .write(0, timestamp as u64, &test_video(timestamp, frame == 0, 4))
+                .unwrap();
+        }
+    });
+    let kinds = test_boxes(&data)
+        .into_iter()
+        .map(|(kind, _, _)| kind)
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, ["ftyp", "moov", "moof", "mdat", "moof", "mdat"]);
+
+    // The file cut after the first fragment is complete: the run of the fragment fits its mdat
+    let (_, mdat, mdat_size) = test_boxes(&data)[3];
+    data.truncate(mdat + mdat_size);
+    let boxes = test_boxes(&data);
+    assert_eq!(boxes.len(), 4);
+    let (_, moof, moof_size) = boxes[2];
+    let trun = moof
+        + data[moof..]
+            .windows(4)
+            .position(|kind| kind == b"trun")
+            .unwrap();
+    let data_offset = u32::from_be_bytes(data[trun + 12..trun + 16].try_into().unwrap()) as usize;
+    assert_eq!(moof + data_offset, mdat + 8);
+    assert!(moof_size < data_offset);
+    let (_, samples) = test_run(&data, moof);
+    assert_eq!(samples.len(), 30);
+    for (index, (duration, _, flags, _)) in samples.iter().enumerate() {
+        assert_eq!(*duration, 3000);
+        let expected = if index == 0 {
+            SYNC_SAMPLE
+        } else {
+            NON_SYNC_SAMPLE
+        };
+        assert_eq!(*flags, expected);
+    }
+    let size = samples
+        .iter()
+        .map(|(_, size, _, _)| *size as usize)
+        .sum::<usize>();
+    assert_eq!(size, mdat_size - 8);
+}
+
+#[test]
+fn test_mp4_key_frame_without_parameter_sets() {
+    // Only the SPS: the recording waits for the PPS and says so
+    let data = test_record("pps.mp4", TEST_VIDEO_SDP, |recorder| {
+        let Recorder::Mp4(writer) = recorder else {
+            unreachable!()
+        };
+        let frame = Frame::Video(AccessUnit {
+            timestamp: 0,
+            key_frame: true,
+            nal_units: vec![
+                Bytes::from_static(TEST_SPS),
+                Bytes::from_static(&[0x65, 1, 2, 3]),
+            ],
+        });
+        writer.write(0, 0, &frame).unwrap();
+        assert!(writer.unconfigured);
+        assert!(!writer.initialized);
+    });
+    assert!(data.is_empty());
+}
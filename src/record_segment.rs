@@ -0,0 +1,582 @@
+use crate::record::Recorder;
+use crate::rtp_depacketizer::Frame;
+use crate::rtsp_sdp::MediaDescription;
+use chrono::format::{Item, StrftimeItems};
+use chrono::Local;
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, metadata, remove_dir, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The placeholder of the file names replaced by the camera
+const CAMERA_PLACEHOLDER: &str = "{camera}";
+
+/// `SegmentLimits` bounds the segments of a recording by time and by bytes: the length and the
+/// size of each segment, or the age and the total size of the segments kept.
+///
+/// Properties:
+///
+/// * `duration`: The time limit, `None` if there is none.
+/// * `size`: The byte limit, `None` if there is none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SegmentLimits {
+    pub(crate) duration: Option<Duration>,
+    pub(crate) size: Option<u64>,
+}
+
+impl SegmentLimits {
+    /// It parses `<seconds>[:<bytes>[K|M|G]]`, a limit left empty or 0 is unbounded
+    ///
+    /// Arguments:
+    ///
+    /// * `limits`: &str, the limits, e.g. `3600`, `0:500M` or `86400:20G`
+    ///
+    /// Returns:
+    ///
+    /// A Result<SegmentLimits>, an error if there is no limit.
+    pub(crate) fn parse(limits: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid segment limits {}", limits),
+            )
+        };
+        let (seconds, size) = limits.split_once(':').unwrap_or((limits, ""));
+        let seconds = match seconds.trim() {
+            "" => 0,
+            seconds => seconds.parse::<u64>().map_err(|_| invalid())?,
+        };
+        let size = size.trim();
+        let (size, unit) = match size.char_indices().last() {
+            Some((index, 'K' | 'k')) => (&size[..index], 1 << 10),
+            Some((index, 'M' | 'm')) => (&size[..index], 1 << 20),
+            Some((index, 'G' | 'g')) => (&size[..index], 1 << 30),
+            _ => (size, 1),
+        };
+        let size = match size {
+            "" => 0,
+            size => size
+                .parse::<u64>()
+                .ok()
+                .and_then(|size| size.checked_mul(unit))
+                .ok_or_else(invalid)?,
+        };
+        let limits = Self {
+            duration: (seconds != 0).then(|| Duration::from_secs(seconds)),
+            size: (size != 0).then_some(size),
+        };
+        if limits.duration.is_none() && limits.size.is_none() {
+            return Err(invalid());
+        }
+        Ok(limits)
+    }
+}
+
+/// `SegmentWriter` splits a recording into files, a new one is started at the first key frame
+/// once the segment is long or large enough. The files are named by a strftime pattern of the
+/// time they start, and the oldest ones written are deleted by the retention limits. The files
+/// of other recordings, e.g. of an earlier run, are never deleted.
+///
+/// Properties:
+///
+/// * `pattern`: The pattern of the file names, with the camera filled in.
+/// * `segment`: The limits of each segment.
+/// * `retention`: The limits of the segments kept, `None` if they are all kept.
+/// * `medias`: The media of the tracks, each segment is started with them.
+/// * `key_track`: The track the segments start on a key frame of, the first video track.
+/// * `recorder`: The segment being written and its file, `None` until the recording started.
+/// * `origin`: The unwrapped RTP timestamp of the key track the segment started at.
+/// * `elapsed`: The time of the segment before the session was set up again, e.g. after a
+///   redirect, the timestamps do not go on.
+/// * `last`: The unwrapped RTP timestamp of the last frame of the key track.
+/// * `finished`: The paths of the segments finished and not deleted yet, the oldest first.
+/// * `directories`: The directories created for the segments, deleted with their last segment.
+#[derive(Debug)]
+pub(crate) struct SegmentWriter {
+    pattern: String,
+    segment: SegmentLimits,
+    retention: Option<SegmentLimits>,
+    medias: Vec<MediaDescription>,
+    key_track: usize,
+    recorder: Option<(Box<Recorder>, PathBuf)>,
+    origin: Option<u64>,
+    elapsed: Duration,
+    last: u64,
+    finished: VecDeque<PathBuf>,
+    directories: Vec<PathBuf>,
+}
+
+impl SegmentWriter {
+    /// It checks the pattern of the file names, the first segment is created once the tracks
+    /// are set up
+    ///
+    /// Arguments:
+    ///
+    /// * `pattern`: &str, e.g. `/var/rec/{camera}/%Y%m%d-%H%M%S.mp4`, `{camera}` is replaced by
+    ///   the camera and the strftime specifiers by the local time the segment starts at
+    /// * `camera`: &str, the address of the camera
+    /// * `segment`: SegmentLimits, the limits of each segment
+    /// * `retention`: Option<SegmentLimits>, the limits of the segments kept
+    ///
+    /// Returns:
+    ///
+    /// A Result<SegmentWriter>.
+    pub(crate) fn new(
+        pattern: &str,
+        camera: &str,
+        segment: SegmentLimits,
+        retention: Option<SegmentLimits>,
+    ) -> Result<Self> {
+        let camera = camera
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        let pattern = pattern.replace(CAMERA_PLACEHOLDER, &camera);
+        if StrftimeItems::new(&pattern).any(|item| item == Item::Error) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid segment file pattern {}", pattern),
+            ));
+        }
+        let extension = Path::new(&pattern)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        if !matches!(extension.as_deref(), Some("mp4" | "ts")) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Segments are recorded to .mp4 or .ts files, not {}",
+                    pattern
+                ),
+            ));
+        }
+        Ok(Self {
+            pattern,
+            segment,
+            retention,
+            medias: vec![],
+            key_track: 0,
+            recorder: None,
+            origin: None,
+            elapsed: Duration::ZERO,
+            last: 0,
+            finished: VecDeque::new(),
+            directories: vec![],
+        })
+    }
+
+    /// It takes the tracks of the session and starts the first segment, once the recording
+    /// started the segment goes on
+    ///
+    /// Arguments:
+    ///
+    /// * `medias`: &[MediaDescription], the media of the tracks received
+    pub(crate) fn start(&mut self, medias: &[MediaDescription]) -> Result<()> {
+        if self.recorder.is_some() {
+            self.elapsed += self.since_origin(self.last);
+            self.origin = None;
+        }
+        self.medias = medias.to_vec();
+        self.key_track = medias
+            .iter()
+            .position(|media| media.media == "video")
+            .unwrap_or_default();
+        match self.recorder {
+            Some((ref mut recorder, _)) => recorder.start(medias),
+            None => self.next_segment(),
+        }
+    }
+
+    /// It writes a frame of a track, a key frame of the key track starts a new segment once
+    /// one of the limits is reached
+    ///
+    /// Arguments:
+    ///
+    /// * `track`: usize, the index of the track
+    /// * `timestamp`: u64, the unwrapped RTP timestamp of the frame
+    /// * `frame`: &Frame, the frame
+    pub(crate) fn write(&mut self, track: usize, timestamp: u64, frame: &Frame) -> Result<()> {
+        if track == self.key_track {
+            let key_frame = match frame {
+                Frame::Video(access_unit) => access_unit.key_frame,
+                _ => true,
+            };
+            if key_frame && self.is_full(timestamp) {
+                self.next_segment()?;
+            }
+            if key_frame && self.origin.is_none() {
+                self.origin = Some(timestamp);
+            }
+            self.last = timestamp;
+        }
+        match self.recorder {
+            Some((ref mut recorder, _)) => recorder.write(track, timestamp, frame),
+            None => Ok(()),
+        }
+    }
+
+    /// It writes the frames left of the segment
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        match self.recorder {
+            Some((ref mut recorder, _)) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// The time of the segment at a timestamp of the key track
+    fn since_origin(&self, timestamp: u64) -> Duration {
+        let clock_rate = self
+            .medias
+            .get(self.key_track)
+            .and_then(|media| media.rtpmap.as_ref())
+            .map_or(90000, |rtpmap| rtpmap.clock_rate.max(1) as u64);
+        match self.origin {
+            Some(origin) => {
+                Duration::from_micros(timestamp.saturating_sub(origin) * 1_000_000 / clock_rate)
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// The segment reached its length or its size
+    fn is_full(&self, timestamp: u64) -> bool {
+        let path = match self.recorder {
+            Some((_, ref path)) if self.origin.is_some() => path,
+            _ => return false,
+        };
+        let duration = self.elapsed + self.since_origin(timestamp);
+        self.segment.duration.is_some_and(|limit| duration >= limit)
+            || self
+                .segment
+                .size
+                .is_some_and(|limit| metadata(path).is_ok_and(|metadata| metadata.len() >= limit))
+    }
+
+    /// The segment is finished and the next one is created, named by the current time, then
+    /// the segments out of the retention limits are deleted
+    fn next_segment(&mut self) -> Result<()> {
+        if let Some((mut recorder, path)) = self.recorder.take() {
+            self.finished.push_back(path);
+            recorder.finish()?;
+        }
+        let name = Local::now().format(&self.pattern).to_string();
+        // A segment started within the same second gets a counter before its extension
+        let mut path = PathBuf::from(&name);
+        let mut count = 1;
+        while path.exists() {
+            let (stem, extension) = name.rsplit_once('.').unwrap_or((&name, ""));
+            path = PathBuf::from(format!("{}_{}.{}", stem, count, extension));
+            count += 1;
+        }
+        if let Some(directory) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty() && !parent.exists())
+        {
+            create_dir_all(directory)?;
+            self.directories.push(directory.to_path_buf());
+        }
+        let mut recorder = Recorder::create(&path.to_string_lossy())?;
+        recorder.start(&self.medias)?;
+        self.recorder = Some((Box::new(recorder), path));
+        self.origin = None;
+        self.elapsed = Duration::ZERO;
+        if let Some(retention) = self.retention {
+            if let Err(err) = self.retain(retention) {
+                println!("Delete old segments failed with error: {:?}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// The oldest segments written are deleted while they are older than the age limit, or all
+    /// the segments are larger than the size limit. The one being written is kept, and a
+    /// directory created for the segments is deleted once it is left empty
+    fn retain(&mut self, retention: SegmentLimits) -> Result<()> {
+        let current = match self.recorder {
+            Some((_, ref path)) => metadata(path).map_or(0, |metadata| metadata.len()),
+            None => return Ok(()),
+        };
+        // The segments deleted by someone else are forgotten
+        let mut segments = vec![];
+        for path in self.finished.drain(..) {
+            match metadata(&path) {
+                Ok(metadata) => segments.push((metadata.modified()?, metadata.len(), path)),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let mut total = segments.iter().map(|(_, size, _)| size).sum::<u64>() + current;
+        let now = SystemTime::now();
+        let mut segments = segments.into_iter();
+        for (modified, size, path) in segments.by_ref() {
+            let expired = retention
+                .duration
+                .is_some_and(|limit| now.duration_since(modified).is_ok_and(|age| age > limit));
+            let too_large = retention.size.is_some_and(|limit| total > limit);
+            if !expired && !too_large {
+                self.finished.push_back(path);
+                break;
+            }
+            if let Err(err) = remove_file(&path) {
+                self.finished.push_back(path);
+                self.finished.extend(segments.map(|(_, _, path)| path));
+                return Err(err);
+            }
+            total -= size;
+            if let Some(directory) = path.parent() {
+                let created = self
+                    .directories
+                    .iter()
+                    .position(|created| created == directory);
+                // It fails while other files are left
+                if let Some(index) = created.filter(|_| remove_dir(directory).is_ok()) {
+                    self.directories.swap_remove(index);
+                }
+            }
+        }
+        self.finished.extend(segments.map(|(_, _, path)| path));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use std::fs::read_dir;
+
+#[cfg(test)]
+const TEST_SDP: &str = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+    m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n";
+
+/// Segments of the camera 10.0.0.1 started in a directory of their own
+#[cfg(test)]
+fn test_segments(
+    name: &str,
+    file: &str,
+    segment: &str,
+    retention: Option<&str>,
+) -> (SegmentWriter, PathBuf) {
+    let directory = std::env::temp_dir().join(format!(
+        "rtsp_invitor_segment_{}_{}",
+        std::process::id(),
+        name
+    ));
+    let pattern = format!("{}/{}", directory.display(), file);
+    let mut writer = SegmentWriter::new(
+        &pattern,
+        "10.0.0.1",
+        SegmentLimits::parse(segment).unwrap(),
+        retention.map(|retention| SegmentLimits::parse(retention).unwrap()),
+    )
+    .unwrap();
+    writer
+        .start(
+            &crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+                .unwrap()
+                .medias,
+        )
+        .unwrap();
+    (writer, directory)
+}
+
+#[cfg(test)]
+fn test_access_unit(key_frame: bool, len: usize) -> Frame {
+    Frame::Video(crate::rtp_depacketizer::AccessUnit {
+        timestamp: 0,
+        key_frame,
+        nal_units: vec![
+            bytes::Bytes::from_static(&[0x67, 0x42, 0x00, 0x28]),
+            bytes::Bytes::from(vec![0x65; len]),
+        ],
+    })
+}
+
+#[cfg(test)]
+fn test_names(directory: &Path) -> Vec<String> {
+    let mut names = read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_segment_limits_parse() {
+    assert_eq!(
+        SegmentLimits::parse("3600").unwrap(),
+        SegmentLimits {
+            duration: Some(Duration::from_secs(3600)),
+            size: None
+        }
+    );
+    assert_eq!(
+        SegmentLimits::parse("0:500M").unwrap(),
+        SegmentLimits {
+            duration: None,
+            size: Some(500 << 20)
+        }
+    );
+    assert_eq!(
+        SegmentLimits::parse("86400:20g").unwrap(),
+        SegmentLimits {
+            duration: Some(Duration::from_secs(86400)),
+            size: Some(20 << 30)
+        }
+    );
+    assert_eq!(
+        SegmentLimits::parse(":2048").unwrap(),
+        SegmentLimits {
+            duration: None,
+            size: Some(2048)
+        }
+    );
+
+    // There must be a limit, in seconds and bytes
+    for limits in ["", "0", ":", "0:0", "1h", "1:2T", "-1"] {
+        let err = SegmentLimits::parse(limits).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", limits);
+    }
+    assert!(SegmentLimits::parse("1:18446744073709551615K").is_err());
+}
+
+#[test]
+fn test_segment_file_pattern() {
+    let limits = SegmentLimits::parse("60").unwrap();
+    let writer = SegmentWriter::new("/rec/{camera}/%Y%m%d.mp4", "fe80::1%eth0", limits, None);
+    assert_eq!(writer.unwrap().pattern, "/rec/fe80__1_eth0/%Y%m%d.mp4");
+    let writer = SegmentWriter::new("/rec/%H%M%S.TS", "10.0.0.1", limits, None);
+    assert!(writer.is_ok());
+
+    for pattern in [
+        "/rec/%Y%m%d.mkv",
+        "/rec/%Y%m%d",
+        "/rec/%Q.mp4",
+        "/rec/100%.ts",
+    ] {
+        let err = SegmentWriter::new(pattern, "10.0.0.1", limits, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", pattern);
+    }
+}
+
+#[test]
+fn test_segment_per_duration() {
+    // A segment per second of video, started at a key frame only
+    let (mut writer, directory) = test_segments("duration", "{camera}_%Y.ts", "1", None);
+    writer.write(0, 0, &test_access_unit(true, 2)).unwrap();
+    writer.write(0, 90000, &test_access_unit(false, 2)).unwrap();
+    writer.write(0, 135000, &test_access_unit(true, 2)).unwrap();
+    writer.write(0, 180000, &test_access_unit(true, 2)).unwrap();
+    writer.write(0, 225000, &test_access_unit(true, 2)).unwrap();
+    writer.finish().unwrap();
+    // The ones started within the same year have a counter
+    let year = Local::now().format("%Y");
+    assert_eq!(
+        test_names(&directory),
+        [
+            format!("10.0.0.1_{}.ts", year),
+            format!("10.0.0.1_{}_1.ts", year),
+            format!("10.0.0.1_{}_2.ts", year),
+        ]
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_segment_per_size() {
+    let (mut writer, directory) = test_segments("size", "{camera}_%H%M%S.ts", ":1K", None);
+    writer.write(0, 0, &test_access_unit(true, 100)).unwrap();
+    writer
+        .write(0, 3000, &test_access_unit(false, 1000))
+        .unwrap();
+    writer
+        .write(0, 6000, &test_access_unit(false, 100))
+        .unwrap();
+    assert_eq!(test_names(&directory).len(), 1);
+    writer.write(0, 9000, &test_access_unit(true, 100)).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(test_names(&directory).len(), 2);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_segment_resumed_session() {
+    // The time of the segment goes on when the session is set up again with new timestamps
+    let (mut writer, directory) = test_segments("resumed", "{camera}_%H%M%S.ts", "1", None);
+    writer.write(0, 0, &test_access_unit(true, 2)).unwrap();
+    writer.write(0, 45000, &test_access_unit(false, 2)).unwrap();
+    writer
+        .start(
+            &crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+                .unwrap()
+                .medias,
+        )
+        .unwrap();
+    writer.write(0, 900000, &test_access_unit(true, 2)).unwrap();
+    assert_eq!(test_names(&directory).len(), 1);
+    writer.write(0, 945000, &test_access_unit(true, 2)).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(test_names(&directory).len(), 2);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_segment_retention_by_size() {
+    // 2K keeps the one being written and the last one
+    let (mut writer, directory) =
+        test_segments("retention", "{camera}_%H%M%S.ts", "1", Some(":2K"));
+    for second in 0..4u64 {
+        writer
+            .write(0, second * 90000, &test_access_unit(true, 2))
+            .unwrap();
+        writer
+            .write(0, second * 90000 + 45000, &test_access_unit(false, 2))
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    let names = test_names(&directory);
+    assert_eq!(names.len(), 2);
+    let current = match writer.recorder {
+        Some((_, ref path)) => path.file_name().unwrap().to_string_lossy().into_owned(),
+        None => panic!("No segment started"),
+    };
+    assert!(names.contains(&current));
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_segment_retention_of_written_files() {
+    use std::fs::{create_dir_all, File};
+
+    // An hour old segment written on another day is deleted with its directory, an older file
+    // named by the pattern but not written by the recorder is kept
+    let (mut writer, directory) =
+        test_segments("retain", "%Y%m%d/{camera}_%H%M%S.ts", "1", Some("3600"));
+    let old = SystemTime::now() - Duration::from_secs(7200);
+    for day in ["20000101", "20000102"] {
+        create_dir_all(directory.join(day)).unwrap();
+        let file = File::create(directory.join(day).join("10.0.0.1_000000.ts")).unwrap();
+        file.set_modified(old).unwrap();
+    }
+    writer
+        .finished
+        .push_back(directory.join("20000102/10.0.0.1_000000.ts"));
+    writer.directories.push(directory.join("20000102"));
+    writer.write(0, 0, &test_access_unit(true, 2)).unwrap();
+    writer.write(0, 90000, &test_access_unit(true, 2)).unwrap();
+    writer.finish().unwrap();
+
+    assert!(directory.join("20000101/10.0.0.1_000000.ts").exists());
+    assert!(!directory.join("20000102").exists());
+    assert_eq!(writer.finished.len(), 1);
+    assert!(writer.finished[0].exists());
+    let current = match writer.recorder {
+        Some((_, ref path)) => path.clone(),
+        None => panic!("No segment started"),
+    };
+    assert!(current.exists());
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,771 @@
+#[cfg(test)]
+use crate::record::{test_audio, test_record, test_video};
+use crate::rtp_aac::AudioSpecificConfig;
+use crate::rtp_depacketizer::{AccessUnit, Frame};
+use crate::rtsp_sdp::MediaDescription;
+use bytes::{BufMut, BytesMut};
+use std::fs::File;
+use std::io::Write;
+use std::iter::repeat_n;
+use std::net::UdpSocket;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The size of a transport stream packet
+const TS_PACKET_SIZE: usize = 188;
+
+/// The transport stream packets of a UDP datagram, the usual 1316 bytes
+const TS_PACKETS_PER_DATAGRAM: usize = 7;
+
+/// The PID of the program map table, the elementary streams follow `ES_PID`
+const PMT_PID: u16 = 0x1000;
+
+/// The PID of the first elementary stream
+const ES_PID: u16 = 0x100;
+
+/// The clock of PTS and PCR base
+const TS_CLOCK_RATE: u64 = 90000;
+
+/// The time a frame is presented after its PCR, so it can be decoded in time
+const PTS_OFFSET: u64 = 63000;
+
+/// The most a video frame may be presented before a frame decoded ahead of it, e.g. with
+/// B-frames. The DTS is this much before the PTS of a frame presented in decode order
+const MAX_REORDER: u64 = TS_CLOCK_RATE / 2;
+
+/// PAT and PMT are repeated at least this often in the PCR clock, and at each key frame
+const PSI_INTERVAL: u64 = TS_CLOCK_RATE / 2;
+
+/// The codecs recorded to MPEG-TS
+#[derive(Debug)]
+enum Codec {
+    H264,
+    H265,
+    Aac(AudioSpecificConfig),
+}
+
+/// `TsSink` is where the transport stream is written: a file or named pipe, or UDP datagrams of
+/// 7 packets
+#[derive(Debug)]
+pub(crate) enum TsSink {
+    File(File),
+    Udp(UdpSocket),
+}
+
+/// `TsTrack` is an elementary stream of the program.
+///
+/// Properties:
+///
+/// * `input`: The index of the track of the session.
+/// * `codec`: The codec of the stream.
+/// * `pid`: The PID of the stream.
+/// * `clock_rate`: The RTP clock rate of the track.
+/// * `continuity_counter`: The continuity counter of the last packet of the stream.
+/// * `origin`: The unwrapped RTP timestamp of the time 0 of the track, `None` until the first
+///   frame, or the first one after the track is resumed.
+/// * `end`: The time of the track in the PTS clock the next frame is expected at.
+/// * `decode`: The decode time of the last video frame in the PTS clock.
+#[derive(Debug)]
+struct TsTrack {
+    input: usize,
+    codec: Codec,
+    pid: u16,
+    clock_rate: u32,
+    continuity_counter: u8,
+    origin: Option<i64>,
+    end: u64,
+    decode: Option<u64>,
+}
+
+/// `TsWriter` records H.264, H.265 and AAC tracks to an MPEG-2 transport stream (ISO/IEC
+/// 13818-1) of one program: a PES packet per frame, AAC in ADTS, and the PCR carried by the
+/// first video stream. Video frames arrive in decode order, the RTP timestamp is their
+/// presentation time: the decode time is the highest presentation time so far, video
+/// reordered by more than `MAX_REORDER` is not recorded.
+///
+/// Properties:
+///
+/// * `sink`: Where the transport stream is written.
+/// * `tracks`: The elementary streams, the other tracks of the session are left out.
+/// * `pcr_track`: The index of the stream carrying the PCR, the first video stream.
+/// * `psi`: The PCR of the last PAT and PMT and their continuity counters.
+/// * `buf`: The packets not written to the sink yet.
+#[derive(Debug)]
+pub(crate) struct TsWriter {
+    sink: TsSink,
+    tracks: Vec<TsTrack>,
+    pcr_track: usize,
+    psi: (Option<u64>, u8, u8),
+    buf: BytesMut,
+}
+
+impl TsWriter {
+    pub(crate) fn new(sink: TsSink) -> Self {
+        Self {
+            sink,
+            tracks: vec![],
+            pcr_track: 0,
+            psi: (None, 0, 0),
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// It takes the tracks of the session. Once the recording started, the tracks go on from
+    /// where they stopped: the session is the same one set up again, e.g. after a redirect
+    ///
+    /// Arguments:
+    ///
+    /// * `medias`: &[MediaDescription], the media of the tracks received
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an error if no track can be recorded.
+    pub(crate) fn start(&mut self, medias: &[MediaDescription]) -> Result<()> {
+        if self.psi.0.is_some() {
+            self.tracks.iter_mut().for_each(|track| track.origin = None);
+            return Ok(());
+        }
+        self.tracks = medias
+            .iter()
+            .enumerate()
+            .filter_map(|(input, media)| TsTrack::from_media(input, media).transpose())
+            .collect::<Result<Vec<_>>>()?;
+        if self.tracks.is_empty() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "No H.264, H.265 or AAC track to record to MPEG-TS",
+            ));
+        }
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            track.pid = ES_PID + index as u16;
+        }
+        self.pcr_track = self
+            .tracks
+            .iter()
+            .position(TsTrack::is_video)
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    /// It writes a frame of a track as a PES packet, video is written from its first key frame
+    /// on and audio once the video started
+    ///
+    /// Arguments:
+    ///
+    /// * `input`: usize, the index of the track of the session
+    /// * `timestamp`: u64, the unwrapped RTP timestamp of the frame
+    /// * `frame`: &Frame, the frame
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, frames of the tracks not recorded are ignored.
+    pub(crate) fn write(&mut self, input: usize, timestamp: u64, frame: &Frame) -> Result<()> {
+        let index = match self.tracks.iter().position(|track| track.input == input) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let (data, key_frame) = match (&self.tracks[index].codec, frame) {
+            (Codec::H264 | Codec::H265, Frame::Video(access_unit)) => (
+                self.tracks[index].annex_b(access_unit),
+                access_unit.key_frame,
+            ),
+            (Codec::Aac(config), Frame::Audio(audio)) => {
+                let mut data = BytesMut::from(&config.adts_header(audio.data.len())?[..]);
+                data.put_slice(&audio.data);
+                (data, true)
+            }
+            _ => return Ok(()),
+        };
+        let pcr_started = self.tracks[self.pcr_track].origin.is_some();
+        let track = &mut self.tracks[index];
+        if track.origin.is_none() && (!key_frame || (index != self.pcr_track && !pcr_started)) {
+            return Ok(());
+        }
+
+        // The time of the track in the PTS clock, the frame is expected to last as long as the
+        // one before
+        let clock_rate = track.clock_rate as u64;
+        let origin = *track
+            .origin
+            .get_or_insert(timestamp as i64 - (track.end * clock_rate / TS_CLOCK_RATE) as i64);
+        let time = (timestamp as i64 - origin).max(0) as u64 * TS_CLOCK_RATE / clock_rate;
+        let decode = match track.decode {
+            Some(last) if track.is_video() => time.max(last + 1),
+            _ => time,
+        };
+        if decode > time + MAX_REORDER {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Video frame presented too long before the frames decoded ahead of it",
+            ));
+        }
+        let previous = std::mem::replace(&mut track.end, time);
+        track.end = time + time.saturating_sub(previous).max(1);
+        track.decode = Some(decode);
+
+        // The PCR follows the decode times, which never go back
+        let pcr = (index == self.pcr_track).then_some(decode);
+        if let Some(pcr) = pcr {
+            if (key_frame && track.is_video())
+                || self.psi.0.is_none_or(|last| pcr >= last + PSI_INTERVAL)
+            {
+                self.write_psi(pcr);
+            }
+        }
+        let track = &self.tracks[index];
+        let dts = track
+            .is_video()
+            .then_some(decode + PTS_OFFSET - MAX_REORDER);
+        let pes = track.pes(&data, time + PTS_OFFSET, dts);
+        let random_access = key_frame && track.is_video();
+        let (pid, continuity_counter) = (track.pid, track.continuity_counter);
+        let continuity_counter =
+            self.write_packets(pid, continuity_counter, &pes, pcr, random_access);
+        self.tracks[index].continuity_counter = continuity_counter;
+        self.flush(false)
+    }
+
+    /// It writes the packets left, e.g. when the session is torn down
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.flush(true)
+    }
+
+    /// PAT and PMT of the program
+    fn write_psi(&mut self, pcr: u64) {
+        // program_number 1 -> PMT_PID
+        let mut pat = BytesMut::new();
+        pat.put_u16(1);
+        pat.put_u16(0xe000 | PMT_PID);
+        let mut pmt = BytesMut::new();
+        pmt.put_u16(0xe000 | self.tracks[self.pcr_track].pid);
+        pmt.put_u16(0xf000); // program_info_length
+        for track in self.tracks.iter() {
+            pmt.put_u8(track.stream_type());
+            pmt.put_u16(0xe000 | track.pid);
+            pmt.put_u16(0xf000); // ES_info_length
+        }
+        let (_, pat_counter, pmt_counter) = self.psi;
+        self.psi = (
+            Some(pcr),
+            self.write_section(0, pat_counter, 0x00, &pat),
+            self.write_section(PMT_PID, pmt_counter, 0x02, &pmt),
+        );
+    }
+
+    /// A PSI section with the program or transport stream ID 1, in a packet of its own
+    fn write_section(&mut self, pid: u16, continuity_counter: u8, table_id: u8, body: &[u8]) -> u8 {
+        let mut section = BytesMut::new();
+        section.put_u8(table_id);
+        // section_syntax_indicator | section_length, with the header after it and the CRC
+        section.put_u16(0xb000 | (body.len() + 9) as u16);
+        section.put_u16(1);
+        section.put_u8(0xc1); // version_number 0, current_next_indicator
+        section.put_u16(0); // section_number, last_section_number
+        section.put_slice(body);
+        let crc = crc32_mpeg2(&section);
+        section.put_u32(crc);
+
+        let continuity_counter = (continuity_counter + 1) & 0xf;
+        self.buf.put_u8(0x47);
+        self.buf.put_u16(0x4000 | pid); // payload_unit_start_indicator
+        self.buf.put_u8(0x10 | continuity_counter);
+        self.buf.put_u8(0); // pointer_field
+        self.buf.put_slice(&section);
+        let stuffing = TS_PACKET_SIZE - 5 - section.len();
+        self.buf.extend(repeat_n(0xff, stuffing));
+        continuity_counter
+    }
+
+    /// The packets of a PES packet, the first one has the PCR and the random access indicator
+    /// in its adaptation field, the last one is stuffed with its adaptation field
+    ///
+    /// Returns:
+    ///
+    /// The continuity counter of the last packet.
+    fn write_packets(
+        &mut self,
+        pid: u16,
+        mut continuity_counter: u8,
+        pes: &[u8],
+        pcr: Option<u64>,
+        random_access: bool,
+    ) -> u8 {
+        let mut offset = 0;
+        while offset < pes.len() {
+            let first = offset == 0;
+            let mut adaptation: Option<Vec<u8>> = None;
+            if first && (pcr.is_some() || random_access) {
+                let mut field = vec![(random_access as u8) << 6 | (pcr.is_some() as u8) << 4];
+                if let Some(pcr) = pcr {
+                    // program_clock_reference_base(33) | reserved(6) | extension(9)
+                    let base = pcr & ((1 << 33) - 1);
+                    field.extend_from_slice(&(base << 15 | 0x7e00).to_be_bytes()[2..]);
+                }
+                adaptation = Some(field);
+            }
+            let mut room =
+                TS_PACKET_SIZE - 4 - adaptation.as_ref().map_or(0, |field| field.len() + 1);
+            let left = pes.len() - offset;
+            if left < room {
+                // The length byte alone stuffs one byte, flags and 0xff the others
+                let field = adaptation.get_or_insert_with(Vec::new);
+                let mut stuffing = room - left;
+                if field.is_empty() {
+                    stuffing -= 1;
+                    if stuffing > 0 {
+                        field.push(0);
+                        stuffing -= 1;
+                    }
+                }
+                field.extend(repeat_n(0xff, stuffing));
+                room = left;
+            }
+
+            continuity_counter = (continuity_counter + 1) & 0xf;
+            self.buf.put_u8(0x47);
+            self.buf.put_u16((first as u16) << 14 | pid);
+            let control = if adaptation.is_some() { 0x30 } else { 0x10 };
+            self.buf.put_u8(control | continuity_counter);
+            if let Some(field) = adaptation {
+                self.buf.put_u8(field.len() as u8);
+                self.buf.put_slice(&field);
+            }
+            self.buf.put_slice(&pes[offset..offset + room]);
+            offset += room;
+        }
+        continuity_counter
+    }
+
+    /// The packets are written to the sink, UDP takes them 7 at a time unless all are flushed
+    fn flush(&mut self, all: bool) -> Result<()> {
+        match self.sink {
+            TsSink::File(ref mut file) => file.write_all(&self.buf.split())?,
+            TsSink::Udp(ref socket) => {
+                let datagram = TS_PACKET_SIZE * TS_PACKETS_PER_DATAGRAM;
+                while self.buf.len() >= datagram || (all && !self.buf.is_empty()) {
+                    let len = self.buf.len().min(datagram);
+                    socket.send(&self.buf.split_to(len))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TsTrack {
+    /// The elementary stream of a media, `None` if its codec is not recorded
+    fn from_media(input: usize, media: &MediaDescription) -> Result<Option<Self>> {
+        let rtpmap = match media.rtpmap {
+            Some(ref rtpmap) => rtpmap,
+            None => return Ok(None),
+        };
+        let codec = match rtpmap.encoding.to_uppercase().as_str() {
+            "H264" => Codec::H264,
+            "H265" => Codec::H265,
+            "MPEG4-GENERIC" => match media.fmtp.get("config") {
+                Some(config) => Codec::Aac(AudioSpecificConfig::parse(config)?),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "mpeg4-generic config missing",
+                    ))
+                }
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            input,
+            codec,
+            pid: ES_PID,
+            clock_rate: rtpmap.clock_rate,
+            continuity_counter: 0xf,
+            origin: None,
+            end: 0,
+            decode: None,
+        }))
+    }
+
+    fn is_video(&self) -> bool {
+        !matches!(self.codec, Codec::Aac(_))
+    }
+
+    /// The stream_type of the PMT
+    fn stream_type(&self) -> u8 {
+        match self.codec {
+            Codec::H264 => 0x1b,
+            Codec::H265 => 0x24,
+            Codec::Aac(_) => 0x0f,
+        }
+    }
+
+    /// The NAL units with start codes, starting with the access unit delimiter MPEG-TS needs
+    fn annex_b(&self, access_unit: &AccessUnit) -> BytesMut {
+        let (delimiter, aud): (u8, &[u8]) = match self.codec {
+            Codec::H264 => (9, &[0x09, 0xf0]),
+            _ => (35, &[0x46, 0x01, 0x50]),
+        };
+        let nal_type = |nal_unit: &[u8]| match self.codec {
+            Codec::H264 => nal_unit[0] & 0x1f,
+            _ => nal_unit[0] >> 1 & 0x3f,
+        };
+        let mut data = BytesMut::new();
+        let nal_units = access_unit
+            .nal_units
+            .iter()
+            .filter(|nal_unit| !nal_unit.is_empty());
+        if nal_units
+            .clone()
+            .next()
+            .is_none_or(|nal_unit| nal_type(nal_unit) != delimiter)
+        {
+            data.put_u32(1);
+            data.put_slice(aud);
+        }
+        for nal_unit in nal_units {
+            data.put_u32(1);
+            data.put_slice(nal_unit);
+        }
+        data
+    }
+
+    /// The PES packet of a frame with its PTS, and its DTS if given, the length of video is
+    /// left unbounded
+    fn pes(&self, data: &[u8], pts: u64, dts: Option<u64>) -> BytesMut {
+        let header_len = if dts.is_some() { 10 } else { 5 };
+        let mut pes = BytesMut::with_capacity(data.len() + 9 + header_len);
+        pes.put_slice(&[0, 0, 1]);
+        pes.put_u8(if self.is_video() { 0xe0 } else { 0xc0 });
+        let length = data.len() + 3 + header_len;
+        pes.put_u16(if self.is_video() || length > 0xffff {
+            0
+        } else {
+            length as u16
+        });
+        pes.put_u8(0x80);
+        pes.put_u8(if dts.is_some() { 0xc0 } else { 0x80 }); // PTS_DTS_flags
+        pes.put_u8(header_len as u8); // PES_header_data_length
+        match dts {
+            Some(dts) => {
+                put_timestamp(&mut pes, 0x3, pts);
+                put_timestamp(&mut pes, 0x1, dts);
+            }
+            None => put_timestamp(&mut pes, 0x2, pts),
+        }
+        pes.put_slice(data);
+        pes
+    }
+}
+
+/// A PTS or DTS of 33 bits after its 4 bits prefix:
+/// `prefix | TS[32..30] | marker | TS[29..15] | marker | TS[14..0] | marker`
+fn put_timestamp(pes: &mut BytesMut, prefix: u8, timestamp: u64) {
+    let timestamp = timestamp & ((1 << 33) - 1);
+    pes.put_u8(prefix << 4 | (timestamp >> 29 & 0x0e) as u8 | 1);
+    pes.put_u16((timestamp >> 14 & 0xfffe) as u16 | 1);
+    pes.put_u16((timestamp << 1 & 0xfffe) as u16 | 1);
+}
+
+/// The CRC-32 of the PSI sections (ISO/IEC 13818-1 annex A)
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+const TEST_SDP: &str = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+    m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+    m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\na=fmtp:97 config=1410\r\n";
+
+/// The PID, the continuity counter and the data of each packet
+#[cfg(test)]
+fn test_packets(data: &[u8]) -> Vec<(u16, u8, &[u8])> {
+    assert!(data.len().is_multiple_of(TS_PACKET_SIZE));
+    data.chunks(TS_PACKET_SIZE)
+        .map(|packet| {
+            assert_eq!(packet[0], 0x47);
+            let pid = u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff;
+            (pid, packet[3] & 0xf, packet)
+        })
+        .collect()
+}
+
+#[test]
+fn test_crc32_mpeg2() {
+    // The CRC of a PAT of program 1 at PID 0x1000, as written by common muxers
+    let pat = [
+        0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xf0, 0x00,
+    ];
+    assert_eq!(crc32_mpeg2(&pat), 0x2ab1_04b2);
+    assert_eq!(crc32_mpeg2(&[]), 0xffff_ffff);
+}
+
+#[test]
+fn test_ts_key_frame_over_udp() {
+    let medias = crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+        .unwrap()
+        .medias;
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(receiver.local_addr().unwrap()).unwrap();
+    let mut writer = TsWriter::new(TsSink::Udp(socket));
+    writer.start(&medias).unwrap();
+
+    // Audio waits for the video, the key frame starts with PAT and PMT
+    writer.write(1, 1 << 32, &test_audio(0)).unwrap();
+    writer.write(0, 1 << 32, &test_video(0, true, 400)).unwrap();
+    writer.write(1, 1 << 32 | 1024, &test_audio(1024)).unwrap();
+    writer.finish().unwrap();
+
+    let mut datagram = [0; 2048];
+    let len = receiver.recv(&mut datagram).unwrap();
+    assert_eq!(len, 6 * TS_PACKET_SIZE);
+    let packets = test_packets(&datagram[..len]);
+    let pids = packets.iter().map(|(pid, _, _)| *pid).collect::<Vec<_>>();
+    assert_eq!(pids, [0, PMT_PID, ES_PID, ES_PID, ES_PID, ES_PID + 1]);
+
+    // The key frame has the PCR 0, PTS and DTS at their offsets, and the AUD prepended
+    let video = packets[2].2;
+    assert_eq!(video[3], 0x30);
+    assert_eq!(&video[5..12], &[0x50, 0, 0, 0, 0, 0x7e, 0]);
+    assert_eq!(&video[12..16], &[0, 0, 1, 0xe0]);
+    assert_eq!(&video[21..26], &[0x31, 0x00, 0x03, 0xec, 0x31]);
+    assert_eq!(&video[26..31], &[0x11, 0x00, 0x01, 0x8c, 0xa1]);
+    assert_eq!(&video[31..37], &[0, 0, 0, 1, 0x09, 0xf0]);
+    assert_eq!(packets[3].1, 1);
+}
+
+#[test]
+fn test_ts_udp_datagrams() {
+    let medias = crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+        .unwrap()
+        .medias;
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(receiver.local_addr().unwrap()).unwrap();
+    let mut writer = TsWriter::new(TsSink::Udp(socket));
+    writer.start(&medias).unwrap();
+
+    // 7 packets a datagram, the packets left wait for the next frame or the end
+    writer.write(0, 0, &test_video(0, true, 2200)).unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let mut datagram = [0; 2048];
+    assert_eq!(receiver.recv(&mut datagram).unwrap(), 7 * TS_PACKET_SIZE);
+    assert_eq!(receiver.recv(&mut datagram).unwrap(), 7 * TS_PACKET_SIZE);
+    assert!(receiver.recv(&mut datagram).is_err());
+    receiver.set_nonblocking(false).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(receiver.recv(&mut datagram).unwrap(), TS_PACKET_SIZE);
+}
+
+#[test]
+fn test_ts_continuity_counter_wrap() {
+    // Without video the audio carries the PCR, each frame fits a packet
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\na=fmtp:97 config=1410\r\n";
+    let data = test_record("counter.ts", sdp, |recorder| {
+        for frame in 0..20 {
+            recorder
+                .write(0, frame * 1024, &test_audio(frame as u32 * 1024))
+                .unwrap();
+        }
+    });
+    let packets = test_packets(&data);
+    let counters = |pid: u16| {
+        packets
+            .iter()
+            .filter(|(packet_pid, _, _)| *packet_pid == pid)
+            .map(|(_, counter, _)| *counter)
+            .collect::<Vec<_>>()
+    };
+    let expected = (0..20)
+        .map(|counter| counter as u8 & 0xf)
+        .collect::<Vec<_>>();
+    assert_eq!(counters(ES_PID), expected);
+    // PAT and PMT every half second of the 5760 ticks of a frame
+    assert_eq!(counters(0), [1, 2, 3]);
+    assert_eq!(counters(PMT_PID), [1, 2, 3]);
+}
+
+#[test]
+fn test_ts_psi_at_key_frames() {
+    let data = test_record("psi.ts", TEST_SDP, |recorder| {
+        recorder.write(0, 0, &test_video(0, true, 10)).unwrap();
+        recorder.write(0, 3000, &test_video(0, false, 10)).unwrap();
+        recorder.write(0, 6000, &test_video(0, true, 10)).unwrap();
+    });
+    let pids = test_packets(&data)
+        .iter()
+        .map(|(pid, _, _)| *pid)
+        .collect::<Vec<_>>();
+    assert_eq!(pids, [0, PMT_PID, ES_PID, ES_PID, 0, PMT_PID, ES_PID]);
+
+    // The PMT lists the streams with the PCR on the video
+    let pmt = test_packets(&data)[1].2;
+    assert_eq!(&pmt[13..15], &(0xe000 | ES_PID).to_be_bytes());
+    assert_eq!(pmt[17], 0x1b);
+    assert_eq!(pmt[22], 0x0f);
+}
+
+#[test]
+fn test_ts_adaptation_stuffing() {
+    let mut writer = TsWriter::new(TsSink::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()));
+
+    // A full payload, then 1, 2 and 84 bytes of room left stuffed by the adaptation field
+    for (len, adaptation) in [
+        (184, None),
+        (183, Some(vec![])),
+        (182, Some(vec![0])),
+        (100, Some([&[0][..], &[0xff; 82]].concat())),
+    ] {
+        let pes = vec![0xab; len];
+        assert_eq!(writer.write_packets(ES_PID, 0xf, &pes, None, false), 0);
+        let packet = writer.buf.split();
+        assert_eq!(packet.len(), TS_PACKET_SIZE);
+        match adaptation {
+            Some(field) => {
+                assert_eq!(packet[3], 0x30);
+                assert_eq!(packet[4] as usize, field.len());
+                assert_eq!(&packet[5..5 + field.len()], &field[..]);
+            }
+            None => assert_eq!(packet[3], 0x10),
+        }
+        assert_eq!(&packet[TS_PACKET_SIZE - len..], &pes[..]);
+    }
+
+    // The PCR and the random access indicator take the first packet, the last one is stuffed
+    let pes = vec![0xab; 200];
+    assert_eq!(writer.write_packets(ES_PID, 0xe, &pes, Some(1), true), 0);
+    let packets = writer.buf.split();
+    assert_eq!(packets.len(), 2 * TS_PACKET_SIZE);
+    assert_eq!(&packets[3..12], &[0x3f, 7, 0x50, 0, 0, 0, 0, 0xfe, 0]);
+    let second = &packets[TS_PACKET_SIZE..];
+    assert_eq!(&second[1..4], &[0x01, 0x00, 0x30]);
+    assert_eq!(second[4] as usize, TS_PACKET_SIZE - 5 - (200 - 176));
+}
+
+#[test]
+fn test_ts_reordered_video() {
+    // I, P and two B-frames: the PTS goes back, the DTS and the PCR do not
+    let data = test_record("reordered.ts", TEST_SDP, |recorder| {
+        for (timestamp, key_frame) in [(0, true), (9000, false), (3000, false), (6000, false)] {
+            let frame = test_video(0, key_frame, 10);
+            recorder.write(0, timestamp, &frame).unwrap();
+        }
+        // A frame presented more than MAX_REORDER before one decoded ahead of it
+        recorder.write(0, 90000, &test_video(0, false, 10)).unwrap();
+        let err = recorder
+            .write(0, 30000, &test_video(0, false, 10))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    });
+    let time = |field: &[u8]| {
+        (field[0] as u64 >> 1 & 7) << 30
+            | (u16::from_be_bytes([field[1], field[2]]) as u64 >> 1) << 15
+            | u16::from_be_bytes([field[3], field[4]]) as u64 >> 1
+    };
+    let mut times = vec![];
+    for (_, _, packet) in test_packets(&data)
+        .into_iter()
+        .filter(|(pid, _, packet)| *pid == ES_PID && packet[1] & 0x40 != 0)
+    {
+        assert_eq!(packet[5] & 0x10, 0x10);
+        let pcr = u64::from_be_bytes([
+            0, 0, packet[6], packet[7], packet[8], packet[9], packet[10], packet[11],
+        ]) >> 15;
+        let pes = &packet[5 + packet[4] as usize..];
+        assert_eq!(&pes[7..9], &[0xc0, 10]);
+        times.push((pcr, time(&pes[9..14]), time(&pes[14..19])));
+    }
+    assert_eq!(
+        times,
+        [
+            (0, PTS_OFFSET, 18000),
+            (9000, 9000 + PTS_OFFSET, 27000),
+            (9001, 3000 + PTS_OFFSET, 27001),
+            (9002, 6000 + PTS_OFFSET, 27002),
+            (90000, 90000 + PTS_OFFSET, 108000),
+        ]
+    );
+}
+
+#[test]
+fn test_ts_pes_and_annex_b() {
+    let medias = crate::rtsp_sdp::SessionDescription::parse(TEST_SDP)
+        .unwrap()
+        .medias;
+    let video = TsTrack::from_media(0, &medias[0]).unwrap().unwrap();
+    let audio = TsTrack::from_media(1, &medias[1]).unwrap().unwrap();
+
+    // Audio has its length and the PTS is 33 bits
+    let pes = audio.pes(&[1, 2, 3], 1 << 33 | 5, None);
+    assert_eq!(&pes[..6], &[0, 0, 1, 0xc0, 0, 11]);
+    assert_eq!(&pes[9..14], &[0x21, 0x00, 0x01, 0x00, 0x0b]);
+    assert_eq!(&pes[14..], &[1, 2, 3]);
+
+    // Video has its DTS after the PTS, its length is unbounded
+    let pes = video.pes(&[1], 0, Some(1));
+    assert_eq!(&pes[3..9], &[0xe0, 0, 0, 0x80, 0xc0, 10]);
+    assert_eq!(&pes[9..19], &[0x31, 0, 1, 0, 1, 0x11, 0, 1, 0, 3]);
+    assert_eq!(&pes[19..], &[1]);
+
+    // The access unit delimiter is not doubled, empty NAL units are left out
+    let access_unit = AccessUnit {
+        timestamp: 0,
+        key_frame: false,
+        nal_units: vec![
+            bytes::Bytes::from_static(&[0x09, 0xf0]),
+            bytes::Bytes::new(),
+            bytes::Bytes::from_static(&[0x41, 1]),
+        ],
+    };
+    assert_eq!(
+        &video.annex_b(&access_unit)[..],
+        &[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x41, 1]
+    );
+
+    // H.265 gets its own delimiter
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=video 0 RTP/AVP 96\r\na=rtpmap:96 H265/90000\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let track = TsTrack::from_media(0, &medias[0]).unwrap().unwrap();
+    assert_eq!(track.stream_type(), 0x24);
+    let access_unit = AccessUnit {
+        timestamp: 0,
+        key_frame: false,
+        nal_units: vec![bytes::Bytes::from_static(&[0x02, 0x01, 0xd0])],
+    };
+    assert_eq!(
+        &track.annex_b(&access_unit)[..],
+        &[0, 0, 0, 1, 0x46, 0x01, 0x50, 0, 0, 0, 1, 0x02, 0x01, 0xd0]
+    );
+}
+
+#[test]
+fn test_ts_tracks() {
+    // Only H.264, H.265 and AAC are recorded, AAC needs its config
+    let mut writer = TsWriter::new(TsSink::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()));
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\nm=audio 0 RTP/AVP 0\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let sdp = "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000\r\n";
+    let medias = crate::rtsp_sdp::SessionDescription::parse(sdp)
+        .unwrap()
+        .medias;
+    let err = writer.start(&medias).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,584 @@
+use crate::rtp_depacketizer::AudioFrame;
+use crate::rtp_packet::RtpPacket;
+use crate::rtsp_sdp::FormatParameters;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The samples of an AAC frame, unless the AudioSpecificConfig asks for 960
+const AAC_FRAME_LENGTH: u32 = 1024;
+
+/// The stream type of audio in the ISO/IEC 14496-1 decoder configuration
+const AUDIO_STREAM_TYPE: &str = "5";
+
+/// `AacDepacketizer` takes the access units of an MPEG-4 audio stream in the `mpeg4-generic`
+/// payload format (RFC 3640), the AU header section is described by the format parameters.
+///
+/// Properties:
+///
+/// * `size_length`: The bits of the AU-size field.
+/// * `index_length`: The bits of the AU-Index field of the first AU header.
+/// * `index_delta_length`: The bits of the AU-Index-delta field of the next AU headers.
+/// * `cts_delta_length`: The bits of the CTS-delta field, 0 if absent.
+/// * `dts_delta_length`: The bits of the DTS-delta field, 0 if absent.
+/// * `random_access_indication`: The AU headers have a RAP-flag.
+/// * `stream_state_length`: The bits of the Stream-state field.
+/// * `auxiliary_length`: The bits of the auxiliary-data-size field, 0 without auxiliary section.
+/// * `frame_length`: The duration of an access unit in RTP clock ticks.
+/// * `fragment`: The RTP timestamp, the size and the data of the access unit being put together
+///   from fragments.
+/// * `sequence_number`: The sequence number of the last packet pushed.
+/// * `ready`: The frames completed and not pulled yet.
+#[derive(Debug, Default)]
+pub(crate) struct AacDepacketizer {
+    size_length: u32,
+    index_length: u32,
+    index_delta_length: u32,
+    cts_delta_length: u32,
+    dts_delta_length: u32,
+    random_access_indication: bool,
+    stream_state_length: u32,
+    auxiliary_length: u32,
+    frame_length: u32,
+    fragment: Option<(u32, usize, BytesMut)>,
+    sequence_number: Option<u16>,
+    ready: VecDeque<AudioFrame>,
+}
+
+/// `AudioSpecificConfig` is the decoder configuration of an MPEG-4 audio stream (ISO/IEC
+/// 14496-3 section 1.6.2.1), given in hexadecimal by the `config` format parameter.
+///
+/// Properties:
+///
+/// * `data`: The configuration as given.
+/// * `object_type`: The audio object type of the core, e.g. 2 for AAC LC under SBR.
+/// * `frequency_index`: The samplingFrequencyIndex of the core, 15 for an explicit frequency.
+/// * `channels`: The channelConfiguration, the number of channels of the common layouts.
+/// * `frame_length`: The samples of an access unit, 960 with the frameLengthFlag of AAC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AudioSpecificConfig {
+    pub(crate) data: Vec<u8>,
+    object_type: u32,
+    frequency_index: u8,
+    pub(crate) channels: u16,
+    frame_length: u32,
+}
+
+/// `BitReader` reads the fields of the AU header section, from the most significant bit
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    end: usize,
+}
+
+impl AacDepacketizer {
+    /// It creates the depacketizer of the AU headers described by the format parameters, the
+    /// AAC-hbr and AAC-lbr modes give the lengths they define when they are left out
+    ///
+    /// Arguments:
+    ///
+    /// * `fmtp`: &FormatParameters, the `a=fmtp` parameters of the payload type
+    ///
+    /// Returns:
+    ///
+    /// A Result<AacDepacketizer>, only audio streams are supported.
+    pub(crate) fn new(fmtp: &FormatParameters) -> Result<Self> {
+        if fmtp
+            .get("streamtype")
+            .is_some_and(|stream_type| stream_type != AUDIO_STREAM_TYPE)
+        {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "mpeg4-generic stream type not supported",
+            ));
+        }
+        let (size_length, index_length) = match fmtp.get("mode").map(|mode| mode.to_lowercase()) {
+            Some(mode) if mode == "aac-hbr" => (13, 3),
+            Some(mode) if mode == "aac-lbr" => (6, 2),
+            _ => (0, 0),
+        };
+        let length = |name: &str, default: u32| -> Result<u32> {
+            match fmtp.get(name) {
+                Some(value) => value.parse().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid mpeg4-generic {}: {}", name, value),
+                    )
+                }),
+                None => Ok(default),
+            }
+        };
+        let depacketizer = Self {
+            size_length: length("sizelength", size_length)?,
+            index_length: length("indexlength", index_length)?,
+            index_delta_length: length("indexdeltalength", index_length)?,
+            cts_delta_length: length("ctsdeltalength", 0)?,
+            dts_delta_length: length("dtsdeltalength", 0)?,
+            random_access_indication: length("randomaccessindication", 0)? != 0,
+            stream_state_length: length("streamstateindication", 0)?,
+            auxiliary_length: length("auxiliarydatasizelength", 0)?,
+            frame_length: match fmtp.get("config") {
+                Some(config) => length(
+                    "constantduration",
+                    AudioSpecificConfig::parse(config)?.frame_length,
+                )?,
+                None => length("constantduration", AAC_FRAME_LENGTH)?,
+            },
+            ..Self::default()
+        };
+        if depacketizer.size_length == 0 || depacketizer.size_length > 32 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "mpeg4-generic sizelength missing",
+            ));
+        }
+        Ok(depacketizer)
+    }
+
+    /// It pushes the next packet of the stream. An access unit larger than a packet is
+    /// fragmented over packets of the same timestamp, a lost fragment drops it
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an invalid AU header section drops the packet.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        if let Some(sequence_number) = self.sequence_number {
+            if packet.sequence_number != sequence_number.wrapping_add(1) {
+                self.fragment = None;
+            }
+        }
+        self.sequence_number = Some(packet.sequence_number);
+
+        // AU-headers-length(16) in bits | AU headers | padding to the byte
+        let payload = &packet.payload;
+        if payload.len() < 2 {
+            return Err(Error::other("mpeg4-generic payload truncated"));
+        }
+        let headers_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        let mut offset = 2 + headers_len.div_ceil(8);
+        if payload.len() < offset {
+            return Err(Error::other("mpeg4-generic AU headers exceed packet"));
+        }
+        let mut reader = BitReader::new(&payload[2..], headers_len);
+        let mut access_units = vec![];
+        let mut index = 0u32;
+        while !reader.is_empty() {
+            let size = reader.read(self.size_length)? as usize;
+            if access_units.is_empty() {
+                reader.read(self.index_length)?;
+            } else {
+                index += reader.read(self.index_delta_length)? + 1;
+            }
+            if self.cts_delta_length > 0 && !access_units.is_empty() && reader.read(1)? == 1 {
+                reader.read(self.cts_delta_length)?;
+            }
+            if self.dts_delta_length > 0 && reader.read(1)? == 1 {
+                reader.read(self.dts_delta_length)?;
+            }
+            reader.read(self.random_access_indication as u32 + self.stream_state_length)?;
+            access_units.push((index, size));
+        }
+
+        // auxiliary-data-size(n) in bits | auxiliary data | padding to the byte
+        if self.auxiliary_length > 0 {
+            let mut reader = BitReader::new(&payload[offset..], (payload.len() - offset) * 8);
+            let auxiliary_len = reader.read(self.auxiliary_length)? as usize;
+            offset += (self.auxiliary_length as usize + auxiliary_len).div_ceil(8);
+        }
+        let mut data = payload.slice(offset.min(payload.len())..);
+
+        match access_units[..] {
+            [(_, size)] if size > data.len() => {
+                self.add_fragment(packet.timestamp, size, data, packet.marker)
+            }
+            _ => {
+                self.fragment = None;
+                for (index, size) in access_units {
+                    if data.len() < size {
+                        return Err(Error::other("mpeg4-generic AU exceeds packet"));
+                    }
+                    self.ready.push_back(AudioFrame {
+                        timestamp: packet.timestamp.wrapping_add(index * self.frame_length),
+                        duration: self.frame_length,
+                        data: data.split_to(size),
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// It pulls the next frame completed
+    pub(crate) fn pull(&mut self) -> Option<AudioFrame> {
+        self.ready.pop_front()
+    }
+
+    /// The marker bit is only set on the last fragment of an AU (RFC 3640 section 3.2.3.1)
+    fn add_fragment(&mut self, timestamp: u32, size: usize, data: Bytes, last: bool) -> Result<()> {
+        let mut fragment = match self.fragment.take() {
+            Some(fragment) if fragment.0 == timestamp && fragment.1 == size => fragment,
+            // The fragments before are lost
+            _ if last => return Ok(()),
+            _ => (timestamp, size, BytesMut::with_capacity(size)),
+        };
+        fragment.2.put(data);
+        if fragment.2.len() > size {
+            return Err(Error::other("mpeg4-generic AU fragments exceed AU size"));
+        }
+        if last && fragment.2.len() < size {
+            return Err(Error::other("mpeg4-generic AU fragments short of AU size"));
+        }
+        if fragment.2.len() == size {
+            self.ready.push_back(AudioFrame {
+                timestamp,
+                duration: self.frame_length,
+                data: fragment.2.freeze(),
+            });
+        } else {
+            self.fragment = Some(fragment);
+        }
+        Ok(())
+    }
+}
+
+impl AudioSpecificConfig {
+    /// It parses the AudioSpecificConfig in hexadecimal
+    ///
+    /// Arguments:
+    ///
+    /// * `config`: &str, the `config` format parameter
+    ///
+    /// Returns:
+    ///
+    /// A Result<AudioSpecificConfig>.
+    pub(crate) fn parse(config: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid AudioSpecificConfig {}", config),
+            )
+        };
+        let data = (0..config.len())
+            .step_by(2)
+            .map(|index| {
+                config
+                    .get(index..index + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let mut reader = BitReader::new(&data, data.len() * 8);
+        let object_type = |reader: &mut BitReader| -> Result<u32> {
+            match reader.read(5)? {
+                31 => Ok(32 + reader.read(6)?),
+                object_type => Ok(object_type),
+            }
+        };
+        let frequency = |reader: &mut BitReader| -> Result<u8> {
+            let index = reader.read(4)?;
+            if index == 0xf {
+                reader.read(24)?;
+            }
+            Ok(index as u8)
+        };
+        let mut audio_object_type = object_type(&mut reader).map_err(|_| invalid())?;
+        let frequency_index = frequency(&mut reader).map_err(|_| invalid())?;
+        let channels = reader.read(4).map_err(|_| invalid())? as u16;
+        // SBR and PS signal the object type of the core after the extension frequency
+        if audio_object_type == 5 || audio_object_type == 29 {
+            frequency(&mut reader).map_err(|_| invalid())?;
+            audio_object_type = object_type(&mut reader).map_err(|_| invalid())?;
+        }
+        let frame_length = match audio_object_type {
+            1..=4 | 6 | 7 | 17 | 19..=23 if reader.read(1).map_err(|_| invalid())? == 1 => 960,
+            _ => AAC_FRAME_LENGTH,
+        };
+        Ok(Self {
+            data,
+            object_type: audio_object_type,
+            frequency_index,
+            channels,
+            frame_length,
+        })
+    }
+
+    /// The ADTS header of an access unit (ISO/IEC 13818-7 section 6.2), without CRC
+    ///
+    /// Arguments:
+    ///
+    /// * `length`: usize, the bytes of the access unit
+    ///
+    /// Returns:
+    ///
+    /// A Result of the header, ADTS only carries the AAC profiles and the indexed frequencies.
+    pub(crate) fn adts_header(&self, length: usize) -> Result<[u8; 7]> {
+        if !(1..=4).contains(&self.object_type) || self.frequency_index > 12 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "AudioSpecificConfig cannot be carried in ADTS",
+            ));
+        }
+        let frame_length = length + 7;
+        if frame_length >= 1 << 13 {
+            return Err(Error::other("AAC frame too large for ADTS"));
+        }
+        let profile = self.object_type as u8 - 1;
+        Ok([
+            0xff,
+            0xf1, // MPEG-4, layer 0, protection_absent
+            profile << 6 | self.frequency_index << 2 | (self.channels >> 2 & 1) as u8,
+            ((self.channels & 3) as u8) << 6 | (frame_length >> 11) as u8,
+            (frame_length >> 3) as u8,
+            (frame_length as u8 & 7) << 5 | 0x1f, // buffer fullness 0x7ff, variable rate
+            0xfc,
+        ])
+    }
+}
+
+impl<'a> BitReader<'a> {
+    /// A reader of the first bits of the data
+    fn new(data: &'a [u8], bits: usize) -> Self {
+        Self {
+            data,
+            position: 0,
+            end: bits.min(data.len() * 8),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.end
+    }
+
+    /// It reads a field of up to 32 bits
+    fn read(&mut self, bits: u32) -> Result<u32> {
+        if self.position + bits as usize > self.end {
+            return Err(Error::other("Bit field exceeds data"));
+        }
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let bit = self.data[self.position / 8] >> (7 - self.position % 8) & 1;
+            value = value << 1 | bit as u64;
+            self.position += 1;
+        }
+        Ok(value as u32)
+    }
+}
+
+#[cfg(test)]
+fn test_hbr_depacketizer() -> AacDepacketizer {
+    let fmtp = FormatParameters::from([
+        (String::from("streamtype"), String::from("5")),
+        (String::from("mode"), String::from("AAC-hbr")),
+        (String::from("config"), String::from("1210")),
+        (String::from("sizelength"), String::from("13")),
+        (String::from("indexlength"), String::from("3")),
+        (String::from("indexdeltalength"), String::from("3")),
+    ]);
+    AacDepacketizer::new(&fmtp).unwrap()
+}
+
+#[test]
+fn test_aac_access_units() {
+    let mut depacketizer = test_hbr_depacketizer();
+
+    // Two AUs of 3 and 2 bytes, the second one frame later
+    let payload = [0x00, 0x20, 0x00, 0x18, 0x00, 0x10, 1, 2, 3, 4, 5];
+    depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &payload))
+        .unwrap();
+    let frame = depacketizer.pull().unwrap();
+    assert_eq!((frame.timestamp, &frame.data[..]), (1000, &[1, 2, 3][..]));
+    assert_eq!(frame.duration, 1024);
+    let frame = depacketizer.pull().unwrap();
+    assert_eq!((frame.timestamp, &frame.data[..]), (2024, &[4, 5][..]));
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_aac_fragments() {
+    let mut depacketizer = test_hbr_depacketizer();
+
+    // An AU of 5 bytes in two fragments
+    let first = [0x00, 0x10, 0x00, 0x28, 1, 2, 3];
+    let last = [0x00, 0x10, 0x00, 0x28, 4, 5];
+    depacketizer
+        .push(&RtpPacket::test(2, 1000, false, &first))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&RtpPacket::test(3, 1000, true, &last))
+        .unwrap();
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[1, 2, 3, 4, 5]);
+
+    // The first fragment is lost, the last one does not start another AU
+    depacketizer
+        .push(&RtpPacket::test(5, 1000, true, &last))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(6, 1000, false, &first))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // Fragments larger or smaller than the AU
+    let long = [0x00, 0x10, 0x00, 0x28, 4, 5, 6];
+    assert!(depacketizer
+        .push(&RtpPacket::test(7, 1000, true, &long))
+        .is_err());
+    depacketizer
+        .push(&RtpPacket::test(8, 1000, false, &first))
+        .unwrap();
+    let short = [0x00, 0x10, 0x00, 0x28, 4];
+    assert!(depacketizer
+        .push(&RtpPacket::test(9, 1000, true, &short))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_aac_low_bit_rate() {
+    // AAC-lbr defaults to 6 bits of size and 2 of index
+    let fmtp = FormatParameters::from([(String::from("mode"), String::from("AAC-lbr"))]);
+    let mut depacketizer = AacDepacketizer::new(&fmtp).unwrap();
+    let payload = [0x00, 0x10, 0x04, 0x08, 1, 2, 3];
+    depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &payload))
+        .unwrap();
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[1]);
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[2, 3]);
+}
+
+#[test]
+fn test_aac_optional_fields() {
+    // The DTS-delta of the first AU and the RAP-flags are skipped
+    let fmtp = FormatParameters::from([
+        (String::from("sizelength"), String::from("13")),
+        (String::from("indexlength"), String::from("3")),
+        (String::from("indexdeltalength"), String::from("3")),
+        (String::from("dtsdeltalength"), String::from("4")),
+        (String::from("randomaccessindication"), String::from("1")),
+    ]);
+    let mut depacketizer = AacDepacketizer::new(&fmtp).unwrap();
+    let payload = [0x00, 0x28, 0x00, 0x08, 0xac, 0x00, 0x20, 7, 8];
+    depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &payload))
+        .unwrap();
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[7]);
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[8]);
+
+    // The auxiliary section is skipped
+    let fmtp = FormatParameters::from([
+        (String::from("mode"), String::from("AAC-hbr")),
+        (String::from("auxiliarydatasizelength"), String::from("8")),
+    ]);
+    let mut depacketizer = AacDepacketizer::new(&fmtp).unwrap();
+    let payload = [0x00, 0x10, 0x00, 0x10, 0x10, 0xaa, 0xbb, 1, 2];
+    depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &payload))
+        .unwrap();
+    assert_eq!(&depacketizer.pull().unwrap().data[..], &[1, 2]);
+}
+
+#[test]
+fn test_aac_invalid_payloads() {
+    let mut depacketizer = test_hbr_depacketizer();
+
+    // Cut short, AU headers beyond the packet or cut in the middle, AUs beyond the packet
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &[0x00]))
+        .is_err());
+    assert!(depacketizer
+        .push(&RtpPacket::test(2, 1000, true, &[0x00, 0x20, 0x00]))
+        .is_err());
+    assert!(depacketizer
+        .push(&RtpPacket::test(3, 1000, true, &[0x00, 0x0c, 0x00, 0x10]))
+        .is_err());
+    let payload = [0x00, 0x20, 0x00, 0x18, 0x00, 0x10, 1, 2, 3, 4];
+    assert!(depacketizer
+        .push(&RtpPacket::test(4, 1000, true, &payload))
+        .is_err());
+}
+
+#[test]
+fn test_aac_format_parameters() {
+    // The size length is required, only audio is supported
+    assert!(AacDepacketizer::new(&FormatParameters::new()).is_err());
+    let fmtp = FormatParameters::from([
+        (String::from("mode"), String::from("AAC-hbr")),
+        (String::from("streamtype"), String::from("4")),
+    ]);
+    let err = AacDepacketizer::new(&fmtp).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let fmtp = FormatParameters::from([(String::from("sizelength"), String::from("x"))]);
+    let err = AacDepacketizer::new(&fmtp).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let fmtp = FormatParameters::from([(String::from("sizelength"), String::from("33"))]);
+    assert!(AacDepacketizer::new(&fmtp).is_err());
+
+    // The frame length of the config is the duration of the AUs
+    let fmtp = FormatParameters::from([
+        (String::from("mode"), String::from("AAC-hbr")),
+        (String::from("config"), String::from("1214")),
+    ]);
+    let mut depacketizer = AacDepacketizer::new(&fmtp).unwrap();
+    let payload = [0x00, 0x20, 0x00, 0x08, 0x00, 0x08, 1, 2];
+    depacketizer
+        .push(&RtpPacket::test(1, 1000, true, &payload))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().duration, 960);
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 1960);
+}
+
+#[test]
+fn test_audio_specific_config() {
+    // 960 samples a frame, stereo
+    let config = AudioSpecificConfig::parse("1214").unwrap();
+    assert_eq!((config.frame_length, config.channels), (960, 2));
+    let config = AudioSpecificConfig::parse("1210").unwrap();
+    assert_eq!(
+        (config.object_type, config.frequency_index, config.channels),
+        (2, 4, 2)
+    );
+
+    // SBR signals the core AAC LC after the extension frequency
+    let config = AudioSpecificConfig::parse("2b1188").unwrap();
+    assert_eq!(
+        (config.object_type, config.frequency_index, config.channels),
+        (2, 6, 2)
+    );
+
+    // The escaped object types and an explicit frequency
+    assert_eq!(
+        AudioSpecificConfig::parse("f8e620").unwrap().object_type,
+        39
+    );
+    let config = AudioSpecificConfig::parse("17805dc008").unwrap();
+    assert_eq!((config.frequency_index, config.channels), (15, 1));
+
+    for config in ["", "12g0", "121", "f8"] {
+        let err = AudioSpecificConfig::parse(config).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_adts_header() {
+    let config = AudioSpecificConfig::parse("1210").unwrap();
+    assert_eq!(
+        config.adts_header(9).unwrap(),
+        [0xff, 0xf1, 0x50, 0x80, 0x02, 0x1f, 0xfc]
+    );
+    assert!(config.adts_header(8185).is_err());
+
+    // ADTS has no escaped object type nor explicit frequency
+    for config in ["f8e620", "17805dc008"] {
+        let err = AudioSpecificConfig::parse(config)
+            .unwrap()
+            .adts_header(9)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::rtp_depacketizer::AudioFrame;
+use crate::rtp_packet::RtpPacket;
+use crate::rtp_sender::G711;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// `SampleDepacketizer` takes the frames of the sample-based audio encodings (RFC 3551 section
+/// 4.3): G.711 and G.726, every packet is a frame of consecutive samples.
+///
+/// Properties:
+///
+/// * `bits_per_sample`: The bits of a sample, or of a G.726 code word.
+/// * `big_endian`: The code words are packed from the most significant bit (`AAL2-G726-*`),
+///   they are repacked from the least significant bit as in RFC 3551 section 4.5.4.
+/// * `ready`: The frames not pulled yet.
+#[derive(Debug)]
+pub(crate) struct SampleDepacketizer {
+    bits_per_sample: u32,
+    big_endian: bool,
+    ready: VecDeque<AudioFrame>,
+}
+
+impl SampleDepacketizer {
+    /// It creates the depacketizer of a sample-based encoding
+    ///
+    /// Arguments:
+    ///
+    /// * `encoding`: &str, the encoding name of the rtpmap: `PCMU`, `PCMA`, `G726-<bit rate>`
+    ///   or `AAL2-G726-<bit rate>`
+    ///
+    /// Returns:
+    ///
+    /// A Result of the depacketizer, `None` if the encoding is not sample-based.
+    pub(crate) fn new(encoding: &str) -> Result<Option<Self>> {
+        let encoding = encoding.to_ascii_uppercase();
+        if G711::from_encoding(&encoding).is_some() {
+            return Ok(Some(Self {
+                bits_per_sample: 8,
+                big_endian: false,
+                ready: VecDeque::new(),
+            }));
+        }
+        let (big_endian, bit_rate) = match encoding.split_once("G726-") {
+            Some(("", bit_rate)) => (false, bit_rate),
+            Some(("AAL2-", bit_rate)) => (true, bit_rate),
+            _ => return Ok(None),
+        };
+        // 8000 code words per second
+        let bits_per_sample = match bit_rate {
+            "16" | "24" | "32" | "40" => bit_rate.parse::<u32>().unwrap() / 8,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("G.726 bit rate {} not supported", bit_rate),
+                ))
+            }
+        };
+        Ok(Some(Self {
+            bits_per_sample,
+            big_endian,
+            ready: VecDeque::new(),
+        }))
+    }
+
+    /// It pushes the next packet of the stream
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, a packet without a whole sample is invalid.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        let bits = packet.payload.len() as u32 * 8;
+        if bits < self.bits_per_sample {
+            return Err(Error::other("Audio payload without samples"));
+        }
+        let data = match self.big_endian {
+            true => self.repack(&packet.payload),
+            false => packet.payload.clone(),
+        };
+        self.ready.push_back(AudioFrame {
+            timestamp: packet.timestamp,
+            duration: bits / self.bits_per_sample,
+            data,
+        });
+        Ok(())
+    }
+
+    /// It pulls the next frame
+    pub(crate) fn pull(&mut self) -> Option<AudioFrame> {
+        self.ready.pop_front()
+    }
+
+    /// The code words read from the most significant bit are written from the least significant
+    /// one
+    fn repack(&self, payload: &Bytes) -> Bytes {
+        let mask = (1u32 << self.bits_per_sample) - 1;
+        let mut repacked = BytesMut::with_capacity(payload.len());
+        let (mut input, mut input_bits) = (0u32, 0);
+        let (mut output, mut output_bits) = (0u32, 0);
+        for byte in payload.iter() {
+            input = input << 8 | *byte as u32;
+            input_bits += 8;
+            while input_bits >= self.bits_per_sample {
+                input_bits -= self.bits_per_sample;
+                output |= (input >> input_bits & mask) << output_bits;
+                output_bits += self.bits_per_sample;
+                while output_bits >= 8 {
+                    repacked.put_u8(output as u8);
+                    output >>= 8;
+                    output_bits -= 8;
+                }
+            }
+            input &= (1 << input_bits) - 1;
+        }
+        if output_bits > 0 {
+            repacked.put_u8(output as u8);
+        }
+        repacked.freeze()
+    }
+}
+
+#[test]
+fn test_sample_depacketizer_g711() {
+    // One byte a sample
+    let mut depacketizer = SampleDepacketizer::new("PCMU").unwrap().unwrap();
+    depacketizer
+        .push(&RtpPacket::test(0, 160, false, &[0xff; 160]))
+        .unwrap();
+    let frame = depacketizer.pull().unwrap();
+    assert_eq!((frame.timestamp, frame.duration), (160, 160));
+}
+
+#[test]
+fn test_sample_depacketizer_g726() {
+    // G.726-24, the code words 1 to 7 and 0 packed from the most significant bit
+    let mut depacketizer = SampleDepacketizer::new("AAL2-G726-24").unwrap().unwrap();
+    depacketizer
+        .push(&RtpPacket::test(
+            0,
+            0,
+            false,
+            &[0b0010_1001, 0b1100_1011, 0b1011_1000],
+        ))
+        .unwrap();
+    let frame = depacketizer.pull().unwrap();
+    assert_eq!(frame.duration, 8);
+    assert_eq!(&frame.data[..], &[0xd1, 0x58, 0x1f]);
+}
+
+#[test]
+fn test_sample_depacketizer_encodings() {
+    assert!(SampleDepacketizer::new("G726-48").is_err());
+    assert!(SampleDepacketizer::new("H264").unwrap().is_none());
+}
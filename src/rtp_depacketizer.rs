@@ -0,0 +1,179 @@
+use crate::rtp_aac::AacDepacketizer;
+use crate::rtp_audio::SampleDepacketizer;
+use crate::rtp_h264::H264Depacketizer;
+use crate::rtp_h265::H265Depacketizer;
+use crate::rtp_jitter::JitterPacket;
+use crate::rtp_jpeg::{JpegDepacketizer, JpegImage};
+use crate::rtp_metadata::{MetadataDepacketizer, MetadataFrame};
+use crate::rtp_packet::RtpPacket;
+use crate::rtsp_sdp::MediaDescription;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::io::Result;
+
+/// `AccessUnit` is the NAL units of a video picture, all sharing one RTP timestamp.
+///
+/// Properties:
+///
+/// * `timestamp`: The RTP timestamp of the picture.
+/// * `key_frame`: The picture can be decoded on its own, the parameter sets are included.
+/// * `nal_units`: The NAL units in decoding order, without start codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AccessUnit {
+    pub(crate) timestamp: u32,
+    pub(crate) key_frame: bool,
+    pub(crate) nal_units: Vec<Bytes>,
+}
+
+/// `AudioFrame` is an audio access unit: an AAC frame, or the G.711 or G.726 samples of a packet.
+///
+/// Properties:
+///
+/// * `timestamp`: The RTP timestamp of the first sample.
+/// * `duration`: The samples of the frame, in RTP clock ticks.
+/// * `data`: The encoded frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AudioFrame {
+    pub(crate) timestamp: u32,
+    pub(crate) duration: u32,
+    pub(crate) data: Bytes,
+}
+
+/// A frame put together from the packets of a track
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Frame {
+    /// An H.264 or H.265 picture
+    Video(AccessUnit),
+    /// A Motion JPEG picture
+    Jpeg(JpegImage),
+    /// An audio frame
+    Audio(AudioFrame),
+    /// An ONVIF metadata document
+    Metadata(MetadataFrame),
+}
+
+/// `FrameStats` counts the frames put together from the packets of a track.
+///
+/// Properties:
+///
+/// * `frames`: The frames completed.
+/// * `key_frames`: The frames which can be decoded on their own.
+/// * `lost`: The packets the jitter buffer gave up on.
+/// * `duration`: The media time between the first packet and the latest one.
+/// * `start`: The unwrapped timestamp of the first packet.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FrameStats {
+    pub(crate) frames: u64,
+    pub(crate) key_frames: u64,
+    pub(crate) lost: u64,
+    pub(crate) duration: Duration,
+    start: Option<u64>,
+}
+
+/// `Depacketizer` puts the frames of a track together from its RTP packets, by payload format.
+/// The packets are pushed in sequence number order, a gap is taken as a loss
+#[derive(Debug)]
+pub(crate) enum Depacketizer {
+    H264(H264Depacketizer),
+    H265(H265Depacketizer),
+    Jpeg(JpegDepacketizer),
+    Aac(AacDepacketizer),
+    Samples(SampleDepacketizer),
+    Metadata(MetadataDepacketizer),
+}
+
+impl Depacketizer {
+    /// It creates the depacketizer of the encoding given by the rtpmap of the media
+    ///
+    /// Arguments:
+    ///
+    /// * `media`: &MediaDescription, the media section of the track
+    ///
+    /// Returns:
+    ///
+    /// A Result of the depacketizer, `None` if the encoding is not supported.
+    pub(crate) fn for_media(media: &MediaDescription) -> Result<Option<Self>> {
+        let encoding = match media.rtpmap {
+            Some(ref rtpmap) => rtpmap.encoding.to_uppercase(),
+            None => return Ok(None),
+        };
+        match encoding.as_str() {
+            "H264" => Ok(Some(Self::H264(H264Depacketizer::new(&media.fmtp)?))),
+            "H265" => Ok(Some(Self::H265(H265Depacketizer::new(&media.fmtp)?))),
+            "JPEG" => Ok(Some(Self::Jpeg(JpegDepacketizer::default()))),
+            "MPEG4-GENERIC" => Ok(Some(Self::Aac(AacDepacketizer::new(&media.fmtp)?))),
+            "VND.ONVIF.METADATA" => Ok(Some(Self::Metadata(MetadataDepacketizer::default()))),
+            _ => Ok(SampleDepacketizer::new(&encoding)?.map(Self::Samples)),
+        }
+    }
+
+    /// It pushes the next packet of the track, the payload of an invalid packet is dropped with
+    /// the frame it belongs to
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        match self {
+            Self::H264(depacketizer) => depacketizer.push(packet),
+            Self::H265(depacketizer) => depacketizer.push(packet),
+            Self::Jpeg(depacketizer) => depacketizer.push(packet),
+            Self::Aac(depacketizer) => depacketizer.push(packet),
+            Self::Samples(depacketizer) => depacketizer.push(packet),
+            Self::Metadata(depacketizer) => depacketizer.push(packet),
+        }
+    }
+
+    /// It pulls the next frame completed
+    pub(crate) fn pull(&mut self) -> Option<Frame> {
+        match self {
+            Self::H264(depacketizer) => depacketizer.pull().map(Frame::Video),
+            Self::H265(depacketizer) => depacketizer.pull().map(Frame::Video),
+            Self::Jpeg(depacketizer) => depacketizer.pull().map(Frame::Jpeg),
+            Self::Aac(depacketizer) => depacketizer.pull().map(Frame::Audio),
+            Self::Samples(depacketizer) => depacketizer.pull().map(Frame::Audio),
+            Self::Metadata(depacketizer) => depacketizer.pull().map(Frame::Metadata),
+        }
+    }
+}
+
+impl Frame {
+    /// The RTP timestamp of the frame
+    pub(crate) fn timestamp(&self) -> u32 {
+        match self {
+            Self::Video(access_unit) => access_unit.timestamp,
+            Self::Jpeg(image) => image.timestamp,
+            Self::Audio(frame) => frame.timestamp,
+            Self::Metadata(metadata) => metadata.timestamp,
+        }
+    }
+
+    /// The frame can be decoded on its own
+    pub(crate) fn key_frame(&self) -> bool {
+        match self {
+            Self::Video(access_unit) => access_unit.key_frame,
+            Self::Jpeg(_) | Self::Audio(_) | Self::Metadata(_) => true,
+        }
+    }
+}
+
+impl FrameStats {
+    /// It counts a frame completed
+    pub(crate) fn count(&mut self, frame: &Frame) {
+        self.frames += 1;
+        if frame.key_frame() {
+            self.key_frames += 1;
+        }
+    }
+
+    /// It counts a packet released by the jitter buffer and the ones lost before it
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &JitterPacket, the packet released
+    /// * `clock_rate`: u32, the RTP clock rate of the media
+    pub(crate) fn release(&mut self, packet: &JitterPacket, clock_rate: u32) {
+        self.lost += packet.lost;
+        let start = *self.start.get_or_insert(packet.timestamp);
+        let ticks = packet.timestamp.saturating_sub(start);
+        self.duration = self
+            .duration
+            .max(Duration::from_secs_f64(ticks as f64 / clock_rate as f64));
+    }
+}
@@ -0,0 +1,396 @@
+use crate::rtp_depacketizer::AccessUnit;
+use crate::rtp_packet::RtpPacket;
+use crate::rtsp_sdp::FormatParameters;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// NAL unit types of H.264 (ITU-T H.264 table 7-1) and of its RTP payload format (RFC 6184
+/// section 5.4)
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+const NAL_STAP_A: u8 = 24;
+const NAL_FU_A: u8 = 28;
+
+/// `H264Depacketizer` puts the access units of an H.264 stream together from the single NAL
+/// unit, STAP-A and FU-A packets of the non-interleaved packetization modes (RFC 6184).
+///
+/// Properties:
+///
+/// * `sps`: The last sequence parameter set, from `sprop-parameter-sets` or in-band.
+/// * `pps`: The last picture parameter set, from `sprop-parameter-sets` or in-band.
+/// * `timestamp`: The RTP timestamp of the access unit being put together.
+/// * `nal_units`: The NAL units of the access unit being put together.
+/// * `fragment`: The NAL unit being put together from FU-A packets.
+/// * `sequence_number`: The sequence number of the last packet pushed.
+/// * `corrupt`: A packet of the access unit was lost or invalid, the access unit is dropped.
+/// * `ready`: The access units completed and not pulled yet.
+#[derive(Debug, Default)]
+pub(crate) struct H264Depacketizer {
+    sps: Option<Bytes>,
+    pps: Option<Bytes>,
+    timestamp: Option<u32>,
+    nal_units: Vec<Bytes>,
+    fragment: Option<BytesMut>,
+    sequence_number: Option<u16>,
+    corrupt: bool,
+    ready: VecDeque<AccessUnit>,
+}
+
+impl H264Depacketizer {
+    /// It creates a depacketizer with the parameter sets given out-of-band by the session
+    /// description
+    ///
+    /// Arguments:
+    ///
+    /// * `fmtp`: &FormatParameters, the `a=fmtp` parameters of the payload type
+    ///
+    /// Returns:
+    ///
+    /// A Result<H264Depacketizer>, the interleaved mode is not supported.
+    pub(crate) fn new(fmtp: &FormatParameters) -> Result<Self> {
+        if fmtp.get("packetization-mode").map(String::as_str) == Some("2") {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "H.264 interleaved packetization mode not supported",
+            ));
+        }
+        let mut depacketizer = Self::default();
+        let sprop = fmtp.get("sprop-parameter-sets").map_or("", String::as_str);
+        for parameter_set in sprop.split(',').filter(|set| !set.is_empty()) {
+            let nal_unit = STANDARD.decode(parameter_set).map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid sprop-parameter-sets {}: {}", sprop, err),
+                )
+            })?;
+            depacketizer.save_parameter_set(&Bytes::from(nal_unit));
+        }
+        Ok(depacketizer)
+    }
+
+    /// It pushes the next packet of the stream. The access unit ends with the marker bit, or
+    /// with the first packet of the next timestamp if the marker is lost
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an invalid payload drops the access unit.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        if let Some(sequence_number) = self.sequence_number {
+            if packet.sequence_number != sequence_number.wrapping_add(1) {
+                self.fragment = None;
+                self.corrupt = true;
+            }
+        }
+        self.sequence_number = Some(packet.sequence_number);
+        if self
+            .timestamp
+            .is_some_and(|timestamp| timestamp != packet.timestamp)
+        {
+            self.finish();
+        }
+        self.timestamp = Some(packet.timestamp);
+        let result = self.depacketize(packet.payload.clone());
+        if result.is_err() {
+            self.fragment = None;
+            self.corrupt = true;
+        }
+        if packet.marker {
+            self.finish();
+        }
+        result
+    }
+
+    /// It pulls the next access unit completed
+    pub(crate) fn pull(&mut self) -> Option<AccessUnit> {
+        self.ready.pop_front()
+    }
+
+    fn depacketize(&mut self, mut payload: Bytes) -> Result<()> {
+        let header = *payload
+            .first()
+            .ok_or_else(|| Error::other("Empty H.264 payload"))?;
+        match header & 0x1f {
+            1..=23 => self.add_nal_unit(payload),
+            NAL_STAP_A => {
+                // STAP-A: header(8) | { NAL unit size(16) | NAL unit }...
+                payload.advance(1);
+                while !payload.is_empty() {
+                    if payload.len() < 2 {
+                        return Err(Error::other("H.264 STAP-A truncated"));
+                    }
+                    let len = payload.get_u16() as usize;
+                    if payload.len() < len {
+                        return Err(Error::other("H.264 STAP-A NAL unit exceeds packet"));
+                    }
+                    self.add_nal_unit(payload.split_to(len));
+                }
+            }
+            NAL_FU_A => {
+                // FU-A: FU indicator F(1) NRI(2) type(5) | FU header S(1) E(1) R(1) type(5)
+                if payload.len() < 2 {
+                    return Err(Error::other("H.264 FU-A truncated"));
+                }
+                let fu_header = payload[1];
+                let fragment = payload.slice(2..);
+                if fu_header & 0x80 != 0 {
+                    // The end of the previous fragmented NAL unit is lost
+                    if self.fragment.is_some() {
+                        self.corrupt = true;
+                    }
+                    let mut nal_unit = BytesMut::with_capacity(1 + fragment.len());
+                    nal_unit.put_u8(header & 0xe0 | fu_header & 0x1f);
+                    nal_unit.put(fragment);
+                    self.fragment = Some(nal_unit);
+                } else {
+                    match self.fragment {
+                        Some(ref mut nal_unit) => nal_unit.put(fragment),
+                        // The start is lost, the loss is already known
+                        None if self.corrupt => return Ok(()),
+                        None => return Err(Error::other("H.264 FU-A without start fragment")),
+                    }
+                }
+                if fu_header & 0x40 != 0 {
+                    if let Some(nal_unit) = self.fragment.take() {
+                        self.add_nal_unit(nal_unit.freeze());
+                    }
+                }
+            }
+            kind => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("H.264 NAL unit type {} not supported", kind),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn add_nal_unit(&mut self, nal_unit: Bytes) {
+        if nal_unit.is_empty() {
+            return;
+        }
+        self.save_parameter_set(&nal_unit);
+        self.nal_units.push(nal_unit);
+    }
+
+    /// The parameter sets are kept for the key frames sent without them
+    fn save_parameter_set(&mut self, nal_unit: &Bytes) {
+        match nal_unit.first().map(|header| header & 0x1f) {
+            Some(NAL_SPS) => self.sps = Some(nal_unit.clone()),
+            Some(NAL_PPS) => self.pps = Some(nal_unit.clone()),
+            _ => {}
+        }
+    }
+
+    /// It completes the access unit, an IDR picture is a key frame and gets the parameter sets
+    /// after the access unit delimiter if it was sent without them
+    fn finish(&mut self) {
+        let mut nal_units = std::mem::take(&mut self.nal_units);
+        let corrupt = self.corrupt || self.fragment.is_some();
+        self.fragment = None;
+        self.corrupt = false;
+        let timestamp = match self.timestamp.take() {
+            Some(timestamp) if !corrupt && !nal_units.is_empty() => timestamp,
+            _ => return,
+        };
+        let kinds = nal_units
+            .iter()
+            .map(|nal_unit| nal_unit[0] & 0x1f)
+            .collect::<Vec<_>>();
+        let key_frame = kinds.contains(&NAL_IDR);
+        if key_frame && !kinds.contains(&NAL_SPS) {
+            if let (Some(sps), Some(pps)) = (&self.sps, &self.pps) {
+                let position = (kinds[0] == NAL_AUD) as usize;
+                nal_units.splice(position..position, [sps.clone(), pps.clone()]);
+            }
+        }
+        self.ready.push_back(AccessUnit {
+            timestamp,
+            key_frame,
+            nal_units,
+        });
+    }
+}
+
+#[test]
+fn test_h264_fu_a_with_sprop_parameter_sets() {
+    let fmtp = FormatParameters::from([(
+        String::from("sprop-parameter-sets"),
+        String::from("Z0IAKeKQFAe2AtwEBAaQeJEV,aM48gA=="),
+    )]);
+    let mut depacketizer = H264Depacketizer::new(&fmtp).unwrap();
+
+    // IDR picture in FU-A fragments, the parameter sets come from the SDP
+    depacketizer
+        .push(&RtpPacket::test(1, 3000, false, &[0x7c, 0x85, 1, 2]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(2, 3000, false, &[0x7c, 0x05, 3]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, true, &[0x7c, 0x45, 4]))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert!(access_unit.key_frame);
+    assert_eq!(access_unit.timestamp, 3000);
+    assert_eq!(access_unit.nal_units.len(), 3);
+    assert_eq!(access_unit.nal_units[0][0], 0x67);
+    assert_eq!(&access_unit.nal_units[1][..], &[0x68, 0xce, 0x3c, 0x80]);
+    assert_eq!(&access_unit.nal_units[2][..], &[0x65, 1, 2, 3, 4]);
+}
+
+#[test]
+fn test_h264_in_band_parameter_sets() {
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // The parameter sets of a key frame are kept for the next one sent without them
+    let stap_a = [
+        0x18, 0x00, 0x02, 0x09, 0xf0, 0x00, 0x02, 0x67, 0x42, 0x00, 0x02, 0x68, 0xce, 0x00, 0x02,
+        0x65, 1,
+    ];
+    depacketizer
+        .push(&RtpPacket::test(1, 0, true, &stap_a))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().nal_units.len(), 4);
+    depacketizer
+        .push(&RtpPacket::test(2, 3000, false, &[0x09, 0xf0]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, true, &[0x65, 2]))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert_eq!(
+        access_unit.nal_units,
+        vec![
+            Bytes::from_static(&[0x09, 0xf0]),
+            Bytes::from_static(&[0x67, 0x42]),
+            Bytes::from_static(&[0x68, 0xce]),
+            Bytes::from_static(&[0x65, 2]),
+        ]
+    );
+}
+
+#[test]
+fn test_h264_stap_a_lost_marker() {
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // STAP-A with an access unit delimiter and a slice, the next timestamp ends it
+    let stap_a = [0x18, 0x00, 0x02, 0x09, 0xf0, 0x00, 0x02, 0x41, 0x9a];
+    depacketizer
+        .push(&RtpPacket::test(4, 6000, false, &stap_a))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(5, 9000, true, &[0x41, 0x9b]))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert!(!access_unit.key_frame);
+    assert_eq!(access_unit.timestamp, 6000);
+    assert_eq!(
+        access_unit.nal_units,
+        vec![
+            Bytes::from_static(&[0x09, 0xf0]),
+            Bytes::from_static(&[0x41, 0x9a])
+        ]
+    );
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 9000);
+}
+
+#[test]
+fn test_h264_stap_a_truncated() {
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // A size cut short, then a NAL unit larger than the packet, each drops its access unit
+    let cut = [0x18, 0x00, 0x02, 0x41, 0x9a, 0x00];
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 0, true, &cut))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+    let overrun = [0x18, 0x00, 0x02, 0x41, 0x9a, 0x00, 0x05, 0x41];
+    assert!(depacketizer
+        .push(&RtpPacket::test(2, 3000, true, &overrun))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+
+    depacketizer
+        .push(&RtpPacket::test(3, 6000, true, &[0x41, 0x9b]))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 6000);
+}
+
+#[test]
+fn test_h264_fu_a_without_start() {
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // Without a loss the missing start fragment is an error, the rest of it is dropped quietly
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 0, false, &[0x7c, 0x05, 1]))
+        .is_err());
+    depacketizer
+        .push(&RtpPacket::test(2, 0, true, &[0x7c, 0x45, 2]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // A start fragment whose end is lost is dropped with its access unit
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, false, &[0x7c, 0x85, 1]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(4, 3000, false, &[0x7c, 0x85, 2]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(5, 3000, true, &[0x7c, 0x45, 3]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    assert!(depacketizer
+        .push(&RtpPacket::test(6, 6000, true, &[0x7c]))
+        .is_err());
+}
+
+#[test]
+fn test_h264_lost_fragment() {
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // A lost fragment drops the access unit, the next one is complete again
+    depacketizer
+        .push(&RtpPacket::test(6, 12000, false, &[0x7c, 0x81, 1]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(8, 12000, true, &[0x7c, 0x41, 3]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&RtpPacket::test(9, 15000, true, &[0x41, 0x9c]))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 15000);
+}
+
+#[test]
+fn test_h264_unsupported() {
+    // The interleaved mode and its types are refused
+    let fmtp = FormatParameters::from([(String::from("packetization-mode"), String::from("2"))]);
+    let err = H264Depacketizer::new(&fmtp).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let mut depacketizer = H264Depacketizer::new(&FormatParameters::new()).unwrap();
+    let err = depacketizer
+        .push(&RtpPacket::test(10, 18000, true, &[0x19, 0, 0]))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(depacketizer
+        .push(&RtpPacket::test(11, 21000, true, &[]))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+
+    let fmtp =
+        FormatParameters::from([(String::from("sprop-parameter-sets"), String::from("Z0IA!"))]);
+    let err = H264Depacketizer::new(&fmtp).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,477 @@
+use crate::rtp_depacketizer::AccessUnit;
+use crate::rtp_packet::RtpPacket;
+use crate::rtsp_sdp::FormatParameters;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::VecDeque;
+use tokio::io::{Error, ErrorKind, Result};
+
+/// NAL unit types of H.265 (ITU-T H.265 table 7-1) and of its RTP payload format (RFC 7798
+/// section 4.4)
+const NAL_IRAP: std::ops::RangeInclusive<u8> = 16..=23;
+const NAL_VPS: u8 = 32;
+const NAL_SPS: u8 = 33;
+const NAL_PPS: u8 = 34;
+const NAL_AUD: u8 = 35;
+const NAL_AP: u8 = 48;
+const NAL_FU: u8 = 49;
+
+/// `H265Depacketizer` puts the access units of an H.265 stream together from the single NAL
+/// unit, aggregation (AP) and fragmentation unit (FU) packets (RFC 7798).
+///
+/// Properties:
+///
+/// * `vps`: The last video parameter set, from `sprop-vps` or in-band.
+/// * `sps`: The last sequence parameter set, from `sprop-sps` or in-band.
+/// * `pps`: The last picture parameter set, from `sprop-pps` or in-band.
+/// * `donl`: The packets carry the decoding order numbers of their NAL units, as the stream may
+///   be sent out of decoding order.
+/// * `timestamp`: The RTP timestamp of the access unit being put together.
+/// * `nal_units`: The NAL units of the access unit being put together, with their decoding order
+///   number.
+/// * `fragment`: The NAL unit being put together from FU packets, with its decoding order number.
+/// * `sequence_number`: The sequence number of the last packet pushed.
+/// * `corrupt`: A packet of the access unit was lost or invalid, the access unit is dropped.
+/// * `ready`: The access units completed and not pulled yet.
+#[derive(Debug, Default)]
+pub(crate) struct H265Depacketizer {
+    vps: Option<Bytes>,
+    sps: Option<Bytes>,
+    pps: Option<Bytes>,
+    donl: bool,
+    timestamp: Option<u32>,
+    nal_units: Vec<(u16, Bytes)>,
+    fragment: Option<(u16, BytesMut)>,
+    sequence_number: Option<u16>,
+    corrupt: bool,
+    ready: VecDeque<AccessUnit>,
+}
+
+impl H265Depacketizer {
+    /// It creates a depacketizer with the parameter sets given out-of-band by the session
+    /// description. The decoding order numbers are sent when the server may reorder the NAL
+    /// units (RFC 7798 section 7.1)
+    ///
+    /// Arguments:
+    ///
+    /// * `fmtp`: &FormatParameters, the `a=fmtp` parameters of the payload type
+    ///
+    /// Returns:
+    ///
+    /// A Result<H265Depacketizer>.
+    pub(crate) fn new(fmtp: &FormatParameters) -> Result<Self> {
+        let positive = |name: &str| {
+            fmtp.get(name)
+                .and_then(|value| value.parse::<u32>().ok())
+                .is_some_and(|value| value > 0)
+        };
+        let mut depacketizer = Self {
+            donl: positive("sprop-max-don-diff") || positive("sprop-depack-buf-nalus"),
+            ..Self::default()
+        };
+        for name in ["sprop-vps", "sprop-sps", "sprop-pps"] {
+            let sprop = fmtp.get(name).map_or("", String::as_str);
+            for parameter_set in sprop.split(',').filter(|set| !set.is_empty()) {
+                let nal_unit = STANDARD.decode(parameter_set).map_err(|err| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid {} {}: {}", name, sprop, err),
+                    )
+                })?;
+                depacketizer.save_parameter_set(&Bytes::from(nal_unit));
+            }
+        }
+        Ok(depacketizer)
+    }
+
+    /// It pushes the next packet of the stream. The access unit ends with the marker bit, or
+    /// with the first packet of the next timestamp if the marker is lost
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an invalid payload drops the access unit.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        if let Some(sequence_number) = self.sequence_number {
+            if packet.sequence_number != sequence_number.wrapping_add(1) {
+                self.fragment = None;
+                self.corrupt = true;
+            }
+        }
+        self.sequence_number = Some(packet.sequence_number);
+        if self
+            .timestamp
+            .is_some_and(|timestamp| timestamp != packet.timestamp)
+        {
+            self.finish();
+        }
+        self.timestamp = Some(packet.timestamp);
+        let result = self.depacketize(packet.payload.clone());
+        if result.is_err() {
+            self.fragment = None;
+            self.corrupt = true;
+        }
+        if packet.marker {
+            self.finish();
+        }
+        result
+    }
+
+    /// It pulls the next access unit completed
+    pub(crate) fn pull(&mut self) -> Option<AccessUnit> {
+        self.ready.pop_front()
+    }
+
+    fn depacketize(&mut self, mut payload: Bytes) -> Result<()> {
+        // Payload header: F(1) type(6) layer id(6) TID(3)
+        if payload.len() < 2 {
+            return Err(Error::other("H.265 payload truncated"));
+        }
+        let header = [payload[0], payload[1]];
+        match header[0] >> 1 & 0x3f {
+            0..=47 if self.donl => {
+                // Single NAL unit: header(16) | DONL(16) | payload
+                let mut rest = payload.slice(2..);
+                let don = self.take_don(&mut rest)?;
+                let mut nal_unit = BytesMut::with_capacity(2 + rest.len());
+                nal_unit.put(&header[..]);
+                nal_unit.put(rest);
+                self.add_nal_unit(don, nal_unit.freeze());
+            }
+            0..=47 => self.add_nal_unit(0, payload),
+            NAL_AP => {
+                // AP: header(16) | [DONL(16)] size(16) NAL unit | { [DOND(8)] size(16) NAL unit }...
+                payload.advance(2);
+                let mut don = 0u16;
+                let mut first = true;
+                while !payload.is_empty() {
+                    if self.donl {
+                        don = match first {
+                            true => self.take_don(&mut payload)?,
+                            false => don.wrapping_add(payload.get_u8() as u16 + 1),
+                        };
+                    }
+                    first = false;
+                    if payload.len() < 2 {
+                        return Err(Error::other("H.265 AP truncated"));
+                    }
+                    let len = payload.get_u16() as usize;
+                    if payload.len() < len {
+                        return Err(Error::other("H.265 AP NAL unit exceeds packet"));
+                    }
+                    self.add_nal_unit(don, payload.split_to(len));
+                }
+            }
+            NAL_FU => {
+                // FU: header(16) | FU header S(1) E(1) type(6) | [DONL(16)] | fragment
+                if payload.len() < 3 {
+                    return Err(Error::other("H.265 FU truncated"));
+                }
+                let fu_header = payload[2];
+                let mut fragment = payload.slice(3..);
+                if fu_header & 0x80 != 0 {
+                    // The end of the previous fragmented NAL unit is lost
+                    if self.fragment.is_some() {
+                        self.corrupt = true;
+                    }
+                    let don = self.take_don(&mut fragment)?;
+                    let mut nal_unit = BytesMut::with_capacity(2 + fragment.len());
+                    nal_unit.put_u8(header[0] & 0x81 | (fu_header & 0x3f) << 1);
+                    nal_unit.put_u8(header[1]);
+                    nal_unit.put(fragment);
+                    self.fragment = Some((don, nal_unit));
+                } else {
+                    match self.fragment {
+                        Some((_, ref mut nal_unit)) => nal_unit.put(fragment),
+                        // The start is lost, the loss is already known
+                        None if self.corrupt => return Ok(()),
+                        None => return Err(Error::other("H.265 FU without start fragment")),
+                    }
+                }
+                if fu_header & 0x40 != 0 {
+                    if let Some((don, nal_unit)) = self.fragment.take() {
+                        self.add_nal_unit(don, nal_unit.freeze());
+                    }
+                }
+            }
+            kind => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("H.265 NAL unit type {} not supported", kind),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// It takes the DONL field the payload starts with, if the stream carries it
+    fn take_don(&self, payload: &mut Bytes) -> Result<u16> {
+        if !self.donl {
+            return Ok(0);
+        }
+        if payload.len() < 2 {
+            return Err(Error::other("H.265 DONL truncated"));
+        }
+        Ok(payload.get_u16())
+    }
+
+    fn add_nal_unit(&mut self, don: u16, nal_unit: Bytes) {
+        if nal_unit.len() < 2 {
+            return;
+        }
+        self.save_parameter_set(&nal_unit);
+        self.nal_units.push((don, nal_unit));
+    }
+
+    /// The parameter sets are kept for the key frames sent without them
+    fn save_parameter_set(&mut self, nal_unit: &Bytes) {
+        match nal_unit.first().map(|header| header >> 1 & 0x3f) {
+            Some(NAL_VPS) => self.vps = Some(nal_unit.clone()),
+            Some(NAL_SPS) => self.sps = Some(nal_unit.clone()),
+            Some(NAL_PPS) => self.pps = Some(nal_unit.clone()),
+            _ => {}
+        }
+    }
+
+    /// It completes the access unit in decoding order, an IRAP picture is a key frame and gets
+    /// the parameter sets after the access unit delimiter if it was sent without them
+    fn finish(&mut self) {
+        let mut nal_units = std::mem::take(&mut self.nal_units);
+        let corrupt = self.corrupt || self.fragment.is_some();
+        self.fragment = None;
+        self.corrupt = false;
+        let timestamp = match self.timestamp.take() {
+            Some(timestamp) if !corrupt && !nal_units.is_empty() => timestamp,
+            _ => return,
+        };
+        if self.donl {
+            let first = nal_units[0].0;
+            nal_units.sort_by_key(|(don, _)| don.wrapping_sub(first) as i16);
+        }
+        let mut nal_units = nal_units
+            .into_iter()
+            .map(|(_, nal_unit)| nal_unit)
+            .collect::<Vec<_>>();
+        let kinds = nal_units
+            .iter()
+            .map(|nal_unit| nal_unit[0] >> 1 & 0x3f)
+            .collect::<Vec<_>>();
+        let key_frame = kinds.iter().any(|kind| NAL_IRAP.contains(kind));
+        if key_frame && !kinds.contains(&NAL_SPS) {
+            if let (Some(vps), Some(sps), Some(pps)) = (&self.vps, &self.sps, &self.pps) {
+                let position = (kinds[0] == NAL_AUD) as usize;
+                nal_units.splice(position..position, [vps.clone(), sps.clone(), pps.clone()]);
+            }
+        }
+        self.ready.push_back(AccessUnit {
+            timestamp,
+            key_frame,
+            nal_units,
+        });
+    }
+}
+
+#[test]
+fn test_h265_fu_with_sprop_parameter_sets() {
+    let fmtp = FormatParameters::from([
+        (
+            String::from("sprop-vps"),
+            STANDARD.encode([0x40, 0x01, 0x0c]),
+        ),
+        (
+            String::from("sprop-sps"),
+            STANDARD.encode([0x42, 0x01, 0x01]),
+        ),
+        (
+            String::from("sprop-pps"),
+            STANDARD.encode([0x44, 0x01, 0xc1]),
+        ),
+    ]);
+    let mut depacketizer = H265Depacketizer::new(&fmtp).unwrap();
+
+    // IDR_W_RADL (19) in FU fragments, the parameter sets come from the SDP
+    depacketizer
+        .push(&RtpPacket::test(1, 3000, false, &[0x62, 0x01, 0x93, 1, 2]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(2, 3000, false, &[0x62, 0x01, 0x13, 3]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, true, &[0x62, 0x01, 0x53, 4]))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert!(access_unit.key_frame);
+    assert_eq!(access_unit.nal_units.len(), 4);
+    assert_eq!(&access_unit.nal_units[0][..], &[0x40, 0x01, 0x0c]);
+    assert_eq!(&access_unit.nal_units[1][..], &[0x42, 0x01, 0x01]);
+    assert_eq!(&access_unit.nal_units[2][..], &[0x44, 0x01, 0xc1]);
+    assert_eq!(&access_unit.nal_units[3][..], &[0x26, 0x01, 1, 2, 3, 4]);
+}
+
+#[test]
+fn test_h265_ap() {
+    let mut depacketizer = H265Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // AP with an access unit delimiter and a trailing picture (1)
+    let ap = [
+        0x60, 0x01, 0x00, 0x03, 0x46, 0x01, 0x50, 0x00, 0x03, 0x02, 0x01, 0xd0,
+    ];
+    depacketizer
+        .push(&RtpPacket::test(3, 6000, true, &ap))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert!(!access_unit.key_frame);
+    assert_eq!(access_unit.timestamp, 6000);
+    assert_eq!(
+        access_unit.nal_units,
+        vec![
+            Bytes::from_static(&[0x46, 0x01, 0x50]),
+            Bytes::from_static(&[0x02, 0x01, 0xd0])
+        ]
+    );
+}
+
+#[test]
+fn test_h265_ap_truncated() {
+    let mut depacketizer = H265Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // A size cut short, then a NAL unit larger than the packet, each drops its access unit
+    let cut = [0x60, 0x01, 0x00, 0x03, 0x02, 0x01, 0xd0, 0x00];
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 0, true, &cut))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+    let overrun = [0x60, 0x01, 0x00, 0x08, 0x02, 0x01, 0xd0];
+    assert!(depacketizer
+        .push(&RtpPacket::test(2, 3000, true, &overrun))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+
+    depacketizer
+        .push(&RtpPacket::test(3, 6000, true, &[0x02, 0x01, 0xd0]))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 6000);
+}
+
+#[test]
+fn test_h265_fu_without_start() {
+    let mut depacketizer = H265Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // Without a loss the missing start fragment is an error, the rest of it is dropped quietly
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 0, false, &[0x62, 0x01, 0x01, 1]))
+        .is_err());
+    depacketizer
+        .push(&RtpPacket::test(2, 0, true, &[0x62, 0x01, 0x41, 2]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // A start fragment whose end is lost is dropped with its access unit
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, false, &[0x62, 0x01, 0x81, 1]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(4, 3000, false, &[0x62, 0x01, 0x81, 2]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(5, 3000, true, &[0x62, 0x01, 0x41, 3]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    assert!(depacketizer
+        .push(&RtpPacket::test(6, 6000, true, &[0x62, 0x01]))
+        .is_err());
+}
+
+#[test]
+fn test_h265_lost_fragment() {
+    let mut depacketizer = H265Depacketizer::new(&FormatParameters::new()).unwrap();
+
+    // A lost fragment drops the access unit, the next one is complete again
+    depacketizer
+        .push(&RtpPacket::test(4, 9000, false, &[0x62, 0x01, 0x81, 1]))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(6, 9000, true, &[0x62, 0x01, 0x41, 3]))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&RtpPacket::test(7, 12000, true, &[0x02, 0x01, 0xd0]))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 12000);
+}
+
+#[test]
+fn test_h265_decoding_order() {
+    // With DONL the NAL units are put back into decoding order
+    let fmtp = FormatParameters::from([(String::from("sprop-max-don-diff"), String::from("2"))]);
+    let mut depacketizer = H265Depacketizer::new(&fmtp).unwrap();
+    depacketizer
+        .push(&RtpPacket::test(
+            1,
+            3000,
+            false,
+            &[0x02, 0x01, 0x00, 0x06, 0xaa],
+        ))
+        .unwrap();
+    let ap = [
+        0x60, 0x01, 0x00, 0x04, 0x00, 0x03, 0x02, 0x01, 0xbb, 0x00, 0x00, 0x03, 0x02, 0x01, 0xcc,
+    ];
+    depacketizer
+        .push(&RtpPacket::test(2, 3000, true, &ap))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    let payloads = access_unit.nal_units.iter().map(|nal_unit| nal_unit[2]);
+    assert_eq!(payloads.collect::<Vec<_>>(), vec![0xbb, 0xcc, 0xaa]);
+
+    // The DONL of a FU is in its start fragment, the order wraps around
+    depacketizer
+        .push(&RtpPacket::test(
+            3,
+            6000,
+            false,
+            &[0x02, 0x01, 0x00, 0x01, 0xaa],
+        ))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(
+            4,
+            6000,
+            false,
+            &[0x62, 0x01, 0x81, 0xff, 0xff, 0xbb],
+        ))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(5, 6000, true, &[0x62, 0x01, 0x41, 0xcc]))
+        .unwrap();
+    let access_unit = depacketizer.pull().unwrap();
+    assert_eq!(
+        access_unit.nal_units,
+        vec![
+            Bytes::from_static(&[0x02, 0x01, 0xbb, 0xcc]),
+            Bytes::from_static(&[0x02, 0x01, 0xaa])
+        ]
+    );
+
+    // A start fragment too short for its DONL
+    assert!(depacketizer
+        .push(&RtpPacket::test(6, 9000, true, &[0x62, 0x01, 0x81, 0x00]))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_h265_unsupported() {
+    // PACI packets are refused, as are payloads without header
+    let mut depacketizer = H265Depacketizer::new(&FormatParameters::new()).unwrap();
+    let err = depacketizer
+        .push(&RtpPacket::test(1, 0, true, &[0x64, 0x01, 0, 0]))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(depacketizer
+        .push(&RtpPacket::test(2, 3000, true, &[0x02]))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+}
@@ -0,0 +1,342 @@
+use crate::rtp_packet::RtpPacket;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// The time a packet waits for the packets before it, unless another latency is given
+pub(crate) const DEFAULT_LATENCY: Duration = Duration::from_millis(200);
+
+/// A sequence number this far after the next one expected restarts the source (RFC 3550
+/// appendix A.1)
+const MAX_DROPOUT: u64 = 3000;
+
+/// A sequence number this far before the next one expected restarts the source, a packet less
+/// late is dropped
+const MAX_MISORDER: u64 = 100;
+
+/// The packets kept for a source, the oldest one is released early when there are more
+const MAX_PACKETS: usize = 1000;
+
+/// `JitterPacket` is a packet released by the jitter buffer, in sequence number order.
+///
+/// Properties:
+///
+/// * `packet`: The RTP packet.
+/// * `timestamp`: The RTP timestamp unwrapped to 64 bits, it starts in the second cycle so the
+///   timestamps before the first one stay positive.
+/// * `lost`: The packets of the source given up on right before this one.
+#[derive(Debug)]
+pub(crate) struct JitterPacket {
+    pub(crate) packet: RtpPacket,
+    pub(crate) timestamp: u64,
+    pub(crate) lost: u64,
+}
+
+/// `JitterBuffer` puts the packets of each source back in sequence number order. A packet waits
+/// for the missing packets before it up to the latency, then they are declared lost.
+///
+/// Properties:
+///
+/// * `latency`: The time a packet waits for the packets before it.
+/// * `sources`: The packets waiting, by SSRC.
+#[derive(Debug)]
+pub(crate) struct JitterBuffer {
+    latency: Duration,
+    sources: HashMap<u32, SourceBuffer>,
+}
+
+/// `SourceBuffer` holds the packets of a source.
+///
+/// Properties:
+///
+/// * `next`: The extended sequence number of the next packet to release, `None` until the
+///   first one is released.
+/// * `packets`: The packets waiting and their arrival, by extended sequence number.
+/// * `timestamp`: The unwrapped timestamp of the last packet released.
+/// * `ready`: The packets released by a restart of the source and not pulled yet.
+#[derive(Debug, Default)]
+struct SourceBuffer {
+    next: Option<u64>,
+    packets: BTreeMap<u64, (Instant, RtpPacket)>,
+    timestamp: Option<u64>,
+    ready: VecDeque<JitterPacket>,
+}
+
+impl JitterBuffer {
+    pub(crate) fn new(latency: Duration) -> Self {
+        Self {
+            latency,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// It pushes a packet received
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: RtpPacket, the packet
+    /// * `arrival`: Instant, the time the packet was received
+    ///
+    /// Returns:
+    ///
+    /// A bool, false if the packet is a duplicate or came after its turn and is dropped.
+    pub(crate) fn push(&mut self, packet: RtpPacket, arrival: Instant) -> bool {
+        let source = self.sources.entry(packet.ssrc).or_default();
+        let expected = source
+            .next
+            .or_else(|| source.packets.keys().next().copied());
+        let mut sequence_number = match expected {
+            Some(expected) => extend(expected, packet.sequence_number),
+            None => 1 << 16 | packet.sequence_number as u64,
+        };
+        if let Some(expected) = expected {
+            if sequence_number + MAX_MISORDER < expected || sequence_number > expected + MAX_DROPOUT
+            {
+                source.restart();
+                sequence_number = 1 << 16 | packet.sequence_number as u64;
+            } else if source.next.is_some_and(|next| sequence_number < next)
+                || source.packets.contains_key(&sequence_number)
+            {
+                return false;
+            }
+        }
+        source.packets.insert(sequence_number, (arrival, packet));
+        true
+    }
+
+    /// It pulls the next packet released, the packets of the sources are not ordered between
+    /// them
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: Instant, the current time
+    ///
+    /// Returns:
+    ///
+    /// The packet, `None` if the packets left are still waiting for the ones before them.
+    pub(crate) fn pull(&mut self, now: Instant) -> Option<JitterPacket> {
+        let latency = self.latency;
+        self.sources
+            .values_mut()
+            .find_map(|source| source.pull(now, latency))
+    }
+
+    /// The time the first packet waiting is released, `None` if there is none
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.sources
+            .values()
+            .filter_map(|source| source.packets.values().next())
+            .map(|(arrival, _)| *arrival + self.latency)
+            .min()
+    }
+}
+
+impl SourceBuffer {
+    fn pull(&mut self, now: Instant, latency: Duration) -> Option<JitterPacket> {
+        if let Some(packet) = self.ready.pop_front() {
+            return Some(packet);
+        }
+        let (&first, (arrival, _)) = self.packets.iter().next()?;
+        let expired = now >= *arrival + latency || self.packets.len() > MAX_PACKETS;
+        let lost = match self.next {
+            Some(next) if next == first => 0,
+            Some(next) if expired => first - next,
+            // The first packet waits in case the ones before it were reordered
+            None if expired => 0,
+            _ => return None,
+        };
+        Some(self.release(first, lost))
+    }
+
+    /// The packets waiting are released as they are, the next packet starts the sequence again
+    fn restart(&mut self) {
+        while let Some(&first) = self.packets.keys().next() {
+            let lost = self.next.map_or(0, |next| first - next);
+            let packet = self.release(first, lost);
+            self.ready.push_back(packet);
+        }
+        self.next = None;
+    }
+
+    /// The timestamp is unwrapped from the last one released, timestamps may go back a little
+    /// with the B-frames
+    fn release(&mut self, sequence_number: u64, lost: u64) -> JitterPacket {
+        let (_, packet) = self.packets.remove(&sequence_number).unwrap();
+        self.next = Some(sequence_number + 1);
+        let timestamp = match self.timestamp {
+            Some(last) => extend_timestamp(last, packet.timestamp),
+            None => 1 << 32 | packet.timestamp as u64,
+        };
+        self.timestamp = Some(timestamp);
+        JitterPacket {
+            packet,
+            timestamp,
+            lost,
+        }
+    }
+}
+
+/// The unwrapped timestamp closest to an unwrapped one, e.g. the one of the last packet released
+pub(crate) fn extend_timestamp(reference: u64, timestamp: u32) -> u64 {
+    (reference as i64 + timestamp.wrapping_sub(reference as u32) as i32 as i64) as u64
+}
+
+/// The extended sequence number closest to the expected one
+fn extend(expected: u64, sequence_number: u16) -> u64 {
+    let delta = sequence_number.wrapping_sub(expected as u16) as i16;
+    (expected as i64 + delta as i64) as u64
+}
+
+#[test]
+fn test_jitter_first_packet_waits() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert_eq!(jitter.deadline(), None);
+    assert!(jitter.push(RtpPacket::test(10, 0, false, &[]), start));
+    assert!(jitter.pull(start).is_none());
+    assert_eq!(jitter.deadline(), Some(start + DEFAULT_LATENCY));
+
+    // A packet reordered before the first one is still in time
+    assert!(jitter.push(RtpPacket::test(9, 0, false, &[]), start));
+    let first = jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert_eq!((first.packet.sequence_number, first.lost), (9, 0));
+    assert_eq!(first.timestamp, 1 << 32);
+    assert_eq!(jitter.pull(start).unwrap().packet.sequence_number, 10);
+    assert!(jitter.pull(start + DEFAULT_LATENCY).is_none());
+}
+
+#[test]
+fn test_jitter_wraparound() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(65534, u32::MAX - 3000, false, &[]), start));
+    let first = jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert_eq!(first.packet.sequence_number, 65534);
+
+    // The sequence number and the timestamp wrap around, the packets are put back in order
+    assert!(jitter.push(RtpPacket::test(0, 3000, false, &[]), start));
+    assert!(jitter.push(RtpPacket::test(65535, 0, false, &[]), start));
+    let second = jitter.pull(start).unwrap();
+    assert_eq!(second.packet.sequence_number, 65535);
+    assert_eq!(second.timestamp - first.timestamp, 3001);
+    let third = jitter.pull(start).unwrap();
+    assert_eq!(third.packet.sequence_number, 0);
+    assert_eq!(third.timestamp - first.timestamp, 6001);
+}
+
+#[test]
+fn test_jitter_duplicate_and_late_packets() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(1, 0, false, &[]), start));
+    assert!(!jitter.push(RtpPacket::test(1, 0, false, &[]), start));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+
+    // Released already, or given up on
+    assert!(!jitter.push(RtpPacket::test(1, 0, false, &[]), start));
+    assert!(jitter.push(RtpPacket::test(4, 0, false, &[]), start));
+    assert!(!jitter.push(RtpPacket::test(4, 0, false, &[]), start));
+    let packet = jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (4, 2));
+    assert!(!jitter.push(RtpPacket::test(3, 0, false, &[]), start));
+}
+
+#[test]
+fn test_jitter_loss_after_latency() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(1, 0, false, &[]), start));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+
+    // The packet after the missing ones waits, then they are declared lost
+    let later = start + Duration::from_millis(50);
+    assert!(jitter.push(RtpPacket::test(5, 0, false, &[]), later));
+    assert!(jitter.pull(later + DEFAULT_LATENCY / 2).is_none());
+    assert_eq!(jitter.deadline(), Some(later + DEFAULT_LATENCY));
+    let packet = jitter.pull(later + DEFAULT_LATENCY).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (5, 3));
+}
+
+#[test]
+fn test_jitter_restart_forward_jump() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(1, 0, false, &[]), start));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+
+    // Past the dropout, the sequence starts again and its first packet waits
+    assert!(jitter.push(
+        RtpPacket::test(2 + MAX_DROPOUT as u16 + 1, 90000, false, &[]),
+        start
+    ));
+    assert!(jitter.pull(start).is_none());
+    let packet = jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (3003, 0));
+    assert_eq!(packet.timestamp, 1 << 32 | 90000);
+}
+
+#[test]
+fn test_jitter_restart_backwards_jump() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(1000, 0, false, &[]), start));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert!(jitter.push(RtpPacket::test(1003, 0, false, &[]), start));
+
+    // Further back than the misorder, the packet waiting is released at once with its loss
+    // and the sequence starts again
+    assert!(jitter.push(RtpPacket::test(500, 0, false, &[]), start));
+    let packet = jitter.pull(start).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (1003, 2));
+    assert!(jitter.pull(start).is_none());
+    let packet = jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (500, 0));
+    assert!(jitter.push(RtpPacket::test(501, 0, false, &[]), start));
+    assert_eq!(jitter.pull(start).unwrap().packet.sequence_number, 501);
+}
+
+#[test]
+fn test_jitter_packet_limit() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(0, 0, false, &[]), start));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+
+    // Too many packets waiting for a missing one release the oldest early
+    for sequence_number in 2..MAX_PACKETS as u16 + 3 {
+        assert!(jitter.push(RtpPacket::test(sequence_number, 0, false, &[]), start));
+    }
+    let packet = jitter.pull(start).unwrap();
+    assert_eq!((packet.packet.sequence_number, packet.lost), (2, 1));
+}
+
+#[test]
+fn test_jitter_sources() {
+    let start = Instant::now();
+    let mut jitter = JitterBuffer::new(DEFAULT_LATENCY);
+    assert!(jitter.push(RtpPacket::test(100, 0, false, &[]), start));
+    assert!(jitter.push(
+        RtpPacket {
+            ssrc: 2,
+            ..RtpPacket::test(100, 0, false, &[])
+        },
+        start
+    ));
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+    jitter.pull(start + DEFAULT_LATENCY).unwrap();
+
+    // Each source has its own sequence
+    assert!(jitter.push(
+        RtpPacket {
+            ssrc: 2,
+            ..RtpPacket::test(101, 0, false, &[])
+        },
+        start
+    ));
+    let packet = jitter.pull(start).unwrap();
+    assert_eq!(
+        (packet.packet.ssrc, packet.packet.sequence_number),
+        (2, 101)
+    );
+    assert!(jitter.push(RtpPacket::test(102, 0, false, &[]), start));
+    assert!(jitter.pull(start).is_none());
+}
@@ -0,0 +1,589 @@
+use crate::rtp_packet::RtpPacket;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::{HashMap, VecDeque};
+use tokio::io::{Error, ErrorKind, Result};
+
+/// The luminance quantization table of the JPEG specification in zigzag order, scaled by the Q
+/// factor (RFC 2435 appendix A)
+const LUMA_QUANTIZER: [u8; 64] = [
+    16, 11, 12, 14, 12, 10, 16, 14, 13, 14, 18, 17, 16, 19, 24, 40, 26, 24, 22, 22, 24, 49, 35, 37,
+    29, 40, 58, 51, 61, 60, 57, 51, 56, 55, 64, 72, 92, 78, 64, 68, 87, 69, 55, 56, 80, 109, 81,
+    87, 95, 98, 103, 104, 103, 62, 77, 113, 121, 112, 100, 120, 92, 101, 103, 99,
+];
+
+/// The chrominance quantization table of the JPEG specification in zigzag order
+const CHROMA_QUANTIZER: [u8; 64] = [
+    17, 18, 18, 24, 21, 24, 47, 26, 26, 47, 99, 66, 56, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The Huffman tables of the JPEG specification (ITU-T T.81 annex K.3) the payload format
+/// requires: the code counts by length and the symbols
+const LUMA_DC_CODE_LENS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const LUMA_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const LUMA_AC_CODE_LENS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_SYMBOLS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const CHROMA_DC_CODE_LENS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const CHROMA_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const CHROMA_AC_CODE_LENS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_SYMBOLS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// The Q factor of a frame with its own quantization tables, which are not kept
+const Q_DYNAMIC_TABLES: u8 = 255;
+
+/// `JpegImage` is a JFIF file rebuilt from the RTP packets of a Motion JPEG picture.
+///
+/// Properties:
+///
+/// * `timestamp`: The RTP timestamp of the picture.
+/// * `width`: The width in pixels.
+/// * `height`: The height in pixels.
+/// * `data`: The JFIF file, from SOI to EOI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JpegImage {
+    pub(crate) timestamp: u32,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) data: Bytes,
+}
+
+/// `JpegHeader` is what the main JPEG header and the optional headers of the first packet of a
+/// picture tell about it.
+///
+/// Properties:
+///
+/// * `kind`: The type, 0 for 4:2:2 and 1 for 4:2:0 chroma subsampling.
+/// * `width`: The width in pixels.
+/// * `height`: The height in pixels.
+/// * `restart_interval`: The MCUs between restart markers, 0 without restart markers.
+/// * `tables`: The quantization tables, in zigzag order, with their precision.
+#[derive(Debug, Clone)]
+struct JpegHeader {
+    kind: u8,
+    width: u16,
+    height: u16,
+    restart_interval: u16,
+    tables: Vec<(bool, Bytes)>,
+}
+
+/// `JpegDepacketizer` puts the pictures of a Motion JPEG stream together from its fragments and
+/// rebuilds the headers the payload format leaves out (RFC 2435).
+///
+/// Properties:
+///
+/// * `tables`: The quantization tables sent in-band by Q factor, a frame may refer to them
+///   without sending them again.
+/// * `header`: The header of the picture being put together.
+/// * `timestamp`: The RTP timestamp of the picture being put together.
+/// * `scan`: The entropy-coded data of the picture being put together.
+/// * `ready`: The pictures completed and not pulled yet.
+#[derive(Debug, Default)]
+pub(crate) struct JpegDepacketizer {
+    tables: HashMap<u8, Vec<(bool, Bytes)>>,
+    header: Option<JpegHeader>,
+    timestamp: Option<u32>,
+    scan: BytesMut,
+    ready: VecDeque<JpegImage>,
+}
+
+impl JpegDepacketizer {
+    /// It pushes the next packet of the stream. The fragments are placed by their offset, a
+    /// fragment not following the previous one drops the picture, which ends with the marker bit
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, an invalid payload drops the picture.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        // A picture without its last packet is dropped
+        if self.timestamp != Some(packet.timestamp) {
+            self.header = None;
+            self.scan.clear();
+        }
+        self.timestamp = Some(packet.timestamp);
+        let result = self.depacketize(packet.payload.clone());
+        if result.is_err() {
+            self.header = None;
+            self.scan.clear();
+        }
+        if packet.marker {
+            self.finish();
+        }
+        result
+    }
+
+    /// It pulls the next picture completed
+    pub(crate) fn pull(&mut self) -> Option<JpegImage> {
+        self.ready.pop_front()
+    }
+
+    fn depacketize(&mut self, mut payload: Bytes) -> Result<()> {
+        // Main JPEG header:
+        //      type-specific(8) | fragment offset(24) | type(8) | Q(8) | width / 8(8) | height / 8(8)
+        if payload.len() < 8 {
+            return Err(Error::other("JPEG payload truncated"));
+        }
+        let offset = (payload.get_u32() & 0x00ff_ffff) as usize;
+        let kind = payload.get_u8();
+        let q = payload.get_u8();
+        let width = payload.get_u8() as u16 * 8;
+        let height = payload.get_u8() as u16 * 8;
+
+        // Restart marker header: restart interval(16) | F(1) L(1) restart count(14)
+        let restart_interval = match kind {
+            0 | 1 => 0,
+            64 | 65 => {
+                if payload.len() < 4 {
+                    return Err(Error::other("JPEG restart marker header truncated"));
+                }
+                let restart_interval = payload.get_u16();
+                payload.advance(2);
+                restart_interval
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("JPEG type {} not supported", kind),
+                ))
+            }
+        };
+
+        if offset == 0 {
+            let tables = self.quantization_tables(q, &mut payload)?;
+            self.header = Some(JpegHeader {
+                kind: kind & 1,
+                width,
+                height,
+                restart_interval,
+                tables,
+            });
+            self.scan.clear();
+        } else if self.header.is_none() || offset != self.scan.len() {
+            // The first fragment or the one before is lost
+            self.header = None;
+            self.scan.clear();
+            return Ok(());
+        }
+        self.scan.put(payload);
+        Ok(())
+    }
+
+    /// The quantization tables of the picture: scaled from the tables of the specification for
+    /// Q below 128, or sent in the quantization table header of the first fragment. Tables of a
+    /// Q below 255 may be sent once only
+    fn quantization_tables(&mut self, q: u8, payload: &mut Bytes) -> Result<Vec<(bool, Bytes)>> {
+        match q {
+            1..=99 => return Ok(Self::scaled_tables(q)),
+            128..=255 => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("JPEG Q factor {} reserved", q),
+                ))
+            }
+        }
+
+        // Quantization table header: MBZ(8) | precision(8) | length(16) | tables
+        if payload.len() < 4 {
+            return Err(Error::other("JPEG quantization table header truncated"));
+        }
+        payload.advance(1);
+        let precision = payload.get_u8();
+        let len = payload.get_u16() as usize;
+        if payload.len() < len {
+            return Err(Error::other("JPEG quantization tables exceed packet"));
+        }
+        let mut data = payload.split_to(len);
+        if len == 0 {
+            return match q {
+                Q_DYNAMIC_TABLES => Err(Error::other("JPEG quantization tables missing")),
+                _ => self.tables.get(&q).cloned().ok_or_else(|| {
+                    Error::other(format!("JPEG quantization tables of Q {} unknown", q))
+                }),
+            };
+        }
+        let mut tables = vec![];
+        while !data.is_empty() {
+            // The bit of each table tells if it has 16-bit values
+            let wide = precision >> tables.len() & 1 != 0;
+            let table_len = if wide { 128 } else { 64 };
+            if data.len() < table_len || tables.len() == 4 {
+                return Err(Error::other("Invalid JPEG quantization tables"));
+            }
+            tables.push((wide, data.split_to(table_len)));
+        }
+        if q != Q_DYNAMIC_TABLES {
+            self.tables.insert(q, tables.clone());
+        }
+        Ok(tables)
+    }
+
+    /// The tables of the specification scaled as by the IJG quality setting
+    fn scaled_tables(q: u8) -> Vec<(bool, Bytes)> {
+        let q = q as u32;
+        let scale = if q < 50 { 5000 / q } else { 200 - q * 2 };
+        let scaled = |table: &[u8; 64]| {
+            let table = table
+                .iter()
+                .map(|value| ((*value as u32 * scale + 50) / 100).clamp(1, 255) as u8)
+                .collect::<Vec<_>>();
+            (false, Bytes::from(table))
+        };
+        vec![scaled(&LUMA_QUANTIZER), scaled(&CHROMA_QUANTIZER)]
+    }
+
+    /// It completes the picture with the headers of a baseline JFIF file (RFC 2435 appendix B)
+    fn finish(&mut self) {
+        let header = self.header.take();
+        let scan = std::mem::take(&mut self.scan);
+        let (header, timestamp) = match (header, self.timestamp.take()) {
+            (Some(header), Some(timestamp)) if !scan.is_empty() => (header, timestamp),
+            _ => return,
+        };
+        let mut data = BytesMut::with_capacity(1024 + scan.len());
+        data.put_u16(0xffd8);
+
+        // APP0: JFIF 1.1 without thumbnail
+        data.put_u16(0xffe0);
+        data.put_u16(16);
+        data.put(&b"JFIF\0"[..]);
+        data.put(&[1, 1, 0, 0, 1, 0, 1, 0, 0][..]);
+
+        for (index, (wide, table)) in header.tables.iter().enumerate() {
+            data.put_u16(0xffdb);
+            data.put_u16(3 + table.len() as u16);
+            data.put_u8((*wide as u8) << 4 | index as u8);
+            data.put(&table[..]);
+        }
+
+        if header.restart_interval != 0 {
+            data.put_u16(0xffdd);
+            data.put_u16(4);
+            data.put_u16(header.restart_interval);
+        }
+
+        // SOF0: Y at twice the chroma resolution, horizontally for type 0 and in both
+        // directions for type 1
+        let chroma_table = (header.tables.len() > 1) as u8;
+        data.put_u16(0xffc0);
+        data.put_u16(17);
+        data.put_u8(8);
+        data.put_u16(header.height);
+        data.put_u16(header.width);
+        data.put_u8(3);
+        data.put(&[1, 0x21 + header.kind, 0][..]);
+        data.put(&[2, 0x11, chroma_table][..]);
+        data.put(&[3, 0x11, chroma_table][..]);
+
+        let huffman_tables: [(u8, &[u8], &[u8]); 4] = [
+            (0x00, &LUMA_DC_CODE_LENS, &LUMA_DC_SYMBOLS),
+            (0x10, &LUMA_AC_CODE_LENS, &LUMA_AC_SYMBOLS),
+            (0x01, &CHROMA_DC_CODE_LENS, &CHROMA_DC_SYMBOLS),
+            (0x11, &CHROMA_AC_CODE_LENS, &CHROMA_AC_SYMBOLS),
+        ];
+        for (class_id, code_lens, symbols) in huffman_tables {
+            data.put_u16(0xffc4);
+            data.put_u16(3 + code_lens.len() as u16 + symbols.len() as u16);
+            data.put_u8(class_id);
+            data.put(code_lens);
+            data.put(symbols);
+        }
+
+        // SOS: Y with the luminance tables, Cb and Cr with the chrominance ones
+        data.put_u16(0xffda);
+        data.put_u16(12);
+        data.put_u8(3);
+        data.put(&[1, 0x00, 2, 0x11, 3, 0x11][..]);
+        data.put(&[0, 63, 0][..]);
+
+        data.put(&scan[..]);
+        if !scan.ends_with(&[0xff, 0xd9]) {
+            data.put_u16(0xffd9);
+        }
+        self.ready.push_back(JpegImage {
+            timestamp,
+            width: header.width,
+            height: header.height,
+            data: data.freeze(),
+        });
+    }
+}
+
+/// A packet of the main JPEG header and the payload following it
+#[cfg(test)]
+fn test_jpeg_packet(timestamp: u32, marker: bool, header: [u8; 8], payload: &[u8]) -> RtpPacket {
+    RtpPacket::test(0, timestamp, marker, &[&header[..], payload].concat())
+}
+
+#[test]
+fn test_jpeg_scaled_tables() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // Q 50 uses the tables of the specification, the picture comes in two fragments
+    depacketizer
+        .push(&test_jpeg_packet(
+            3000,
+            false,
+            [0, 0, 0, 0, 1, 50, 80, 60],
+            &[1, 2, 3],
+        ))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&test_jpeg_packet(
+            3000,
+            true,
+            [0, 0, 0, 3, 1, 50, 80, 60],
+            &[4],
+        ))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert_eq!(
+        (image.width, image.height, image.timestamp),
+        (640, 480, 3000)
+    );
+    let data = &image.data[..];
+    assert_eq!(&data[..4], &[0xff, 0xd8, 0xff, 0xe0]);
+    assert_eq!(&data[data.len() - 6..], &[1, 2, 3, 4, 0xff, 0xd9]);
+    // DQT of table 0 right after APP0
+    assert_eq!(&data[20..25], &[0xff, 0xdb, 0, 67, 0]);
+    assert_eq!(&data[25..89], &LUMA_QUANTIZER[..]);
+    // SOF0 after the two tables: 4:2:0, 480x640
+    assert_eq!(&data[158..166], &[0xff, 0xc0, 0, 17, 8, 0x01, 0xe0, 0x02]);
+    assert_eq!(&data[168..171], &[1, 0x22, 0]);
+    // No DRI without restart markers
+    assert!(!data.windows(2).any(|marker| marker == [0xff, 0xdd]));
+}
+
+#[test]
+fn test_jpeg_scan_with_eoi() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // The EOI marker sent with the scan is not added again, type 0 is 4:2:2
+    depacketizer
+        .push(&test_jpeg_packet(
+            0,
+            true,
+            [0, 0, 0, 0, 0, 90, 1, 1],
+            &[5, 0xff, 0xd9],
+        ))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert_eq!((image.width, image.height), (8, 8));
+    assert!(image.data.ends_with(&[5, 0xff, 0xd9]));
+    assert!(!image.data.ends_with(&[0xff, 0xd9, 0xff, 0xd9]));
+    assert!(image.data.windows(3).any(|y| y == [1, 0x21, 0]));
+}
+
+#[test]
+fn test_jpeg_in_band_tables() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // Q 200 sends its tables once, with restart markers
+    let mut tables = vec![0, 0, 0, 128];
+    tables.extend([2; 64]);
+    tables.extend([3; 64]);
+    tables.extend([0xab, 0xcd]);
+    let header = [0, 0, 0, 0, 65, 200, 2, 2];
+    let restart = [0, 4, 0xff, 0xff];
+    let payload = [&restart[..], &tables[..]].concat();
+    depacketizer
+        .push(&test_jpeg_packet(6000, true, header, &payload))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert!(image
+        .data
+        .windows(6)
+        .any(|dri| dri == [0xff, 0xdd, 0, 4, 0, 4]));
+    assert!(image.data.ends_with(&[0xab, 0xcd, 0xff, 0xd9]));
+
+    // The next frame of the same Q refers to them with an empty table header
+    let payload = [&restart[..], &[0, 0, 0, 0, 0xef][..]].concat();
+    depacketizer
+        .push(&test_jpeg_packet(9000, true, header, &payload))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert!(image.data.windows(4).any(|dqt| dqt == [0xff, 0xdb, 0, 67]));
+    assert!(image.data.windows(3).any(|dqt| dqt == [3, 3, 3]));
+}
+
+#[test]
+fn test_jpeg_wide_tables() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // The precision bit of the first table gives it 16-bit values
+    let mut tables = vec![0, 1, 0, 192];
+    tables.extend([0; 128]);
+    tables.extend([3; 64]);
+    tables.push(0xef);
+    depacketizer
+        .push(&test_jpeg_packet(
+            0,
+            true,
+            [0, 0, 0, 0, 1, 255, 1, 1],
+            &tables,
+        ))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert!(image
+        .data
+        .windows(5)
+        .any(|dqt| dqt == [0xff, 0xdb, 0, 131, 0x10]));
+
+    // The tables of Q 255 are not kept
+    assert!(depacketizer
+        .push(&test_jpeg_packet(
+            3000,
+            true,
+            [0, 0, 0, 0, 1, 255, 1, 1],
+            &[0, 0, 0, 0, 0xef]
+        ))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_jpeg_invalid_tables() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // Unknown tables of a Q, reserved Q factors, tables cut short or in excess
+    let unknown = [0, 0, 0, 0, 0xef];
+    let header = [0, 0, 0, 0, 1, 128, 1, 1];
+    assert!(depacketizer
+        .push(&test_jpeg_packet(0, true, header, &unknown))
+        .is_err());
+    for q in [0, 100, 127] {
+        let err = depacketizer
+            .push(&test_jpeg_packet(
+                0,
+                true,
+                [0, 0, 0, 0, 1, q, 1, 1],
+                &[0xef],
+            ))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+    let cut = [0, 0, 0, 64, 1, 2, 3];
+    assert!(depacketizer
+        .push(&test_jpeg_packet(0, true, header, &cut))
+        .is_err());
+    let mut partial = vec![0, 0, 0, 65];
+    partial.extend([1; 65]);
+    assert!(depacketizer
+        .push(&test_jpeg_packet(0, true, header, &partial))
+        .is_err());
+    let mut five = vec![0, 0, 1, 64];
+    five.extend([1; 320]);
+    assert!(depacketizer
+        .push(&test_jpeg_packet(0, true, header, &five))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_jpeg_invalid_headers() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // A main header or a restart marker header cut short, and the types not supported
+    let mut packet = test_jpeg_packet(0, true, [0, 0, 0, 0, 1, 50, 1, 1], &[]);
+    packet.payload.truncate(7);
+    assert!(depacketizer.push(&packet).is_err());
+    assert!(depacketizer
+        .push(&test_jpeg_packet(
+            0,
+            true,
+            [0, 0, 0, 0, 65, 50, 1, 1],
+            &[0, 4]
+        ))
+        .is_err());
+    let err = depacketizer
+        .push(&test_jpeg_packet(0, true, [0, 0, 0, 0, 2, 50, 1, 1], &[1]))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_jpeg_lost_fragments() {
+    let mut depacketizer = JpegDepacketizer::default();
+
+    // A lost fragment drops the picture
+    depacketizer
+        .push(&test_jpeg_packet(
+            12000,
+            false,
+            [0, 0, 0, 0, 1, 50, 80, 60],
+            &[1, 2],
+        ))
+        .unwrap();
+    depacketizer
+        .push(&test_jpeg_packet(
+            12000,
+            true,
+            [0, 0, 0, 3, 1, 50, 80, 60],
+            &[4],
+        ))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // A lost first fragment too
+    depacketizer
+        .push(&test_jpeg_packet(
+            15000,
+            true,
+            [0, 0, 0, 2, 1, 50, 80, 60],
+            &[3],
+        ))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // A lost last fragment drops the picture when the next one starts
+    depacketizer
+        .push(&test_jpeg_packet(
+            18000,
+            false,
+            [0, 0, 0, 0, 1, 50, 80, 60],
+            &[1],
+        ))
+        .unwrap();
+    depacketizer
+        .push(&test_jpeg_packet(
+            21000,
+            true,
+            [0, 0, 0, 0, 1, 50, 80, 60],
+            &[2],
+        ))
+        .unwrap();
+    let image = depacketizer.pull().unwrap();
+    assert_eq!(image.timestamp, 21000);
+    assert!(image.data.ends_with(&[2, 0xff, 0xd9]));
+    assert!(depacketizer.pull().is_none());
+}
@@ -0,0 +1,710 @@
+use crate::rtp_packet::RtpPacket;
+use bytes::{BufMut, BytesMut};
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::fmt;
+use tokio::io::{Error, Result};
+
+/// The lowercase names of the data items telling if a motion or tampering event is active, as
+/// used by ONVIF and Axis
+const STATE_ITEMS: [&str; 4] = ["state", "ismotion", "istamper", "active"];
+
+/// `BoundingBox` is the box of an object in normalized coordinates, from -1 to 1 with the y axis
+/// pointing up.
+///
+/// Properties:
+///
+/// * `left`: The left edge.
+/// * `top`: The top edge.
+/// * `right`: The right edge.
+/// * `bottom`: The bottom edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BoundingBox {
+    pub(crate) left: f32,
+    pub(crate) top: f32,
+    pub(crate) right: f32,
+    pub(crate) bottom: f32,
+}
+
+/// An event of the ONVIF metadata stream (ONVIF Streaming Specification section 5.1.2), the
+/// time is the `UtcTime` of the message or frame
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum MetadataEvent {
+    /// A motion alarm or motion detector changed state
+    Motion {
+        time: Option<DateTime<Utc>>,
+        source: Option<String>,
+        active: bool,
+    },
+    /// A tampering or global scene change detector changed state
+    Tampering {
+        time: Option<DateTime<Utc>>,
+        source: Option<String>,
+        active: bool,
+    },
+    /// An object of the video analytics in a frame
+    Object {
+        time: Option<DateTime<Utc>>,
+        id: String,
+        class: Option<String>,
+        bounding_box: Option<BoundingBox>,
+    },
+    /// Any other notification, with its data items
+    Notification {
+        time: Option<DateTime<Utc>>,
+        topic: String,
+        data: Vec<(String, String)>,
+    },
+}
+
+/// `MetadataFrame` is an XML document of the metadata stream and the events it carries.
+///
+/// Properties:
+///
+/// * `timestamp`: The RTP timestamp of the document.
+/// * `events`: The events of the document, in document order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MetadataFrame {
+    pub(crate) timestamp: u32,
+    pub(crate) events: Vec<MetadataEvent>,
+}
+
+/// `MetadataDepacketizer` puts the XML documents of a `vnd.onvif.metadata` stream together, a
+/// document ends with the marker bit.
+///
+/// Properties:
+///
+/// * `timestamp`: The RTP timestamp of the document being put together.
+/// * `document`: The document being put together.
+/// * `sequence_number`: The sequence number of the last packet pushed.
+/// * `corrupt`: A packet of the document was lost, the document is dropped.
+/// * `ready`: The documents completed and not pulled yet.
+#[derive(Debug, Default)]
+pub(crate) struct MetadataDepacketizer {
+    timestamp: Option<u32>,
+    document: BytesMut,
+    sequence_number: Option<u16>,
+    corrupt: bool,
+    ready: VecDeque<MetadataFrame>,
+}
+
+/// `XmlElement` is an element of the document, names are kept without namespace prefix.
+///
+/// Properties:
+///
+/// * `name`: The local name.
+/// * `attributes`: The attributes by local name.
+/// * `children`: The child elements.
+/// * `text`: The text content, without the one of the children.
+#[derive(Debug, Default)]
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl MetadataDepacketizer {
+    /// It pushes the next packet of the stream
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &RtpPacket, the packet in sequence number order
+    ///
+    /// Returns:
+    ///
+    /// A Result<()>, a document which is not well-formed XML is dropped.
+    pub(crate) fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        let lost = self.sequence_number.is_some_and(|sequence_number| {
+            packet.sequence_number != sequence_number.wrapping_add(1)
+        });
+        self.sequence_number = Some(packet.sequence_number);
+        // A new timestamp without the marker of the last document drops it
+        if self.timestamp != Some(packet.timestamp) {
+            self.document.clear();
+            self.corrupt = false;
+        }
+        self.corrupt |= lost;
+        self.timestamp = Some(packet.timestamp);
+        self.document.put(&packet.payload[..]);
+        if !packet.marker {
+            return Ok(());
+        }
+
+        let document = std::mem::take(&mut self.document);
+        let corrupt = std::mem::replace(&mut self.corrupt, false);
+        self.timestamp = None;
+        if corrupt {
+            return Ok(());
+        }
+        let document = std::str::from_utf8(&document)
+            .map_err(|_| Error::other("Metadata document is not UTF-8"))?;
+        let root = XmlElement::parse(document)?;
+        self.ready.push_back(MetadataFrame {
+            timestamp: packet.timestamp,
+            events: Self::events(&root),
+        });
+        Ok(())
+    }
+
+    /// It pulls the next document completed
+    pub(crate) fn pull(&mut self) -> Option<MetadataFrame> {
+        self.ready.pop_front()
+    }
+
+    /// The objects of the video analytics frames and the event notifications of the
+    /// `MetadataStream` document
+    fn events(root: &XmlElement) -> Vec<MetadataEvent> {
+        let mut events = vec![];
+        for frame in root.descendants("Frame") {
+            let time = frame.time();
+            for object in frame.children_named("Object") {
+                let class = object
+                    .descendants("Type")
+                    .first()
+                    .map(|kind| kind.text.trim().to_string())
+                    .or_else(|| {
+                        object
+                            .descendants("ClassCandidate")
+                            .first()
+                            .and_then(|candidate| {
+                                candidate
+                                    .child("Type")
+                                    .map(|kind| kind.text.trim().to_string())
+                            })
+                    });
+                let bounding_box = object
+                    .descendants("BoundingBox")
+                    .first()
+                    .and_then(|bounds| {
+                        Some(BoundingBox {
+                            left: bounds.attribute("left")?.parse().ok()?,
+                            top: bounds.attribute("top")?.parse().ok()?,
+                            right: bounds.attribute("right")?.parse().ok()?,
+                            bottom: bounds.attribute("bottom")?.parse().ok()?,
+                        })
+                    });
+                events.push(MetadataEvent::Object {
+                    time,
+                    id: object.attribute("ObjectId").unwrap_or_default().to_string(),
+                    class,
+                    bounding_box,
+                });
+            }
+        }
+        for notification in root.descendants("NotificationMessage") {
+            let topic = notification
+                .child("Topic")
+                .map(|topic| topic.text.trim().to_string())
+                .unwrap_or_default();
+            let message = notification.descendants("Message");
+            let message = match message
+                .iter()
+                .find(|message| message.attribute("UtcTime").is_some())
+            {
+                Some(message) => *message,
+                None => continue,
+            };
+            let items = |name: &str| {
+                message
+                    .child(name)
+                    .map(|items| {
+                        items
+                            .children_named("SimpleItem")
+                            .filter_map(|item| {
+                                Some((
+                                    item.attribute("Name")?.to_string(),
+                                    item.attribute("Value")?.to_string(),
+                                ))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default()
+            };
+            let source = items("Source").into_iter().next().map(|(_, value)| value);
+            let data = items("Data");
+            let active = data
+                .iter()
+                .find(|(name, _)| STATE_ITEMS.contains(&name.to_lowercase().as_str()))
+                .map(|(_, value)| value == "true" || value == "1");
+            // Topics look like `tns1:VideoSource/MotionAlarm`, vendors add their own prefixes
+            let kind = topic.to_lowercase();
+            let time = message.time();
+            events.push(match active {
+                Some(active) if kind.contains("motion") || kind.contains("vmd") => {
+                    MetadataEvent::Motion {
+                        time,
+                        source,
+                        active,
+                    }
+                }
+                Some(active) if kind.contains("tamper") || kind.contains("globalscenechange") => {
+                    MetadataEvent::Tampering {
+                        time,
+                        source,
+                        active,
+                    }
+                }
+                _ => MetadataEvent::Notification { time, topic, data },
+            });
+        }
+        events
+    }
+}
+
+impl fmt::Display for MetadataEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |time: &Option<DateTime<Utc>>| {
+            time.map_or(String::from("unknown time"), |time| time.to_rfc3339())
+        };
+        let state = |active: &bool| if *active { "active" } else { "inactive" };
+        match self {
+            Self::Motion {
+                time: at,
+                source,
+                active,
+            } => write!(
+                f,
+                "Motion {} at {} on {}",
+                state(active),
+                time(at),
+                source.as_deref().unwrap_or("unknown source")
+            ),
+            Self::Tampering {
+                time: at,
+                source,
+                active,
+            } => write!(
+                f,
+                "Tampering {} at {} on {}",
+                state(active),
+                time(at),
+                source.as_deref().unwrap_or("unknown source")
+            ),
+            Self::Object {
+                time: at,
+                id,
+                class,
+                bounding_box,
+            } => {
+                write!(
+                    f,
+                    "Object {} ({}) at {}",
+                    id,
+                    class.as_deref().unwrap_or("unknown class"),
+                    time(at)
+                )?;
+                match bounding_box {
+                    Some(bounds) => write!(
+                        f,
+                        " in [{}, {}, {}, {}]",
+                        bounds.left, bounds.top, bounds.right, bounds.bottom
+                    ),
+                    None => Ok(()),
+                }
+            }
+            Self::Notification {
+                time: at,
+                topic,
+                data,
+            } => {
+                let data = data
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>();
+                write!(f, "Event {} at {}: {}", topic, time(at), data.join(", "))
+            }
+        }
+    }
+}
+
+impl XmlElement {
+    /// It parses the root element of a document, the declaration, comments and processing
+    /// instructions are skipped. Only the subset of XML used by the metadata stream is supported:
+    /// no DTD, and CDATA is taken as text
+    fn parse(document: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::other(format!("Invalid metadata XML: {}", reason));
+        let mut stack: Vec<XmlElement> = vec![];
+        let mut rest = document;
+        while let Some(start) = rest.find('<') {
+            if let Some(element) = stack.last_mut() {
+                element.text.push_str(&unescape(&rest[..start]));
+            }
+            rest = &rest[start..];
+            if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let end = cdata
+                    .find("]]>")
+                    .ok_or_else(|| invalid("unterminated CDATA"))?;
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&cdata[..end]);
+                }
+                rest = &cdata[end + 3..];
+                continue;
+            }
+            let (terminator, skip) = match rest.as_bytes().get(1) {
+                Some(b'?') => ("?>", true),
+                Some(b'!') if rest.starts_with("<!--") => ("-->", true),
+                Some(b'!') => (">", true),
+                _ => (">", false),
+            };
+            let end = match terminator {
+                ">" => tag_end(rest),
+                _ => rest.find(terminator),
+            }
+            .ok_or_else(|| invalid("unterminated tag"))?;
+            let tag = &rest[1..end];
+            rest = &rest[end + terminator.len()..];
+            if skip {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().ok_or_else(|| invalid("unexpected end tag"))?;
+                if element.name != local_name(name.trim()) {
+                    return Err(invalid("mismatched end tag"));
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+                continue;
+            }
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = Self::start_tag(tag).ok_or_else(|| invalid("malformed start tag"))?;
+            if !empty {
+                stack.push(element);
+                continue;
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Ok(element),
+            }
+        }
+        Err(invalid("unterminated document"))
+    }
+
+    /// `name attribute="value" attribute='value'...`
+    fn start_tag(tag: &str) -> Option<Self> {
+        let tag = tag.trim();
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let mut element = Self {
+            name: local_name(&tag[..name_end]).to_string(),
+            ..Self::default()
+        };
+        let mut rest = tag[name_end..].trim_start();
+        while !rest.is_empty() {
+            let (name, value) = rest.split_once('=')?;
+            let value = value.trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|quote| *quote == '"' || *quote == '\'')?;
+            let end = value[1..].find(quote)? + 1;
+            element.attributes.push((
+                local_name(name.trim()).to_string(),
+                unescape(&value[1..end]),
+            ));
+            rest = value[end + 1..].trim_start();
+        }
+        (!element.name.is_empty()).then_some(element)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The elements of the given name below this one, in document order
+    fn descendants(&self, name: &str) -> Vec<&XmlElement> {
+        let mut found = vec![];
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            }
+            found.extend(child.descendants(name));
+        }
+        found
+    }
+
+    /// The `UtcTime` attribute of a frame or message
+    fn time(&self) -> Option<DateTime<Utc>> {
+        let time = self.attribute("UtcTime")?;
+        DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    }
+}
+
+/// The position of the `>` ending the tag at the start of the text, the `>` in the quoted
+/// attribute values are skipped
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    text.char_indices().find_map(|(i, c)| {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            (Some(open), _) if open == c => quote = None,
+            _ => {}
+        }
+        None
+    })
+}
+
+/// The name without namespace prefix
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// It replaces the predefined entities and the character references
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let character = match &rest[1..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => match reference.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => reference
+                    .strip_prefix('#')
+                    .and_then(|decimal| decimal.parse().ok()),
+            }
+            .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+const TEST_DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tt:MetadataStream xmlns:tt="http://www.onvif.org/ver10/schema">
+  <tt:VideoAnalytics>
+    <tt:Frame UtcTime="2022-05-18T10:15:00.5Z">
+      <tt:Object ObjectId="12">
+        <tt:Appearance>
+          <tt:Shape>
+            <tt:BoundingBox left="-0.5" top="0.6" right="-0.2" bottom="0.1"/>
+          </tt:Shape>
+          <tt:Class><tt:Type Likelihood="0.8">Human</tt:Type></tt:Class>
+        </tt:Appearance>
+      </tt:Object>
+    </tt:Frame>
+  </tt:VideoAnalytics>
+  <tt:Event>
+    <wsnt:NotificationMessage>
+      <wsnt:Topic Dialect="http://www.onvif.org/ver10/tev/topicExpression/ConcreteSet">tns1:VideoSource/MotionAlarm</wsnt:Topic>
+      <wsnt:Message>
+        <tt:Message UtcTime="2022-05-18T10:15:01Z" PropertyOperation="Changed">
+          <tt:Source><tt:SimpleItem Name="Source" Value="VideoSource&amp;1"/></tt:Source>
+          <tt:Data><tt:SimpleItem Name="State" Value="true"/></tt:Data>
+        </tt:Message>
+      </wsnt:Message>
+    </wsnt:NotificationMessage>
+    <wsnt:NotificationMessage>
+      <wsnt:Topic>tns1:VideoSource/tnsaxis:Tampering</wsnt:Topic>
+      <wsnt:Message>
+        <tt:Message UtcTime="2022-05-18T10:15:02Z">
+          <tt:Data><tt:SimpleItem Name="Active" Value="1"/></tt:Data>
+        </tt:Message>
+      </wsnt:Message>
+    </wsnt:NotificationMessage>
+  </tt:Event>
+</tt:MetadataStream>"#;
+
+/// The events of a document of a single packet
+#[cfg(test)]
+fn test_events(document: &str) -> Vec<MetadataEvent> {
+    let mut depacketizer = MetadataDepacketizer::default();
+    depacketizer
+        .push(&RtpPacket::test(1, 3000, true, document.as_bytes()))
+        .unwrap();
+    depacketizer.pull().unwrap().events
+}
+
+#[test]
+fn test_metadata_events() {
+    let events = test_events(TEST_DOCUMENT);
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0].to_string(),
+        "Object 12 (Human) at 2022-05-18T10:15:00.500+00:00 in [-0.5, 0.6, -0.2, 0.1]"
+    );
+    assert_eq!(
+        events[1],
+        MetadataEvent::Motion {
+            time: DateTime::parse_from_rfc3339("2022-05-18T10:15:01Z")
+                .ok()
+                .map(|time| time.with_timezone(&Utc)),
+            source: Some(String::from("VideoSource&1")),
+            active: true,
+        }
+    );
+    assert_eq!(
+        events[2].to_string(),
+        "Tampering active at 2022-05-18T10:15:02+00:00 on unknown source"
+    );
+}
+
+#[test]
+fn test_metadata_other_events() {
+    // A class candidate, an inactive motion detector, a global scene change, any other
+    // notification, and a message without time which is skipped
+    let document = r#"<MetadataStream>
+  <Frame>
+    <Object ObjectId="3"><Appearance><Class>
+      <ClassCandidate><Type>Vehicle</Type><Likelihood>0.6</Likelihood></ClassCandidate>
+    </Class></Appearance></Object>
+  </Frame>
+  <NotificationMessage><Topic>tns1:RuleEngine/CellMotionDetector/Motion</Topic><Message>
+    <Message UtcTime="2022-05-18T10:15:01Z"><Data><SimpleItem Name="IsMotion" Value="false"/></Data></Message>
+  </Message></NotificationMessage>
+  <NotificationMessage><Topic>tns1:VideoSource/GlobalSceneChange/ImagingService</Topic><Message>
+    <Message UtcTime="bad"><Data><SimpleItem Name="State" Value="true"/></Data></Message>
+  </Message></NotificationMessage>
+  <NotificationMessage><Topic>tns1:Device/Trigger/DigitalInput</Topic><Message>
+    <Message UtcTime="2022-05-18T10:15:02Z">
+      <Source><SimpleItem Name="InputToken" Value="DI_0"/></Source>
+      <Data><SimpleItem Name="LogicalState" Value="true"/><SimpleItem Name="Level"/></Data>
+    </Message>
+  </Message></NotificationMessage>
+  <NotificationMessage><Topic>tns1:VideoSource/MotionAlarm</Topic><Message/></NotificationMessage>
+</MetadataStream>"#;
+    let events = test_events(document)
+        .iter()
+        .map(|event| event.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            "Object 3 (Vehicle) at unknown time",
+            "Motion inactive at 2022-05-18T10:15:01+00:00 on unknown source",
+            "Tampering active at unknown time on unknown source",
+            "Event tns1:Device/Trigger/DigitalInput at 2022-05-18T10:15:02+00:00: LogicalState=true",
+        ]
+    );
+}
+
+#[test]
+fn test_metadata_fragments() {
+    let (first, last) = TEST_DOCUMENT.as_bytes().split_at(400);
+    let mut depacketizer = MetadataDepacketizer::default();
+    depacketizer
+        .push(&RtpPacket::test(1, 3000, false, first))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+    depacketizer
+        .push(&RtpPacket::test(2, 3000, true, last))
+        .unwrap();
+    let frame = depacketizer.pull().unwrap();
+    assert_eq!(frame.timestamp, 3000);
+    assert_eq!(frame.events.len(), 3);
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_metadata_lost_packets() {
+    let (first, last) = TEST_DOCUMENT.as_bytes().split_at(400);
+    let mut depacketizer = MetadataDepacketizer::default();
+    // A lost packet drops the document
+    depacketizer
+        .push(&RtpPacket::test(1, 3000, false, first))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(3, 3000, true, last))
+        .unwrap();
+    assert!(depacketizer.pull().is_none());
+
+    // A new timestamp without the marker drops the document
+    depacketizer
+        .push(&RtpPacket::test(4, 6000, false, first))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(5, 9000, false, first))
+        .unwrap();
+    depacketizer
+        .push(&RtpPacket::test(6, 9000, true, last))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 9000);
+    assert!(depacketizer.pull().is_none());
+}
+
+#[test]
+fn test_metadata_invalid_documents() {
+    let mut depacketizer = MetadataDepacketizer::default();
+    assert!(depacketizer
+        .push(&RtpPacket::test(1, 3000, true, &[0x3c, 0xff, 0x3e]))
+        .is_err());
+    assert!(depacketizer
+        .push(&RtpPacket::test(2, 6000, true, b"<a><b></a>"))
+        .is_err());
+    assert!(depacketizer.pull().is_none());
+    // The next document is not affected
+    depacketizer
+        .push(&RtpPacket::test(3, 9000, true, TEST_DOCUMENT.as_bytes()))
+        .unwrap();
+    assert_eq!(depacketizer.pull().unwrap().timestamp, 9000);
+}
+
+#[test]
+fn test_xml_parse() {
+    let document = r#"<?xml version="1.0"?>
+<!DOCTYPE a>
+<!-- <b>comment</b> -->
+<ns:a ns:x='1 &lt; 2' y = "&#x41;&#66;&bogus;&amp">t&gt;<b z="a>b"/><![CDATA[<c>]]><b z='"/>'>u</b>v</ns:a>"#;
+    let root = XmlElement::parse(document).unwrap();
+    assert_eq!(root.name, "a");
+    assert_eq!(root.attribute("x"), Some("1 < 2"));
+    assert_eq!(root.attribute("y"), Some("AB&bogus;&amp"));
+    assert_eq!(root.text, "t><c>v");
+    assert_eq!(root.children_named("b").count(), 2);
+    assert_eq!(root.descendants("b")[1].text, "u");
+    // A `>` in a quoted attribute value does not end the tag
+    assert_eq!(root.descendants("b")[0].attribute("z"), Some("a>b"));
+    assert_eq!(root.descendants("b")[1].attribute("z"), Some("\"/>"));
+
+    for document in [
+        "<a><b></a>",
+        "<a>",
+        "</a>",
+        "<a",
+        "<a x=1/>",
+        "<a x/>",
+        "<a x='>'",
+        "<a><![CDATA[</a>",
+        "",
+    ] {
+        assert!(XmlElement::parse(document).is_err(), "{}", document);
+    }
+}
@@ -0,0 +1,253 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::SystemTime;
+use tokio::io::{Error, Result};
+
+/// Profile identifier of the RTP header extension defined by the ONVIF streaming specification
+/// for replay, see "RTP header extension" in the ONVIF Streaming Specification
+const ONVIF_REPLAY_EXTENSION_ID: u16 = 0xABAC;
+
+/// Seconds between the NTP epoch (1900-01-01) and the Unix epoch (1970-01-01)
+const NTP_UNIX_EPOCH_OFFSET: i64 = 2_208_988_800;
+
+/// `RtpHeaderExtension` is the optional header extension following the CSRC list.
+///
+/// Properties:
+///
+/// * `profile`: The 16 bits defined by profile.
+/// * `data`: The extension data, the length is a multiple of 4 bytes.
+#[derive(Debug, Clone)]
+pub(crate) struct RtpHeaderExtension {
+    pub(crate) profile: u16,
+    pub(crate) data: Bytes,
+}
+
+/// `RtpPacket` is a parsed RTP packet (RFC 3550 section 5.1).
+///
+/// Properties:
+///
+/// * `marker`: The marker bit, its meaning is defined by the payload format.
+/// * `payload_type`: The payload type.
+/// * `sequence_number`: The sequence number, increments by one for each packet.
+/// * `timestamp`: The sampling instant of the first octet of the payload.
+/// * `ssrc`: The synchronization source.
+/// * `csrcs`: The contributing sources.
+/// * `extension`: Option<RtpHeaderExtension>
+/// * `payload`: The payload without padding.
+#[derive(Debug, Clone)]
+pub(crate) struct RtpPacket {
+    pub(crate) marker: bool,
+    pub(crate) payload_type: u8,
+    pub(crate) sequence_number: u16,
+    pub(crate) timestamp: u32,
+    pub(crate) ssrc: u32,
+    pub(crate) csrcs: Vec<u32>,
+    pub(crate) extension: Option<RtpHeaderExtension>,
+    pub(crate) payload: Bytes,
+}
+
+/// `OnvifReplayExtension` is the content of the ONVIF replay RTP header extension, which is sent
+/// with every packet of a replayed recording.
+///
+/// Properties:
+///
+/// * `ntp_timestamp`: The wall-clock time of the recorded sample, in NTP format.
+/// * `clean_point`: C flag, the access unit is a key frame.
+/// * `end`: E flag, the packet is the last one of an access unit ending a contiguous section,
+///   e.g. the last GOP before a gap. It does not mark the end of the whole recording.
+/// * `discontinuity`: D flag, there is a gap in the recording before this access unit.
+/// * `c_seq`: The low 8 bits of the CSeq of the PLAY request which started this stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OnvifReplayExtension {
+    pub(crate) ntp_timestamp: u64,
+    pub(crate) clean_point: bool,
+    pub(crate) end: bool,
+    pub(crate) discontinuity: bool,
+    pub(crate) c_seq: u8,
+}
+
+impl RtpPacket {
+    /// It parses a raw RTP packet, the padding is removed from the payload
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: Bytes, the datagram received
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpPacket>.
+    pub(crate) fn parse(mut buf: Bytes) -> Result<Self> {
+        // Fixed header:
+        //      V(2) P(1) X(1) CC(4) | M(1) PT(7) | sequence number(16) | timestamp(32) | SSRC(32)
+        if buf.len() < 12 {
+            return Err(Error::other("RTP packet too short"));
+        }
+        let first = buf.get_u8();
+        if first >> 6 != 2 {
+            return Err(Error::other("Invalid RTP version"));
+        }
+        let padding = first & 0x20 != 0;
+        let has_extension = first & 0x10 != 0;
+        let csrc_count = (first & 0x0f) as usize;
+        let second = buf.get_u8();
+        let sequence_number = buf.get_u16();
+        let timestamp = buf.get_u32();
+        let ssrc = buf.get_u32();
+
+        if buf.len() < csrc_count * 4 {
+            return Err(Error::other("RTP packet too short for CSRC list"));
+        }
+        let csrcs = (0..csrc_count).map(|_| buf.get_u32()).collect();
+
+        // Header extension:
+        //      defined by profile(16) | length in 32-bit words(16) | extension data
+        let extension = if has_extension {
+            if buf.len() < 4 {
+                return Err(Error::other("RTP packet too short for header extension"));
+            }
+            let profile = buf.get_u16();
+            let len = buf.get_u16() as usize * 4;
+            if buf.len() < len {
+                return Err(Error::other("RTP header extension exceeds packet"));
+            }
+            Some(RtpHeaderExtension {
+                profile,
+                data: buf.split_to(len),
+            })
+        } else {
+            None
+        };
+
+        if padding {
+            let padding_len = buf.last().copied().unwrap_or(0) as usize;
+            if padding_len == 0 || padding_len > buf.len() {
+                return Err(Error::other("Invalid RTP padding"));
+            }
+            buf.truncate(buf.len() - padding_len);
+        }
+
+        Ok(Self {
+            marker: second & 0x80 != 0,
+            payload_type: second & 0x7f,
+            sequence_number,
+            timestamp,
+            ssrc,
+            csrcs,
+            extension,
+            payload: buf,
+        })
+    }
+
+    /// It assembles the packet into the buffer, without padding
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &mut BytesMut, the buffer to write the packet to
+    ///
+    /// Returns:
+    ///
+    /// The number of bytes written to the buffer.
+    pub(crate) fn assemble(&self, buf: &mut BytesMut) -> usize {
+        let len = buf.len();
+        let extension_bit = if self.extension.is_some() { 0x10 } else { 0 };
+        buf.put_u8(0x80 | extension_bit | self.csrcs.len() as u8);
+        buf.put_u8((self.marker as u8) << 7 | self.payload_type);
+        buf.put_u16(self.sequence_number);
+        buf.put_u32(self.timestamp);
+        buf.put_u32(self.ssrc);
+        self.csrcs.iter().for_each(|csrc| buf.put_u32(*csrc));
+        if let Some(ref extension) = self.extension {
+            buf.put_u16(extension.profile);
+            buf.put_u16((extension.data.len() / 4) as u16);
+            buf.put(&extension.data[..]);
+        }
+        buf.put(&self.payload[..]);
+        buf.len() - len
+    }
+
+    /// If the packet carries the ONVIF replay header extension, parse and return it
+    pub(crate) fn onvif_replay_extension(&self) -> Option<OnvifReplayExtension> {
+        // Replay extension, 3 words, it may be followed by further extension data:
+        //      NTP timestamp(64) | C(1) E(1) D(1) mbz(5) | CSeq(8) | padding(16)
+        let extension = self.extension.as_ref()?;
+        if extension.profile != ONVIF_REPLAY_EXTENSION_ID || extension.data.len() < 12 {
+            return None;
+        }
+        let mut data = &extension.data[..];
+        let ntp_timestamp = data.get_u64();
+        let flags = data.get_u8();
+        let c_seq = data.get_u8();
+        Some(OnvifReplayExtension {
+            ntp_timestamp,
+            clean_point: flags & 0x80 != 0,
+            end: flags & 0x40 != 0,
+            discontinuity: flags & 0x20 != 0,
+            c_seq,
+        })
+    }
+}
+
+#[cfg(test)]
+impl RtpPacket {
+    /// A packet of the payload type 96 and the source 1, as the depacketizers get it
+    pub(crate) fn test(sequence_number: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Self {
+        Self {
+            marker,
+            payload_type: 96,
+            sequence_number,
+            timestamp,
+            ssrc: 1,
+            csrcs: vec![],
+            extension: None,
+            payload: Bytes::copy_from_slice(payload),
+        }
+    }
+}
+
+impl OnvifReplayExtension {
+    /// The NTP timestamp converted to UTC
+    pub(crate) fn time(&self) -> DateTime<Utc> {
+        ntp_to_utc(self.ntp_timestamp)
+    }
+}
+
+/// It converts a 64-bit NTP timestamp (32-bit seconds since 1900 and 32-bit fraction) to UTC
+pub(crate) fn ntp_to_utc(ntp_timestamp: u64) -> DateTime<Utc> {
+    let seconds = (ntp_timestamp >> 32) as i64 - NTP_UNIX_EPOCH_OFFSET;
+    let nanos = ((ntp_timestamp & 0xffff_ffff) * 1_000_000_000) >> 32;
+    Utc.timestamp_opt(seconds, 0).unwrap() + Duration::nanoseconds(nanos as i64)
+}
+
+/// A random number for the SSRC, the initial sequence number and timestamp of an RTP stream
+pub(crate) fn random_u32() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish() as u32
+}
+
+#[test]
+fn test_onvif_replay_extension() {
+    let packet = Bytes::from_static(&[
+        0x90, 0x60, 0x12, 0x34, // V=2, X=1, PT=96, seq=0x1234
+        0x00, 0x00, 0x00, 0x10, // timestamp
+        0xde, 0xad, 0xbe, 0xef, // SSRC
+        0xab, 0xac, 0x00, 0x03, // ONVIF replay extension, 3 words
+        0xe6, 0x2f, 0x45, 0xa4, // NTP seconds: 2022-05-18T10:15:00Z
+        0x80, 0x00, 0x00, 0x00, // NTP fraction: 0.5s
+        0xa0, 0x05, 0x00, 0x00, // C=1, E=0, D=1, CSeq=5
+        0x65, 0x88, // payload
+    ]);
+    let packet = RtpPacket::parse(packet).unwrap();
+    assert_eq!(packet.payload_type, 96);
+    assert_eq!(packet.sequence_number, 0x1234);
+    assert_eq!(&packet.payload[..], &[0x65, 0x88]);
+
+    let replay = packet.onvif_replay_extension().unwrap();
+    assert!(replay.clean_point && !replay.end && replay.discontinuity);
+    assert_eq!(replay.c_seq, 5);
+    assert_eq!(replay.time().to_rfc3339(), "2022-05-18T10:15:00.500+00:00");
+}
@@ -0,0 +1,182 @@
+#[cfg(test)]
+use std::net::Ipv4Addr;
+use std::net::{IpAddr, UdpSocket as StdUdpSocket};
+use std::sync::atomic::{AtomicU16, Ordering};
+use tokio::io::{Error, ErrorKind, Result};
+use tokio::net::UdpSocket;
+
+/// The range the client ports are allocated from when no RTP port is given
+pub(crate) const DEFAULT_PORT_RANGE: RtpPortRange = RtpPortRange {
+    first: 20000,
+    last: 29999,
+};
+
+/// The offset of the next pair tried in the range, shared by the sessions of the process so they
+/// do not race for the same ports
+static NEXT_PAIR: AtomicU16 = AtomicU16::new(0);
+
+/// `RtpPortRange` is the range of UDP ports the client RTP and RTCP ports are allocated from.
+///
+/// Properties:
+///
+/// * `first`: The first port of the range, even.
+/// * `last`: The last port of the range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RtpPortRange {
+    pub(crate) first: u16,
+    pub(crate) last: u16,
+}
+
+impl RtpPortRange {
+    /// It parses a port range, the first port is rounded up to an even one
+    ///
+    /// Arguments:
+    ///
+    /// * `range`: &str, `<first>-<last>`, e.g. `30000-30999`
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpPortRange> holding at least one pair of ports.
+    pub(crate) fn parse(range: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid RTP port range {}", range),
+            )
+        };
+        let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+        let first = first.trim().parse::<u16>().map_err(|_| invalid())?;
+        let last = last.trim().parse::<u16>().map_err(|_| invalid())?;
+        let range = Self {
+            first: first.checked_add(first % 2).ok_or_else(invalid)?,
+            last,
+        };
+        if range.first == 0 || range.pairs() == 0 {
+            return Err(invalid());
+        }
+        Ok(range)
+    }
+
+    /// The count of even/odd pairs in the range
+    fn pairs(&self) -> u16 {
+        if self.last > self.first {
+            (self.last - self.first).div_ceil(2)
+        } else {
+            0
+        }
+    }
+}
+
+/// `RtpPortPair` holds the sockets bound to the RTP and RTCP ports of a track, from SETUP until
+/// the receiver or sender of the track takes them, so no other session can take the ports.
+///
+/// Properties:
+///
+/// * `rtp`: The socket bound to the RTP port.
+/// * `rtcp`: The socket bound to the RTCP port, the next one.
+#[derive(Debug)]
+pub(crate) struct RtpPortPair {
+    rtp: StdUdpSocket,
+    rtcp: StdUdpSocket,
+}
+
+impl RtpPortPair {
+    /// It binds the given RTP port and the next one, as asked for on the command line
+    ///
+    /// Arguments:
+    ///
+    /// * `address`: IpAddr, the local address, `0.0.0.0` or `::` for an IPv6 server
+    /// * `port`: u16, the RTP port
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpPortPair>.
+    pub(crate) fn bind(address: IpAddr, port: u16) -> Result<Self> {
+        let rtcp_port = port
+            .checked_add(1)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No RTCP port after 65535"))?;
+        Ok(Self {
+            rtp: StdUdpSocket::bind((address, port))?,
+            rtcp: StdUdpSocket::bind((address, rtcp_port))?,
+        })
+    }
+
+    /// It binds an even RTP port and the next odd RTCP port of the range (RFC 3550 section 11).
+    /// Pairs taken by other sessions or processes are skipped, every pair is tried once
+    ///
+    /// Arguments:
+    ///
+    /// * `address`: IpAddr, the local address, `0.0.0.0` or `::` for an IPv6 server
+    /// * `range`: RtpPortRange, the ports allowed
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpPortPair>.
+    pub(crate) fn allocate(address: IpAddr, range: RtpPortRange) -> Result<Self> {
+        let pairs = range.pairs();
+        for _ in 0..pairs {
+            let pair = NEXT_PAIR.fetch_add(1, Ordering::Relaxed) % pairs;
+            match Self::bind(address, range.first + 2 * pair) {
+                Ok(ports) => return Ok(ports),
+                Err(err) if err.kind() == ErrorKind::AddrInUse => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("No free RTP port pair in {}-{}", range.first, range.last),
+        ))
+    }
+
+    /// The RTP port, the RTCP port is the next one
+    pub(crate) fn port(&self) -> u16 {
+        self.rtp.local_addr().map_or(0, |address| address.port())
+    }
+
+    /// It hands the sockets over to tokio
+    ///
+    /// Returns:
+    ///
+    /// A Result of the RTP and RTCP sockets.
+    pub(crate) fn into_sockets(self) -> Result<(UdpSocket, UdpSocket)> {
+        self.rtp.set_nonblocking(true)?;
+        self.rtcp.set_nonblocking(true)?;
+        Ok((
+            UdpSocket::from_std(self.rtp)?,
+            UdpSocket::from_std(self.rtcp)?,
+        ))
+    }
+}
+
+#[test]
+fn test_allocate_port_pairs() {
+    assert_eq!(
+        RtpPortRange::parse("30001-30010").unwrap(),
+        RtpPortRange {
+            first: 30002,
+            last: 30010
+        }
+    );
+    assert!(RtpPortRange::parse("30000-30000").is_err());
+    assert!(RtpPortRange::parse("30000").is_err());
+
+    // Two pairs in the range, each session gets its own, the third finds none
+    let range = RtpPortRange::parse("47810-47813").unwrap();
+    let first = RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), range).unwrap();
+    let second = RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), range).unwrap();
+    let mut ports = [first.port(), second.port()];
+    ports.sort();
+    assert_eq!(ports, [47810, 47812]);
+    assert_eq!(
+        RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), range)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::AddrInUse
+    );
+
+    // A pair released is allocated again
+    drop(first);
+    let third = RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), range).unwrap();
+    assert_eq!(third.port() % 2, 0);
+    assert!(RtpPortPair::bind(Ipv4Addr::UNSPECIFIED.into(), third.port()).is_err());
+}
@@ -0,0 +1,724 @@
+use crate::record::SharedRecorder;
+use crate::rtp_depacketizer::{Depacketizer, Frame, FrameStats};
+use crate::rtp_jitter::{extend_timestamp, JitterBuffer};
+use crate::rtp_packet::{OnvifReplayExtension, RtpPacket};
+use crate::rtp_ports::RtpPortPair;
+use crate::rtp_rtcp::{RtcpReporter, SourceStats};
+use crate::rtp_srtp::SrtpContext;
+use bytes::{Bytes, BytesMut};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{Error, ErrorKind, Result};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+/// Interval of the receiver reports, the minimum of RFC 3550 section 6.2
+pub(crate) const RTCP_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval of the packets keeping the NAT bindings of the client ports open, below the common
+/// UDP binding timeout of 30 seconds
+const NAT_PUNCH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The RTP punch packet has an unassigned payload type, so the server drops it (RFC 6263 section
+/// 4.1.2)
+const NAT_PUNCH_PAYLOAD_TYPE: u8 = 20;
+
+/// `RtpReceiver` owns the UDP sockets bound to the client RTP and RTCP ports announced in SETUP,
+/// or to the ports of the multicast group, and the task reading from them once the stream is
+/// playing.
+///
+/// Properties:
+///
+/// * `socket`: The socket bound to the client RTP port, or to the RTP port of the group.
+/// * `rtcp_socket`: The socket bound to the client RTCP port, or to the RTCP port of the group.
+/// * `srtp`: The SRTP context of an `RTP/SAVP` media, kept across PLAY requests.
+/// * `interleaved`: The RTP and RTCP channels of the media interleaved in the RTSP connection.
+/// * `rtcp`: The statistics of the sources and the receiver reports sent back to them.
+/// * `depacketizer`: The jitter buffer and the depacketizer putting the frames of the media
+///   together, and the recorder they are written to, if supported.
+/// * `frames`: The count of the frames put together.
+/// * `clock_rate`: The RTP clock rate of the media.
+/// * `rtcp_destination`: The address the receiver reports are sent to over UDP.
+/// * `punch_destination`: The RTP address of the server the NAT bindings are opened to, the
+///   server addresses are latched to the source of the media when set.
+/// * `replay`: The CSeq of the PLAY request which started the stream and the replay position.
+/// * `task`: The receiving task, `None` until `start` is called.
+#[derive(Debug)]
+pub(crate) struct RtpReceiver {
+    socket: Arc<UdpSocket>,
+    rtcp_socket: Arc<UdpSocket>,
+    srtp: Option<Arc<Mutex<SrtpContext>>>,
+    interleaved: Option<(u8, u8)>,
+    rtcp: Arc<Mutex<RtcpReporter>>,
+    depacketizer: SharedDepacketizer,
+    frames: Arc<Mutex<FrameStats>>,
+    clock_rate: u32,
+    rtcp_destination: Option<SocketAddr>,
+    punch_destination: Option<SocketAddr>,
+    replay: Arc<Mutex<ReplayPosition>>,
+    task: Option<JoinHandle<()>>,
+}
+
+/// The SRTP context shared by the receiving task and the interleaved packets
+type SharedSrtp = Option<Arc<Mutex<SrtpContext>>>;
+
+/// `Depacketization` puts the frames of the media together from the packets received.
+///
+/// Properties:
+///
+/// * `jitter`: The jitter buffer putting the packets back in order.
+/// * `depacketizer`: The depacketizer of the payload format of the media.
+/// * `timestamp`: The unwrapped RTP timestamp of the last packet released by the jitter buffer.
+/// * `recorder`: The index of the track and the recorder the frames are written to.
+#[derive(Debug)]
+struct Depacketization {
+    jitter: JitterBuffer,
+    depacketizer: Depacketizer,
+    timestamp: u64,
+    recorder: Option<(usize, SharedRecorder)>,
+}
+
+/// The depacketization shared by the receiving task and the interleaved packets
+type SharedDepacketizer = Option<Arc<Mutex<Depacketization>>>;
+
+/// `ReplayPosition` follows the ONVIF replay extension of the packets of a stream, so only the
+/// changes are printed.
+///
+/// Properties:
+///
+/// * `play_c_seq`: The CSeq of the PLAY request which started the stream.
+/// * `last`: The replay extension of the last packet of the stream, `None` before the first.
+#[derive(Debug, Default)]
+struct ReplayPosition {
+    play_c_seq: u16,
+    last: Option<OnvifReplayExtension>,
+}
+
+impl RtpReceiver {
+    /// It takes the sockets of the client RTP and RTCP ports, packets are queued by the OS until
+    /// `start` is called
+    ///
+    /// Arguments:
+    ///
+    /// * `ports`: RtpPortPair, the client RTP and RTCP ports
+    /// * `server`: Option<SocketAddr>, the RTCP address of the server the reports are sent to
+    /// * `interleaved`: Option<(u8, u8)>, the channels of the media if it is interleaved in the
+    ///   RTSP connection
+    /// * `srtp`: Option<SrtpContext>, the context decrypting SRTP and SRTCP, `None` for RTP
+    /// * `clock_rate`: u32, the RTP clock rate of the media
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpReceiver>.
+    pub(crate) fn bind(
+        ports: RtpPortPair,
+        server: Option<SocketAddr>,
+        interleaved: Option<(u8, u8)>,
+        srtp: Option<SrtpContext>,
+        clock_rate: u32,
+    ) -> Result<Self> {
+        let (socket, rtcp_socket) = ports.into_sockets()?;
+        let mut receiver = Self::new(socket, rtcp_socket, interleaved, srtp, clock_rate);
+        receiver.rtcp_destination = server.filter(|_| interleaved.is_none());
+        Ok(receiver)
+    }
+
+    /// It joins the multicast group announced in the SETUP response, with IGMP for IPv4 and MLD
+    /// for IPv6. The ports are shared, so several clients of the same host can receive the group
+    ///
+    /// Arguments:
+    ///
+    /// * `group`: IpAddr, the multicast group
+    /// * `ports`: (u16, u16), the RTP and RTCP ports of the group
+    /// * `interface`: Option<&str>, the IPv4 address or IPv6 index of the interface joining the
+    ///   group, `None` to let the OS choose
+    /// * `ttl`: Option<u8>, the time-to-live of the receiver reports sent to the group
+    /// * `srtp`: Option<SrtpContext>, the context decrypting SRTP and SRTCP, `None` for RTP
+    /// * `clock_rate`: u32, the RTP clock rate of the media
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpReceiver>.
+    pub(crate) fn join(
+        group: IpAddr,
+        ports: (u16, u16),
+        interface: Option<&str>,
+        ttl: Option<u8>,
+        srtp: Option<SrtpContext>,
+        clock_rate: u32,
+    ) -> Result<Self> {
+        if !group.is_multicast() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a multicast group", group),
+            ));
+        }
+        let socket = Self::join_port(group, ports.0, interface)?;
+        let rtcp_socket = Self::join_port(group, ports.1, interface)?;
+        // The reports are sent to the group, for the server and the other receivers
+        if let Some(ttl) = ttl {
+            match group {
+                IpAddr::V4(_) => rtcp_socket.set_multicast_ttl_v4(ttl as u32)?,
+                IpAddr::V6(_) => {
+                    socket2::SockRef::from(&rtcp_socket).set_multicast_hops_v6(ttl as u32)?
+                }
+            }
+        }
+        println!(
+            "Joined multicast group {} on ports {}-{}",
+            group, ports.0, ports.1
+        );
+        let mut receiver = Self::new(socket, rtcp_socket, None, srtp, clock_rate);
+        receiver.rtcp_destination = Some(SocketAddr::new(group, ports.1));
+        Ok(receiver)
+    }
+
+    fn join_port(group: IpAddr, port: u16, interface: Option<&str>) -> Result<UdpSocket> {
+        let invalid = |interface: &str| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid multicast interface {}", interface),
+            )
+        };
+        let socket = Socket::new(
+            Domain::for_address(SocketAddr::new(group, port)),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        match group {
+            IpAddr::V4(group) => {
+                let interface = match interface {
+                    Some(interface) => interface.parse().map_err(|_| invalid(interface))?,
+                    None => Ipv4Addr::UNSPECIFIED,
+                };
+                socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port).into())?;
+                socket.join_multicast_v4(&group, &interface)?;
+            }
+            IpAddr::V6(group) => {
+                let interface = match interface {
+                    Some(interface) => interface.parse().map_err(|_| invalid(interface))?,
+                    None => 0,
+                };
+                socket.set_only_v6(true)?;
+                socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+                socket.join_multicast_v6(&group, interface)?;
+            }
+        }
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket.into())
+    }
+
+    fn new(
+        socket: UdpSocket,
+        rtcp_socket: UdpSocket,
+        interleaved: Option<(u8, u8)>,
+        srtp: Option<SrtpContext>,
+        clock_rate: u32,
+    ) -> Self {
+        Self {
+            socket: Arc::new(socket),
+            rtcp_socket: Arc::new(rtcp_socket),
+            srtp: srtp.map(|srtp| Arc::new(Mutex::new(srtp))),
+            interleaved,
+            rtcp: Arc::new(Mutex::new(RtcpReporter::new(clock_rate))),
+            depacketizer: None,
+            frames: Arc::new(Mutex::new(FrameStats::default())),
+            clock_rate,
+            rtcp_destination: None,
+            punch_destination: None,
+            replay: Arc::default(),
+            task: None,
+        }
+    }
+
+    /// It puts the frames of the media together from the packets received, once the jitter
+    /// buffer put them back in order
+    ///
+    /// Arguments:
+    ///
+    /// * `depacketizer`: Depacketizer, the depacketizer of the payload format of the media
+    /// * `latency`: Duration, the time a packet waits for the packets before it
+    pub(crate) fn depacketize(&mut self, depacketizer: Depacketizer, latency: Duration) {
+        self.depacketizer = Some(Arc::new(Mutex::new(Depacketization {
+            jitter: JitterBuffer::new(latency),
+            depacketizer,
+            timestamp: 0,
+            recorder: None,
+        })));
+    }
+
+    /// It records the frames put together, the media is not recorded if its payload format is
+    /// not supported
+    ///
+    /// Arguments:
+    ///
+    /// * `track`: usize, the index of the track given to the recorder
+    /// * `recorder`: SharedRecorder, the recorder
+    pub(crate) fn record(&mut self, track: usize, recorder: SharedRecorder) {
+        if let Some(ref depacketizer) = self.depacketizer {
+            depacketizer.lock().unwrap().recorder = Some((track, recorder));
+        }
+    }
+
+    /// It opens the NAT bindings of the client ports, so the media of the server is let in: a
+    /// punch packet is sent from the RTP port and a receiver report from the RTCP port. The task
+    /// repeats them and latches the server addresses to the source of the media (symmetric RTP).
+    /// Only the host of the server may be latched to, the packets of other hosts are dropped so
+    /// a stray or spoofed packet cannot take the media path
+    ///
+    /// Arguments:
+    ///
+    /// * `server`: SocketAddr, the RTP address of the server given by SETUP
+    pub(crate) async fn punch(&mut self, server: SocketAddr) -> Result<()> {
+        self.punch_destination = Some(server);
+        self.socket.send_to(&self.punch_packet(), server).await?;
+        if let Some(rtcp_destination) = self.rtcp_destination {
+            let report = Self::report(&self.srtp, &self.rtcp)?;
+            self.rtcp_socket.send_to(&report, rtcp_destination).await?;
+        }
+        println!("Punched NAT bindings to {}", server);
+        Ok(())
+    }
+
+    /// An RTP header of the SSRC of the client without payload
+    fn punch_packet(&self) -> Bytes {
+        let packet = RtpPacket {
+            marker: false,
+            payload_type: NAT_PUNCH_PAYLOAD_TYPE,
+            sequence_number: 0,
+            timestamp: 0,
+            ssrc: self.rtcp.lock().unwrap().ssrc(),
+            csrcs: vec![],
+            extension: None,
+            payload: Bytes::new(),
+        };
+        let mut buf = BytesMut::new();
+        packet.assemble(&mut buf);
+        buf.freeze()
+    }
+
+    /// It spawns the task receiving the RTP and RTCP packets of the stream started by the PLAY
+    /// request with the given CSeq, and sending the receiver reports and NAT punches over UDP
+    ///
+    /// Arguments:
+    ///
+    /// * `play_c_seq`: u16, the CSeq of the PLAY request
+    pub(crate) fn start(&mut self, play_c_seq: u16) {
+        *self.replay.lock().unwrap() = ReplayPosition {
+            play_c_seq,
+            last: None,
+        };
+        let replay = self.replay.clone();
+        let socket = self.socket.clone();
+        let rtcp_socket = self.rtcp_socket.clone();
+        let srtp = self.srtp.clone();
+        let rtcp = self.rtcp.clone();
+        let depacketizer = self.depacketizer.clone();
+        let frames = self.frames.clone();
+        let clock_rate = self.clock_rate;
+        let mut rtcp_peer = self.rtcp_destination;
+        let mut rtp_peer = self.punch_destination;
+        let latching = rtp_peer.is_some();
+        let server_ip = rtp_peer.map(|peer| peer.ip());
+        let punch_packet = self.punch_packet();
+        self.task = Some(tokio::spawn(async move {
+            let mut buf = vec![0; 65536];
+            let mut rtcp_buf = vec![0; 65536];
+            let now = tokio::time::Instant::now();
+            let mut interval =
+                tokio::time::interval_at(now + RTCP_REPORT_INTERVAL, RTCP_REPORT_INTERVAL);
+            let mut punch_interval =
+                tokio::time::interval_at(now + NAT_PUNCH_INTERVAL, NAT_PUNCH_INTERVAL);
+            loop {
+                // The packets waiting for lost ones are released once the latency expired
+                let deadline = depacketizer
+                    .as_ref()
+                    .and_then(|depacketizer| depacketizer.lock().unwrap().jitter.deadline());
+                tokio::select! {
+                    received = socket.recv_from(&mut buf) => {
+                        let (len, source) = match received {
+                            Ok(received) => received,
+                            Err(err) => {
+                                println!("Receive RTP packet failed with error: {:?}", err);
+                                break;
+                            }
+                        };
+                        if server_ip.is_some_and(|ip| ip != source.ip()) {
+                            continue;
+                        }
+                        // Only valid packets move the latched address, which is punched at once
+                        let received =
+                            Self::receive_rtp(&srtp, &rtcp, &depacketizer, &frames, clock_rate, &buf[..len], &replay);
+                        if received && latching
+                            && rtp_peer != Some(source)
+                        {
+                            println!("RTP latched to {}", source);
+                            rtp_peer = Some(source);
+                            if let Err(err) = socket.send_to(&punch_packet, source).await {
+                                println!("Send NAT punch failed with error: {:?}", err);
+                            }
+                        }
+                    }
+                    received = rtcp_socket.recv_from(&mut rtcp_buf) => {
+                        let (len, source) = match received {
+                            Ok(received) => received,
+                            Err(err) => {
+                                println!("Receive RTCP packet failed with error: {:?}", err);
+                                break;
+                            }
+                        };
+                        if server_ip.is_some_and(|ip| ip != source.ip()) {
+                            continue;
+                        }
+                        if Self::receive_rtcp(&srtp, &rtcp, &rtcp_buf[..len])
+                            && latching
+                            && rtcp_peer != Some(source)
+                        {
+                            println!("RTCP latched to {}", source);
+                            rtcp_peer = Some(source);
+                        }
+                    }
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                        if let Some(ref depacketizer) = depacketizer {
+                            Self::depacketize_rtp(&mut depacketizer.lock().unwrap(), &frames, clock_rate);
+                        }
+                    }
+                    _ = interval.tick(), if rtcp_peer.is_some() => {
+                        let report = match Self::report(&srtp, &rtcp) {
+                            Ok(report) => report,
+                            Err(err) => {
+                                println!("Build RTCP report failed with error: {:?}", err);
+                                continue;
+                            }
+                        };
+                        if let Err(err) = rtcp_socket.send_to(&report, rtcp_peer.unwrap()).await {
+                            println!("Send RTCP report failed with error: {:?}", err);
+                        }
+                    }
+                    // The receiver reports keep the RTCP binding open
+                    _ = punch_interval.tick(), if latching => {
+                        if let Err(err) = socket.send_to(&punch_packet, rtp_peer.unwrap()).await {
+                            println!("Send NAT punch failed with error: {:?}", err);
+                        }
+                    }
+                }
+            }
+        }));
+    }
+
+    /// It stops the receiving task
+    pub(crate) fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+
+    /// It receives a packet interleaved in the RTSP connection, packets of the channels of other
+    /// tracks are ignored
+    ///
+    /// Arguments:
+    ///
+    /// * `channel`: u8, the interleaved channel
+    /// * `payload`: &[u8], the RTP or RTCP packet
+    pub(crate) fn receive_interleaved(&self, channel: u8, payload: &[u8]) {
+        match self.interleaved {
+            Some((rtp, _)) if channel == rtp => {
+                Self::receive_rtp(
+                    &self.srtp,
+                    &self.rtcp,
+                    &self.depacketizer,
+                    &self.frames,
+                    self.clock_rate,
+                    payload,
+                    &self.replay,
+                );
+            }
+            Some((_, rtcp)) if channel == rtcp => {
+                Self::receive_rtcp(&self.srtp, &self.rtcp, payload);
+            }
+            _ => {}
+        }
+    }
+
+    /// The receiver report of a media interleaved in the RTSP connection, which is sent by the
+    /// RTSP session
+    ///
+    /// Returns:
+    ///
+    /// The RTCP channel and the report, `None` if the media is received over UDP.
+    pub(crate) fn interleaved_report(&self) -> Option<(u8, Bytes)> {
+        let (_, channel) = self.interleaved?;
+        match Self::report(&self.srtp, &self.rtcp) {
+            Ok(report) => Some((channel, report)),
+            Err(err) => {
+                println!("Build RTCP report failed with error: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// The reception statistics of each source of the media, by SSRC
+    pub(crate) fn stats(&self) -> HashMap<u32, SourceStats> {
+        self.rtcp.lock().unwrap().sources().clone()
+    }
+
+    /// The count of the frames put together, `None` if the payload format is not supported
+    pub(crate) fn frames(&self) -> Option<FrameStats> {
+        self.depacketizer
+            .as_ref()
+            .map(|_| *self.frames.lock().unwrap())
+    }
+
+    fn receive_rtp(
+        srtp: &SharedSrtp,
+        rtcp: &Mutex<RtcpReporter>,
+        depacketizer: &SharedDepacketizer,
+        frames: &Mutex<FrameStats>,
+        clock_rate: u32,
+        buf: &[u8],
+        replay: &Mutex<ReplayPosition>,
+    ) -> bool {
+        let arrival = Instant::now();
+        let packet = match srtp {
+            Some(srtp) => srtp.lock().unwrap().unprotect_rtp(buf),
+            None => Ok(Bytes::copy_from_slice(buf)),
+        };
+        match packet.and_then(RtpPacket::parse) {
+            Ok(packet) => {
+                rtcp.lock().unwrap().receive_rtp(&packet, arrival);
+                for line in replay.lock().unwrap().report(&packet) {
+                    println!("{}", line);
+                }
+                if let Some(depacketizer) = depacketizer {
+                    let mut depacketizer = depacketizer.lock().unwrap();
+                    if !depacketizer.jitter.push(packet, arrival) {
+                        println!("Drop late or duplicate RTP packet");
+                    }
+                    Self::depacketize_rtp(&mut depacketizer, frames, clock_rate);
+                }
+                true
+            }
+            Err(err) => {
+                println!("Drop invalid RTP packet: {:?}", err);
+                false
+            }
+        }
+    }
+
+    /// The packets released by the jitter buffer are depacketized, the frames completed are
+    /// counted, recorded and the metadata events are printed. A packet the depacketizer cannot
+    /// handle is still valid RTP
+    fn depacketize_rtp(
+        depacketization: &mut Depacketization,
+        frames: &Mutex<FrameStats>,
+        clock_rate: u32,
+    ) {
+        while let Some(packet) = depacketization.jitter.pull(Instant::now()) {
+            frames.lock().unwrap().release(&packet, clock_rate);
+            depacketization.timestamp = packet.timestamp;
+            if let Err(err) = depacketization.depacketizer.push(&packet.packet) {
+                println!("Drop invalid RTP payload: {:?}", err);
+            }
+        }
+        while let Some(frame) = depacketization.depacketizer.pull() {
+            frames.lock().unwrap().count(&frame);
+            if let Some((track, ref recorder)) = depacketization.recorder {
+                let timestamp = extend_timestamp(depacketization.timestamp, frame.timestamp());
+                if let Err(err) = recorder.lock().unwrap().write(track, timestamp, &frame) {
+                    println!("Record frame failed with error: {:?}", err);
+                }
+            }
+            if let Frame::Metadata(ref metadata) = frame {
+                metadata
+                    .events
+                    .iter()
+                    .for_each(|event| println!("{}", event));
+            }
+        }
+    }
+
+    /// The sender reports of the server give the wall clock of its sources, SRTCP is decrypted
+    /// first
+    fn receive_rtcp(srtp: &SharedSrtp, rtcp: &Mutex<RtcpReporter>, buf: &[u8]) -> bool {
+        let arrival = Instant::now();
+        let packet = match srtp {
+            Some(srtp) => srtp.lock().unwrap().unprotect_rtcp(buf),
+            None => Ok(Bytes::copy_from_slice(buf)),
+        };
+        match packet.and_then(|packet| rtcp.lock().unwrap().receive_rtcp(&packet, arrival)) {
+            Ok(_) => true,
+            Err(err) => {
+                println!("Drop invalid RTCP packet: {:?}", err);
+                false
+            }
+        }
+    }
+
+    /// The receiver report of the sources, protected with SRTCP for `RTP/SAVP`
+    fn report(srtp: &SharedSrtp, rtcp: &Mutex<RtcpReporter>) -> Result<Bytes> {
+        let report = rtcp.lock().unwrap().report(Instant::now());
+        match srtp {
+            Some(srtp) => srtp.lock().unwrap().protect_rtcp(&report),
+            None => Ok(report),
+        }
+    }
+}
+
+impl ReplayPosition {
+    /// It tells where the replay starts, then the discontinuities and the ends of the contiguous
+    /// sections, once each as the packets of an access unit carry the same flags. Packets
+    /// belonging to an earlier PLAY request are ignored
+    ///
+    /// Returns:
+    ///
+    /// The lines to print, none while the flags stay the same.
+    fn report(&mut self, packet: &RtpPacket) -> Vec<String> {
+        let replay = match packet.onvif_replay_extension() {
+            Some(replay) if replay.c_seq == self.play_c_seq as u8 => replay,
+            _ => return vec![],
+        };
+        let mut lines = vec![];
+        match self.last {
+            None => lines.push(format!("Replay started at {}", replay.time())),
+            Some(ref last) => {
+                if replay.discontinuity && !last.discontinuity {
+                    lines.push(format!("Replay discontinuity before {}", replay.time()));
+                }
+                if replay.end && !last.end {
+                    lines.push(format!("Replay section ended at {}", replay.time()));
+                }
+            }
+        }
+        self.last = Some(replay);
+        lines
+    }
+}
+
+/// A packet with the ONVIF replay extension of 2022-05-18T10:15:00Z, its flags and CSeq
+#[cfg(test)]
+fn test_replay_packet(flags: u8, c_seq: u8) -> RtpPacket {
+    let mut buf = vec![0x90, 0x60, 0, 1, 0, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef];
+    buf.extend_from_slice(&[0xab, 0xac, 0x00, 0x03, 0xe6, 0x2f, 0x45, 0xa4, 0, 0, 0, 0]);
+    buf.extend_from_slice(&[flags, c_seq, 0, 0, 0x65]);
+    RtpPacket::parse(Bytes::from(buf)).unwrap()
+}
+
+#[test]
+fn test_replay_position() {
+    let mut replay = ReplayPosition {
+        play_c_seq: 0x105,
+        last: None,
+    };
+    // A stream of an earlier PLAY request is ignored
+    assert!(replay.report(&test_replay_packet(0x80, 4)).is_empty());
+    assert_eq!(
+        replay.report(&test_replay_packet(0x80, 5)),
+        ["Replay started at 2022-05-18 10:15:00 UTC"]
+    );
+
+    // The packets of a key frame and the next frames carry no change
+    for flags in [0x80, 0x00, 0x00] {
+        assert!(replay.report(&test_replay_packet(flags, 5)).is_empty());
+    }
+    assert_eq!(
+        replay.report(&test_replay_packet(0x40, 5)),
+        ["Replay section ended at 2022-05-18 10:15:00 UTC"]
+    );
+    assert_eq!(
+        replay.report(&test_replay_packet(0xa0, 5)),
+        ["Replay discontinuity before 2022-05-18 10:15:00 UTC"]
+    );
+    assert!(replay.report(&test_replay_packet(0xa0, 5)).is_empty());
+}
+
+#[tokio::test]
+async fn test_punch_nat_bindings() {
+    use crate::rtp_ports::DEFAULT_PORT_RANGE;
+
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_rtcp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let ports = RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT_RANGE).unwrap();
+    let port = ports.port();
+    let mut receiver = RtpReceiver::bind(
+        ports,
+        Some(server_rtcp.local_addr().unwrap()),
+        None,
+        None,
+        90000,
+    )
+    .unwrap();
+    receiver.punch(server.local_addr().unwrap()).await.unwrap();
+
+    // An RTP header of an unassigned payload type from the RTP port, a report from the RTCP port
+    let mut buf = [0; 1500];
+    let (len, source) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!((len, source.port()), (12, port));
+    assert_eq!(buf[1] & 0x7f, NAT_PUNCH_PAYLOAD_TYPE);
+    let (len, source) = server_rtcp.recv_from(&mut buf).await.unwrap();
+    assert_eq!(source.port(), port + 1);
+    assert!(crate::rtp_rtcp::RtcpPacket::parse_compound(&buf[..len]).is_ok());
+
+    receiver.stop();
+}
+
+#[tokio::test]
+async fn test_punch_nat_bindings_ipv6() {
+    use crate::rtp_ports::DEFAULT_PORT_RANGE;
+
+    // The ports of an IPv6 server are bound to `::`, a socket of `0.0.0.0` cannot reach it
+    let server = UdpSocket::bind("[::1]:0").await.unwrap();
+    let ports = RtpPortPair::allocate(Ipv6Addr::UNSPECIFIED.into(), DEFAULT_PORT_RANGE).unwrap();
+    let port = ports.port();
+    let mut receiver = RtpReceiver::bind(ports, None, None, None, 90000).unwrap();
+    receiver.punch(server.local_addr().unwrap()).await.unwrap();
+    let mut buf = [0; 1500];
+    let (len, source) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!((len, source), (12, (Ipv6Addr::LOCALHOST, port).into()));
+    receiver.stop();
+}
+
+#[tokio::test]
+async fn test_latch_server_host_only() {
+    use crate::rtp_ports::DEFAULT_PORT_RANGE;
+
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let ports = RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT_RANGE).unwrap();
+    let port = ports.port();
+    let mut receiver = RtpReceiver::bind(ports, None, None, None, 90000).unwrap();
+    receiver.punch(server.local_addr().unwrap()).await.unwrap();
+    let mut buf = [0; 1500];
+    server.recv_from(&mut buf).await.unwrap();
+    receiver.start(1);
+
+    // Another host is ignored, even with a valid packet
+    let stray = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+    let packet = [0x80, 0x60, 0, 1, 0, 0, 0, 0, 0xba, 0xad, 0xf0, 0x0d, 0x65];
+    stray.send_to(&packet, ("127.0.0.1", port)).await.unwrap();
+
+    // The media may come from another port of the server host, which is latched and punched
+    let media = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let packet = [0x80, 0x60, 0, 1, 0, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef, 0x65];
+    media.send_to(&packet, ("127.0.0.1", port)).await.unwrap();
+    let (len, source) = tokio::time::timeout(Duration::from_secs(1), media.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((len, source.port()), (12, port));
+    assert_eq!(buf[1] & 0x7f, NAT_PUNCH_PAYLOAD_TYPE);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), stray.recv_from(&mut buf))
+            .await
+            .is_err()
+    );
+    // The first packet of a source is on probation, it is known but not counted yet
+    let stats = receiver.stats();
+    assert_eq!(stats[&0xdeadbeef].received(), 0);
+    assert!(!stats.contains_key(&0xbaadf00d));
+    receiver.stop();
+}
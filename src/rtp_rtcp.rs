@@ -0,0 +1,969 @@
+use crate::rtp_packet::{ntp_to_utc, random_u32, RtpPacket};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::io::{Error, Result};
+
+/// RTCP packet types (RFC 3550 section 12.1)
+const RTCP_SR: u8 = 200;
+const RTCP_RR: u8 = 201;
+const RTCP_SDES: u8 = 202;
+const RTCP_BYE: u8 = 203;
+const RTCP_APP: u8 = 204;
+
+/// The SDES item ending a chunk, and the canonical name every compound packet carries
+const SDES_END: u8 = 0;
+const SDES_CNAME: u8 = 1;
+
+/// The packets in sequence making a new source valid (RFC 3550 appendix A.1)
+const MIN_SEQUENTIAL: u32 = 2;
+
+/// The largest jump ahead of the sequence number taken as packets lost
+const MAX_DROPOUT: u16 = 3000;
+
+/// The largest step back of the sequence number taken as packets reordered
+const MAX_MISORDER: u16 = 100;
+
+/// `ReportBlock` is the reception report of one source, carried by SR and RR packets (RFC 3550
+/// section 6.4.1).
+///
+/// Properties:
+///
+/// * `ssrc`: The source the report is about.
+/// * `fraction_lost`: The fraction of packets lost since the previous report, in 1/256.
+/// * `cumulative_lost`: The packets lost since the beginning of reception, 24 bits signed.
+/// * `highest_sequence`: The extended highest sequence number received.
+/// * `jitter`: The interarrival jitter, in timestamp units.
+/// * `last_sr`: The middle 32 bits of the NTP timestamp of the last SR received, 0 if none.
+/// * `delay_since_last_sr`: The delay since the last SR was received, in 1/65536 seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReportBlock {
+    pub(crate) ssrc: u32,
+    pub(crate) fraction_lost: u8,
+    pub(crate) cumulative_lost: i32,
+    pub(crate) highest_sequence: u32,
+    pub(crate) jitter: u32,
+    pub(crate) last_sr: u32,
+    pub(crate) delay_since_last_sr: u32,
+}
+
+/// `SdesChunk` holds the source description items of one source, e.g. its CNAME.
+///
+/// Properties:
+///
+/// * `ssrc`: The source described.
+/// * `items`: The type and text of each item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SdesChunk {
+    pub(crate) ssrc: u32,
+    pub(crate) items: Vec<(u8, String)>,
+}
+
+/// `RtcpPacket` is one packet of an RTCP compound packet (RFC 3550 section 6), packets of other
+/// types, e.g. feedback or extended reports, are skipped when parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RtcpPacket {
+    /// SR, the sender info maps the RTP timestamp of the sender to its NTP wall clock
+    SenderReport {
+        ssrc: u32,
+        ntp_timestamp: u64,
+        rtp_timestamp: u32,
+        packet_count: u32,
+        octet_count: u32,
+        reports: Vec<ReportBlock>,
+    },
+    /// RR of a participant sending no media
+    ReceiverReport {
+        ssrc: u32,
+        reports: Vec<ReportBlock>,
+    },
+    /// SDES
+    SourceDescription(Vec<SdesChunk>),
+    /// BYE, the sources left the session
+    Goodbye {
+        sources: Vec<u32>,
+        reason: Option<String>,
+    },
+    /// APP, defined by the application given by the name
+    App {
+        subtype: u8,
+        ssrc: u32,
+        name: [u8; 4],
+        data: Bytes,
+    },
+}
+
+impl ReportBlock {
+    fn parse(buf: &mut &[u8]) -> Self {
+        let ssrc = buf.get_u32();
+        let lost = buf.get_u32();
+        Self {
+            ssrc,
+            fraction_lost: (lost >> 24) as u8,
+            // Sign extension of the 24 bits
+            cumulative_lost: ((lost << 8) as i32) >> 8,
+            highest_sequence: buf.get_u32(),
+            jitter: buf.get_u32(),
+            last_sr: buf.get_u32(),
+            delay_since_last_sr: buf.get_u32(),
+        }
+    }
+
+    fn assemble(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.ssrc);
+        buf.put_u32((self.fraction_lost as u32) << 24 | (self.cumulative_lost as u32 & 0xff_ffff));
+        buf.put_u32(self.highest_sequence);
+        buf.put_u32(self.jitter);
+        buf.put_u32(self.last_sr);
+        buf.put_u32(self.delay_since_last_sr);
+    }
+}
+
+impl RtcpPacket {
+    /// It parses an RTCP compound packet, the padding of the last packet is removed
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &[u8], the datagram received, or the SRTCP packet once decrypted
+    ///
+    /// Returns:
+    ///
+    /// A Result<Vec<RtcpPacket>>.
+    pub(crate) fn parse_compound(mut buf: &[u8]) -> Result<Vec<Self>> {
+        let mut packets = vec![];
+        while !buf.is_empty() {
+            // Header:
+            //      V(2) P(1) count(5) | packet type(8) | length in 32-bit words minus one(16)
+            if buf.len() < 4 || buf[0] >> 6 != 2 {
+                return Err(Error::other("Invalid RTCP packet"));
+            }
+            let len = (u16::from_be_bytes([buf[2], buf[3]]) as usize + 1) * 4;
+            if buf.len() < len {
+                return Err(Error::other("RTCP packet exceeds compound packet"));
+            }
+            let (mut packet, rest) = buf.split_at(len);
+            buf = rest;
+            let count = (packet[0] & 0x1f) as usize;
+            let packet_type = packet[1];
+            if packet[0] & 0x20 != 0 {
+                let padding_len = packet[len - 1] as usize;
+                if padding_len == 0 || padding_len > len - 4 {
+                    return Err(Error::other("Invalid RTCP padding"));
+                }
+                packet = &packet[..len - padding_len];
+            }
+            packet.advance(4);
+            if let Some(packet) = Self::parse_packet(packet_type, count, packet)? {
+                packets.push(packet);
+            }
+        }
+        Ok(packets)
+    }
+
+    fn parse_packet(packet_type: u8, count: usize, mut buf: &[u8]) -> Result<Option<Self>> {
+        let too_short = || Error::other(format!("RTCP packet {} too short", packet_type));
+        let packet = match packet_type {
+            RTCP_SR => {
+                // SSRC | NTP timestamp(64) | RTP timestamp | packet count | octet count | reports
+                if buf.len() < 24 + count * 24 {
+                    return Err(too_short());
+                }
+                let ssrc = buf.get_u32();
+                let ntp_timestamp = buf.get_u64();
+                let rtp_timestamp = buf.get_u32();
+                let packet_count = buf.get_u32();
+                let octet_count = buf.get_u32();
+                RtcpPacket::SenderReport {
+                    ssrc,
+                    ntp_timestamp,
+                    rtp_timestamp,
+                    packet_count,
+                    octet_count,
+                    reports: (0..count).map(|_| ReportBlock::parse(&mut buf)).collect(),
+                }
+            }
+            RTCP_RR => {
+                if buf.len() < 4 + count * 24 {
+                    return Err(too_short());
+                }
+                RtcpPacket::ReceiverReport {
+                    ssrc: buf.get_u32(),
+                    reports: (0..count).map(|_| ReportBlock::parse(&mut buf)).collect(),
+                }
+            }
+            RTCP_SDES => {
+                // Chunks: SSRC | items of type(8) length(8) text | null items up to a word
+                let mut chunks = vec![];
+                for _ in 0..count {
+                    if buf.len() < 4 {
+                        return Err(too_short());
+                    }
+                    let start = buf.len();
+                    let ssrc = buf.get_u32();
+                    let mut items = vec![];
+                    loop {
+                        match buf.first() {
+                            None => return Err(too_short()),
+                            Some(&SDES_END) => {
+                                // The padding ends the chunk on a 32-bit boundary
+                                let consumed = start - buf.len() + 1;
+                                let padding = (4 - consumed % 4) % 4;
+                                buf.advance((1 + padding).min(buf.len()));
+                                break;
+                            }
+                            Some(&item_type) => {
+                                let len = *buf.get(1).ok_or_else(too_short)? as usize;
+                                let text = buf.get(2..2 + len).ok_or_else(too_short)?;
+                                items.push((item_type, String::from_utf8_lossy(text).into_owned()));
+                                buf.advance(2 + len);
+                            }
+                        }
+                    }
+                    chunks.push(SdesChunk { ssrc, items });
+                }
+                RtcpPacket::SourceDescription(chunks)
+            }
+            RTCP_BYE => {
+                if buf.len() < count * 4 {
+                    return Err(too_short());
+                }
+                let sources = (0..count).map(|_| buf.get_u32()).collect();
+                let reason = match buf.first() {
+                    Some(&len) => {
+                        let reason = buf.get(1..1 + len as usize).ok_or_else(too_short)?;
+                        Some(String::from_utf8_lossy(reason).into_owned())
+                    }
+                    None => None,
+                };
+                RtcpPacket::Goodbye { sources, reason }
+            }
+            RTCP_APP => {
+                if buf.len() < 8 {
+                    return Err(too_short());
+                }
+                let ssrc = buf.get_u32();
+                let mut name = [0; 4];
+                buf.copy_to_slice(&mut name);
+                RtcpPacket::App {
+                    subtype: count as u8,
+                    ssrc,
+                    name,
+                    data: Bytes::copy_from_slice(buf),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(packet))
+    }
+
+    /// It assembles the packet into the buffer, padded to a 32-bit boundary where needed
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &mut BytesMut, the buffer to write the packet to, e.g. after the previous packets
+    ///   of the compound packet
+    ///
+    /// Returns:
+    ///
+    /// The number of bytes written to the buffer.
+    pub(crate) fn assemble(&self, buf: &mut BytesMut) -> usize {
+        let start = buf.len();
+        let (count, packet_type) = match self {
+            RtcpPacket::SenderReport { reports, .. } => (reports.len(), RTCP_SR),
+            RtcpPacket::ReceiverReport { reports, .. } => (reports.len(), RTCP_RR),
+            RtcpPacket::SourceDescription(chunks) => (chunks.len(), RTCP_SDES),
+            RtcpPacket::Goodbye { sources, .. } => (sources.len(), RTCP_BYE),
+            RtcpPacket::App { subtype, .. } => (*subtype as usize, RTCP_APP),
+        };
+        buf.put_u8(0x80 | (count as u8 & 0x1f));
+        buf.put_u8(packet_type);
+        // The length is written once the packet is assembled
+        buf.put_u16(0);
+        match self {
+            RtcpPacket::SenderReport {
+                ssrc,
+                ntp_timestamp,
+                rtp_timestamp,
+                packet_count,
+                octet_count,
+                reports,
+            } => {
+                buf.put_u32(*ssrc);
+                buf.put_u64(*ntp_timestamp);
+                buf.put_u32(*rtp_timestamp);
+                buf.put_u32(*packet_count);
+                buf.put_u32(*octet_count);
+                reports.iter().for_each(|report| report.assemble(buf));
+            }
+            RtcpPacket::ReceiverReport { ssrc, reports } => {
+                buf.put_u32(*ssrc);
+                reports.iter().for_each(|report| report.assemble(buf));
+            }
+            RtcpPacket::SourceDescription(chunks) => {
+                for chunk in chunks {
+                    buf.put_u32(chunk.ssrc);
+                    for (item_type, text) in &chunk.items {
+                        let text = &text.as_bytes()[..text.len().min(255)];
+                        buf.put_u8(*item_type);
+                        buf.put_u8(text.len() as u8);
+                        buf.put_slice(text);
+                    }
+                    // At least one null octet ends the chunk
+                    buf.put_u8(SDES_END);
+                    pad_to_word(buf, start);
+                }
+            }
+            RtcpPacket::Goodbye { sources, reason } => {
+                sources.iter().for_each(|source| buf.put_u32(*source));
+                if let Some(reason) = reason {
+                    let reason = &reason.as_bytes()[..reason.len().min(255)];
+                    buf.put_u8(reason.len() as u8);
+                    buf.put_slice(reason);
+                    pad_to_word(buf, start);
+                }
+            }
+            RtcpPacket::App {
+                ssrc, name, data, ..
+            } => {
+                buf.put_u32(*ssrc);
+                buf.put_slice(name);
+                buf.put_slice(data);
+                pad_to_word(buf, start);
+            }
+        }
+        let len = buf.len() - start;
+        buf[start + 2..start + 4].copy_from_slice(&((len / 4 - 1) as u16).to_be_bytes());
+        len
+    }
+}
+
+/// `SourceStats` holds the reception statistics of one source, as kept by RFC 3550 appendix A.
+///
+/// Properties:
+///
+/// * `clock_rate`: The RTP clock rate of the media, the unit of the jitter.
+/// * `base_seq`: The first sequence number received.
+/// * `max_seq`: The highest sequence number received.
+/// * `bad_seq`: The sequence number following a large jump, the sender restarted if it comes next.
+/// * `probation`: The packets in sequence still needed before the source is valid.
+/// * `cycles`: The count of sequence number wraparounds, shifted by 16 bits.
+/// * `received`: The packets received.
+/// * `octets`: The payload octets received.
+/// * `timestamp`: The RTP timestamp of the last packet received.
+/// * `expected_prior`: The packets expected at the previous report.
+/// * `received_prior`: The packets received at the previous report.
+/// * `first_arrival`: The arrival of the first packet, the origin of the arrival timestamps.
+/// * `transit`: The relative transit time of the previous packet, in timestamp units.
+/// * `jitter`: The interarrival jitter estimate, in timestamp units.
+/// * `sender_report`: The NTP and RTP timestamps of the last SR and the instant it arrived.
+/// * `cname`: The canonical name given by SDES.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceStats {
+    clock_rate: u32,
+    base_seq: u16,
+    max_seq: u16,
+    bad_seq: Option<u16>,
+    probation: u32,
+    cycles: u32,
+    received: u64,
+    octets: u64,
+    timestamp: u32,
+    expected_prior: u64,
+    received_prior: u64,
+    first_arrival: Option<Instant>,
+    transit: Option<u32>,
+    jitter: f64,
+    sender_report: Option<(u64, u32, Instant)>,
+    cname: Option<String>,
+}
+
+impl SourceStats {
+    fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate,
+            base_seq: 0,
+            max_seq: 0,
+            bad_seq: None,
+            probation: MIN_SEQUENTIAL,
+            cycles: 0,
+            received: 0,
+            octets: 0,
+            timestamp: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            first_arrival: None,
+            transit: None,
+            jitter: 0.0,
+            sender_report: None,
+            cname: None,
+        }
+    }
+
+    /// It counts an RTP packet of a valid source and updates the jitter estimate with its
+    /// transit time
+    fn update(&mut self, packet: &RtpPacket, arrival: Instant) {
+        let seq = packet.sequence_number;
+        if self.first_arrival.is_none() {
+            // The first packet of the source starts its probation
+            self.max_seq = seq.wrapping_sub(1);
+        }
+        let first_arrival = *self.first_arrival.get_or_insert(arrival);
+        if !self.update_seq(seq) {
+            return;
+        }
+        self.received += 1;
+        self.octets += packet.payload.len() as u64;
+        self.timestamp = packet.timestamp;
+
+        // D(i-1,i) = (Rj - Ri) - (Sj - Si), J += (|D| - J) / 16
+        let arrival = (arrival - first_arrival).as_secs_f64() * self.clock_rate as f64;
+        let transit = (arrival as u64 as u32).wrapping_sub(packet.timestamp);
+        if let Some(previous) = self.transit {
+            let delta = (transit.wrapping_sub(previous) as i32).unsigned_abs() as f64;
+            self.jitter += (delta - self.jitter) / 16.0;
+        }
+        self.transit = Some(transit);
+    }
+
+    /// It checks the sequence number of a packet (RFC 3550 appendix A.1). A source is valid once
+    /// `MIN_SEQUENTIAL` packets arrived in sequence. A jump by more than `MAX_DROPOUT` or back by
+    /// more than `MAX_MISORDER` is taken as a restart of the sender when the next packet follows
+    /// it, and the counts start over
+    ///
+    /// Returns:
+    ///
+    /// `true` if the packet is counted, late and duplicated packets are counted.
+    fn update_seq(&mut self, seq: u16) -> bool {
+        let udelta = seq.wrapping_sub(self.max_seq);
+        if self.probation > 0 {
+            if seq == self.max_seq.wrapping_add(1) {
+                self.probation -= 1;
+                self.max_seq = seq;
+                if self.probation == 0 {
+                    self.init_seq(seq);
+                    return true;
+                }
+            } else {
+                self.probation = MIN_SEQUENTIAL - 1;
+                self.max_seq = seq;
+            }
+            return false;
+        }
+        if udelta < MAX_DROPOUT {
+            // In order, with permissible gap
+            if seq < self.max_seq {
+                self.cycles += 1 << 16;
+            }
+            self.max_seq = seq;
+        } else if udelta as u32 <= (1 << 16) - MAX_MISORDER as u32 {
+            if self.bad_seq != Some(seq) {
+                self.bad_seq = Some(seq.wrapping_add(1));
+                return false;
+            }
+            // Two sequential packets, the sender restarted
+            self.init_seq(seq);
+        }
+        true
+    }
+
+    /// The counts start over from the sequence number
+    fn init_seq(&mut self, seq: u16) {
+        self.base_seq = seq;
+        self.max_seq = seq;
+        self.bad_seq = None;
+        self.cycles = 0;
+        self.received = 0;
+        self.received_prior = 0;
+        self.expected_prior = 0;
+    }
+
+    /// The extended highest sequence number received
+    fn extended_max(&self) -> u32 {
+        self.cycles + self.max_seq as u32
+    }
+
+    /// The packets expected since the first one counted, none while the source is on probation
+    fn expected(&self) -> u64 {
+        if self.probation > 0 {
+            return 0;
+        }
+        (self.extended_max() as u64 + 1).saturating_sub(self.base_seq as u64)
+    }
+
+    /// The packets lost since the first one received, negative when duplicates arrived
+    pub(crate) fn lost(&self) -> i64 {
+        self.expected() as i64 - self.received as i64
+    }
+
+    /// The packets received
+    pub(crate) fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The payload octets received
+    pub(crate) fn octets(&self) -> u64 {
+        self.octets
+    }
+
+    /// The RTP timestamp of the last packet received
+    pub(crate) fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    /// The interarrival jitter in milliseconds
+    pub(crate) fn jitter_ms(&self) -> f64 {
+        self.jitter * 1000.0 / self.clock_rate as f64
+    }
+
+    /// The canonical name of the source, once described
+    pub(crate) fn cname(&self) -> Option<&str> {
+        self.cname.as_deref()
+    }
+
+    /// It maps an RTP timestamp of the source to its wall clock, with the last SR received
+    ///
+    /// Arguments:
+    ///
+    /// * `rtp_timestamp`: u32, the timestamp of a packet of the source
+    ///
+    /// Returns:
+    ///
+    /// The wall clock time of the sample, `None` before the first SR.
+    pub(crate) fn wall_clock(&self, rtp_timestamp: u32) -> Option<DateTime<Utc>> {
+        let (ntp_timestamp, sr_timestamp, _) = self.sender_report?;
+        let delta = rtp_timestamp.wrapping_sub(sr_timestamp) as i32 as i64;
+        let microseconds = (delta * 1_000_000).checked_div(self.clock_rate as i64)?;
+        Some(ntp_to_utc(ntp_timestamp) + Duration::microseconds(microseconds))
+    }
+
+    /// The reception report of the source, the interval counters restart
+    fn report_block(&mut self, ssrc: u32, now: Instant) -> ReportBlock {
+        let expected = self.expected();
+        let expected_interval = expected - self.expected_prior;
+        let received_interval = self.received - self.received_prior;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            ((lost_interval << 8) / expected_interval as i64).min(255) as u8
+        };
+        let (last_sr, delay_since_last_sr) = match self.sender_report {
+            Some((ntp_timestamp, _, arrival)) => (
+                (ntp_timestamp >> 16) as u32,
+                ((now - arrival).as_secs_f64() * 65536.0) as u32,
+            ),
+            None => (0, 0),
+        };
+        ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: self.lost().clamp(-0x80_0000, 0x7f_ffff) as i32,
+            highest_sequence: self.extended_max(),
+            jitter: self.jitter as u32,
+            last_sr,
+            delay_since_last_sr,
+        }
+    }
+}
+
+/// `RtcpReporter` keeps the statistics of the sources of a media stream and builds the receiver
+/// reports sent back to them.
+///
+/// Properties:
+///
+/// * `ssrc`: The SSRC of the client in its reports.
+/// * `cname`: The canonical name of the client.
+/// * `clock_rate`: The RTP clock rate of the media.
+/// * `sources`: The statistics of each source, by SSRC.
+#[derive(Debug)]
+pub(crate) struct RtcpReporter {
+    ssrc: u32,
+    cname: String,
+    clock_rate: u32,
+    sources: HashMap<u32, SourceStats>,
+}
+
+impl RtcpReporter {
+    /// It creates a reporter with a random SSRC
+    ///
+    /// Arguments:
+    ///
+    /// * `clock_rate`: u32, the RTP clock rate of the media
+    ///
+    /// Returns:
+    ///
+    /// A new RtcpReporter.
+    pub(crate) fn new(clock_rate: u32) -> Self {
+        let ssrc = random_u32();
+        Self {
+            ssrc,
+            cname: format!("rtsp-invitor-{:08x}", ssrc),
+            clock_rate,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// The SSRC of the client
+    pub(crate) fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// The statistics of each source, by SSRC
+    pub(crate) fn sources(&self) -> &HashMap<u32, SourceStats> {
+        &self.sources
+    }
+
+    /// It counts an RTP packet of its source
+    pub(crate) fn receive_rtp(&mut self, packet: &RtpPacket, arrival: Instant) {
+        let clock_rate = self.clock_rate;
+        self.sources
+            .entry(packet.ssrc)
+            .or_insert_with(|| SourceStats::new(clock_rate))
+            .update(packet, arrival);
+    }
+
+    /// It processes an RTCP compound packet of the server: SR give the wall clock of their
+    /// source, SDES the name, BYE ends the source
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &[u8], the compound packet
+    /// * `arrival`: Instant, the time the packet was received
+    ///
+    /// Returns:
+    ///
+    /// A Result<Vec<RtcpPacket>> of the packets parsed.
+    pub(crate) fn receive_rtcp(&mut self, buf: &[u8], arrival: Instant) -> Result<Vec<RtcpPacket>> {
+        let packets = RtcpPacket::parse_compound(buf)?;
+        let clock_rate = self.clock_rate;
+        for packet in &packets {
+            match packet {
+                RtcpPacket::SenderReport {
+                    ssrc,
+                    ntp_timestamp,
+                    rtp_timestamp,
+                    ..
+                } => {
+                    self.sources
+                        .entry(*ssrc)
+                        .or_insert_with(|| SourceStats::new(clock_rate))
+                        .sender_report = Some((*ntp_timestamp, *rtp_timestamp, arrival));
+                }
+                RtcpPacket::SourceDescription(chunks) => {
+                    for chunk in chunks {
+                        let cname = chunk
+                            .items
+                            .iter()
+                            .find(|(item_type, _)| *item_type == SDES_CNAME);
+                        if let (Some(source), Some((_, cname))) =
+                            (self.sources.get_mut(&chunk.ssrc), cname)
+                        {
+                            source.cname = Some(cname.clone());
+                        }
+                    }
+                }
+                RtcpPacket::Goodbye { sources, reason } => {
+                    for source in sources {
+                        println!(
+                            "RTP source {:08x} left: {}",
+                            source,
+                            reason.as_deref().unwrap_or("no reason")
+                        );
+                        self.sources.remove(source);
+                    }
+                }
+                RtcpPacket::App { name, .. } => {
+                    println!("RTCP APP packet {} ignored", String::from_utf8_lossy(name));
+                }
+                RtcpPacket::ReceiverReport { .. } => {}
+            }
+        }
+        Ok(packets)
+    }
+
+    /// The compound packet of the periodic report: RR with a block for each source, followed
+    /// by the CNAME of the client (RFC 3550 section 6.1)
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: Instant, the time the report is sent
+    ///
+    /// Returns:
+    ///
+    /// The compound packet.
+    pub(crate) fn report(&mut self, now: Instant) -> Bytes {
+        // At most 31 report blocks fit in one RR
+        let reports = self
+            .sources
+            .iter_mut()
+            .filter(|(_, source)| source.received > 0)
+            .take(31)
+            .map(|(ssrc, source)| source.report_block(*ssrc, now))
+            .collect();
+        let mut buf = BytesMut::new();
+        RtcpPacket::ReceiverReport {
+            ssrc: self.ssrc,
+            reports,
+        }
+        .assemble(&mut buf);
+        RtcpPacket::SourceDescription(vec![SdesChunk {
+            ssrc: self.ssrc,
+            items: vec![(SDES_CNAME, self.cname.clone())],
+        }])
+        .assemble(&mut buf);
+        buf.freeze()
+    }
+}
+
+/// The packets and SDES chunks end on a 32-bit boundary, padded with null octets
+fn pad_to_word(buf: &mut BytesMut, start: usize) {
+    buf.put_bytes(0, (4 - (buf.len() - start) % 4) % 4);
+}
+
+/// SR with one report block, SDES with a CNAME, BYE with a reason (RFC 3550 section 6)
+#[cfg(test)]
+const TEST_COMPOUND: [u8; 84] = [
+    0x81, 0xc8, 0x00, 0x0c, // SR, 1 report block, 13 words
+    0xde, 0xad, 0xbe, 0xef, // sender SSRC
+    0xe6, 0x2f, 0x45, 0xa4, // NTP seconds: 2022-05-18T10:15:00Z
+    0x80, 0x00, 0x00, 0x00, // NTP fraction: 0.5s
+    0x00, 0x01, 0x5f, 0x90, // RTP timestamp: 90000
+    0x00, 0x00, 0x00, 0x0a, // packet count
+    0x00, 0x00, 0x03, 0xe8, // octet count
+    0x12, 0x34, 0x56, 0x78, // reportee SSRC
+    0x40, 0xff, 0xff, 0xfe, // fraction lost 1/4, cumulative lost -2
+    0x00, 0x01, 0x00, 0x05, // extended highest sequence number
+    0x00, 0x00, 0x00, 0x20, // jitter
+    0x45, 0xa4, 0x80, 0x00, // last SR
+    0x00, 0x01, 0x00, 0x00, // delay since last SR: 1s
+    0x81, 0xca, 0x00, 0x04, // SDES, 1 chunk, 5 words
+    0xde, 0xad, 0xbe, 0xef, // SSRC
+    0x01, 0x06, b'c', b'a', // CNAME "camera"
+    b'm', b'e', b'r', b'a', //
+    0x00, 0x00, 0x00, 0x00, // end of chunk
+    0x81, 0xcb, 0x00, 0x02, // BYE, 1 source, 3 words
+    0xde, 0xad, 0xbe, 0xef, // SSRC
+    0x03, b'e', b'n', b'd', // reason "end"
+];
+
+/// An RTP packet of the source 0xdeadbeef
+#[cfg(test)]
+fn test_packet(seq: u16) -> RtpPacket {
+    RtpPacket {
+        ssrc: 0xdeadbeef,
+        ..RtpPacket::test(seq, 90000, false, &[])
+    }
+}
+
+#[test]
+fn test_rtcp_compound() {
+    let packets = RtcpPacket::parse_compound(&TEST_COMPOUND).unwrap();
+    assert_eq!(packets.len(), 3);
+    match &packets[0] {
+        RtcpPacket::SenderReport {
+            ssrc,
+            rtp_timestamp,
+            packet_count,
+            octet_count,
+            reports,
+            ..
+        } => {
+            assert_eq!((*ssrc, *rtp_timestamp), (0xdeadbeef, 90000));
+            assert_eq!((*packet_count, *octet_count), (10, 1000));
+            assert_eq!(reports[0].fraction_lost, 0x40);
+            assert_eq!(reports[0].cumulative_lost, -2);
+            assert_eq!(reports[0].delay_since_last_sr, 65536);
+        }
+        packet => panic!("Unexpected packet {:?}", packet),
+    }
+    assert_eq!(
+        packets[1],
+        RtcpPacket::SourceDescription(vec![SdesChunk {
+            ssrc: 0xdeadbeef,
+            items: vec![(SDES_CNAME, String::from("camera"))],
+        }])
+    );
+    assert_eq!(
+        packets[2],
+        RtcpPacket::Goodbye {
+            sources: vec![0xdeadbeef],
+            reason: Some(String::from("end")),
+        }
+    );
+    let mut buf = BytesMut::new();
+    packets.iter().for_each(|packet| {
+        packet.assemble(&mut buf);
+    });
+    assert_eq!(&buf[..], &TEST_COMPOUND[..]);
+}
+
+#[test]
+fn test_rtcp_padding_and_unknown_packets() {
+    let compound = [
+        0xa3, 0xcc, 0x00, 0x04, // APP, subtype 3, padding, 5 words
+        0xde, 0xad, 0xbe, 0xef, // SSRC
+        b'o', b'n', b'v', b'f', // name
+        0x01, 0x02, 0x03, 0x04, // data
+        0x05, 0x00, 0x00, 0x03, // data, 3 bytes of padding
+        0x81, 0xcd, 0x00, 0x02, // RTPFB, skipped
+        0xde, 0xad, 0xbe, 0xef, //
+        0x12, 0x34, 0x56, 0x78, //
+        0x80, 0xcb, 0x00, 0x00, // BYE without source
+    ];
+    let packets = RtcpPacket::parse_compound(&compound).unwrap();
+    assert_eq!(
+        packets,
+        [
+            RtcpPacket::App {
+                subtype: 3,
+                ssrc: 0xdeadbeef,
+                name: *b"onvf",
+                data: Bytes::from_static(&[1, 2, 3, 4, 5]),
+            },
+            RtcpPacket::Goodbye {
+                sources: vec![],
+                reason: None,
+            }
+        ]
+    );
+
+    // The APP data is padded with null octets when assembled
+    let mut buf = BytesMut::new();
+    assert_eq!(packets[0].assemble(&mut buf), 20);
+    assert_eq!(&buf[..4], &[0x83, 0xcc, 0x00, 0x04]);
+    assert_eq!(&buf[16..], &[0x05, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn test_rtcp_invalid() {
+    let invalid: [&[u8]; 8] = [
+        // Version 1, shorter than a header, longer than the compound packet
+        &[0x40, 0xc9, 0x00, 0x00],
+        &[0x80, 0xc9, 0x00],
+        &[0x80, 0xc9, 0x00, 0x02, 0xde, 0xad, 0xbe, 0xef],
+        // Padding of 0 bytes, and longer than the packet
+        &[0xa0, 0xc9, 0x00, 0x01, 0xde, 0xad, 0xbe, 0x00],
+        &[0xa0, 0xc9, 0x00, 0x01, 0xde, 0xad, 0xbe, 0x05],
+        // SR without sender info, SDES chunk without end, BYE reason longer than the packet
+        &[0x80, 0xc8, 0x00, 0x01, 0xde, 0xad, 0xbe, 0xef],
+        &[
+            0x81, 0xca, 0x00, 0x02, 0xde, 0xad, 0xbe, 0xef, 0x01, 0x02, b'a', b'b',
+        ],
+        &[
+            0x81, 0xcb, 0x00, 0x02, 0xde, 0xad, 0xbe, 0xef, 0x05, b'e', b'n', b'd',
+        ],
+    ];
+    for packet in invalid {
+        assert!(
+            RtcpPacket::parse_compound(packet).is_err(),
+            "{:02x?}",
+            packet
+        );
+    }
+}
+
+#[test]
+fn test_rtcp_sender_report() {
+    // The SR maps the RTP timestamps of its source to the wall clock
+    let mut reporter = RtcpReporter::new(90000);
+    let start = Instant::now();
+    reporter.receive_rtp(&test_packet(1), start);
+    assert!(reporter.sources()[&0xdeadbeef].wall_clock(90000).is_none());
+    reporter.receive_rtcp(&TEST_COMPOUND[..72], start).unwrap();
+    let source = &reporter.sources()[&0xdeadbeef];
+    assert_eq!(source.cname(), Some("camera"));
+    assert_eq!(
+        source.wall_clock(90000 + 45000).unwrap().to_rfc3339(),
+        "2022-05-18T10:15:01+00:00"
+    );
+    assert_eq!(
+        source.wall_clock(90000 - 45000).unwrap().to_rfc3339(),
+        "2022-05-18T10:15:00+00:00"
+    );
+
+    // The BYE ends the source
+    reporter.receive_rtcp(&TEST_COMPOUND[72..], start).unwrap();
+    assert!(reporter.sources().is_empty());
+}
+
+#[test]
+fn test_rtcp_receiver_report() {
+    let mut reporter = RtcpReporter::new(90000);
+    let start = Instant::now();
+    // No report block before a packet is received
+    let report = reporter.report(start);
+    match &RtcpPacket::parse_compound(&report).unwrap()[..] {
+        [RtcpPacket::ReceiverReport { ssrc, reports }, RtcpPacket::SourceDescription(chunks)] => {
+            assert_eq!(*ssrc, reporter.ssrc());
+            assert!(reports.is_empty());
+            assert_eq!(chunks[0].ssrc, reporter.ssrc());
+            assert_eq!(
+                chunks[0].items,
+                [(SDES_CNAME, format!("rtsp-invitor-{:08x}", reporter.ssrc()))]
+            );
+        }
+        packets => panic!("Unexpected packets {:?}", packets),
+    }
+
+    // The RR reports the loss across the wraparound and the last SR, the first packet is on
+    // probation
+    for seq in [0xfffd, 0xfffe, 0xffff, 0x0002] {
+        reporter.receive_rtp(&test_packet(seq), start);
+    }
+    reporter.receive_rtcp(&TEST_COMPOUND[..52], start).unwrap();
+    let source = &reporter.sources()[&0xdeadbeef];
+    assert_eq!((source.received(), source.lost()), (3, 2));
+    let report = reporter.report(start + std::time::Duration::from_secs(2));
+    match &RtcpPacket::parse_compound(&report).unwrap()[0] {
+        RtcpPacket::ReceiverReport { reports, .. } => {
+            assert_eq!(reports[0].ssrc, 0xdeadbeef);
+            assert_eq!(reports[0].highest_sequence, 0x1_0002);
+            assert_eq!(reports[0].cumulative_lost, 2);
+            assert_eq!(reports[0].fraction_lost, 102);
+            assert_eq!(reports[0].last_sr, 0x45a4_8000);
+            assert_eq!(reports[0].delay_since_last_sr, 2 * 65536);
+        }
+        packet => panic!("Unexpected packet {:?}", packet),
+    }
+
+    // The fraction lost is of the interval, the late and duplicated packets make up for losses
+    for seq in [0x0000, 0x0001, 0x0001, 0x0001, 0x0003] {
+        reporter.receive_rtp(&test_packet(seq), start);
+    }
+    let report = reporter.report(start);
+    match &RtcpPacket::parse_compound(&report).unwrap()[0] {
+        RtcpPacket::ReceiverReport { reports, .. } => {
+            assert_eq!(reports[0].highest_sequence, 0x1_0003);
+            assert_eq!(reports[0].cumulative_lost, -2);
+            assert_eq!(reports[0].fraction_lost, 0);
+        }
+        packet => panic!("Unexpected packet {:?}", packet),
+    }
+}
+
+#[test]
+fn test_rtcp_sequence_validation() {
+    let mut reporter = RtcpReporter::new(90000);
+    let start = Instant::now();
+    let mut receive = |seqs: &[u16]| {
+        for seq in seqs {
+            reporter.receive_rtp(&test_packet(*seq), start);
+        }
+        let source = &reporter.sources()[&0xdeadbeef];
+        (source.received(), source.lost(), source.extended_max())
+    };
+
+    // The source is valid after two packets in sequence, the probation starts over on a gap
+    assert_eq!(receive(&[10, 20]), (0, 0, 20));
+    assert_eq!(receive(&[21, 22, 23]), (3, 0, 23));
+
+    // A single large jump is dropped, a packet a little late is counted
+    assert_eq!(receive(&[30000, 24, 20]), (5, -1, 24));
+
+    // Two packets in sequence after a large jump are a restart of the sender
+    assert_eq!(receive(&[40000]), (5, -1, 24));
+    assert_eq!(receive(&[40001, 40003]), (2, 1, 40003));
+}
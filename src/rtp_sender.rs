@@ -0,0 +1,371 @@
+use crate::rtp_packet::{random_u32, RtpPacket};
+use crate::rtp_ports::RtpPortPair;
+use crate::rtsp_frame::RtspFrame;
+use crate::rtsp_session::RtspFrameWriter;
+use bytes::{Bytes, BytesMut};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{Error, ErrorKind, Result};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+/// Duration of the audio carried by one RTP packet, in milliseconds
+const PACKET_DURATION_MS: u64 = 20;
+
+/// The G.711 companding laws, `PCMU` and `PCMA` in the SDP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum G711 {
+    MuLaw,
+    ALaw,
+}
+
+impl G711 {
+    /// The G.711 law of an rtpmap encoding name
+    pub(crate) fn from_encoding(encoding: &str) -> Option<Self> {
+        match encoding.to_ascii_uppercase().as_str() {
+            "PCMU" => Some(G711::MuLaw),
+            "PCMA" => Some(G711::ALaw),
+            _ => None,
+        }
+    }
+
+    /// It compresses a 16-bit linear PCM sample
+    pub(crate) fn encode(&self, sample: i16) -> u8 {
+        match self {
+            G711::MuLaw => {
+                const BIAS: i32 = 0x84;
+                const CLIP: i32 = 32635;
+                let mut pcm = sample as i32;
+                let sign = if pcm < 0 {
+                    pcm = -pcm;
+                    0x80
+                } else {
+                    0
+                };
+                pcm = pcm.min(CLIP) + BIAS;
+                let mut exponent = 7;
+                while exponent > 0 && pcm & (0x4000 >> (7 - exponent)) == 0 {
+                    exponent -= 1;
+                }
+                let mantissa = (pcm >> (exponent + 3)) & 0x0f;
+                !(sign | (exponent << 4) | mantissa) as u8
+            }
+            G711::ALaw => {
+                const SEGMENT_END: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+                let mut pcm = sample as i32 >> 3;
+                let mask = if pcm >= 0 {
+                    0xd5
+                } else {
+                    pcm = -pcm - 1;
+                    0x55
+                };
+                match SEGMENT_END.iter().position(|end| pcm <= *end) {
+                    Some(segment) => {
+                        let shift = if segment < 2 { 1 } else { segment };
+                        ((segment << 4) as i32 | ((pcm >> shift) & 0x0f)) as u8 ^ mask
+                    }
+                    None => 0x7f ^ mask,
+                }
+            }
+        }
+    }
+
+    /// It expands a G.711 sample to 16-bit linear PCM
+    pub(crate) fn decode(&self, sample: u8) -> i16 {
+        match self {
+            G711::MuLaw => {
+                let sample = !sample;
+                let exponent = (sample >> 4) & 0x07;
+                let mantissa = (sample & 0x0f) as i32;
+                let pcm = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+                if sample & 0x80 != 0 {
+                    -pcm as i16
+                } else {
+                    pcm as i16
+                }
+            }
+            G711::ALaw => {
+                let sample = sample ^ 0x55;
+                let segment = ((sample & 0x70) >> 4) as i32;
+                let mut pcm = ((sample & 0x0f) as i32) << 4;
+                pcm = match segment {
+                    0 => pcm + 8,
+                    1 => pcm + 0x108,
+                    _ => (pcm + 0x108) << (segment - 1),
+                };
+                if sample & 0x80 != 0 {
+                    pcm as i16
+                } else {
+                    -pcm as i16
+                }
+            }
+        }
+    }
+}
+
+/// It loads the audio to send and converts it to the G.711 law of the track. A WAV file must be
+/// mono at the clock rate of the track, either 16-bit PCM, A-law or mu-law. Any other file is
+/// taken as raw samples already encoded with the law of the track.
+///
+/// Arguments:
+///
+/// * `path`: &str, the WAV or raw audio file
+/// * `law`: G711, the law negotiated for the track
+/// * `clock_rate`: u32, the sample rate of the track
+///
+/// Returns:
+///
+/// A Result<Vec<u8>> of the encoded samples.
+pub(crate) async fn load_g711_audio(path: &str, law: G711, clock_rate: u32) -> Result<Vec<u8>> {
+    let data = tokio::fs::read(path).await?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Ok(data);
+    }
+
+    // RIFF chunks: <id(4)> <size(4, little endian)> <data>, padded to an even size
+    let mut format = None;
+    let mut samples = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = &data[pos + 8..(pos + 8 + size).min(data.len())];
+        match &data[pos..pos + 4] {
+            b"fmt " if body.len() >= 16 => {
+                let audio_format = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((audio_format, channels, sample_rate, bits));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        pos += 8 + size + (size & 1);
+    }
+
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let (audio_format, channels, sample_rate, bits) =
+        format.ok_or_else(|| invalid(format!("{}: missing WAV fmt chunk", path)))?;
+    let samples = samples.ok_or_else(|| invalid(format!("{}: missing WAV data chunk", path)))?;
+    if channels != 1 || sample_rate != clock_rate {
+        return Err(invalid(format!(
+            "{}: expected mono audio at {} Hz, got {} channels at {} Hz",
+            path, clock_rate, channels, sample_rate
+        )));
+    }
+    match (audio_format, bits) {
+        (1, 16) => Ok(samples
+            .chunks_exact(2)
+            .map(|sample| law.encode(i16::from_le_bytes([sample[0], sample[1]])))
+            .collect()),
+        (6, 8) if law == G711::ALaw => Ok(samples.to_vec()),
+        (7, 8) if law == G711::MuLaw => Ok(samples.to_vec()),
+        (6, 8) => Ok(samples
+            .iter()
+            .map(|sample| law.encode(G711::ALaw.decode(*sample)))
+            .collect()),
+        (7, 8) => Ok(samples
+            .iter()
+            .map(|sample| law.encode(G711::MuLaw.decode(*sample)))
+            .collect()),
+        _ => Err(invalid(format!(
+            "{}: unsupported WAV format {} with {} bits per sample",
+            path, audio_format, bits
+        ))),
+    }
+}
+
+/// Where the packets of the sent track go
+#[derive(Debug)]
+pub(crate) enum RtpDestination {
+    /// The RTP port of the server
+    Udp(SocketAddr),
+    /// The RTP channel of the track, interleaved in the RTSP connection over the HTTP tunnel or
+    /// the WebSocket
+    Interleaved(RtspFrameWriter, u8),
+}
+
+/// `RtpSender` owns the UDP socket bound to the client RTP port of a track the client sends, and
+/// the task streaming the audio to the server.
+///
+/// Properties:
+///
+/// * `socket`: The socket bound to the client RTP port.
+/// * `task`: The sending task, `None` until `start` is called.
+#[derive(Debug)]
+pub(crate) struct RtpSender {
+    socket: Arc<UdpSocket>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl RtpSender {
+    /// It takes the socket of the client RTP port, the server may only accept media from the
+    /// announced port
+    ///
+    /// Arguments:
+    ///
+    /// * `ports`: RtpPortPair, the client RTP and RTCP ports
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtpSender>.
+    pub(crate) fn bind(ports: RtpPortPair) -> Result<Self> {
+        let (socket, _) = ports.into_sockets()?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            task: None,
+        })
+    }
+
+    /// It spawns the task sending the samples in real time, one packet every 20 ms
+    ///
+    /// Arguments:
+    ///
+    /// * `destination`: RtpDestination, the RTP port of the server or the interleaved channel
+    /// * `payload_type`: u8, the payload type of the track
+    /// * `clock_rate`: u32, the clock rate of the track
+    /// * `samples`: Vec<u8>, the encoded samples, one byte per sample
+    pub(crate) fn start(
+        &mut self,
+        destination: RtpDestination,
+        payload_type: u8,
+        clock_rate: u32,
+        samples: Vec<u8>,
+    ) {
+        let socket = self.socket.clone();
+        // A packet carries at least one sample at the lowest clock rates
+        let samples_per_packet = ((clock_rate as u64 * PACKET_DURATION_MS / 1000) as usize).max(1);
+        self.task = Some(tokio::spawn(async move {
+            let ssrc = random_u32();
+            let mut sequence_number = random_u32() as u16;
+            let mut timestamp = random_u32();
+            let mut interval = tokio::time::interval(Duration::from_millis(PACKET_DURATION_MS));
+            let mut buf = BytesMut::with_capacity(1500);
+            for (index, chunk) in samples.chunks(samples_per_packet).enumerate() {
+                interval.tick().await;
+                let packet = RtpPacket {
+                    // The first packet starts a talkspurt
+                    marker: index == 0,
+                    payload_type,
+                    sequence_number,
+                    timestamp,
+                    ssrc,
+                    csrcs: vec![],
+                    extension: None,
+                    payload: Bytes::copy_from_slice(chunk),
+                };
+                buf.clear();
+                packet.assemble(&mut buf);
+                let sent = match destination {
+                    RtpDestination::Udp(address) => socket.send_to(&buf, address).await.map(drop),
+                    RtpDestination::Interleaved(ref writer, channel) => writer
+                        .write_frame(&RtspFrame::Interleaved {
+                            channel,
+                            payload: buf.split().freeze(),
+                        })
+                        .map(drop),
+                };
+                if let Err(err) = sent {
+                    println!("Send RTP packet failed with error: {:?}", err);
+                    return;
+                }
+                sequence_number = sequence_number.wrapping_add(1);
+                timestamp = timestamp.wrapping_add(chunk.len() as u32);
+            }
+            match destination {
+                RtpDestination::Udp(address) => {
+                    println!("Sent {} audio samples to {}", samples.len(), address)
+                }
+                RtpDestination::Interleaved(_, channel) => println!(
+                    "Sent {} audio samples on interleaved channel {}",
+                    samples.len(),
+                    channel
+                ),
+            }
+        }));
+    }
+
+    /// It stops the sending task
+    pub(crate) fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[test]
+fn test_g711_round_trip() {
+    for law in [G711::MuLaw, G711::ALaw] {
+        for sample in [
+            0i16, 1, -1, 100, -100, 1000, -1000, 12345, -12345, 32767, -32768,
+        ] {
+            let decoded = law.decode(law.encode(sample)) as i32;
+            // The quantization step grows with the magnitude, at most 1/16 of the segment
+            let tolerance = (sample as i32).abs() / 16 + 16;
+            assert!(
+                (decoded - sample as i32).abs() <= tolerance,
+                "{:?}: {} decoded as {}",
+                law,
+                sample,
+                decoded
+            );
+        }
+    }
+    assert_eq!(G711::MuLaw.encode(0), 0xff);
+    assert_eq!(G711::ALaw.encode(0), 0xd5);
+}
+
+#[tokio::test]
+async fn test_send_interleaved() {
+    use crate::rtp_ports::DEFAULT_PORT_RANGE;
+    use std::net::Ipv4Addr;
+    use tokio::io::AsyncReadExt;
+
+    // 200 samples at 8 kHz are sent as 160 then 40 samples on the RTP channel
+    let (client, mut server) = tokio::io::duplex(1024);
+    let mut sender = RtpSender::bind(
+        RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT_RANGE).unwrap(),
+    )
+    .unwrap();
+    let writer = RtspFrameWriter::spawn(Box::new(client));
+    sender.start(
+        RtpDestination::Interleaved(writer, 2),
+        0,
+        8000,
+        vec![0xff; 200],
+    );
+    let mut lengths = vec![];
+    for _ in 0..2 {
+        let mut header = [0; 4];
+        server.read_exact(&mut header).await.unwrap();
+        assert_eq!(&header[..2], b"$\x02");
+        let mut packet = vec![0; u16::from_be_bytes([header[2], header[3]]) as usize];
+        server.read_exact(&mut packet).await.unwrap();
+        let packet = RtpPacket::parse(Bytes::from(packet)).unwrap();
+        assert_eq!(packet.payload_type, 0);
+        lengths.push(packet.payload.len());
+    }
+    assert_eq!(lengths, [160, 40]);
+}
+
+#[tokio::test]
+async fn test_send_low_clock_rate() {
+    use crate::rtp_ports::DEFAULT_PORT_RANGE;
+    use std::net::Ipv4Addr;
+
+    // A packet carries one sample when 20 ms hold less than one
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let destination = RtpDestination::Udp(receiver.local_addr().unwrap());
+    let mut sender = RtpSender::bind(
+        RtpPortPair::allocate(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT_RANGE).unwrap(),
+    )
+    .unwrap();
+    sender.start(destination, 0, 20, vec![1, 2]);
+    let mut buf = [0; 64];
+    for sample in [1, 2] {
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let packet = RtpPacket::parse(Bytes::copy_from_slice(&buf[..len])).unwrap();
+        assert_eq!(&packet.payload[..], [sample]);
+    }
+}
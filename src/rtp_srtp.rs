@@ -0,0 +1,1017 @@
+use aes::Aes128;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::collections::HashMap;
+use tokio::io::{Error, Result};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+type HmacSha1 = Hmac<Sha1>;
+
+/// The SRTCP authentication tag is 80 bits for both crypto suites (RFC 4568 section 6.2)
+const SRTCP_TAG_LEN: usize = 10;
+
+/// Length of the E flag and SRTCP index following the RTCP compound packet
+const SRTCP_INDEX_LEN: usize = 4;
+
+/// Labels of the session keys derived from the master key (RFC 3711 section 4.3.2)
+const LABEL_RTP_ENCRYPTION: u8 = 0x00;
+const LABEL_RTCP_ENCRYPTION: u8 = 0x03;
+
+/// MIKEY payload types (RFC 3830 section 6.1)
+const MIKEY_LAST: u8 = 0;
+const MIKEY_KEMAC: u8 = 1;
+const MIKEY_T: u8 = 5;
+const MIKEY_ID: u8 = 6;
+const MIKEY_CERT: u8 = 7;
+const MIKEY_SP: u8 = 10;
+const MIKEY_RAND: u8 = 11;
+const MIKEY_GENERAL_EXT: u8 = 21;
+
+/// MIKEY key types of the key data sub-payload (RFC 3830 section 6.13)
+const MIKEY_TGK: u8 = 0;
+const MIKEY_TGK_SALT: u8 = 1;
+const MIKEY_TEK: u8 = 2;
+const MIKEY_TEK_SALT: u8 = 3;
+
+/// Constants of the MIKEY key derivation (RFC 3830 section 4.1.3)
+const MIKEY_TEK_CONSTANT: u32 = 0x2AD0_1C64;
+const MIKEY_SALT_CONSTANT: u32 = 0x39A2_C14B;
+
+/// The key type, key and optional salt of a MIKEY key data sub-payload
+type MikeyKeyData<'a> = (u8, &'a [u8], Option<&'a [u8]>);
+
+/// The SRTP crypto suites of RFC 4568 section 6.2, both encrypt with AES in counter mode and a
+/// 128 bits key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SrtpProfile {
+    /// AES_CM_128_HMAC_SHA1_80, the SRTP authentication tag is 80 bits
+    AesCm128HmacSha1_80,
+    /// AES_CM_128_HMAC_SHA1_32, the SRTP authentication tag is 32 bits
+    AesCm128HmacSha1_32,
+}
+
+impl SrtpProfile {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "AES_CM_128_HMAC_SHA1_80" => Some(SrtpProfile::AesCm128HmacSha1_80),
+            "AES_CM_128_HMAC_SHA1_32" => Some(SrtpProfile::AesCm128HmacSha1_32),
+            _ => None,
+        }
+    }
+
+    /// The length of the SRTP authentication tag in bytes
+    fn rtp_tag_len(&self) -> usize {
+        match self {
+            SrtpProfile::AesCm128HmacSha1_80 => 10,
+            SrtpProfile::AesCm128HmacSha1_32 => 4,
+        }
+    }
+}
+
+/// `SrtpKeys` is the master key of an `RTP/SAVP` media, given by the server in its session
+/// description with `a=crypto` (SDES, RFC 4568) or `a=key-mgmt:mikey` (RFC 4567).
+///
+/// Properties:
+///
+/// * `profile`: The crypto suite.
+/// * `master_key`: The 128 bits master key.
+/// * `master_salt`: The 112 bits master salt.
+/// * `mki_len`: The length of the master key identifier carried by each packet, 0 if none.
+/// * `roc`: The initial rollover counter of the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SrtpKeys {
+    pub(crate) profile: SrtpProfile,
+    pub(crate) master_key: [u8; 16],
+    pub(crate) master_salt: [u8; 14],
+    pub(crate) mki_len: usize,
+    pub(crate) roc: u32,
+}
+
+impl SrtpKeys {
+    /// It parses the content of an `a=crypto` attribute, the first key is used
+    ///
+    /// Arguments:
+    ///
+    /// * `crypto`: &str, e.g. `1 AES_CM_128_HMAC_SHA1_80 inline:<key||salt>|2^20|1:4`
+    ///
+    /// Returns:
+    ///
+    /// A Result<Option<SrtpKeys>>, `None` if the crypto suite is not supported.
+    pub(crate) fn from_crypto(crypto: &str) -> Result<Option<Self>> {
+        // <tag> <crypto-suite> inline:<key||salt>[|<lifetime>][|<MKI>:<length>][;inline:...]
+        let invalid = || Error::other(format!("Invalid SDP crypto: {}", crypto));
+        let mut parts = crypto.split_whitespace().skip(1);
+        let profile = match SrtpProfile::from_name(parts.next().ok_or_else(invalid)?) {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+        let key_param = parts.next().ok_or_else(invalid)?.split(';').next();
+        let mut key_info = key_param
+            .and_then(|key_param| key_param.strip_prefix("inline:"))
+            .ok_or_else(invalid)?
+            .split('|');
+        let key_salt = STANDARD
+            .decode(key_info.next().unwrap_or_default())
+            .map_err(|_| invalid())?;
+        if key_salt.len() != 30 {
+            return Err(invalid());
+        }
+        // The lifetime is left out or has no colon, unlike the MKI
+        let mki_len = match key_info.find_map(|info| info.split_once(':')) {
+            Some((_, len)) => len.parse().map_err(|_| invalid())?,
+            None => 0,
+        };
+        Ok(Some(Self::new(
+            profile,
+            &key_salt[..16],
+            &key_salt[16..],
+            mki_len,
+            0,
+        )?))
+    }
+
+    /// It parses the content of an `a=key-mgmt` attribute. Only the pre-shared key init message
+    /// carrying its keys unencrypted is supported, since the client shares no key with the server
+    ///
+    /// Arguments:
+    ///
+    /// * `key_mgmt`: &str, e.g. `mikey AQAFgM0XUA...`
+    ///
+    /// Returns:
+    ///
+    /// A Result<Option<SrtpKeys>>, `None` if the key management protocol is not MIKEY.
+    pub(crate) fn from_mikey(key_mgmt: &str) -> Result<Option<Self>> {
+        match key_mgmt.trim().strip_prefix("mikey ") {
+            Some(message) => {
+                let message = STANDARD
+                    .decode(message.trim())
+                    .map_err(|err| Error::other(format!("Invalid MIKEY message: {}", err)))?;
+                Self::parse_mikey(&message).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn new(
+        profile: SrtpProfile,
+        master_key: &[u8],
+        master_salt: &[u8],
+        mki_len: usize,
+        roc: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            profile,
+            master_key: master_key
+                .try_into()
+                .map_err(|_| Error::other("SRTP master key must be 128 bits"))?,
+            master_salt: master_salt
+                .try_into()
+                .map_err(|_| Error::other("SRTP master salt must be 112 bits"))?,
+            mki_len,
+            roc,
+        })
+    }
+
+    /// The payloads are chained by their next payload field, the first crypto session of the
+    /// SRTP-ID map is used
+    fn parse_mikey(message: &[u8]) -> Result<Self> {
+        let mut buf = message;
+        // HDR: version(8) | data type(8) | next payload(8) | V(1) PRF(7) | CSB ID(32) | #CS(8) |
+        //      CS ID map type(8) | CS ID map info
+        let header = take(&mut buf, 10)?;
+        if header[0] != 1 {
+            return Err(Error::other("Unsupported MIKEY version"));
+        }
+        if header[1] != 0 {
+            return Err(Error::other(
+                "Only MIKEY pre-shared key init messages are supported",
+            ));
+        }
+        let mut next = header[2];
+        let csb_id = &header[4..8];
+        if header[8] == 0 || header[9] != 0 {
+            return Err(Error::other("MIKEY message has no SRTP crypto session"));
+        }
+        // SRTP-ID map: policy no(8) | SSRC(32) | ROC(32) for each crypto session
+        let map = take(&mut buf, header[8] as usize * 9)?;
+        let policy_no = map[0];
+        let roc = u32::from_be_bytes([map[5], map[6], map[7], map[8]]);
+
+        let mut rand = None;
+        let mut key_data = None;
+        let mut profile = SrtpProfile::AesCm128HmacSha1_80;
+        while next != MIKEY_LAST {
+            let payload = next;
+            next = take(&mut buf, 1)?[0];
+            match payload {
+                MIKEY_T => {
+                    // TS type(8) | TS value, NTP-UTC and NTP are 64 bits, COUNTER is 32 bits
+                    let ts_type = take(&mut buf, 1)?[0];
+                    take(&mut buf, if ts_type == 2 { 4 } else { 8 })?;
+                }
+                MIKEY_RAND => {
+                    let len = take(&mut buf, 1)?[0] as usize;
+                    rand = Some(take(&mut buf, len)?);
+                }
+                MIKEY_SP => {
+                    // Policy no(8) | prot type(8) | policy param length(16) | policy params
+                    let header = take(&mut buf, 4)?;
+                    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+                    let params = take(&mut buf, len)?;
+                    if header[0] == policy_no && header[1] == 0 {
+                        profile = Self::parse_mikey_policy(params)?;
+                    }
+                }
+                MIKEY_ID | MIKEY_CERT | MIKEY_GENERAL_EXT => {
+                    // Type(8) | length(16) | data
+                    let header = take(&mut buf, 3)?;
+                    take(
+                        &mut buf,
+                        u16::from_be_bytes([header[1], header[2]]) as usize,
+                    )?;
+                }
+                MIKEY_KEMAC => {
+                    // Encr alg(8) | encr data len(16) | encr data | MAC alg(8) | MAC
+                    let header = take(&mut buf, 3)?;
+                    let data = take(
+                        &mut buf,
+                        u16::from_be_bytes([header[1], header[2]]) as usize,
+                    )?;
+                    if header[0] != 0 {
+                        return Err(Error::other(
+                            "MIKEY keys are encrypted with a key the client does not know",
+                        ));
+                    }
+                    key_data = Some(Self::parse_mikey_key_data(data)?);
+                    let mac_len = match take(&mut buf, 1)?[0] {
+                        0 => 0,
+                        1 => 20,
+                        mac => return Err(Error::other(format!("Unsupported MIKEY MAC {}", mac))),
+                    };
+                    take(&mut buf, mac_len)?;
+                }
+                _ => {
+                    return Err(Error::other(format!(
+                        "Unsupported MIKEY payload {}",
+                        payload
+                    )))
+                }
+            }
+        }
+
+        let (key_type, key, salt) =
+            key_data.ok_or_else(|| Error::other("MIKEY message has no KEMAC payload"))?;
+        let (master_key, master_salt) = match key_type {
+            MIKEY_TEK | MIKEY_TEK_SALT => (key.to_vec(), salt.map(<[u8]>::to_vec)),
+            MIKEY_TGK | MIKEY_TGK_SALT => {
+                // The TEK of the first crypto session is derived from the TGK
+                let rand = rand.ok_or_else(|| Error::other("MIKEY message has no RAND payload"))?;
+                let label =
+                    |constant: u32| [&constant.to_be_bytes()[..], &[1], csb_id, rand].concat();
+                let salt = salt
+                    .map(<[u8]>::to_vec)
+                    .unwrap_or_else(|| mikey_prf(key, &label(MIKEY_SALT_CONSTANT), 14));
+                (mikey_prf(key, &label(MIKEY_TEK_CONSTANT), 16), Some(salt))
+            }
+            _ => {
+                return Err(Error::other(format!(
+                    "Unsupported MIKEY key type {}",
+                    key_type
+                )))
+            }
+        };
+        let master_salt = master_salt.unwrap_or_else(|| vec![0; 14]);
+        Self::new(profile, &master_key, &master_salt, 0, roc)
+    }
+
+    /// SRTP policy params: type(8) | length(8) | value, only AES-CM is supported
+    fn parse_mikey_policy(mut params: &[u8]) -> Result<SrtpProfile> {
+        let mut profile = SrtpProfile::AesCm128HmacSha1_80;
+        while !params.is_empty() {
+            let header = take(&mut params, 2)?;
+            let value = take(&mut params, header[1] as usize)?;
+            match (header[0], value) {
+                // Encryption algorithm
+                (0, [1]) => {}
+                (0, _) => return Err(Error::other("Unsupported MIKEY SRTP encryption")),
+                // Authentication tag length in bytes
+                (11, [4]) => profile = SrtpProfile::AesCm128HmacSha1_32,
+                (11, [10]) => profile = SrtpProfile::AesCm128HmacSha1_80,
+                (11, _) => return Err(Error::other("Unsupported MIKEY SRTP tag length")),
+                _ => {}
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Key data: next payload(8) | type(4) KV(4) | key data len(16) | key data |
+    /// [salt len(16) | salt], the key validity is not used
+    fn parse_mikey_key_data(mut data: &[u8]) -> Result<MikeyKeyData<'_>> {
+        let header = take(&mut data, 4)?;
+        let key_type = header[1] >> 4;
+        let key = take(
+            &mut data,
+            u16::from_be_bytes([header[2], header[3]]) as usize,
+        )?;
+        let salt = if key_type == MIKEY_TGK_SALT || key_type == MIKEY_TEK_SALT {
+            let len = take(&mut data, 2)?;
+            Some(take(
+                &mut data,
+                u16::from_be_bytes([len[0], len[1]]) as usize,
+            )?)
+        } else {
+            None
+        };
+        Ok((key_type, key, salt))
+    }
+}
+
+/// The session keys of SRTP or SRTCP
+#[derive(Debug)]
+struct SessionKeys {
+    encryption: [u8; 16],
+    authentication: [u8; 20],
+    salt: [u8; 14],
+}
+
+impl SessionKeys {
+    /// The encryption key, authentication key and salt have consecutive labels
+    fn derive(keys: &SrtpKeys, label: u8) -> Self {
+        Self {
+            encryption: derive_key(keys, label),
+            authentication: derive_key(keys, label + 1),
+            salt: derive_key(keys, label + 2),
+        }
+    }
+
+    /// AES-CM keystream of a packet: IV = (salt * 2^16) XOR (SSRC * 2^64) XOR (index * 2^16)
+    fn cipher(&self, ssrc: u32, index: u64) -> Aes128Ctr {
+        let mut iv = [0; 16];
+        iv[..14].copy_from_slice(&self.salt);
+        iv[4..8]
+            .iter_mut()
+            .zip(ssrc.to_be_bytes())
+            .for_each(|(iv, ssrc)| *iv ^= ssrc);
+        iv[8..14]
+            .iter_mut()
+            .zip(&index.to_be_bytes()[2..])
+            .for_each(|(iv, index)| *iv ^= index);
+        Aes128Ctr::new(&self.encryption.into(), &iv.into())
+    }
+
+    /// It checks the truncated HMAC-SHA1 tag of the authenticated portion
+    fn verify(&self, authenticated: &[&[u8]], tag: &[u8]) -> bool {
+        let mut mac = HmacSha1::new_from_slice(&self.authentication).unwrap();
+        authenticated.iter().for_each(|data| mac.update(data));
+        mac.verify_truncated_left(tag).is_ok()
+    }
+
+    /// The truncated HMAC-SHA1 tag of the authenticated portion
+    fn sign(&self, authenticated: &[u8], tag_len: usize) -> Vec<u8> {
+        let mut mac = HmacSha1::new_from_slice(&self.authentication).unwrap();
+        mac.update(authenticated);
+        mac.finalize().into_bytes()[..tag_len].to_vec()
+    }
+}
+
+/// The replay list of RFC 3711 section 3.3.2, a sliding window of 64 packets
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    /// `false` if the packet was received already, or is too old to tell
+    fn check(&self, index: u64) -> bool {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                let delta = highest - index;
+                delta < 64 && self.bitmap & (1 << delta) == 0
+            }
+            _ => true,
+        }
+    }
+
+    fn update(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => self.bitmap |= 1 << (highest - index),
+            Some(highest) => {
+                let shift = index - highest;
+                self.bitmap = if shift < 64 { self.bitmap << shift } else { 0 } | 1;
+                self.highest = Some(index);
+            }
+            None => {
+                self.bitmap = 1;
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+/// The index of an SRTP stream is tracked per SSRC
+#[derive(Debug)]
+struct RtpStream {
+    roc: u32,
+    seq: u16,
+    replay: ReplayWindow,
+}
+
+impl RtpStream {
+    /// The rollover counter of a sequence number, guessed as in RFC 3711 appendix A
+    fn estimate_roc(&self, seq: u16) -> u32 {
+        let (highest, seq) = (self.seq as i32, seq as i32);
+        if highest < 32768 {
+            if seq - highest > 32768 {
+                self.roc.wrapping_sub(1)
+            } else {
+                self.roc
+            }
+        } else if highest - 32768 > seq {
+            self.roc.wrapping_add(1)
+        } else {
+            self.roc
+        }
+    }
+}
+
+/// `SrtpContext` authenticates and decrypts the SRTP and SRTCP packets of a media stream.
+///
+/// Properties:
+///
+/// * `rtp_keys`: The SRTP session keys.
+/// * `rtcp_keys`: The SRTCP session keys.
+/// * `rtp_tag_len`: The length of the SRTP authentication tag.
+/// * `mki_len`: The length of the master key identifier carried by each packet.
+/// * `roc`: The initial rollover counter of new streams.
+/// * `rtp_streams`: The index and replay list of each SSRC.
+/// * `rtcp_replays`: The replay list of SRTCP, for each SSRC.
+/// * `rtcp_index`: The SRTCP index of the next report sent by the client.
+#[derive(Debug)]
+pub(crate) struct SrtpContext {
+    rtp_keys: SessionKeys,
+    rtcp_keys: SessionKeys,
+    rtp_tag_len: usize,
+    mki_len: usize,
+    roc: u32,
+    rtp_streams: HashMap<u32, RtpStream>,
+    rtcp_replays: HashMap<u32, ReplayWindow>,
+    rtcp_index: u32,
+}
+
+impl SrtpContext {
+    /// It derives the session keys of SRTP and SRTCP from the master key, the key derivation rate
+    /// is 0 so they are derived once
+    ///
+    /// Arguments:
+    ///
+    /// * `keys`: &SrtpKeys, the master key of the media
+    ///
+    /// Returns:
+    ///
+    /// A new SrtpContext.
+    pub(crate) fn new(keys: &SrtpKeys) -> Self {
+        Self {
+            rtp_keys: SessionKeys::derive(keys, LABEL_RTP_ENCRYPTION),
+            rtcp_keys: SessionKeys::derive(keys, LABEL_RTCP_ENCRYPTION),
+            rtp_tag_len: keys.profile.rtp_tag_len(),
+            mki_len: keys.mki_len,
+            roc: keys.roc,
+            rtp_streams: HashMap::new(),
+            rtcp_replays: HashMap::new(),
+            rtcp_index: 0,
+        }
+    }
+
+    /// It authenticates and decrypts an SRTP packet, replayed packets are rejected
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &[u8], the datagram received
+    ///
+    /// Returns:
+    ///
+    /// A Result<Bytes> of the RTP packet.
+    pub(crate) fn unprotect_rtp(&mut self, packet: &[u8]) -> Result<Bytes> {
+        // Header | encrypted payload | [MKI] | authentication tag
+        let header_len = rtp_header_len(packet)?;
+        let trailer_len = self.mki_len + self.rtp_tag_len;
+        if packet.len() < header_len + trailer_len {
+            return Err(Error::other("SRTP packet too short"));
+        }
+        let protected = &packet[..packet.len() - trailer_len];
+        let tag = &packet[packet.len() - self.rtp_tag_len..];
+        let seq = u16::from_be_bytes([packet[2], packet[3]]);
+        let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+
+        let initial_roc = self.roc;
+        let stream = self.rtp_streams.entry(ssrc).or_insert_with(|| RtpStream {
+            roc: initial_roc,
+            seq,
+            replay: ReplayWindow::default(),
+        });
+        let roc = stream.estimate_roc(seq);
+        let index = (roc as u64) << 16 | seq as u64;
+        if !stream.replay.check(index) {
+            return Err(Error::other("SRTP packet replayed"));
+        }
+        // The rollover counter is authenticated but not sent
+        if !self.rtp_keys.verify(&[protected, &roc.to_be_bytes()], tag) {
+            return Err(Error::other("SRTP authentication failed"));
+        }
+        let mut rtp = protected.to_vec();
+        self.rtp_keys
+            .cipher(ssrc, index)
+            .apply_keystream(&mut rtp[header_len..]);
+
+        if stream.replay.highest.is_none_or(|highest| index > highest) {
+            stream.roc = roc;
+            stream.seq = seq;
+        }
+        stream.replay.update(index);
+        Ok(Bytes::from(rtp))
+    }
+
+    /// It authenticates and decrypts an SRTCP packet, replayed packets are rejected
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &[u8], the datagram received
+    ///
+    /// Returns:
+    ///
+    /// A Result<Bytes> of the RTCP compound packet.
+    pub(crate) fn unprotect_rtcp(&mut self, packet: &[u8]) -> Result<Bytes> {
+        // Header and sender SSRC | encrypted RTCP | E(1) index(31) | [MKI] | authentication tag
+        let trailer_len = SRTCP_INDEX_LEN + self.mki_len + SRTCP_TAG_LEN;
+        if packet.len() < 8 + trailer_len || packet[0] >> 6 != 2 {
+            return Err(Error::other("Invalid SRTCP packet"));
+        }
+        let protected = &packet[..packet.len() - self.mki_len - SRTCP_TAG_LEN];
+        let tag = &packet[packet.len() - SRTCP_TAG_LEN..];
+        let (rtcp, index) = protected.split_at(protected.len() - SRTCP_INDEX_LEN);
+        let index = u32::from_be_bytes([index[0], index[1], index[2], index[3]]);
+        let encrypted = index & 0x8000_0000 != 0;
+        let index = (index & 0x7fff_ffff) as u64;
+        let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+
+        let replay = self.rtcp_replays.entry(ssrc).or_default();
+        if !replay.check(index) {
+            return Err(Error::other("SRTCP packet replayed"));
+        }
+        if !self.rtcp_keys.verify(&[protected], tag) {
+            return Err(Error::other("SRTCP authentication failed"));
+        }
+        let mut rtcp = rtcp.to_vec();
+        if encrypted {
+            self.rtcp_keys
+                .cipher(ssrc, index)
+                .apply_keystream(&mut rtcp[8..]);
+        }
+        replay.update(index);
+        Ok(Bytes::from(rtcp))
+    }
+
+    /// It encrypts and authenticates an RTCP compound packet of the client, e.g. its receiver
+    /// reports. The master key identifier of SDES is not known, so keys with MKI are refused
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: &[u8], the RTCP compound packet
+    ///
+    /// Returns:
+    ///
+    /// A Result<Bytes> of the SRTCP packet.
+    pub(crate) fn protect_rtcp(&mut self, packet: &[u8]) -> Result<Bytes> {
+        if self.mki_len != 0 {
+            return Err(Error::other("Sending SRTCP with MKI is not supported"));
+        }
+        if packet.len() < 8 {
+            return Err(Error::other("Invalid RTCP packet"));
+        }
+        let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let index = self.rtcp_index;
+        self.rtcp_index = (self.rtcp_index + 1) & 0x7fff_ffff;
+        let mut srtcp = packet.to_vec();
+        self.rtcp_keys
+            .cipher(ssrc, index as u64)
+            .apply_keystream(&mut srtcp[8..]);
+        srtcp.extend((0x8000_0000 | index).to_be_bytes());
+        let tag = self.rtcp_keys.sign(&srtcp, SRTCP_TAG_LEN);
+        srtcp.extend(tag);
+        Ok(Bytes::from(srtcp))
+    }
+}
+
+/// The length of the RTP header which is sent in the clear, including CSRCs and extension
+fn rtp_header_len(packet: &[u8]) -> Result<usize> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return Err(Error::other("Invalid SRTP packet"));
+    }
+    let mut len = 12 + 4 * (packet[0] & 0x0f) as usize;
+    if packet[0] & 0x10 != 0 {
+        if packet.len() < len + 4 {
+            return Err(Error::other("SRTP packet too short for header extension"));
+        }
+        len += 4 + 4 * u16::from_be_bytes([packet[len + 2], packet[len + 3]]) as usize;
+    }
+    Ok(len)
+}
+
+/// AES-CM key derivation of RFC 3711 section 4.3.3 with key derivation rate 0: the label is
+/// XORed into the master salt which is the IV of the keystream
+fn derive_key<const N: usize>(keys: &SrtpKeys, label: u8) -> [u8; N] {
+    let mut iv = [0; 16];
+    iv[..14].copy_from_slice(&keys.master_salt);
+    iv[7] ^= label;
+    let mut key = [0; N];
+    Aes128Ctr::new(&keys.master_key.into(), &iv.into()).apply_keystream(&mut key);
+    key
+}
+
+/// The MIKEY PRF of RFC 3830 section 4.1.2, the P-SHA1 outputs of each 256 bits chunk of the
+/// input key are XORed
+fn mikey_prf(inkey: &[u8], label: &[u8], len: usize) -> Vec<u8> {
+    let hmac = |key: &[u8], data: &[&[u8]]| {
+        let mut mac = HmacSha1::new_from_slice(key).unwrap();
+        data.iter().for_each(|data| mac.update(data));
+        mac.finalize().into_bytes().to_vec()
+    };
+    let mut outkey = vec![0; len];
+    for chunk in inkey.chunks(32) {
+        // P(s, label, m) = HMAC(s, A_1 || label) || HMAC(s, A_2 || label) || ...,
+        // A_0 = label and A_i = HMAC(s, A_i-1)
+        let mut a = label.to_vec();
+        let mut p = vec![];
+        while p.len() < len {
+            a = hmac(chunk, &[&a]);
+            p.extend(hmac(chunk, &[&a, label]));
+        }
+        outkey.iter_mut().zip(p).for_each(|(out, p)| *out ^= p);
+    }
+    outkey
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(Error::other("MIKEY message too short"));
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+fn test_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// The master key and salt of RFC 3711 appendix B.3
+#[cfg(test)]
+fn test_keys(profile: SrtpProfile, mki_len: usize, roc: u32) -> SrtpKeys {
+    SrtpKeys::new(
+        profile,
+        &test_hex("E1F97A0D3E018BE0D64FA32C06DE4139"),
+        &test_hex("0EC675AD498AFEEBB6960B3AABE6"),
+        mki_len,
+        roc,
+    )
+    .unwrap()
+}
+
+/// The RTP packet of SSRC 0xCAFEBABE with the sequence number and 16 bytes of 0xAB, the SRTP
+/// packets below protect it with the keys of RFC 3711 appendix B.3
+#[cfg(test)]
+fn test_rtp(seq: &str) -> Vec<u8> {
+    test_hex(&format!("800F{}DECAFBADCAFEBABE{}", seq, "AB".repeat(16)))
+}
+
+#[test]
+fn test_srtp_key_derivation() {
+    // RFC 3711 appendix B.3
+    let context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    assert_eq!(
+        context.rtp_keys.encryption.to_vec(),
+        test_hex("C61E7A93744F39EE10734AFE3FF7A087")
+    );
+    assert_eq!(
+        context.rtp_keys.salt.to_vec(),
+        test_hex("30CBBC08863D8C85D49DB34A9AE1")
+    );
+    assert_eq!(
+        context.rtp_keys.authentication.to_vec(),
+        test_hex("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4")
+    );
+}
+
+#[test]
+fn test_srtp_keystream() {
+    // RFC 3711 appendix B.2, the first blocks of the keystream of SSRC 0 and index 0
+    let keys = SessionKeys {
+        encryption: test_hex("2B7E151628AED2A6ABF7158809CF4F3C")
+            .try_into()
+            .unwrap(),
+        authentication: [0; 20],
+        salt: test_hex("F0F1F2F3F4F5F6F7F8F9FAFBFCFD").try_into().unwrap(),
+    };
+    let mut keystream = [0; 48];
+    keys.cipher(0, 0).apply_keystream(&mut keystream);
+    assert_eq!(
+        keystream.to_vec(),
+        test_hex(
+            "E03EAD0935C95E80E166B16DD92B4EB4\
+             D23513162B02D0F72A43A2FE4A5F97AB\
+             41E95B3BB0A2E8DD477901E4FCA894C0"
+        )
+    );
+}
+
+#[test]
+fn test_srtp_unprotect() {
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    let srtp =
+        test_hex("800F1234DECAFBADCAFEBABE4E55DC4CE79978D88CA4D215949D2402B78D6ACC99EA179B8DBB");
+
+    // The payload and the tag are authenticated
+    for index in [12, srtp.len() - 1] {
+        let mut tampered = srtp.clone();
+        tampered[index] ^= 1;
+        assert!(context.unprotect_rtp(&tampered).is_err());
+    }
+    assert_eq!(
+        context.unprotect_rtp(&srtp).unwrap().to_vec(),
+        test_rtp("1234")
+    );
+    // Replayed
+    assert!(context.unprotect_rtp(&srtp).is_err());
+
+    // Too short for the tag, not RTP version 2
+    assert!(context.unprotect_rtp(&srtp[..21]).is_err());
+    assert!(context.unprotect_rtp(&srtp[..11]).is_err());
+    let mut invalid = srtp.clone();
+    invalid[0] = 0x40;
+    assert!(context.unprotect_rtp(&invalid).is_err());
+}
+
+#[test]
+fn test_srtp_header_extension() {
+    // A CSRC and a header extension are sent in the clear
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    let header = "910F0007DECAFBADCAFEBABE01020304BEDE000110FF0000";
+    let srtp = test_hex(&format!(
+        "{}C73F7407CED3A585F25357E8403DE2B789CA37D252DE422AE94C",
+        header
+    ));
+    let rtp = test_hex(&format!("{}{}", header, "AB".repeat(16)));
+    assert_eq!(context.unprotect_rtp(&srtp).unwrap().to_vec(), rtp);
+
+    // The extension is longer than the packet
+    assert!(context.unprotect_rtp(&srtp[..14]).is_err());
+}
+
+#[test]
+fn test_srtp_rollover() {
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    let last =
+        test_hex("800FFFFFDECAFBADCAFEBABEF36E96FC87AC01758CEA5F94BA171DB81149C9C49EFA93C1B483");
+    let wrapped =
+        test_hex("800F0000DECAFBADCAFEBABE24ECF92D9C97BF2AC679B796FDFD365A267BEECDC56456590E62");
+    let late =
+        test_hex("800FFFFEDECAFBADCAFEBABEDAE8B0DE83C8B04E96F24A2425BCE81EFD162555E37F4389D3A4");
+    assert_eq!(
+        context.unprotect_rtp(&last).unwrap().to_vec(),
+        test_rtp("FFFF")
+    );
+    // The sequence number wraps, the rollover counter is 1
+    assert_eq!(
+        context.unprotect_rtp(&wrapped).unwrap().to_vec(),
+        test_rtp("0000")
+    );
+    // A late packet of the previous rollover counter
+    assert_eq!(
+        context.unprotect_rtp(&late).unwrap().to_vec(),
+        test_rtp("FFFE")
+    );
+    assert!(context.unprotect_rtp(&last).is_err());
+    assert!(context.unprotect_rtp(&wrapped).is_err());
+}
+
+#[test]
+fn test_srtp_initial_roc_and_mki() {
+    // A 32 bits tag after a 4 bytes MKI, with the rollover counter of MIKEY
+    let srtp = test_hex("800F0005DECAFBADCAFEBABE535B5082D8D1D98DD6B9B43C8289280D00000001E7DE5196");
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_32, 4, 2));
+    assert_eq!(
+        context.unprotect_rtp(&srtp).unwrap().to_vec(),
+        test_rtp("0005")
+    );
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_32, 4, 0));
+    assert!(context.unprotect_rtp(&srtp).is_err());
+}
+
+#[test]
+fn test_srtcp_unprotect() {
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    let rtcp = test_hex(&format!("81C8000BCAFEBABE{}", "AB".repeat(16)));
+    let encrypted =
+        test_hex("81C8000BCAFEBABE7128035BE487B9BDBEF89041F977A5A880000001993E08CD54D6C1230798");
+    let unencrypted = test_hex(&format!(
+        "81C8000BCAFEBABE{}0000000293973C076D2FFF8495E4",
+        "AB".repeat(16)
+    ));
+
+    let mut tampered = encrypted.clone();
+    tampered[27] ^= 1;
+    assert!(context.unprotect_rtcp(&tampered).is_err());
+    assert_eq!(context.unprotect_rtcp(&encrypted).unwrap().to_vec(), rtcp);
+    // The E flag is not set
+    assert_eq!(context.unprotect_rtcp(&unencrypted).unwrap().to_vec(), rtcp);
+    assert!(context.unprotect_rtcp(&encrypted).is_err());
+    assert!(context.unprotect_rtcp(&encrypted[..21]).is_err());
+}
+
+#[test]
+fn test_srtcp_protect() {
+    // The receiver reports of the client have increasing SRTCP indexes
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 0, 0));
+    let rtcp = test_hex("81C90007DEADBEEFCAFEBABE000000000000FFFF000000100000000000000000");
+    assert_eq!(
+        context.protect_rtcp(&rtcp).unwrap().to_vec(),
+        test_hex(
+            "81C90007DEADBEEFA93797AD446EA64D80B00E098B8DC429CAF8E6BD54994AFA\
+             800000002FAFF6EBCCFFA36733F3"
+        )
+    );
+    assert_eq!(
+        context.protect_rtcp(&rtcp).unwrap().to_vec(),
+        test_hex(
+            "81C90007DEADBEEFD504AF2B870F8D8CF1F176E9ACD0E662F22FF13695C5B002\
+             80000001C5D3EB3D1CD43DCE8194"
+        )
+    );
+    assert!(context.protect_rtcp(&rtcp[..4]).is_err());
+
+    let mut context = SrtpContext::new(&test_keys(SrtpProfile::AesCm128HmacSha1_80, 4, 0));
+    assert!(context.protect_rtcp(&rtcp).is_err());
+}
+
+#[test]
+fn test_srtp_replay_window() {
+    let mut replay = ReplayWindow::default();
+    assert!(replay.check(100));
+    replay.update(100);
+    assert!(!replay.check(100));
+    // Late packets within the window are accepted once
+    assert!(replay.check(37));
+    replay.update(37);
+    assert!(!replay.check(37));
+    assert!(!replay.check(36));
+    // The window slides with the highest index
+    replay.update(164);
+    assert!(!replay.check(100));
+    assert!(replay.check(101));
+    replay.update(1000);
+    assert!(!replay.check(164));
+    assert!(replay.check(999));
+}
+
+#[test]
+fn test_sdes_keys() {
+    let inline = STANDARD.encode((0..30).collect::<Vec<u8>>());
+    let keys = SrtpKeys::from_crypto(&format!("1 AES_CM_128_HMAC_SHA1_80 inline:{}", inline))
+        .unwrap()
+        .unwrap();
+    assert_eq!(keys.profile, SrtpProfile::AesCm128HmacSha1_80);
+    assert_eq!(keys.master_key.to_vec(), (0..16).collect::<Vec<u8>>());
+    assert_eq!(keys.master_salt.to_vec(), (16..30).collect::<Vec<u8>>());
+    assert_eq!(keys.mki_len, 0);
+
+    // Lifetime and MKI, the first key is used
+    for crypto in [
+        format!("1 AES_CM_128_HMAC_SHA1_32 inline:{}|2^20|1:4", inline),
+        format!(
+            "1 AES_CM_128_HMAC_SHA1_32 inline:{}|1:4;inline:AAAA",
+            inline
+        ),
+    ] {
+        let keys = SrtpKeys::from_crypto(&crypto).unwrap().unwrap();
+        assert_eq!(keys.profile, SrtpProfile::AesCm128HmacSha1_32);
+        assert_eq!(keys.master_key.to_vec(), (0..16).collect::<Vec<u8>>());
+        assert_eq!(keys.mki_len, 4);
+    }
+    assert!(
+        SrtpKeys::from_crypto(&format!("1 F8_128_HMAC_SHA1_80 inline:{}", inline))
+            .unwrap()
+            .is_none()
+    );
+
+    let short = STANDARD.encode([0; 29]);
+    for crypto in [
+        String::from("1"),
+        String::from("1 AES_CM_128_HMAC_SHA1_80"),
+        format!("1 AES_CM_128_HMAC_SHA1_80 uri:{}", inline),
+        format!("1 AES_CM_128_HMAC_SHA1_80 inline:{}", short),
+        String::from("1 AES_CM_128_HMAC_SHA1_80 inline:%%%%"),
+        format!("1 AES_CM_128_HMAC_SHA1_80 inline:{}|1:x", inline),
+    ] {
+        assert!(SrtpKeys::from_crypto(&crypto).is_err(), "{}", crypto);
+    }
+}
+
+/// The MIKEY header of CSB ID 0xDEADBEEF with a crypto session of SSRC 0xAABBCCDD and ROC 2,
+/// followed by the payloads
+#[cfg(test)]
+fn test_mikey(next: &str, payloads: &str) -> String {
+    let message = format!("0100{}00DEADBEEF010001AABBCCDD00000002{}", next, payloads);
+    format!("mikey {}", STANDARD.encode(test_hex(&message)))
+}
+
+#[test]
+fn test_mikey_tgk() {
+    // RAND | SP with a 32 bits tag | KEMAC with an unencrypted TGK
+    let rand = "09".repeat(16);
+    let payloads = format!(
+        "0A10{}01010000030B0104000000140000001007070707070707070707070707070707\
+         00",
+        rand
+    );
+    let keys = SrtpKeys::from_mikey(&test_mikey("0B", &payloads))
+        .unwrap()
+        .unwrap();
+    assert_eq!(keys.profile, SrtpProfile::AesCm128HmacSha1_32);
+    assert_eq!(keys.roc, 2);
+    assert_eq!(keys.mki_len, 0);
+    // TEK and salt of the PRF of RFC 3830 section 4.1.2
+    assert_eq!(
+        keys.master_key.to_vec(),
+        test_hex("15C3834B4914BB6EEE2DFB572771C9CA")
+    );
+    assert_eq!(
+        keys.master_salt.to_vec(),
+        test_hex("BE264D87988700F6CA9D50D009D6")
+    );
+
+    // The TGK is derived with the RAND
+    let payloads = "00000014000000100707070707070707070707070707070700";
+    assert!(SrtpKeys::from_mikey(&test_mikey("01", payloads)).is_err());
+}
+
+#[test]
+fn test_mikey_tek() {
+    // T | KEMAC with an unencrypted TEK and salt
+    let payloads = format!(
+        "0100{}0000002400300010{}000E{}00",
+        "0102030405060708",
+        "11".repeat(16),
+        "22".repeat(14)
+    );
+    let keys = SrtpKeys::from_mikey(&test_mikey("05", &payloads))
+        .unwrap()
+        .unwrap();
+    assert_eq!(keys.profile, SrtpProfile::AesCm128HmacSha1_80);
+    assert_eq!(keys.master_key, [0x11; 16]);
+    assert_eq!(keys.master_salt, [0x22; 14]);
+    assert_eq!(keys.roc, 2);
+}
+
+#[test]
+fn test_mikey_errors() {
+    assert!(SrtpKeys::from_mikey("sdp-security AAAA").unwrap().is_none());
+    assert!(SrtpKeys::from_mikey("mikey %%%%").is_err());
+
+    let key_data = format!("00000010{}", "07".repeat(16));
+    let messages = [
+        // Version 2, a DH message, no crypto session
+        format!(
+            "mikey {}",
+            STANDARD.encode(test_hex("0200010000000000010001AABBCCDD00000000"))
+        ),
+        format!(
+            "mikey {}",
+            STANDARD.encode(test_hex("0104010000000000010001AABBCCDD00000000"))
+        ),
+        format!(
+            "mikey {}",
+            STANDARD.encode(test_hex("0100010000000000000000"))
+        ),
+        // No KEMAC, encrypted KEMAC, KEMAC with an unknown MAC, a PKE payload
+        test_mikey("00", ""),
+        test_mikey("01", &format!("00010014{}00", key_data)),
+        test_mikey("01", &format!("00000014{}02", key_data)),
+        test_mikey("02", "00"),
+        // Truncated
+        test_mikey("01", &format!("00000014{}", &key_data[..20])),
+        // An unsupported SRTP encryption or tag length
+        test_mikey("0A", &format!("010100000300010200000014{}00", key_data)),
+        test_mikey("0A", &format!("01010000030B010500000014{}00", key_data)),
+    ];
+    for message in messages {
+        assert!(SrtpKeys::from_mikey(&message).is_err(), "{}", message);
+    }
+}
@@ -1,14 +1,43 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use sscanf::scanf;
 use std::{collections::HashMap, io::Cursor};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, Error, ErrorKind, Result};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, Error, Result};
 
 use crate::{
-    describe_authenticate_request, describe_request, option_request, play_authenticate_request,
-    play_request, setup_authenticate_request, setup_request, teardown_authenticate_request,
+    announce_authenticate_request, announce_request, describe_authenticate_request,
+    describe_request, option_request, play_authenticate_request, play_request,
+    record_authenticate_request, record_request, response, setup_authenticate_request,
+    setup_record_authenticate_request, setup_record_request, setup_request,
+    setup_rtsp2_authenticate_request, setup_rtsp2_request, teardown_authenticate_request,
     teardown_request,
 };
 
+/// The versions of the protocol, RTSP/2.0 (RFC 7826) is offered to the server first and RTSP/1.0
+/// (RFC 2326) is the fallback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RtspVersion {
+    Rtsp10,
+    Rtsp20,
+}
+
+impl RtspVersion {
+    /// The version of a request or status line, e.g. `RTSP/2.0`
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "RTSP/1.0" => Some(RtspVersion::Rtsp10),
+            "RTSP/2.0" => Some(RtspVersion::Rtsp20),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            RtspVersion::Rtsp10 => "RTSP/1.0",
+            RtspVersion::Rtsp20 => "RTSP/2.0",
+        }
+    }
+}
+
 /// Defining an enumeration of the possible methods that can be used in an RTSP request.
 #[derive(Debug)]
 pub(crate) enum RtspMethod {
@@ -17,6 +46,36 @@ pub(crate) enum RtspMethod {
     Setup,
     Play,
     Teardown,
+    Announce,
+    Record,
+    /// Sent by the server to ping the client, or to query its parameters
+    GetParameter,
+    /// Sent by the server to change a parameter of the client
+    SetParameter,
+    /// Sent by the server to move the session to another location
+    Redirect,
+    /// Sent by the RTSP/2.0 server to notify the client of a change of the stream
+    PlayNotify,
+}
+
+impl RtspMethod {
+    /// The method of a request line, e.g. `GET_PARAMETER`
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "OPTIONS" => Some(RtspMethod::Option),
+            "DESCRIBE" => Some(RtspMethod::Describe),
+            "SETUP" => Some(RtspMethod::Setup),
+            "PLAY" => Some(RtspMethod::Play),
+            "TEARDOWN" => Some(RtspMethod::Teardown),
+            "ANNOUNCE" => Some(RtspMethod::Announce),
+            "RECORD" => Some(RtspMethod::Record),
+            "GET_PARAMETER" => Some(RtspMethod::GetParameter),
+            "SET_PARAMETER" => Some(RtspMethod::SetParameter),
+            "REDIRECT" => Some(RtspMethod::Redirect),
+            "PLAY_NOTIFY" => Some(RtspMethod::PlayNotify),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) type RtspHeaderMap = HashMap<String, String>;
@@ -27,16 +86,22 @@ pub(crate) enum RtspFrame {
     RtspRequest {
         method: RtspMethod,
         url: String,
+        version: RtspVersion,
         c_seq: u16,
         headers: RtspHeaderMap,
+        body: String,
     },
     RtspResponse {
+        version: RtspVersion,
         status_code: u16,
         reason_phrase: String,
         c_seq: u16,
         headers: RtspHeaderMap,
-        _body: String,
+        body: String,
     },
+    /// Binary data interleaved in the connection, `$<channel><length><data>` (RFC 2326 section
+    /// 10.12), e.g. the media of the HTTP tunnel
+    Interleaved { channel: u8, payload: Bytes },
 }
 
 impl RtspFrame {
@@ -55,19 +120,23 @@ impl RtspFrame {
             RtspFrame::RtspRequest {
                 method,
                 url,
+                version,
                 c_seq,
                 headers,
+                body,
             } => {
-                let request = match method {
-                    RtspMethod::Option => {
-                        option_request!(url, c_seq)
-                    }
+                let rtsp2 = *version == RtspVersion::Rtsp20;
+                let (request, params) = match method {
+                    RtspMethod::Option => (option_request!(url, c_seq), vec![]),
                     RtspMethod::Describe => {
                         // Received DESCRIBE response "RTSP/1.0 401 Unauthorized\r\n"
                         if let Some(authorization) = headers.get(&String::from("Authorization")) {
-                            describe_authenticate_request!(url, c_seq, authorization)
+                            (
+                                describe_authenticate_request!(url, c_seq, authorization),
+                                vec!["Authorization"],
+                            )
                         } else {
-                            describe_request!(url, c_seq)
+                            (describe_request!(url, c_seq), vec![])
                         }
                     }
                     RtspMethod::Setup => {
@@ -76,41 +145,272 @@ impl RtspFrame {
                             .unwrap()
                             .parse::<u16>()
                             .unwrap();
-                        if let Some(authorization) = headers.get(&String::from("Authorization")) {
-                            setup_authenticate_request!(url, c_seq, authorization, port)
-                        } else {
-                            setup_request!(url, c_seq, port)
+                        let record = headers
+                            .get(&String::from("Mode"))
+                            .is_some_and(|mode| mode == "record");
+                        let (request, params) =
+                            match (headers.get(&String::from("Authorization")), record) {
+                                (_, true) if rtsp2 => {
+                                    return Err(Error::other("RECORD was removed in RTSP/2.0"))
+                                }
+                                // RTSP/2.0 gives the client ports as destination addresses
+                                (Some(authorization), false) if rtsp2 => (
+                                    setup_rtsp2_authenticate_request!(
+                                        url,
+                                        c_seq,
+                                        authorization,
+                                        port
+                                    ),
+                                    vec!["Authorization", "Port"],
+                                ),
+                                (None, false) if rtsp2 => {
+                                    (setup_rtsp2_request!(url, c_seq, port), vec!["Port"])
+                                }
+                                (Some(authorization), true) => (
+                                    setup_record_authenticate_request!(
+                                        url,
+                                        c_seq,
+                                        authorization,
+                                        port
+                                    ),
+                                    vec!["Authorization", "Port", "Mode"],
+                                ),
+                                (None, true) => (
+                                    setup_record_request!(url, c_seq, port),
+                                    vec!["Port", "Mode"],
+                                ),
+                                (Some(authorization), false) => (
+                                    setup_authenticate_request!(url, c_seq, authorization, port),
+                                    vec!["Authorization", "Port"],
+                                ),
+                                (None, false) => (setup_request!(url, c_seq, port), vec!["Port"]),
+                            };
+                        // The media is interleaved in the connection instead of sent over UDP
+                        let (request, params) = match headers.get(&String::from("Interleaved")) {
+                            Some(channel) => (
+                                Self::with_interleaved(request, channel)?,
+                                [params, vec!["Interleaved"]].concat(),
+                            ),
+                            None => (request, params),
+                        };
+                        // The media is received from the multicast group chosen by the server
+                        let (request, params) = match headers.get(&String::from("Multicast")) {
+                            Some(_) => (
+                                Self::with_multicast(request),
+                                [params, vec!["Multicast"]].concat(),
+                            ),
+                            None => (request, params),
+                        };
+                        // SRTP is asked for with the RTP/SAVP profile
+                        match headers.get(&String::from("Profile")) {
+                            Some(profile) => (
+                                request.replacen(
+                                    "Transport: RTP/AVP",
+                                    &format!("Transport: {}", profile),
+                                    1,
+                                ),
+                                [params, vec!["Profile"]].concat(),
+                            ),
+                            None => (request, params),
                         }
                     }
                     RtspMethod::Play => {
                         let session_id = headers.get(&String::from("Session")).unwrap();
+                        let range = headers
+                            .get(&String::from("Range"))
+                            .map_or("npt=0-", |range| range.as_str());
                         if let Some(authorization) = headers.get(&String::from("Authorization")) {
-                            play_authenticate_request!(url, c_seq, authorization, session_id)
+                            (
+                                play_authenticate_request!(
+                                    url,
+                                    c_seq,
+                                    authorization,
+                                    session_id,
+                                    range
+                                ),
+                                vec!["Authorization", "Session", "Range"],
+                            )
                         } else {
-                            play_request!(url, c_seq, session_id)
+                            (
+                                play_request!(url, c_seq, session_id, range),
+                                vec!["Session", "Range"],
+                            )
                         }
                     }
                     RtspMethod::Teardown => {
                         let session_id = headers.get(&String::from("Session")).unwrap();
                         if let Some(authorization) = headers.get(&String::from("Authorization")) {
-                            teardown_authenticate_request!(url, c_seq, authorization, session_id)
+                            (
+                                teardown_authenticate_request!(
+                                    url,
+                                    c_seq,
+                                    authorization,
+                                    session_id
+                                ),
+                                vec!["Authorization", "Session"],
+                            )
                         } else {
-                            teardown_request!(url, c_seq, session_id)
+                            (teardown_request!(url, c_seq, session_id), vec!["Session"])
                         }
                     }
+                    RtspMethod::Announce => {
+                        if let Some(authorization) = headers.get(&String::from("Authorization")) {
+                            (
+                                announce_authenticate_request!(url, c_seq, authorization),
+                                vec!["Authorization"],
+                            )
+                        } else {
+                            (announce_request!(url, c_seq), vec![])
+                        }
+                    }
+                    RtspMethod::Record if rtsp2 => {
+                        return Err(Error::other("RECORD was removed in RTSP/2.0"))
+                    }
+                    RtspMethod::Record => {
+                        let session_id = headers.get(&String::from("Session")).unwrap();
+                        if let Some(authorization) = headers.get(&String::from("Authorization")) {
+                            (
+                                record_authenticate_request!(url, c_seq, authorization, session_id),
+                                vec!["Authorization", "Session"],
+                            )
+                        } else {
+                            (record_request!(url, c_seq, session_id), vec!["Session"])
+                        }
+                    }
+                    RtspMethod::GetParameter
+                    | RtspMethod::SetParameter
+                    | RtspMethod::Redirect
+                    | RtspMethod::PlayNotify => {
+                        return Err(Error::other(format!(
+                            "Cannot assemble {:?} request",
+                            method
+                        )))
+                    }
                 };
+                let request = Self::with_version(request, *version);
+                let request = Self::append_headers(request, headers, &params);
+                let request = Self::append_body(request, body);
                 buf.put(request.as_bytes());
                 Ok(request.len())
             }
-            RtspFrame::RtspResponse { .. } => Err(Error::new(
-                ErrorKind::Other,
-                "Cannot assemble response frame",
-            )),
+            _ => Err(Error::other("Cannot assemble response frame")),
+        }
+    }
+
+    /// It assembles the response of the client to a request sent by the server and writes it to
+    /// the buffer
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &mut BytesMut, the buffer to write the rtsp response to
+    ///
+    /// Returns:
+    ///
+    /// The return type is a Result<usize>, which is the number of bytes written to the buffer.
+    pub(crate) fn assemble_response(&self, buf: &mut BytesMut) -> Result<usize> {
+        match self {
+            RtspFrame::RtspResponse {
+                version,
+                status_code,
+                reason_phrase,
+                c_seq,
+                headers,
+                body,
+            } => {
+                let response = response!(status_code, reason_phrase, c_seq);
+                let response = Self::with_version(response, *version);
+                let response = Self::append_headers(response, headers, &[]);
+                let response = Self::append_body(response, body);
+                buf.put(response.as_bytes());
+                Ok(response.len())
+            }
+            _ => Err(Error::other("Cannot assemble request frame")),
+        }
+    }
+
+    /// The SETUP macros ask for UDP client ports, the transport is replaced by the interleaved
+    /// channels starting at the given one, the mode is kept
+    fn with_interleaved(request: String, channel: &str) -> Result<String> {
+        let channel = channel.parse::<u8>().map_err(|err| {
+            Error::other(format!("Invalid interleaved channel {}: {}", channel, err))
+        })?;
+        let start = request.find("Transport: ").unwrap() + "Transport: ".len();
+        let end = start + request[start..].find("\r\n").unwrap();
+        let mode = if request[start..end].ends_with(";mode=record") {
+            ";mode=record"
+        } else {
+            ""
+        };
+        Ok(format!(
+            "{}RTP/AVP/TCP;unicast;interleaved={}-{}{}{}",
+            &request[..start],
+            channel,
+            channel.wrapping_add(1),
+            mode,
+            &request[end..]
+        ))
+    }
+
+    /// The SETUP macros ask for UDP client ports, the transport is replaced by multicast, the
+    /// server announces the group and ports in its response
+    fn with_multicast(request: String) -> String {
+        let start = request.find("Transport: ").unwrap() + "Transport: ".len();
+        let end = start + request[start..].find("\r\n").unwrap();
+        format!("{}RTP/AVP;multicast{}", &request[..start], &request[end..])
+    }
+
+    /// The message macros write RTSP/1.0, the message is sent with the negotiated version
+    fn with_version(message: String, version: RtspVersion) -> String {
+        match version {
+            RtspVersion::Rtsp10 => message,
+            _ => message.replacen(RtspVersion::Rtsp10.name(), version.name(), 1),
         }
     }
 
-    /// If the first 9 bytes of the buffer are equal to the string "RTSP/1.0 ", then the function
-    /// returns Ok(()), otherwise it returns an error
+    /// The body, e.g. the SDP of ANNOUNCE, follows the empty line and its length is given by
+    /// `Content-Length`
+    fn append_body(message: String, body: &str) -> String {
+        if body.is_empty() {
+            return message;
+        }
+        let mut content_length = RtspHeaderMap::new();
+        content_length.insert(String::from("Content-Length"), body.len().to_string());
+        let mut message = Self::append_headers(message, &content_length, &[]);
+        message.push_str(body);
+        message
+    }
+
+    /// Headers which are not consumed by the request macros (e.g. `Require`, `Rate-Control`) are
+    /// inserted before the blank line terminating the request, sorted by name
+    ///
+    /// Arguments:
+    ///
+    /// * `request`: String, the request assembled by the request macros
+    /// * `headers`: &RtspHeaderMap, all headers of the request frame
+    /// * `params`: &[&str], the headers already consumed by the request macros
+    ///
+    /// Returns:
+    ///
+    /// The request with the additional headers.
+    fn append_headers(mut request: String, headers: &RtspHeaderMap, params: &[&str]) -> String {
+        let mut extra = headers
+            .iter()
+            .filter(|(header, _)| !params.contains(&header.as_str()))
+            .collect::<Vec<_>>();
+        if extra.is_empty() {
+            return request;
+        }
+        extra.sort();
+        request.truncate(request.len() - 2);
+        for (header, content) in extra {
+            request.push_str(&format!("{}: {}\r\n", header, content));
+        }
+        request.push_str("\r\n");
+        request
+    }
+
+    /// If the first 9 bytes of the buffer are equal to the string "RTSP/1.0 " or "RTSP/2.0 ", then
+    /// the function returns Ok(()), otherwise it returns an error
     /// 
     /// Arguments:
     /// 
@@ -121,13 +421,40 @@ impl RtspFrame {
     /// A Result<()>, which is Ok(()) if the first 9 bytes of the buffer passed the check
     pub(crate) fn check_response(buf: &BytesMut) -> Result<()> {
         // Check if the first line is a valid RTSP response
-        if &buf[0..9] == b"RTSP/1.0 " {
+        if buf.starts_with(b"RTSP/1.0 ") || buf.starts_with(b"RTSP/2.0 ") {
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::Other, "Invalid RTSP response"))
+            Err(Error::other("Invalid RTSP response"))
         }
     }
 
+    /// If the buffer holds a complete message, i.e. the header lines up to the empty line and the
+    /// body given by `Content-Length`, or an interleaved frame, return the length of the message
+    ///
+    /// Arguments:
+    ///
+    /// * `buf`: &[u8], the buffer to read from
+    ///
+    /// Returns:
+    ///
+    /// The length of the message, `None` if more data must be read.
+    pub(crate) fn message_len(buf: &[u8]) -> Option<usize> {
+        // $<channel(8)><length(16)><data>
+        if buf.first() == Some(&b'$') {
+            let len = 4 + u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]) as usize;
+            return (buf.len() >= len).then_some(len);
+        }
+        let header_len = buf.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+        let content_length = String::from_utf8_lossy(&buf[..header_len])
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(header, _)| header.trim().eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, content)| content.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        let len = header_len + content_length;
+        (buf.len() >= len).then_some(len)
+    }
+
     /// It reads raw rtsp response message from given buffer and parse it into a `RtspFrame` enum
     /// 
     /// Arguments:
@@ -139,23 +466,90 @@ impl RtspFrame {
     /// A tuple of the RtspFrame and the position of the cursor.
     pub(crate) async fn parse_response(cursor: &mut Cursor<&[u8]>) -> Result<(Self, usize)> {
         // Status:
-        //      RTSP/1.0 <status_code> <reason_phrase>\r\n
+        //      RTSP/<version> <status_code> <reason_phrase>\r\n
         let mut status = String::new();
         cursor.read_line(&mut status).await?;
         status = String::from(status.trim());
-        let (status_code, reason_phrase) = scanf!(status, "RTSP/1.0 {u16} {String}")
-            .or_else(|err| Err(Error::new(ErrorKind::Other, err.to_string())))?;
-
-        // General header:
-        //      C_Seq: <u16>\r\n
-        let mut generic_header = String::new();
-        cursor.read_line(&mut generic_header).await?;
-        generic_header = String::from(generic_header.trim());
-        let c_seq = scanf!(generic_header, "CSeq: {}", u16)
-            .or_else(|err| Err(Error::new(ErrorKind::Other, err.to_string())))?;
-
-        // Require header:
-        //      <header>: <content>\r\n
+        let (version, status_code, reason_phrase) = scanf!(status, "{String} {u16} {String}")
+            .or_else(|err| Err(Error::other(err.to_string())))?;
+        let version = RtspVersion::from_name(&version)
+            .ok_or_else(|| Error::other(format!("Unsupported RTSP version {}", version)))?;
+
+        // The server may send `CSeq` after other headers
+        let mut headers = Self::parse_headers(cursor).await?;
+        let c_seq = headers
+            .remove(&String::from("CSeq"))
+            .and_then(|c_seq| c_seq.parse::<u16>().ok())
+            .ok_or_else(|| Error::other(format!("CSeq not found in response {}", status)))?;
+
+        // SDP body
+        let mut body = String::new();
+        cursor.read_to_string(&mut body).await?;
+        body = String::from(body.trim());
+
+        Ok((
+            RtspFrame::RtspResponse {
+                version,
+                status_code,
+                reason_phrase,
+                c_seq,
+                headers,
+                body,
+            },
+            cursor.position() as usize,
+        ))
+    }
+
+    /// It reads raw rtsp request message sent by the server from given buffer and parse it into a
+    /// `RtspFrame` enum
+    ///
+    /// Arguments:
+    ///
+    /// * `cursor`: &mut Cursor<&[u8]>, the buffer to read from
+    ///
+    /// Returns:
+    ///
+    /// A tuple of the RtspFrame and the position of the cursor.
+    pub(crate) async fn parse_request(cursor: &mut Cursor<&[u8]>) -> Result<(Self, usize)> {
+        // Request line:
+        //      <method> <url> RTSP/<version>\r\n
+        let mut request_line = String::new();
+        cursor.read_line(&mut request_line).await?;
+        request_line = String::from(request_line.trim());
+        let (name, url, version) = scanf!(request_line, "{String} {String} {String}")
+            .or_else(|err| Err(Error::other(err.to_string())))?;
+        let version = RtspVersion::from_name(&version)
+            .ok_or_else(|| Error::other(format!("Unsupported RTSP version {}", version)))?;
+        let method = RtspMethod::from_name(&name)
+            .ok_or_else(|| Error::other(format!("Unsupported RTSP method {}", name)))?;
+
+        // The server may send `CSeq` after other headers
+        let mut headers = Self::parse_headers(cursor).await?;
+        let c_seq = headers
+            .remove(&String::from("CSeq"))
+            .and_then(|c_seq| c_seq.parse::<u16>().ok())
+            .ok_or_else(|| Error::other(format!("CSeq not found in {} request", name)))?;
+
+        // Body, e.g. the SDP of ANNOUNCE or the parameters of GET_PARAMETER
+        let mut body = String::new();
+        cursor.read_to_string(&mut body).await?;
+        body = String::from(body.trim());
+
+        Ok((
+            RtspFrame::RtspRequest {
+                method,
+                url,
+                version,
+                c_seq,
+                headers,
+                body,
+            },
+            cursor.position() as usize,
+        ))
+    }
+
+    /// It parses the header lines up to the empty line, `<header>: <content>\r\n`
+    async fn parse_headers(cursor: &mut Cursor<&[u8]>) -> Result<RtspHeaderMap> {
         let mut headers = RtspHeaderMap::new();
         loop {
             let mut line = String::new();
@@ -168,25 +562,173 @@ impl RtspFrame {
 
             let mut parts = line.splitn(2, ':');
             let header = parts.next().unwrap().trim();
-            let content = parts.next().unwrap().trim();
+            let content = parts
+                .next()
+                .ok_or_else(|| Error::other(format!("Invalid RTSP header: {}", header)))?
+                .trim();
 
             headers.insert(header.to_string(), content.to_string());
         }
+        Ok(headers)
+    }
+}
 
-        // SDP body, ignore it
-        let mut body = String::new();
-        cursor.read_to_string(&mut body).await?;
-        body = String::from(body.trim());
+#[tokio::test]
+async fn test_parse_server_request() {
+    let message = b"REDIRECT rtsp://10.229.86.28/axis-media/media.amp RTSP/1.0\r\n\
+        Session: 12345678\r\n\
+        CSeq: 3\r\n\
+        Location: rtsp://10.229.86.29:8554/axis-media/media.amp\r\n\r\n";
+    let mut cursor = Cursor::new(&message[..]);
+    let (frame, len) = RtspFrame::parse_request(&mut cursor).await.unwrap();
+    assert_eq!(len, message.len());
+    match frame {
+        RtspFrame::RtspRequest {
+            method: RtspMethod::Redirect,
+            c_seq: 3,
+            headers,
+            ..
+        } => assert_eq!(
+            headers.get(&String::from("Location")).unwrap(),
+            "rtsp://10.229.86.29:8554/axis-media/media.amp"
+        ),
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
 
-        Ok((
-            RtspFrame::RtspResponse {
-                status_code,
-                reason_phrase,
-                c_seq,
-                headers,
-                _body: body,
-            },
-            cursor.position() as usize,
-        ))
+    let mut buf = BytesMut::new();
+    let response = RtspFrame::RtspResponse {
+        version: RtspVersion::Rtsp10,
+        status_code: 200,
+        reason_phrase: String::from("OK"),
+        c_seq: 3,
+        headers: RtspHeaderMap::new(),
+        body: String::new(),
+    };
+    let len = response.assemble_response(&mut buf).unwrap();
+    assert_eq!(
+        &buf[..len],
+        b"RTSP/1.0 200 OK\r\nCSeq: 3\r\nServer: rtsp-invitor-1.0\r\n\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_parse_response_cseq() {
+    let message = b"RTSP/1.0 400 Bad Request\r\n\
+        Server: GStreamer RTSP server\r\n\
+        CSeq: 1\r\n\r\n";
+    let mut cursor = Cursor::new(&message[..]);
+    let (frame, len) = RtspFrame::parse_response(&mut cursor).await.unwrap();
+    assert_eq!(len, message.len());
+    match frame {
+        RtspFrame::RtspResponse {
+            version: RtspVersion::Rtsp10,
+            status_code: 400,
+            c_seq: 1,
+            headers,
+            ..
+        } => assert!(!headers.contains_key(&String::from("CSeq"))),
+        frame => panic!("Unexpected frame {:?}", frame),
     }
+
+    let message = b"RTSP/1.0 200 OK\r\nServer: camera\r\n\r\n";
+    let mut cursor = Cursor::new(&message[..]);
+    assert!(RtspFrame::parse_response(&mut cursor).await.is_err());
+}
+
+/// The request assembled for a SETUP with the headers
+#[cfg(test)]
+fn test_setup(version: RtspVersion, headers: &[(&str, &str)]) -> String {
+    let setup = RtspFrame::RtspRequest {
+        method: RtspMethod::Setup,
+        url: String::from("rtsp://example.com/foo/trackID=1"),
+        version,
+        c_seq: 2,
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        body: String::new(),
+    };
+    let mut buf = BytesMut::new();
+    let len = setup.assemble_request(&mut buf).unwrap();
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[tokio::test]
+async fn test_rtsp2_messages() {
+    let message = b"PLAY_NOTIFY rtsp://example.com/fizzle/foo RTSP/2.0\r\n\
+        CSeq: 854\r\n\
+        Notify-Reason: end-of-stream\r\n\
+        Session: uZ3ci0K+Ld\r\n\r\n";
+    let mut cursor = Cursor::new(&message[..]);
+    let (frame, _) = RtspFrame::parse_request(&mut cursor).await.unwrap();
+    assert!(matches!(
+        frame,
+        RtspFrame::RtspRequest {
+            method: RtspMethod::PlayNotify,
+            version: RtspVersion::Rtsp20,
+            c_seq: 854,
+            ..
+        }
+    ));
+
+    let message = b"RTSP/2.0 200 OK\r\nCSeq: 2\r\n\r\n";
+    let mut cursor = Cursor::new(&message[..]);
+    let (frame, _) = RtspFrame::parse_response(&mut cursor).await.unwrap();
+    assert!(matches!(
+        frame,
+        RtspFrame::RtspResponse {
+            version: RtspVersion::Rtsp20,
+            c_seq: 2,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_rtsp2_setup() {
+    // The client ports are given by dest_addr
+    let request = test_setup(RtspVersion::Rtsp20, &[("Port", "20000")]);
+    assert!(request.starts_with("SETUP rtsp://example.com/foo/trackID=1 RTSP/2.0\r\n"));
+    assert!(request.contains("Transport: RTP/AVP;unicast;dest_addr=\":20000\"/\":20001\"\r\n"));
+    let request = test_setup(RtspVersion::Rtsp10, &[("Port", "20000")]);
+    assert!(request.starts_with("SETUP rtsp://example.com/foo/trackID=1 RTSP/1.0\r\n"));
+    assert!(request.contains("Transport: RTP/AVP;unicast;client_port=20000-20001\r\n"));
+}
+
+#[test]
+fn test_setup_interleaved_srtp() {
+    // Interleaved SRTP, e.g. through the HTTP tunnel
+    let request = test_setup(
+        RtspVersion::Rtsp10,
+        &[
+            ("Port", "20000"),
+            ("Interleaved", "2"),
+            ("Profile", "RTP/SAVP"),
+        ],
+    );
+    assert!(request.ends_with("Transport: RTP/SAVP/TCP;unicast;interleaved=2-3\r\n\r\n"));
+}
+
+#[test]
+fn test_setup_multicast() {
+    let request = test_setup(
+        RtspVersion::Rtsp10,
+        &[("Port", "20000"), ("Multicast", "true")],
+    );
+    assert!(request.ends_with("Transport: RTP/AVP;multicast\r\n\r\n"));
+}
+
+#[test]
+fn test_rtsp2_record_removed() {
+    let record = RtspFrame::RtspRequest {
+        method: RtspMethod::Record,
+        url: String::from("rtsp://example.com/foo"),
+        version: RtspVersion::Rtsp20,
+        c_seq: 3,
+        headers: RtspHeaderMap::new(),
+        body: String::new(),
+    };
+    let mut buf = BytesMut::new();
+    assert!(record.assemble_request(&mut buf).is_err());
 }
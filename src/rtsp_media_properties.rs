@@ -0,0 +1,67 @@
+/// How the client may seek in the media of an RTSP/2.0 session
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RandomAccess {
+    /// Any position, the random access points are at most the given seconds apart
+    Anywhere(Option<f64>),
+    /// Only the beginning of the media
+    BeginningOnly,
+    /// No seeking at all, e.g. a live stream
+    NoSeeking,
+}
+
+/// `MediaProperties` is the `Media-Properties` header of the SETUP and PLAY responses of RTSP/2.0
+/// (RFC 7826 section 18.29), only the properties used by the client are kept.
+///
+/// Properties:
+///
+/// * `random_access`: The random access property, `None` if the server gave none.
+/// * `time_progressing`: The media grows while it is played, e.g. a live stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MediaProperties {
+    pub(crate) random_access: Option<RandomAccess>,
+    pub(crate) time_progressing: bool,
+}
+
+impl MediaProperties {
+    /// It parses the content of a `Media-Properties` header, unknown properties are ignored
+    ///
+    /// Arguments:
+    ///
+    /// * `properties`: &str, e.g. `No-Seeking, Time-Progressing, Time-Duration=0.0`
+    ///
+    /// Returns:
+    ///
+    /// The MediaProperties.
+    pub(crate) fn parse(properties: &str) -> Self {
+        let mut result = MediaProperties::default();
+        for property in properties.split(',').map(str::trim) {
+            let (name, value) = match property.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (property, None),
+            };
+            match name {
+                "Random-Access" => {
+                    let max_delta = value.and_then(|value| value.parse::<f64>().ok());
+                    result.random_access = Some(RandomAccess::Anywhere(max_delta));
+                }
+                "Beginning-Only" => result.random_access = Some(RandomAccess::BeginningOnly),
+                "No-Seeking" => result.random_access = Some(RandomAccess::NoSeeking),
+                "Time-Progressing" => result.time_progressing = true,
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn test_parse_media_properties() {
+    let live = MediaProperties::parse("No-Seeking, Time-Progressing, Time-Duration=0.0");
+    assert_eq!(live.random_access, Some(RandomAccess::NoSeeking));
+    assert!(live.time_progressing);
+
+    let vod =
+        MediaProperties::parse("Random-Access=2.5, Unlimited, Immutable, Scales=\"-2, 1, 2\"");
+    assert_eq!(vod.random_access, Some(RandomAccess::Anywhere(Some(2.5))));
+    assert!(!vod.time_progressing);
+}
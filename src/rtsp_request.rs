@@ -22,7 +22,7 @@ macro_rules! describe_request {
 macro_rules! describe_authenticate_request {
     ($url: expr, $c_seq: expr, $authorization: expr) => {
         format!(
-            "DESCRIBE {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\n",
+            "DESCRIBE {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\n\r\n",
             $url, $c_seq, $authorization
         )
     };
@@ -57,20 +57,20 @@ macro_rules! setup_authenticate_request {
 
 #[macro_export]
 macro_rules! play_request {
-    ($url: expr, $c_seq: expr, $session_id: expr) => {
+    ($url: expr, $c_seq: expr, $session_id: expr, $range: expr) => {
         format!(
-            "PLAY {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: npt=0-\r\nSession: {}\r\n\r\n",
-            $url, $c_seq, $session_id
+            "PLAY {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: {}\r\nSession: {}\r\n\r\n",
+            $url, $c_seq, $range, $session_id
         )
     };
 }
 
 #[macro_export]
 macro_rules! play_authenticate_request {
-    ($url: expr, $c_seq: expr, $authorization: expr, $session_id: expr) => {
+    ($url: expr, $c_seq: expr, $authorization: expr, $session_id: expr, $range: expr) => {
         format!(
-            "PLAY {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: npt=0-\r\nSession: {}\r\n\r\n",
-            $url, $c_seq, $authorization, $session_id
+            "PLAY {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: {}\r\nSession: {}\r\n\r\n",
+            $url, $c_seq, $authorization, $range, $session_id
         )
     };
 }
@@ -94,3 +94,107 @@ macro_rules! teardown_authenticate_request {
         )
     };
 }
+
+#[macro_export]
+macro_rules! announce_request {
+    ($url: expr, $c_seq: expr) => {
+        format!(
+            "ANNOUNCE {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\n\r\n",
+            $url, $c_seq
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! announce_authenticate_request {
+    ($url: expr, $c_seq: expr, $authorization: expr) => {
+        format!(
+            "ANNOUNCE {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\n\r\n",
+            $url, $c_seq, $authorization
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! setup_rtsp2_request {
+    ($url: expr, $c_seq: expr, $port: expr) => {
+        format!(
+            "SETUP {} RTSP/2.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nTransport: RTP/AVP;unicast;dest_addr=\":{}\"/\":{}\"\r\n\r\n",
+            $url,
+            $c_seq,
+            $port,
+            $port + 1
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! setup_rtsp2_authenticate_request {
+    ($url: expr, $c_seq: expr, $authorization: expr, $port: expr) => {
+        format!(
+            "SETUP {} RTSP/2.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nTransport: RTP/AVP;unicast;dest_addr=\":{}\"/\":{}\"\r\n\r\n",
+            $url,
+            $c_seq,
+            $authorization,
+            $port,
+            $port + 1
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! setup_record_request {
+    ($url: expr, $c_seq: expr, $port: expr) => {
+        format!(
+            "SETUP {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nTransport: RTP/AVP;unicast;client_port={}-{};mode=record\r\n\r\n",
+            $url,
+            $c_seq,
+            $port,
+            $port + 1
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! setup_record_authenticate_request {
+    ($url: expr, $c_seq: expr, $authorization: expr, $port: expr) => {
+        format!(
+            "SETUP {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nTransport: RTP/AVP;unicast;client_port={}-{};mode=record\r\n\r\n",
+            $url,
+            $c_seq,
+            $authorization,
+            $port,
+            $port + 1
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! record_request {
+    ($url: expr, $c_seq: expr, $session_id: expr) => {
+        format!(
+            "RECORD {} RTSP/1.0\r\nCSeq: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: npt=0-\r\nSession: {}\r\n\r\n",
+            $url, $c_seq, $session_id
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! record_authenticate_request {
+    ($url: expr, $c_seq: expr, $authorization: expr, $session_id: expr) => {
+        format!(
+            "RECORD {} RTSP/1.0\r\nCSeq: {}\r\nAuthorization: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nRange: npt=0-\r\nSession: {}\r\n\r\n",
+            $url, $c_seq, $authorization, $session_id
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! response {
+    ($status_code: expr, $reason_phrase: expr, $c_seq: expr) => {
+        format!(
+            "RTSP/1.0 {} {}\r\nCSeq: {}\r\nServer: rtsp-invitor-1.0\r\n\r\n",
+            $status_code, $reason_phrase, $c_seq
+        )
+    };
+}
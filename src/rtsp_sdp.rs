@@ -0,0 +1,350 @@
+use crate::rtp_srtp::SrtpKeys;
+use std::collections::HashMap;
+use tokio::io::{Error, Result};
+
+/// The direction of a media stream. As in the ONVIF streaming specification, the direction
+/// attributes are read from the point of view of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MediaDirection {
+    SendRecv,
+    /// The client receives the media, e.g. the video of a camera
+    RecvOnly,
+    /// The client sends the media, e.g. the ONVIF audio backchannel
+    SendOnly,
+    Inactive,
+}
+
+/// `RtpMap` is the `a=rtpmap:<payload type> <encoding name>/<clock rate>[/<channels>]` attribute.
+///
+/// Properties:
+///
+/// * `payload_type`: The RTP payload type.
+/// * `encoding`: The encoding name, e.g. `H264`, `PCMU`.
+/// * `clock_rate`: The RTP clock rate in Hz.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RtpMap {
+    pub(crate) payload_type: u8,
+    pub(crate) encoding: String,
+    pub(crate) clock_rate: u32,
+}
+
+/// `MediaDescription` is a `m=` section of a session description.
+///
+/// Properties:
+///
+/// * `media`: The media type, e.g. `video`, `audio`, `application`.
+/// * `protocol`: The transport protocol, e.g. `RTP/AVP`, or `RTP/SAVP` for SRTP.
+/// * `formats`: The payload types offered.
+/// * `control`: The `a=control` URL of the track.
+/// * `direction`: The direction of the stream.
+/// * `rtpmap`: The rtpmap of the first payload type, static payload types are filled in.
+/// * `fmtp`: The format parameters of the first payload type, by lowercase name.
+/// * `srtp`: The SRTP master key given by `a=crypto` or `a=key-mgmt`.
+#[derive(Debug, Clone)]
+pub(crate) struct MediaDescription {
+    pub(crate) media: String,
+    pub(crate) protocol: String,
+    pub(crate) formats: Vec<u8>,
+    pub(crate) control: Option<String>,
+    pub(crate) direction: MediaDirection,
+    pub(crate) rtpmap: Option<RtpMap>,
+    pub(crate) fmtp: FormatParameters,
+    pub(crate) srtp: Option<SrtpKeys>,
+}
+
+/// The `a=fmtp` parameters of a payload type
+pub(crate) type FormatParameters = HashMap<String, String>;
+
+/// `SessionDescription` is the SDP (RFC 4566) returned by DESCRIBE.
+///
+/// Properties:
+///
+/// * `control`: The session level `a=control` URL, used for aggregate control.
+/// * `medias`: The media sections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionDescription {
+    pub(crate) control: Option<String>,
+    pub(crate) medias: Vec<MediaDescription>,
+}
+
+impl MediaDescription {
+    /// An audio track the client sends to the server: the ONVIF audio backchannel of a camera, or
+    /// the audio published with ANNOUNCE and RECORD
+    pub(crate) fn is_backchannel(&self) -> bool {
+        self.media == "audio" && self.direction == MediaDirection::SendOnly
+    }
+
+    /// The ONVIF metadata stream of analytics and events (ONVIF Streaming Specification section
+    /// 5.1.2)
+    pub(crate) fn is_metadata(&self) -> bool {
+        self.media == "application"
+            && self
+                .rtpmap
+                .as_ref()
+                .is_some_and(|rtpmap| rtpmap.encoding.eq_ignore_ascii_case("vnd.onvif.metadata"))
+    }
+
+    /// The media is sent over SRTP
+    pub(crate) fn is_secure(&self) -> bool {
+        self.protocol.starts_with("RTP/SAVP")
+    }
+}
+
+impl SessionDescription {
+    /// It parses the SDP body of a DESCRIBE response, unknown lines are ignored
+    ///
+    /// Arguments:
+    ///
+    /// * `sdp`: &str, the SDP body
+    ///
+    /// Returns:
+    ///
+    /// A Result<SessionDescription>.
+    pub(crate) fn parse(sdp: &str) -> Result<Self> {
+        let mut session = SessionDescription::default();
+        let mut session_direction = MediaDirection::SendRecv;
+        // A key given by the media overrides the one given by the session
+        let mut session_srtp = None;
+        let mut media_srtp = false;
+        let mut rtpmaps = HashMap::new();
+        let mut fmtps = HashMap::new();
+
+        for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (kind, value) = line
+                .split_once('=')
+                .ok_or_else(|| Error::other(format!("Invalid SDP line: {}", line)))?;
+            match kind {
+                "m" => {
+                    // m=<media> <port> <proto> <fmt> ...
+                    Self::finish_media(&mut session, &mut rtpmaps, &mut fmtps);
+                    let mut parts = value.split_whitespace();
+                    let media = parts.next().unwrap_or_default().to_string();
+                    let protocol = parts.nth(1).unwrap_or_default().to_string();
+                    let formats = parts.filter_map(|fmt| fmt.parse().ok()).collect();
+                    session.medias.push(MediaDescription {
+                        media,
+                        protocol,
+                        formats,
+                        control: None,
+                        direction: session_direction,
+                        rtpmap: None,
+                        fmtp: HashMap::new(),
+                        srtp: session_srtp.clone(),
+                    });
+                    media_srtp = false;
+                }
+                "a" => {
+                    let (attribute, content) = match value.split_once(':') {
+                        Some((attribute, content)) => (attribute, Some(content.trim())),
+                        None => (value, None),
+                    };
+                    // The first crypto suite supported is used
+                    let srtp = match (attribute, content) {
+                        ("crypto", Some(crypto)) => SrtpKeys::from_crypto(crypto)?,
+                        ("key-mgmt", Some(key_mgmt)) => SrtpKeys::from_mikey(key_mgmt)?,
+                        _ => None,
+                    };
+                    let direction = match attribute {
+                        "sendrecv" => Some(MediaDirection::SendRecv),
+                        "recvonly" => Some(MediaDirection::RecvOnly),
+                        "sendonly" => Some(MediaDirection::SendOnly),
+                        "inactive" => Some(MediaDirection::Inactive),
+                        _ => None,
+                    };
+                    match (session.medias.last_mut(), attribute, content, direction) {
+                        (None, _, _, _) if srtp.is_some() => session_srtp = session_srtp.or(srtp),
+                        (Some(media), _, _, _) if srtp.is_some() && !media_srtp => {
+                            media.srtp = srtp;
+                            media_srtp = true;
+                        }
+                        (None, _, _, Some(direction)) => session_direction = direction,
+                        (None, "control", Some(control), _) => {
+                            session.control = Some(control.to_string())
+                        }
+                        (Some(media), _, _, Some(direction)) => media.direction = direction,
+                        (Some(media), "control", Some(control), _) => {
+                            media.control = Some(control.to_string())
+                        }
+                        (Some(_), "rtpmap", Some(rtpmap), _) => {
+                            let rtpmap = Self::parse_rtpmap(rtpmap)?;
+                            rtpmaps.insert(rtpmap.payload_type, rtpmap);
+                        }
+                        (Some(_), "fmtp", Some(fmtp), _) => {
+                            let (payload_type, parameters) = Self::parse_fmtp(fmtp)?;
+                            fmtps.insert(payload_type, parameters);
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self::finish_media(&mut session, &mut rtpmaps, &mut fmtps);
+        Ok(session)
+    }
+
+    /// It assembles the session description sent with ANNOUNCE
+    ///
+    /// Returns:
+    ///
+    /// The SDP body.
+    pub(crate) fn assemble(&self) -> String {
+        let mut sdp = String::from(
+            "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\ns=rtsp-invitor\r\nc=IN IP4 0.0.0.0\r\nt=0 0\r\n",
+        );
+        if let Some(ref control) = self.control {
+            sdp.push_str(&format!("a=control:{}\r\n", control));
+        }
+        for media in &self.medias {
+            let formats = media
+                .formats
+                .iter()
+                .map(|format| format.to_string())
+                .collect::<Vec<_>>();
+            sdp.push_str(&format!(
+                "m={} 0 {} {}\r\n",
+                media.media,
+                media.protocol,
+                formats.join(" ")
+            ));
+            if let Some(ref rtpmap) = media.rtpmap {
+                sdp.push_str(&format!(
+                    "a=rtpmap:{} {}/{}\r\n",
+                    rtpmap.payload_type, rtpmap.encoding, rtpmap.clock_rate
+                ));
+            }
+            match media.direction {
+                MediaDirection::SendRecv => {}
+                MediaDirection::RecvOnly => sdp.push_str("a=recvonly\r\n"),
+                MediaDirection::SendOnly => sdp.push_str("a=sendonly\r\n"),
+                MediaDirection::Inactive => sdp.push_str("a=inactive\r\n"),
+            }
+            if let Some(ref control) = media.control {
+                sdp.push_str(&format!("a=control:{}\r\n", control));
+            }
+        }
+        sdp
+    }
+
+    /// The rtpmap and fmtp of the last media section are resolved once all its attributes are
+    /// known
+    fn finish_media(
+        session: &mut SessionDescription,
+        rtpmaps: &mut HashMap<u8, RtpMap>,
+        fmtps: &mut HashMap<u8, FormatParameters>,
+    ) {
+        if let Some(media) = session.medias.last_mut() {
+            media.rtpmap = media.formats.first().and_then(|payload_type| {
+                rtpmaps
+                    .remove(payload_type)
+                    .or_else(|| Self::static_rtpmap(*payload_type))
+            });
+            if let Some(fmtp) = media
+                .formats
+                .first()
+                .and_then(|payload_type| fmtps.remove(payload_type))
+            {
+                media.fmtp = fmtp;
+            }
+        }
+        rtpmaps.clear();
+        fmtps.clear();
+    }
+
+    /// `<payload type> <name>=<value>;<name>=<value>...`, names are case-insensitive
+    fn parse_fmtp(fmtp: &str) -> Result<(u8, FormatParameters)> {
+        let invalid = || Error::other(format!("Invalid SDP fmtp: {}", fmtp));
+        let (payload_type, parameters) = fmtp.split_once(' ').ok_or_else(invalid)?;
+        let parameters = parameters
+            .split(';')
+            .map(str::trim)
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| match parameter.split_once('=') {
+                Some((name, value)) => (name.trim().to_lowercase(), value.trim().to_string()),
+                None => (parameter.to_lowercase(), String::new()),
+            })
+            .collect();
+        Ok((payload_type.parse().map_err(|_| invalid())?, parameters))
+    }
+
+    /// `<payload type> <encoding name>/<clock rate>[/<channels>]`, the clock rate must not be 0
+    fn parse_rtpmap(rtpmap: &str) -> Result<RtpMap> {
+        let invalid = || Error::other(format!("Invalid SDP rtpmap: {}", rtpmap));
+        let (payload_type, encoding) = rtpmap.split_once(' ').ok_or_else(invalid)?;
+        let mut parts = encoding.trim().split('/');
+        let encoding = parts.next().ok_or_else(invalid)?.to_string();
+        let clock_rate = parts
+            .next()
+            .ok_or_else(invalid)?
+            .parse()
+            .map_err(|_| invalid())?;
+        if clock_rate == 0 {
+            return Err(invalid());
+        }
+        Ok(RtpMap {
+            payload_type: payload_type.parse().map_err(|_| invalid())?,
+            encoding,
+            clock_rate,
+        })
+    }
+
+    /// Static payload types of RFC 3551 which may be used without rtpmap
+    fn static_rtpmap(payload_type: u8) -> Option<RtpMap> {
+        let (encoding, clock_rate) = match payload_type {
+            0 => ("PCMU", 8000),
+            8 => ("PCMA", 8000),
+            26 => ("JPEG", 90000),
+            _ => return None,
+        };
+        Some(RtpMap {
+            payload_type,
+            encoding: String::from(encoding),
+            clock_rate,
+        })
+    }
+}
+
+#[test]
+fn test_parse_backchannel_sdp() {
+    let sdp = "v=0\r\n\
+        o=- 1 1 IN IP4 10.229.86.28\r\n\
+        s=Session streamed with GStreamer\r\n\
+        t=0 0\r\n\
+        a=control:*\r\n\
+        m=video 0 RTP/AVP 96\r\n\
+        a=rtpmap:96 H264/90000\r\n\
+        a=fmtp:96 packetization-mode=1; Sprop-Parameter-Sets=Z0IAKeKQFAe2AtwEBAaQeJEV,aM48gA==\r\n\
+        a=control:trackID=1\r\n\
+        a=recvonly\r\n\
+        m=audio 0 RTP/AVP 0\r\n\
+        a=control:rtsp://10.229.86.28/audioback\r\n\
+        a=sendonly\r\n";
+    let session = SessionDescription::parse(sdp).unwrap();
+    assert_eq!(session.control.as_deref(), Some("*"));
+    assert_eq!(session.medias.len(), 2);
+
+    let video = &session.medias[0];
+    assert_eq!(video.direction, MediaDirection::RecvOnly);
+    assert_eq!(video.control.as_deref(), Some("trackID=1"));
+    assert_eq!(video.rtpmap.as_ref().unwrap().encoding, "H264");
+    assert_eq!(video.fmtp["packetization-mode"], "1");
+    assert_eq!(
+        video.fmtp["sprop-parameter-sets"],
+        "Z0IAKeKQFAe2AtwEBAaQeJEV,aM48gA=="
+    );
+
+    let audio = &session.medias[1];
+    assert!(audio.is_backchannel());
+    assert_eq!(audio.rtpmap.as_ref().unwrap().encoding, "PCMU");
+}
+
+#[test]
+fn test_parse_rtpmap_clock_rate() {
+    let sdp = |rtpmap: &str| format!("v=0\r\nm=audio 0 RTP/AVP 97\r\na=rtpmap:{}\r\n", rtpmap);
+    let session = SessionDescription::parse(&sdp("97 L16/44100/2")).unwrap();
+    assert_eq!(session.medias[0].rtpmap.as_ref().unwrap().clock_rate, 44100);
+    for rtpmap in ["97 L16/0", "97 L16/0/2", "97 L16", "97 L16/x"] {
+        let error = SessionDescription::parse(&sdp(rtpmap)).unwrap_err();
+        assert_eq!(error.to_string(), format!("Invalid SDP rtpmap: {}", rtpmap));
+    }
+}
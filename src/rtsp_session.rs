@@ -1,69 +1,214 @@
+use crate::rtsp_camera::authority;
 use crate::rtsp_frame::RtspFrame;
-use bytes::{Buf, BytesMut};
+use crate::rtsp_tls::RtspTlsConfig;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
 use std::io::Cursor;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, ErrorKind, Result};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error, ErrorKind, Result};
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
 
+/// The read half of the plain TCP or TLS stream
+pub(crate) type RtspReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// The write half of the plain TCP or TLS stream
+pub(crate) type RtspWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A plain TCP or TLS stream to the server
+pub(crate) trait RtspStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> RtspStream for S {}
+
+/// The messages of the writer task: a frame to write, or a flush answered once the frames
+/// before it are written
 #[derive(Debug)]
+enum RtspWrite {
+    Frame(Bytes),
+    Flush(oneshot::Sender<()>),
+}
+
+/// `RtspFrameWriter` hands the frames over to the task owning the write half of the stream. A
+/// frame is always written as a whole, even if the future which sent it is dropped, and the
+/// frames of the session and of the media sent interleaved keep their order
+#[derive(Debug, Clone)]
+pub(crate) struct RtspFrameWriter {
+    sender: mpsc::UnboundedSender<RtspWrite>,
+}
+
+impl RtspFrameWriter {
+    /// It spawns the task writing the frames, the write half is shut down once every
+    /// `RtspFrameWriter` is dropped
+    ///
+    /// Arguments:
+    ///
+    /// * `writer`: RtspWriter, the write half of the stream
+    ///
+    /// Returns:
+    ///
+    /// A new RtspFrameWriter.
+    pub(crate) fn spawn(mut writer: RtspWriter) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(write) = receiver.recv().await {
+                let buf = match write {
+                    RtspWrite::Frame(buf) => buf,
+                    RtspWrite::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                let written = match writer.write_all(&buf).await {
+                    Ok(()) => writer.flush().await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = written {
+                    println!("Write to RTSP connection failed with error: {:?}", err);
+                    return;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+        Self { sender }
+    }
+
+    /// This function takes a frame, assembles it into a buffer, and then hands it over to the
+    /// writer task. The frame is a request of the client, the response to a request of the
+    /// server, or an interleaved RTP or RTCP packet of the client
+    /// 
+    /// Arguments:
+    /// 
+    /// * `frame`: &RtspFrame
+    /// 
+    /// Returns:
+    /// 
+    /// The number of bytes to be written.
+    pub(crate) fn write_frame(&self, frame: &RtspFrame) -> Result<usize> {
+        let mut buf = BytesMut::new();
+        match frame {
+            RtspFrame::Interleaved { channel, payload } => {
+                // $ | channel(8) | length(16) | payload
+                let len = u16::try_from(payload.len())
+                    .map_err(|_| Error::other("Interleaved frame too long"))?;
+                buf.put_u8(b'$');
+                buf.put_u8(*channel);
+                buf.put_u16(len);
+                buf.put_slice(payload);
+            }
+            _ => {
+                let (kind, len) = match frame {
+                    RtspFrame::RtspRequest { .. } => ("request", frame.assemble_request(&mut buf)?),
+                    _ => ("response", frame.assemble_response(&mut buf)?),
+                };
+                println!(
+                    "Sending {}:\n{}",
+                    kind,
+                    String::from_utf8_lossy(&buf[..len])
+                );
+                buf.truncate(len);
+            }
+        }
+        let len = buf.len();
+        self.sender
+            .send(RtspWrite::Frame(buf.freeze()))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "RTSP connection closed"))?;
+        Ok(len)
+    }
+
+    /// It waits until the frames handed over before are written
+    pub(crate) async fn flush(&self) -> Result<()> {
+        let closed = || Error::new(ErrorKind::BrokenPipe, "RTSP connection closed");
+        let (done, written) = oneshot::channel();
+        self.sender
+            .send(RtspWrite::Flush(done))
+            .map_err(|_| closed())?;
+        written.await.map_err(|_| closed())
+    }
+}
+
 pub(crate) struct RtspSession {
-    pub(crate) reader: OwnedReadHalf,
-    pub(crate) writer: OwnedWriteHalf,
+    pub(crate) reader: RtspReader,
+    pub(crate) writer: RtspFrameWriter,
     pub(crate) buf: BytesMut,
 }
 
+impl fmt::Debug for RtspSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtspSession")
+            .field("buf", &self.buf)
+            .finish_non_exhaustive()
+    }
+}
+
 impl RtspSession {
-    /// `RtspSession` is a struct that contains a `TcpStream` and a `BytesMut` buffer. The `new`
-    /// function creates a new `RtspSession` by taking a `TcpStream` and creating a `BytesMut` buffer
-    /// with a capacity of 1500 bytes
+    /// `RtspSession` is a struct that contains the read half of a stream, the writer of the write
+    /// half and a `BytesMut` buffer. The `new` function creates a new `RtspSession` by splitting a
+    /// `TcpStream`, or the TLS stream running on it, and creating a `BytesMut` buffer with a
+    /// capacity of 1500 bytes
     ///
     /// Arguments:
     ///
-    /// * `stream`: TcpStream
+    /// * `stream`: the plain TCP or TLS stream
     ///
     /// Returns:
     ///
     /// A new RtspSession struct.
-    pub(crate) fn new(stream: TcpStream) -> RtspSession {
-        let (reader, writer) = stream.into_split();
+    pub(crate) fn new<S>(stream: S) -> RtspSession
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
         RtspSession {
-            reader,
-            writer,
+            reader: Box::new(reader),
+            writer: RtspFrameWriter::spawn(Box::new(writer)),
             buf: BytesMut::with_capacity(1500), // length of rtsp frame <= MTU
         }
     }
 
-    /// This function takes a frame, assembles it into a buffer, and then writes it to the socket
-    /// 
+    /// It opens the TCP connection to the RTSP server, wrapped in TLS for `rtsps://`, and creates a
+    /// new `RtspSession` on it
+    ///
     /// Arguments:
-    /// 
-    /// * `frame`: &RtspFrame
-    /// 
+    ///
+    /// * `host`: &str, the host name or IP address of the server
+    /// * `port`: u16, the RTSP port of the server
+    /// * `tls`: Option<&RtspTlsConfig>, the TLS options, `None` for plain RTSP
+    ///
     /// Returns:
-    /// 
-    /// The number of bytes written.
-    pub(crate) async fn write_frame(&mut self, frame: &RtspFrame) -> Result<usize> {
-        let len = frame.assemble_request(&mut self.buf)?;
-        println!(
-            "Sending request:\n{}",
-            String::from_utf8_lossy(&self.buf[..len])
-        );
-        self.writer.write_all(&self.buf[..len]).await?;
-        self.writer.flush().await?;
-        Ok(len)
+    ///
+    /// A Result<RtspSession>.
+    pub(crate) async fn connect(
+        host: &str,
+        port: u16,
+        tls: Option<&RtspTlsConfig>,
+    ) -> Result<RtspSession> {
+        let stream = TcpStream::connect(authority(host, port)).await?;
+        match tls {
+            Some(tls) => Ok(RtspSession::new(tls.connect(stream, host).await?)),
+            None => Ok(RtspSession::new(stream)),
+        }
+    }
+
+    /// It writes a frame to the connection, see `RtspFrameWriter::write_frame`
+    pub(crate) fn write_frame(&self, frame: &RtspFrame) -> Result<usize> {
+        self.writer.write_frame(frame)
     }
 
-    /// Read from the TCP stream until we get a non-empty buffer, then parse the buffer into a frame
+    /// Read from the TCP stream until the buffer holds a complete message, including the body
+    /// announced by `Content-Length`, then parse the buffer into a frame. A message following in
+    /// the buffer is kept for the next call
     /// 
     /// Returns:
     /// 
     /// a Result<Option<RtspFrame>>.
     pub(crate) async fn read_frame(&mut self) -> Result<Option<RtspFrame>> {
-        loop {
+        while RtspFrame::message_len(&self.buf).is_none() {
             match self.reader.read_buf(&mut self.buf).await {
+                Ok(0) if !self.buf.is_empty() => {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                Ok(0) => break,
                 Ok(_len) => {
                     // println!("Read {} bytes from TcpStream.", len);
-                    break;
                 }
                 Err(err) if err.kind() == ErrorKind::WouldBlock => {
                     /* do nothing */
@@ -77,30 +222,110 @@ impl RtspSession {
                 }
             }
         }
-        println!("Received response:\n{}", String::from_utf8_lossy(&self.buf));
         if self.buf.is_empty() {
-            return Ok(None);
+            Ok(None)
         } else {
             self.parse_frame().await
         }
     }
 
-    /// If the first line of the buffer is a valid RTSP response, parse it and return it
+    /// If the first line of the buffer is a valid RTSP response, parse it and return it, otherwise
+    /// parse the first message as a request of the server. Interleaved data is returned as is
     /// 
     /// Returns:
     /// 
     /// A tuple of the frame and the length of the frame.
     async fn parse_frame(&mut self) -> Result<Option<RtspFrame>> {
-        // Check if the first line is a valid RTSP response
-        match RtspFrame::check_response(&self.buf) {
-            Ok(_) => {
-                let mut cursor = Cursor::new(&self.buf[..]);
-                cursor.set_position(0);
-                let (frame, len) = RtspFrame::parse_response(&mut cursor).await?;
-                self.buf.advance(len);
-                Ok(Some(frame))
-            }
-            Err(err) => Err(err),
+        let len = RtspFrame::message_len(&self.buf).unwrap();
+        if self.buf[0] == b'$' {
+            let mut frame = self.buf.split_to(len);
+            let channel = frame[1];
+            frame.advance(4);
+            return Ok(Some(RtspFrame::Interleaved {
+                channel,
+                payload: frame.freeze(),
+            }));
         }
+        println!(
+            "Received message:\n{}",
+            String::from_utf8_lossy(&self.buf[..len])
+        );
+        let mut cursor = Cursor::new(&self.buf[..len]);
+        cursor.set_position(0);
+        // Check if the first line is a valid RTSP response
+        let frame = match RtspFrame::check_response(&self.buf) {
+            Ok(_) => RtspFrame::parse_response(&mut cursor).await,
+            Err(_) => RtspFrame::parse_request(&mut cursor).await,
+        };
+        // An invalid message is dropped as well, so the next one can be read
+        self.buf.advance(len);
+        frame.map(|(frame, _)| Some(frame))
     }
 }
+
+/// A mock server on a free port of the loopback, `serve` accepts the connections on its own task
+#[cfg(test)]
+pub(crate) async fn test_server<S, F, T>(serve: S) -> (u16, tokio::task::JoinHandle<T>)
+where
+    S: FnOnce(tokio::net::TcpListener) -> F,
+    F: std::future::Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (port, tokio::spawn(serve(listener)))
+}
+
+/// The HTTP header read by a mock server, up to the empty line
+#[cfg(test)]
+pub(crate) async fn test_header(stream: &mut TcpStream) -> String {
+    let mut buf = vec![];
+    while !buf.ends_with(b"\r\n\r\n") {
+        buf.push(stream.read_u8().await.unwrap());
+    }
+    String::from_utf8(buf).unwrap()
+}
+
+#[tokio::test]
+async fn test_frame_writer() {
+    use crate::rtsp_frame::RtspVersion;
+    use std::collections::HashMap;
+
+    // The frames are handed over at once and written as a whole while the peer reads slowly
+    let (client, mut server) = tokio::io::duplex(8);
+    let writer = RtspFrameWriter::spawn(Box::new(client));
+    let response = RtspFrame::RtspResponse {
+        version: RtspVersion::Rtsp10,
+        status_code: 200,
+        reason_phrase: String::from("OK"),
+        c_seq: 3,
+        headers: HashMap::new(),
+        body: String::new(),
+    };
+    let len = writer.write_frame(&response).unwrap();
+    let interleaved = RtspFrame::Interleaved {
+        channel: 1,
+        payload: Bytes::from_static(b"rtcp"),
+    };
+    assert_eq!(writer.write_frame(&interleaved).unwrap(), 8);
+    let flushed = tokio::spawn({
+        let writer = writer.clone();
+        async move { writer.flush().await }
+    });
+    drop(writer);
+    let mut buf = vec![];
+    server.read_to_end(&mut buf).await.unwrap();
+    flushed.await.unwrap().unwrap();
+    assert!(buf[..len].starts_with(b"RTSP/1.0 200 OK\r\nCSeq: 3\r\n"));
+    assert!(buf[..len].ends_with(b"\r\n\r\n"));
+    assert_eq!(&buf[len..], b"$\x01\x00\x04rtcp");
+
+    // The connection is gone once the writer task failed
+    let (client, server) = tokio::io::duplex(8);
+    drop(server);
+    let writer = RtspFrameWriter::spawn(Box::new(client));
+    writer.write_frame(&interleaved).unwrap();
+    let err = writer.flush().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    assert!(writer.write_frame(&interleaved).is_err());
+}
@@ -0,0 +1,346 @@
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName};
+use rustls_pemfile::Item;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{Error, ErrorKind, Result};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+/// `RtspTlsConfig` holds the TLS options of an `rtsps://` connection.
+///
+/// Properties:
+///
+/// * `ca_file`: The PEM file of the trusted CA certificates, the system roots are used otherwise.
+/// * `cert_file`: The PEM file of the client certificate chain, for servers asking for one.
+/// * `key_file`: The PEM file of the private key of the client certificate.
+/// * `insecure`: Skip the verification of the server certificate, for lab use only.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RtspTlsConfig {
+    pub(crate) ca_file: Option<String>,
+    pub(crate) cert_file: Option<String>,
+    pub(crate) key_file: Option<String>,
+    pub(crate) insecure: bool,
+}
+
+impl RtspTlsConfig {
+    /// It performs the TLS handshake on the TCP connection to the server
+    ///
+    /// Arguments:
+    ///
+    /// * `stream`: TcpStream, the connection to the server
+    /// * `host`: &str, the host name or IP address the server certificate must be valid for
+    ///
+    /// Returns:
+    ///
+    /// A Result<TlsStream<TcpStream>>.
+    pub(crate) async fn connect(
+        &self,
+        stream: TcpStream,
+        host: &str,
+    ) -> Result<TlsStream<TcpStream>> {
+        let connector = TlsConnector::from(Arc::new(self.client_config()?));
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(host).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid server name {}: {}", host, err),
+            )
+        })?;
+        connector.connect(server_name, stream).await
+    }
+
+    fn client_config(&self) -> Result<ClientConfig> {
+        let builder = ClientConfig::builder().with_safe_defaults();
+        let builder = if self.insecure {
+            println!("Verification of the server certificate is disabled!");
+            builder.with_custom_certificate_verifier(Arc::new(InsecureVerifier))
+        } else {
+            let verifier = WebPkiVerifier::new(self.root_certificates()?, None);
+            builder.with_custom_certificate_verifier(Arc::new(verifier))
+        };
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => builder
+                .with_client_auth_cert(load_certificates(cert_file)?, load_private_key(key_file)?)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err)),
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Client certificate and key must be given together",
+            )),
+        }
+    }
+
+    /// The CA certificates of the CA file, or the roots of the system
+    fn root_certificates(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match self.ca_file {
+            Some(ref ca_file) => {
+                for certificate in load_certificates(ca_file)? {
+                    roots.add(&certificate).map_err(|err| {
+                        Error::new(ErrorKind::InvalidData, format!("{}: {}", ca_file, err))
+                    })?;
+                }
+            }
+            None => {
+                let certificates = rustls_native_certs::load_native_certs()?;
+                let certificates = certificates
+                    .into_iter()
+                    .map(|certificate| certificate.0)
+                    .collect::<Vec<_>>();
+                roots.add_parsable_certificates(&certificates);
+            }
+        }
+        Ok(roots)
+    }
+}
+
+/// It accepts any server certificate, the signatures of the handshake are still verified
+struct InsecureVerifier;
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader)?;
+    if certificates.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: no certificate found", path),
+        ));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+/// The first PKCS#8, PKCS#1 (RSA) or SEC1 (EC) private key of the PEM file
+fn load_private_key(path: &str) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(Item::PKCS8Key(key)) | Some(Item::RSAKey(key)) | Some(Item::ECKey(key)) => {
+                return Ok(PrivateKey(key))
+            }
+            Some(_) => {}
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: no private key found", path),
+                ))
+            }
+        }
+    }
+}
+
+/// A PEM file of the temporary directory
+#[cfg(test)]
+fn test_pem(name: &str, pem: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "rtsp_invitor_tls_{}_{}.pem",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, pem).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// A mock server answering an OPTIONS over TLS with the certificate, asking for a client
+/// certificate signed by the client CA if given
+#[cfg(test)]
+async fn test_tls_server(
+    certificate: &rcgen::Certificate,
+    client_ca: Option<&rcgen::Certificate>,
+) -> u16 {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(&Certificate(client_ca.serialize_der().unwrap()))
+                .unwrap();
+            builder.with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(
+            vec![Certificate(certificate.serialize_der().unwrap())],
+            PrivateKey(certificate.serialize_private_key_der()),
+        )
+        .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+    let (port, _) = crate::rtsp_session::test_server(|listener| async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+        let mut buf = vec![0; 1500];
+        let len = stream.read(&mut buf).await.unwrap();
+        assert!(buf[..len].starts_with(b"OPTIONS rtsps://localhost/stream RTSP/1.0\r\n"));
+        stream
+            .write_all(b"RTSP/1.0 200 OK\r\nCSeq: 1\r\nPublic: OPTIONS, DESCRIBE\r\n\r\n")
+            .await
+            .unwrap();
+    })
+    .await;
+    port
+}
+
+/// The status code of an OPTIONS over TLS to the server
+#[cfg(test)]
+async fn test_options(host: &str, port: u16, tls: &RtspTlsConfig) -> Result<u16> {
+    use crate::rtsp_frame::{RtspFrame, RtspMethod, RtspVersion};
+
+    let mut session = crate::rtsp_session::RtspSession::connect(host, port, Some(tls)).await?;
+    let request = RtspFrame::RtspRequest {
+        method: RtspMethod::Option,
+        url: String::from("rtsps://localhost/stream"),
+        version: RtspVersion::Rtsp10,
+        c_seq: 1,
+        headers: std::collections::HashMap::new(),
+        body: String::new(),
+    };
+    session.write_frame(&request)?;
+    match session.read_frame().await? {
+        Some(RtspFrame::RtspResponse { status_code, .. }) => Ok(status_code),
+        frame => Err(Error::other(format!("Unexpected frame {:?}", frame))),
+    }
+}
+
+#[tokio::test]
+async fn test_rtsps_trusted_certificate() {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let port = test_tls_server(&certificate, None).await;
+    let tls = RtspTlsConfig {
+        ca_file: Some(test_pem("trusted", &certificate.serialize_pem().unwrap())),
+        ..Default::default()
+    };
+    assert_eq!(test_options("localhost", port, &tls).await.unwrap(), 200);
+}
+
+#[tokio::test]
+async fn test_rtsps_insecure() {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let port = test_tls_server(&certificate, None).await;
+    let tls = RtspTlsConfig {
+        insecure: true,
+        ..Default::default()
+    };
+    assert_eq!(test_options("localhost", port, &tls).await.unwrap(), 200);
+}
+
+#[tokio::test]
+async fn test_rtsps_untrusted_certificate() {
+    let trusted = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let untrusted = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let port = test_tls_server(&untrusted, None).await;
+    let tls = RtspTlsConfig {
+        ca_file: Some(test_pem("untrusted", &trusted.serialize_pem().unwrap())),
+        ..Default::default()
+    };
+    let err = test_options("localhost", port, &tls).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_rtsps_other_host() {
+    // The certificate is valid for localhost, not for its address
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let port = test_tls_server(&certificate, None).await;
+    let tls = RtspTlsConfig {
+        ca_file: Some(test_pem(
+            "other_host",
+            &certificate.serialize_pem().unwrap(),
+        )),
+        ..Default::default()
+    };
+    let err = test_options("127.0.0.1", port, &tls).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn test_rtsps_client_certificate() {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let client = rcgen::generate_simple_self_signed(vec![String::from("client")]).unwrap();
+    let ca_file = test_pem("client_ca", &certificate.serialize_pem().unwrap());
+    let port = test_tls_server(&certificate, Some(&client)).await;
+    let tls = RtspTlsConfig {
+        ca_file: Some(ca_file.clone()),
+        cert_file: Some(test_pem("client_cert", &client.serialize_pem().unwrap())),
+        key_file: Some(test_pem("client_key", &client.serialize_private_key_pem())),
+        insecure: false,
+    };
+    assert_eq!(test_options("localhost", port, &tls).await.unwrap(), 200);
+
+    // The server asking for a certificate ends the session without one
+    let port = test_tls_server(&certificate, Some(&client)).await;
+    let tls = RtspTlsConfig {
+        ca_file: Some(ca_file),
+        ..Default::default()
+    };
+    assert!(test_options("localhost", port, &tls).await.is_err());
+}
+
+#[test]
+fn test_tls_config_errors() {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let cert_file = test_pem("config_cert", &certificate.serialize_pem().unwrap());
+    let key_file = test_pem("config_key", &certificate.serialize_private_key_pem());
+    let invalid = test_pem("config_invalid", "not a PEM file");
+
+    let tls = RtspTlsConfig {
+        cert_file: Some(cert_file.clone()),
+        ..Default::default()
+    };
+    let err = tls.client_config().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let tls = RtspTlsConfig {
+        ca_file: Some(invalid.clone()),
+        ..Default::default()
+    };
+    let err = tls.client_config().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let tls = RtspTlsConfig {
+        ca_file: Some(format!("{}.missing", invalid)),
+        ..Default::default()
+    };
+    let err = tls.client_config().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    // The key file holds no key
+    let tls = RtspTlsConfig {
+        ca_file: Some(cert_file.clone()),
+        cert_file: Some(cert_file.clone()),
+        key_file: Some(cert_file.clone()),
+        insecure: false,
+    };
+    let err = tls.client_config().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let tls = RtspTlsConfig {
+        ca_file: Some(cert_file.clone()),
+        cert_file: Some(cert_file),
+        key_file: Some(key_file),
+        insecure: false,
+    };
+    assert!(tls.client_config().is_ok());
+}
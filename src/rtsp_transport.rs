@@ -0,0 +1,152 @@
+use sscanf::scanf;
+use tokio::io::{Error, Result};
+
+/// `RtspTransport` is the `Transport` header of a SETUP response (RFC 2326 section 12.39, RFC 7826
+/// section 18.54), only the parameters used by the client are kept.
+///
+/// Properties:
+///
+/// * `source`: The address the server sends the media from, if it differs from the RTSP server.
+/// * `server_port`: The RTP and RTCP ports of the server.
+/// * `interleaved`: The RTP and RTCP channels of the media interleaved in the RTSP connection.
+/// * `multicast`: The media is sent to a multicast group instead of the client ports.
+/// * `destination`: The multicast group the media is sent to.
+/// * `port`: The RTP and RTCP ports of the multicast group.
+/// * `ttl`: The time-to-live of the multicast packets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RtspTransport {
+    pub(crate) source: Option<String>,
+    pub(crate) server_port: Option<(u16, u16)>,
+    pub(crate) interleaved: Option<(u8, u8)>,
+    pub(crate) multicast: bool,
+    pub(crate) destination: Option<String>,
+    pub(crate) port: Option<(u16, u16)>,
+    pub(crate) ttl: Option<u8>,
+}
+
+impl RtspTransport {
+    /// It parses the content of a `Transport` header, unknown parameters are ignored
+    ///
+    /// Arguments:
+    ///
+    /// * `transport`: &str, e.g. `RTP/AVP;unicast;client_port=20000-20001;server_port=50000-50001`
+    ///   or `RTP/AVP;unicast;dest_addr=":20000"/":20001";src_addr="10.0.0.1:50000"/"10.0.0.1:50001"`
+    ///   or `RTP/AVP;multicast;destination=232.0.1.1;port=5000-5001;ttl=16`
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtspTransport>.
+    pub(crate) fn parse(transport: &str) -> Result<Self> {
+        let mut result = RtspTransport::default();
+        // The server may list several transports, the first one is selected
+        let transport = transport.split(',').next().unwrap_or_default();
+        for parameter in transport.split(';').map(str::trim) {
+            let (name, value) = match parameter.split_once('=') {
+                Some((name, value)) => (name, value),
+                None => {
+                    result.multicast |= parameter == "multicast";
+                    continue;
+                }
+            };
+            match name {
+                "source" => result.source = Some(value.trim_matches('"').to_string()),
+                "server_port" => {
+                    result.server_port = Some(Self::parse_port_range(value.trim_matches('"'))?)
+                }
+                "interleaved" => {
+                    let (rtp, rtcp) = Self::parse_port_range(value)?;
+                    let channel = |channel: u16| {
+                        u8::try_from(channel).map_err(|_| {
+                            Error::other(format!("Invalid interleaved channels {}", value))
+                        })
+                    };
+                    result.interleaved = Some((channel(rtp)?, channel(rtcp)?));
+                }
+                "destination" => {
+                    let destination = value.trim_matches('"');
+                    let destination = destination.trim_start_matches('[').trim_end_matches(']');
+                    result.destination = Some(destination.to_string())
+                }
+                "port" => result.port = Some(Self::parse_port_range(value)?),
+                "ttl" => {
+                    result.ttl = Some(
+                        value
+                            .parse()
+                            .map_err(|_| Error::other(format!("Invalid ttl {}", value)))?,
+                    )
+                }
+                // The multicast group of RTSP/2.0, the client ports are not echoed otherwise
+                "dest_addr" if result.multicast => {
+                    let (destination, port) = Self::parse_address_list(value)?;
+                    result.destination = destination.or(result.destination);
+                    result.port = Some(port);
+                }
+                "src_addr" => {
+                    let (source, server_port) = Self::parse_address_list(value)?;
+                    result.source = source.or(result.source);
+                    result.server_port = Some(server_port);
+                }
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+
+    /// RTSP/2.0 `"<host>:<port>"[/"<host>:<port>"]`, the RTP address comes first and the RTCP
+    /// address next. The host may be left out, a single address implies the next port for RTCP
+    fn parse_address_list(list: &str) -> Result<(Option<String>, (u16, u16))> {
+        let invalid = || Error::other(format!("Invalid address list {}", list));
+        let mut addresses = list.split('/').map(|address| {
+            let (host, port) = address
+                .trim_matches('"')
+                .rsplit_once(':')
+                .ok_or_else(invalid)?;
+            let port = port.parse::<u16>().map_err(|_| invalid())?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Ok::<_, Error>(((!host.is_empty()).then(|| host.to_string()), port))
+        });
+        let (host, rtp_port) = addresses.next().ok_or_else(invalid)??;
+        let rtcp_port = match addresses.next() {
+            Some(address) => address?.1,
+            None => rtp_port.wrapping_add(1),
+        };
+        Ok((host, (rtp_port, rtcp_port)))
+    }
+
+    /// `<port>[-<port>]`, a single port implies the next port for RTCP
+    fn parse_port_range(range: &str) -> Result<(u16, u16)> {
+        scanf!(range, "{u16}-{u16}")
+            .or_else(|_| scanf!(range, "{u16}").map(|port| (port, port.wrapping_add(1))))
+            .map_err(|err| Error::other(format!("Invalid port range {}: {}", range, err)))
+    }
+}
+
+#[test]
+fn test_parse_rtsp2_transport() {
+    let transport = RtspTransport::parse(
+        "RTP/AVP/UDP;unicast;dest_addr=\":20000\"/\":20001\";src_addr=\"192.0.2.5:50000\"/\"192.0.2.5:50001\";ssrc=93CB001E",
+    )
+    .unwrap();
+    assert_eq!(transport.source.as_deref(), Some("192.0.2.5"));
+    assert_eq!(transport.server_port, Some((50000, 50001)));
+
+    let transport =
+        RtspTransport::parse("RTP/AVP;unicast;src_addr=\"[2001:db8::1]:6000\"").unwrap();
+    assert_eq!(transport.source.as_deref(), Some("2001:db8::1"));
+    assert_eq!(transport.server_port, Some((6000, 6001)));
+
+    let transport =
+        RtspTransport::parse("RTP/AVP;multicast;destination=232.0.1.1;port=5000-5001;ttl=16")
+            .unwrap();
+    assert!(transport.multicast);
+    assert_eq!(transport.destination.as_deref(), Some("232.0.1.1"));
+    assert_eq!(transport.port, Some((5000, 5001)));
+    assert_eq!(transport.ttl, Some(16));
+
+    let transport = RtspTransport::parse(
+        "RTP/AVP/UDP;multicast;dest_addr=\"[ff15::1]:5000\"/\"[ff15::1]:5001\";ttl=8",
+    )
+    .unwrap();
+    assert_eq!(transport.destination.as_deref(), Some("ff15::1"));
+    assert_eq!(transport.port, Some((5000, 5001)));
+}
@@ -0,0 +1,427 @@
+use crate::rtp_packet::random_u32;
+use crate::rtsp_camera::authority;
+#[cfg(test)]
+use crate::rtsp_session::{test_header, test_server};
+use crate::rtsp_session::{RtspFrameWriter, RtspSession, RtspStream};
+use crate::rtsp_tls::RtspTlsConfig;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::BytesMut;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error, ErrorKind, Result};
+use tokio::net::TcpStream;
+
+/// The POST connection never ends, QuickTime announces a large body the server does not wait for
+const POST_CONTENT_LENGTH: u32 = 32767;
+
+/// `RtspTunnel` holds the options of RTSP tunneled over HTTP, as done by QuickTime for networks
+/// only letting HTTP out.
+///
+/// Properties:
+///
+/// * `port`: The HTTP port of the server.
+/// * `proxy`: The `<host>:<port>` of the HTTP proxy the tunnel is opened through with CONNECT.
+#[derive(Debug, Clone)]
+pub(crate) struct RtspTunnel {
+    pub(crate) port: u16,
+    pub(crate) proxy: Option<String>,
+}
+
+impl RtspTunnel {
+    /// It opens the GET and POST connections sharing a session cookie. The server sends the RTSP
+    /// responses and the interleaved media on the GET connection, the requests are sent base64
+    /// encoded on the POST connection
+    ///
+    /// Arguments:
+    ///
+    /// * `host`: &str, the host name or IP address of the server
+    /// * `url`: &str, the RTSP URL whose path is requested over HTTP
+    /// * `tls`: Option<&RtspTlsConfig>, the TLS options, `None` for plain HTTP
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtspSession>.
+    pub(crate) async fn open(
+        &self,
+        host: &str,
+        url: &str,
+        tls: Option<&RtspTlsConfig>,
+    ) -> Result<RtspSession> {
+        let host_start = url.find("://").map_or(0, |scheme| scheme + 3);
+        let path = url[host_start..]
+            .find('/')
+            .map_or("/", |path| &url[host_start + path..]);
+        let cookie = format!("{:08x}{:08x}", random_u32(), random_u32());
+        // HTTP/1.1 proxies and virtual hosts route by the Host header, even for HTTP/1.0
+        let authority = authority(host, self.port);
+
+        let mut get = self.connect(host, tls).await?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nx-sessioncookie: {}\r\nAccept: application/x-rtsp-tunnelled\r\nPragma: no-cache\r\nCache-Control: no-cache\r\n\r\n",
+            path, authority, cookie
+        );
+        get.write_all(request.as_bytes()).await?;
+        // The server may send RTSP right after the header of its response
+        let (status_code, header, buf) = read_http_response(&mut get).await?;
+        check_success(status_code, &header)?;
+
+        let mut post = self.connect(host, tls).await?;
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nx-sessioncookie: {}\r\nContent-Type: application/x-rtsp-tunnelled\r\nPragma: no-cache\r\nCache-Control: no-cache\r\nContent-Length: {}\r\nExpires: Sun, 9 Jan 1972 00:00:00 GMT\r\n\r\n",
+            path, authority, cookie, POST_CONTENT_LENGTH
+        );
+        post.write_all(request.as_bytes()).await?;
+        post.flush().await?;
+        println!("RTSP tunneled over HTTP to {}", authority);
+
+        Ok(RtspSession {
+            reader: get,
+            writer: RtspFrameWriter::spawn(Box::new(Base64Writer::new(post))),
+            buf,
+        })
+    }
+
+    /// It opens a connection to the HTTP port of the server, through the proxy if given, wrapped
+    /// in TLS for `rtsps://`
+    async fn connect(
+        &self,
+        host: &str,
+        tls: Option<&RtspTlsConfig>,
+    ) -> Result<Box<dyn RtspStream>> {
+        let authority = authority(host, self.port);
+        let stream = match self.proxy {
+            Some(ref proxy) => {
+                let mut stream = TcpStream::connect(proxy).await?;
+                let request = format!(
+                    "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\nUser-Agent: rtsp-invitor-1.0\r\n\r\n",
+                    authority
+                );
+                stream.write_all(request.as_bytes()).await?;
+                let (status_code, header, _) = read_http_response(&mut stream).await?;
+                check_success(status_code, &header)?;
+                stream
+            }
+            None => TcpStream::connect(&authority).await?,
+        };
+        match tls {
+            Some(tls) => Ok(Box::new(tls.connect(stream, host).await?)),
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
+
+/// It reads the header of an HTTP response
+///
+/// Returns:
+///
+/// A Result of the status code, the header and the data read after the header.
+pub(crate) async fn read_http_response<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<(u16, String, BytesMut)> {
+    let mut buf = BytesMut::with_capacity(1500);
+    let header_len = loop {
+        if let Some(position) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    };
+    let header = String::from_utf8_lossy(&buf.split_to(header_len)).into_owned();
+    // Status:
+    //      HTTP/<version> <status_code> <reason_phrase>\r\n
+    let status = header.lines().next().unwrap_or_default();
+    let status_code = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status_code| status_code.parse().ok())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid HTTP status {}", status),
+            )
+        })?;
+    Ok((status_code, header, buf))
+}
+
+/// The GET request and CONNECT succeed with 2xx
+fn check_success(status_code: u16, header: &str) -> Result<()> {
+    if (200..300).contains(&status_code) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!(
+                "HTTP tunnel refused: {}",
+                header.lines().next().unwrap_or_default()
+            ),
+        ))
+    }
+}
+
+/// `Base64Writer` encodes what is written to the POST connection. Each write is encoded on its
+/// own, so every RTSP message written at once ends with its own padding.
+///
+/// Properties:
+///
+/// * `inner`: The POST connection.
+/// * `pending`: The encoded data not written to the connection yet.
+/// * `written`: The length of the encoded data written already.
+struct Base64Writer<W> {
+    inner: W,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> Base64Writer<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: vec![],
+            written: 0,
+        }
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.written < self.pending.len() {
+            let len =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if len == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.written += len;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Base64Writer<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        this.pending = STANDARD.encode(buf).into_bytes();
+        this.written = 0;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// A mock server answering the GET with the response, then OPTIONS over the tunnel followed by
+/// interleaved media
+#[cfg(test)]
+async fn test_tunnel_server(response: &'static [u8]) -> (u16, tokio::task::JoinHandle<()>) {
+    test_server(|server| async move {
+        let (mut get, _) = server.accept().await.unwrap();
+        let header = test_header(&mut get).await;
+        assert!(header.starts_with("GET /stream HTTP/1.0\r\n"));
+        let host = format!(
+            "Host: 127.0.0.1:{}\r\n",
+            server.local_addr().unwrap().port()
+        );
+        assert!(header.contains(&host));
+        assert!(header.contains("Accept: application/x-rtsp-tunnelled\r\n"));
+        let cookie = header
+            .lines()
+            .find_map(|line| line.strip_prefix("x-sessioncookie: "))
+            .unwrap()
+            .to_string();
+        get.write_all(response).await.unwrap();
+        if !response.starts_with(b"HTTP/1.0 200") {
+            return;
+        }
+
+        let (mut post, _) = server.accept().await.unwrap();
+        let header = test_header(&mut post).await;
+        assert!(header.starts_with("POST /stream HTTP/1.0\r\n"));
+        assert!(header.contains(&host));
+        assert!(header.contains(&format!("x-sessioncookie: {}\r\n", cookie)));
+        let mut encoded = vec![];
+        let request = loop {
+            encoded.push(post.read_u8().await.unwrap());
+            if let Ok(request) = STANDARD.decode(&encoded) {
+                if request.ends_with(b"\r\n\r\n") {
+                    break String::from_utf8(request).unwrap();
+                }
+            }
+        };
+        assert!(request.starts_with("OPTIONS rtsp://127.0.0.1/stream RTSP/1.0\r\n"));
+        get.write_all(b"RTSP/1.0 200 OK\r\nCSeq: 1\r\n\r\n$\x00\x00\x04abcd")
+            .await
+            .unwrap();
+    })
+    .await
+}
+
+/// A mock HTTP proxy answering CONNECT to the server with the response
+#[cfg(test)]
+async fn test_proxy(server_port: u16, response: &'static [u8]) -> u16 {
+    let (port, _) = test_server(|proxy| async move {
+        loop {
+            let (mut client, _) = proxy.accept().await.unwrap();
+            let header = test_header(&mut client).await;
+            let authority = format!("127.0.0.1:{}", server_port);
+            assert!(header.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", authority)));
+            assert!(header.contains(&format!("Host: {}\r\n", authority)));
+            client.write_all(response).await.unwrap();
+            if !response.starts_with(b"HTTP/1.1 200") {
+                continue;
+            }
+            let mut server = TcpStream::connect(authority).await.unwrap();
+            tokio::spawn(async move {
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+            });
+        }
+    })
+    .await;
+    port
+}
+
+/// It sends OPTIONS over the tunnel, the response and the interleaved media follow
+#[cfg(test)]
+async fn test_options(session: &mut RtspSession) {
+    use crate::rtsp_frame::{RtspFrame, RtspMethod, RtspVersion};
+
+    let request = RtspFrame::RtspRequest {
+        method: RtspMethod::Option,
+        url: String::from("rtsp://127.0.0.1/stream"),
+        version: RtspVersion::Rtsp10,
+        c_seq: 1,
+        headers: std::collections::HashMap::new(),
+        body: String::new(),
+    };
+    session.write_frame(&request).unwrap();
+    match session.read_frame().await.unwrap() {
+        Some(RtspFrame::RtspResponse { status_code, .. }) => assert_eq!(status_code, 200),
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+    match session.read_frame().await.unwrap() {
+        Some(RtspFrame::Interleaved { channel, payload }) => {
+            assert_eq!(channel, 0);
+            assert_eq!(&payload[..], b"abcd");
+        }
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn test_tunnel_direct() {
+    use crate::rtsp_frame::RtspFrame;
+
+    // The media sent right after the header of the GET response is not lost
+    let (port, server) = test_tunnel_server(
+        b"HTTP/1.0 200 OK\r\nContent-Type: application/x-rtsp-tunnelled\r\n\r\n$\x01\x00\x02hi",
+    )
+    .await;
+    let tunnel = RtspTunnel { port, proxy: None };
+    let mut session = tunnel
+        .open("127.0.0.1", "rtsp://127.0.0.1/stream", None)
+        .await
+        .unwrap();
+    match session.read_frame().await.unwrap() {
+        Some(RtspFrame::Interleaved { channel, payload }) => {
+            assert_eq!(channel, 1);
+            assert_eq!(&payload[..], b"hi");
+        }
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+    test_options(&mut session).await;
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tunnel_through_proxy() {
+    let (port, server) = test_tunnel_server(
+        b"HTTP/1.0 200 OK\r\nContent-Type: application/x-rtsp-tunnelled\r\n\r\n",
+    )
+    .await;
+    let proxy_port = test_proxy(port, b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+    let tunnel = RtspTunnel {
+        port,
+        proxy: Some(format!("127.0.0.1:{}", proxy_port)),
+    };
+    let mut session = tunnel
+        .open("127.0.0.1", "rtsp://127.0.0.1/stream", None)
+        .await
+        .unwrap();
+    test_options(&mut session).await;
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tunnel_refused() {
+    let (port, server) = test_tunnel_server(b"HTTP/1.0 403 Forbidden\r\n\r\n").await;
+    let tunnel = RtspTunnel { port, proxy: None };
+    let err = tunnel
+        .open("127.0.0.1", "rtsp://127.0.0.1/stream", None)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    assert!(err.to_string().contains("403 Forbidden"));
+    server.await.unwrap();
+
+    // The proxy refusing CONNECT
+    let proxy_port = test_proxy(port, b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+    let tunnel = RtspTunnel {
+        port,
+        proxy: Some(format!("127.0.0.1:{}", proxy_port)),
+    };
+    let err = tunnel
+        .open("127.0.0.1", "rtsp://127.0.0.1/stream", None)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    assert!(err.to_string().contains("407"));
+}
+
+#[tokio::test]
+async fn test_read_http_response() {
+    let mut response = &b"HTTP/1.1 200 OK\r\nServer: mock\r\n\r\nRTSP"[..];
+    let (status_code, header, buf) = read_http_response(&mut response).await.unwrap();
+    assert_eq!(status_code, 200);
+    assert_eq!(header, "HTTP/1.1 200 OK\r\nServer: mock\r\n\r\n");
+    assert_eq!(&buf[..], b"RTSP");
+
+    let mut response = &b"HTTP/1.1 200 OK\r\nServer: mock\r\n"[..];
+    let err = read_http_response(&mut response).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    for response in [
+        &b"HTTP/1.1\r\n\r\n"[..],
+        b"HTTP/1.1 OK\r\n\r\n",
+        b"\r\n\r\n",
+    ] {
+        let mut response = response;
+        let err = read_http_response(&mut response).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[tokio::test]
+async fn test_base64_writer() {
+    // Each write is padded on its own, and written through a connection taking 3 bytes at once
+    let (writer, mut reader) = tokio::io::duplex(3);
+    let mut writer = Base64Writer::new(writer);
+    let reading = tokio::spawn(async move {
+        let mut encoded = String::new();
+        reader.read_to_string(&mut encoded).await.unwrap();
+        encoded
+    });
+    writer.write_all(b"a").await.unwrap();
+    writer.write_all(b"bc").await.unwrap();
+    writer.write_all(b"OPTIONS").await.unwrap();
+    writer.shutdown().await.unwrap();
+    drop(writer);
+    assert_eq!(reading.await.unwrap(), "YQ==YmM=T1BUSU9OUw==");
+}
@@ -0,0 +1,591 @@
+use crate::rtp_packet::random_u32;
+use crate::rtsp_session::{RtspSession, RtspStream};
+use crate::rtsp_tls::RtspTlsConfig;
+use crate::rtsp_tunnel::read_http_response;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{Buf, BufMut, BytesMut};
+use sha1::{Digest, Sha1};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, Error, ErrorKind, ReadBuf, Result};
+use tokio::net::TcpStream;
+
+/// Appended to the key of the client to compute `Sec-WebSocket-Accept` (RFC 6455 section 1.3)
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// WebSocket opcodes (RFC 6455 section 5.2)
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// The largest frame received, an interleaved packet takes at most 64 KiB and an RTSP message
+/// is far smaller, so a longer frame is not buffered
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// `RtspWebSocket` holds the endpoint of RTSP carried over WebSocket, as exposed by some video
+/// management systems. The messages and interleaved media are framed as on a TCP connection.
+///
+/// Properties:
+///
+/// * `url`: The `ws://` or `wss://` URL of the endpoint.
+/// * `protocol`: The subprotocol asked for in `Sec-WebSocket-Protocol`, e.g. `binary`, which the
+///   server must then select. `None` to ask for none.
+#[derive(Debug, Clone)]
+pub(crate) struct RtspWebSocket {
+    pub(crate) url: String,
+    pub(crate) protocol: Option<String>,
+}
+
+impl RtspWebSocket {
+    /// It opens the WebSocket to the endpoint, over TLS for `wss://`
+    ///
+    /// Arguments:
+    ///
+    /// * `tls`: &RtspTlsConfig, the TLS options used for `wss://`
+    ///
+    /// Returns:
+    ///
+    /// A Result<RtspSession>.
+    pub(crate) async fn open(&self, tls: &RtspTlsConfig) -> Result<RtspSession> {
+        // ws[s]://<host>[:<port>][/<path>]
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid WebSocket URL {}", self.url),
+            )
+        };
+        let (secure, rest) = match self.url.split_once("://").ok_or_else(invalid)? {
+            ("ws", rest) => (false, rest),
+            ("wss", rest) => (true, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(path) => (&rest[..path], &rest[path..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // The colons of an IPv6 address are enclosed in brackets
+            Some((host, port)) if !port.ends_with(']') => {
+                (host, port.parse::<u16>().map_err(|_| invalid())?)
+            }
+            _ => (authority, if secure { 443 } else { 80 }),
+        };
+
+        let stream = TcpStream::connect(format!("{}:{}", host, port)).await?;
+        let mut stream: Box<dyn RtspStream> = if secure {
+            Box::new(tls.connect(stream, host).await?)
+        } else {
+            Box::new(stream)
+        };
+
+        // Opening handshake (RFC 6455 section 4.1)
+        let key = STANDARD.encode(
+            [random_u32(), random_u32(), random_u32(), random_u32()]
+                .iter()
+                .flat_map(|random| random.to_be_bytes())
+                .collect::<Vec<_>>(),
+        );
+        let protocol = self.protocol.as_ref().map_or(String::new(), |protocol| {
+            format!("Sec-WebSocket-Protocol: {}\r\n", protocol)
+        });
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rtsp-invitor-1.0\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+            path, authority, key, protocol
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let (status_code, header, buf) = read_http_response(&mut stream).await?;
+        let field = |field: &str| {
+            header
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case(field))
+                .map(|(_, value)| value.trim())
+        };
+        let expected = STANDARD.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)));
+        // The server selects the subprotocol asked for, or none if none was (RFC 6455 section 4.1)
+        if status_code != 101
+            || field("Sec-WebSocket-Accept") != Some(expected.as_str())
+            || field("Sec-WebSocket-Protocol") != self.protocol.as_deref()
+        {
+            return Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!(
+                    "WebSocket refused: {}",
+                    header.lines().next().unwrap_or_default()
+                ),
+            ));
+        }
+        println!("RTSP over WebSocket to {}", self.url);
+
+        Ok(RtspSession::new(WebSocketStream::new(stream, buf)))
+    }
+}
+
+/// `WebSocketStream` carries a byte stream in the binary messages of a WebSocket. The payload of
+/// the data frames is read as one stream, each write is sent as a masked binary message.
+///
+/// Properties:
+///
+/// * `inner`: The TCP or TLS stream of the WebSocket.
+/// * `frames`: The frames received and not parsed yet.
+/// * `data`: The payload of the data frames not read yet.
+/// * `pending`: The frames not written to the stream yet, e.g. the answer to a ping.
+/// * `closed`: The server closed the WebSocket.
+struct WebSocketStream<S> {
+    inner: S,
+    frames: BytesMut,
+    data: BytesMut,
+    pending: BytesMut,
+    closed: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketStream<S> {
+    fn new(inner: S, frames: BytesMut) -> Self {
+        Self {
+            inner,
+            frames,
+            data: BytesMut::new(),
+            pending: BytesMut::new(),
+            closed: false,
+        }
+    }
+
+    /// FIN(1) RSV(3) opcode(4) | MASK(1) length(7) | [extended length(16/64)] | [masking key(32)] |
+    /// payload
+    ///
+    /// Returns:
+    ///
+    /// A Result of the opcode and payload of the first frame received, `None` if more data must
+    /// be read, an error if the frame is longer than `MAX_FRAME_SIZE`.
+    fn parse_frame(&mut self) -> Result<Option<(u8, BytesMut)>> {
+        let buf = &self.frames[..];
+        let (len, mut header_len) = match buf.get(1).map(|byte| byte & 0x7f) {
+            Some(126) if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            Some(127) if buf.len() >= 10 => {
+                (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10)
+            }
+            Some(126 | 127) | None => return Ok(None),
+            Some(len) => (len as u64, 2),
+        };
+        if len > MAX_FRAME_SIZE as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("WebSocket frame of {} bytes is too long", len),
+            ));
+        }
+        let len = len as usize;
+        let masked = buf[1] & 0x80 != 0;
+        let mask = if masked {
+            header_len += 4;
+            match buf.get(header_len - 4..header_len) {
+                Some(mask) => Some(mask.to_vec()),
+                None => return Ok(None),
+            }
+        } else {
+            None
+        };
+        if buf.len() < header_len + len {
+            return Ok(None);
+        }
+        let opcode = buf[0] & 0x0f;
+        self.frames.advance(header_len);
+        let mut payload = self.frames.split_to(len);
+        if let Some(mask) = mask {
+            payload
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, byte)| *byte ^= mask[i % 4]);
+        }
+        Ok(Some((opcode, payload)))
+    }
+
+    /// The frames of the client are masked with a random key (RFC 6455 section 5.3)
+    fn queue_frame(&mut self, opcode: u8, payload: &[u8]) {
+        self.pending.put_u8(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => self.pending.put_u8(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                self.pending.put_u8(0x80 | 126);
+                self.pending.put_u16(len as u16);
+            }
+            len => {
+                self.pending.put_u8(0x80 | 127);
+                self.pending.put_u64(len as u64);
+            }
+        }
+        let mask = random_u32().to_be_bytes();
+        self.pending.put_slice(&mask);
+        self.pending.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while !self.pending.is_empty() {
+            let len = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if len == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.pending.advance(len);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.data.is_empty() {
+                let len = this.data.len().min(buf.remaining());
+                buf.put_slice(&this.data.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+            if this.closed {
+                return Poll::Ready(Ok(()));
+            }
+            match this.parse_frame()? {
+                Some((OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY, payload)) => {
+                    this.data = payload
+                }
+                Some((OPCODE_PING, payload)) => {
+                    // The pong is sent now if possible, with the next write otherwise
+                    this.queue_frame(OPCODE_PONG, &payload);
+                    let _ = this.poll_drain(cx);
+                }
+                Some((OPCODE_CLOSE, payload)) => {
+                    this.queue_frame(OPCODE_CLOSE, &payload[..payload.len().min(2)]);
+                    let _ = this.poll_drain(cx);
+                    this.closed = true;
+                }
+                Some(_) => {}
+                None => {
+                    let mut read = [0; 4096];
+                    let mut read_buf = ReadBuf::new(&mut read);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                    if read_buf.filled().is_empty() {
+                        this.closed = true;
+                    }
+                    this.frames.extend_from_slice(read_buf.filled());
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        this.queue_frame(OPCODE_BINARY, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if !this.closed {
+            this.queue_frame(OPCODE_CLOSE, &[]);
+            this.closed = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// A mock server accepting a WebSocket with the status, a valid or wrong accept key and the
+/// subprotocol
+#[cfg(test)]
+async fn test_websocket_server(
+    status: &'static str,
+    valid: bool,
+    protocol: Option<&'static str>,
+) -> (u16, tokio::task::JoinHandle<TcpStream>) {
+    use crate::rtsp_session::{test_header, test_server};
+
+    test_server(move |listener| async move {
+        let port = listener.local_addr().unwrap().port();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let header = test_header(&mut stream).await;
+        assert!(header.starts_with("GET /rtsp-over-websocket HTTP/1.1\r\n"));
+        assert!(header.contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+        assert!(header.contains("Sec-WebSocket-Version: 13\r\n"));
+        let key = header
+            .lines()
+            .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+            .unwrap();
+        let key = if valid {
+            key
+        } else {
+            "dGhlIHNhbXBsZSBub25jZQ=="
+        };
+        let accept = STANDARD.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)));
+        let protocol = protocol.map_or(String::new(), |protocol| {
+            format!("Sec-WebSocket-Protocol: {}\r\n", protocol)
+        });
+        let response = format!(
+            "HTTP/1.1 {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nsec-websocket-accept: {}\r\n{}\r\n",
+            status, accept, protocol
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream
+    })
+    .await
+}
+
+/// The opcodes and unmasked payloads of the frames
+#[cfg(test)]
+fn test_frames(frames: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut stream = WebSocketStream::new(tokio::io::duplex(1).0, BytesMut::from(frames));
+    let mut parsed = vec![];
+    while let Some((opcode, payload)) = stream.parse_frame().unwrap() {
+        parsed.push((opcode, payload.to_vec()));
+    }
+    assert!(stream.frames.is_empty());
+    parsed
+}
+
+#[tokio::test]
+async fn test_websocket_session() {
+    use crate::rtsp_frame::{RtspFrame, RtspMethod, RtspVersion};
+    use tokio::io::AsyncReadExt;
+
+    let (port, server) = test_websocket_server("101 Switching Protocols", true, None).await;
+    let websocket = RtspWebSocket {
+        url: format!("ws://127.0.0.1:{}/rtsp-over-websocket", port),
+        protocol: None,
+    };
+    let mut session = websocket.open(&RtspTlsConfig::default()).await.unwrap();
+    let mut stream = server.await.unwrap();
+    let request = RtspFrame::RtspRequest {
+        method: RtspMethod::Option,
+        url: String::from("rtsp://127.0.0.1/stream"),
+        version: RtspVersion::Rtsp10,
+        c_seq: 1,
+        headers: std::collections::HashMap::new(),
+        body: String::new(),
+    };
+    session.write_frame(&request).unwrap();
+    let mut frame = vec![0; 2];
+    stream.read_exact(&mut frame).await.unwrap();
+    frame.resize(6 + (frame[1] & 0x7f) as usize, 0);
+    stream.read_exact(&mut frame[2..]).await.unwrap();
+    let frames = test_frames(&frame);
+    assert_eq!(frames[0].0, OPCODE_BINARY);
+    assert!(frames[0]
+        .1
+        .starts_with(b"OPTIONS rtsp://127.0.0.1/stream RTSP/1.0\r\n"));
+
+    // The response and the interleaved media are split across frames
+    let response = b"RTSP/1.0 200 OK\r\nCSeq: 1\r\n\r\n$\x00\x00\x04ab";
+    let mut frames = vec![OPCODE_BINARY, response.len() as u8];
+    frames.extend(response);
+    frames.extend([0x80 | OPCODE_CONTINUATION, 2, b'c', b'd']);
+    stream.write_all(&frames).await.unwrap();
+    match session.read_frame().await.unwrap() {
+        Some(RtspFrame::RtspResponse { status_code, .. }) => assert_eq!(status_code, 200),
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+    match session.read_frame().await.unwrap() {
+        Some(RtspFrame::Interleaved { channel, payload }) => {
+            assert_eq!(channel, 0);
+            assert_eq!(&payload[..], b"abcd");
+        }
+        frame => panic!("Unexpected frame {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_refused() {
+    // The server must switch protocols with the accept key of the client key and select the
+    // subprotocol asked for
+    for (status, valid, requested, selected) in [
+        ("101 Switching Protocols", false, None, None),
+        ("200 OK", true, None, None),
+        ("101 Switching Protocols", true, Some("binary"), None),
+        ("101 Switching Protocols", true, None, Some("binary")),
+        (
+            "101 Switching Protocols",
+            true,
+            Some("binary"),
+            Some("text"),
+        ),
+    ] {
+        let (port, server) = test_websocket_server(status, valid, selected).await;
+        let websocket = RtspWebSocket {
+            url: format!("ws://127.0.0.1:{}/rtsp-over-websocket", port),
+            protocol: requested.map(String::from),
+        };
+        let err = websocket
+            .open(&RtspTlsConfig::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains(status));
+        server.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_protocol() {
+    // The subprotocol is asked for only when given
+    let (port, server) =
+        test_websocket_server("101 Switching Protocols", true, Some("binary")).await;
+    let websocket = RtspWebSocket {
+        url: format!("ws://127.0.0.1:{}/rtsp-over-websocket", port),
+        protocol: Some(String::from("binary")),
+    };
+    websocket.open(&RtspTlsConfig::default()).await.unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_websocket_invalid_url() {
+    for url in [
+        "127.0.0.1:8554/stream",
+        "http://127.0.0.1/stream",
+        "ws://127.0.0.1:rtsp/stream",
+        "wss://127.0.0.1:65536",
+    ] {
+        let websocket = RtspWebSocket {
+            url: url.to_string(),
+            protocol: None,
+        };
+        let err = websocket
+            .open(&RtspTlsConfig::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", url);
+    }
+}
+
+#[test]
+fn test_websocket_frame_lengths() {
+    // The frames of the client are masked, with a 16 or 64 bits length from 126 bytes on
+    let mut stream = WebSocketStream::new(tokio::io::duplex(1).0, BytesMut::new());
+    let payloads = [vec![1; 125], vec![2; 126], vec![3; 65536]];
+    for payload in payloads.iter() {
+        stream.queue_frame(OPCODE_BINARY, payload);
+    }
+    assert_eq!(&stream.pending[..2], &[0x82, 0x80 | 125]);
+    assert_eq!(&stream.pending[131..135], &[0x82, 0x80 | 126, 0, 126]);
+    assert_eq!(
+        &stream.pending[265..275],
+        &[0x82, 0x80 | 127, 0, 0, 0, 0, 0, 1, 0, 0]
+    );
+    let frames = test_frames(&stream.pending);
+    assert_eq!(
+        frames,
+        payloads
+            .iter()
+            .map(|payload| (OPCODE_BINARY, payload.clone()))
+            .collect::<Vec<_>>()
+    );
+
+    // A frame is parsed once all of it is received
+    let frame = stream.pending.split_to(131);
+    stream.frames.extend_from_slice(&frame[..1]);
+    assert!(stream.parse_frame().unwrap().is_none());
+    stream.frames.extend_from_slice(&frame[1..130]);
+    assert!(stream.parse_frame().unwrap().is_none());
+    stream.frames.extend_from_slice(&frame[130..]);
+    assert_eq!(stream.parse_frame().unwrap().unwrap().1.len(), 125);
+    let mut frame = vec![0x82, 127, 0, 0, 0, 0];
+    stream.frames.extend_from_slice(&frame);
+    assert!(stream.parse_frame().unwrap().is_none());
+    frame.extend([0, 0, 0, 1, 0xff]);
+    stream.frames = BytesMut::from(&frame[..]);
+    assert_eq!(
+        stream.parse_frame().unwrap().unwrap(),
+        (OPCODE_BINARY, BytesMut::from(&[0xff][..]))
+    );
+}
+
+#[test]
+fn test_websocket_frame_too_long() {
+    // A length above the cap fails before the payload is awaited
+    let mut stream = WebSocketStream::new(tokio::io::duplex(1).0, BytesMut::new());
+    stream
+        .frames
+        .extend_from_slice(&[0x82, 127, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    let err = stream.parse_frame().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    stream.frames = BytesMut::from(&[0x82, 127, 0, 0, 0, 0, 0, 0x10, 0, 1][..]);
+    assert!(stream.parse_frame().is_err());
+    stream.frames = BytesMut::from(&[0x82, 127, 0, 0, 0, 0, 0, 0x10, 0, 0][..]);
+    assert!(stream.parse_frame().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_websocket_control_frames() {
+    use tokio::io::AsyncReadExt;
+
+    let (client, mut server) = tokio::io::duplex(4096);
+    let mut stream = WebSocketStream::new(client, BytesMut::new());
+    // A ping is answered with a pong, the unknown opcodes are ignored
+    let mut frames = vec![0x80 | OPCODE_PING, 2, b'h', b'i', 0x83, 1, 0];
+    frames.extend([0x80 | OPCODE_TEXT, 2, b'o', b'k']);
+    server.write_all(&frames).await.unwrap();
+    let mut buf = [0; 16];
+    let len = stream.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], b"ok");
+    let mut pong = [0; 8];
+    server.read_exact(&mut pong).await.unwrap();
+    assert_eq!(test_frames(&pong), [(OPCODE_PONG, b"hi".to_vec())]);
+
+    // A close is echoed with its status code, the stream then ends
+    server
+        .write_all(&[0x80 | OPCODE_CLOSE, 6, 0x03, 0xe8, b'b', b'y', b'e', b'!'])
+        .await
+        .unwrap();
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+    let mut close = [0; 8];
+    server.read_exact(&mut close).await.unwrap();
+    assert_eq!(test_frames(&close), [(OPCODE_CLOSE, vec![0x03, 0xe8])]);
+    stream.shutdown().await.unwrap();
+    drop(stream);
+    let mut rest = vec![];
+    server.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn test_websocket_shutdown() {
+    use tokio::io::AsyncReadExt;
+
+    let (client, mut server) = tokio::io::duplex(4096);
+    let mut stream = WebSocketStream::new(client, BytesMut::new());
+    stream.write_all(b"TEARDOWN").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut frames = vec![];
+    server.read_to_end(&mut frames).await.unwrap();
+    assert_eq!(
+        test_frames(&frames),
+        [
+            (OPCODE_BINARY, b"TEARDOWN".to_vec()),
+            (OPCODE_CLOSE, vec![])
+        ]
+    );
+
+    // The stream ends with the connection
+    drop(server);
+    let mut buf = [0; 16];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+}